@@ -0,0 +1,96 @@
+; Hello world for Ben Eater's breadboard computer, on the LCD
+    .setcpu "6502"
+
+PORTB   = $6000
+PORTA   = $6001
+DDRB    = $6002
+DDRA    = $6003
+
+E       = %10000000
+RW      = %01000000
+RS      = %00100000
+
+    .segment "CODE"
+
+reset:
+    LDX #$ff
+    TXS
+
+    LDA #%11111111      ; all pins on port B are outputs
+    STA DDRB
+    LDA #%11100000      ; the top three pins on port A are outputs
+    STA DDRA
+
+    LDA #%00111000      ; 8 bit mode, 2 lines, 5x8 font
+    JSR lcd_instruction
+    LDA #%00001110      ; display on, cursor on, blink off
+    JSR lcd_instruction
+    LDA #%00000110      ; increment the address, don't shift the display
+    JSR lcd_instruction
+    LDA #%00000001      ; clear the display
+    JSR lcd_instruction
+
+    LDX #$00
+print:
+    LDA message,X
+    BEQ loop
+    JSR print_char
+    INX
+    JMP print
+
+loop:
+    JMP loop
+
+; Wait until the LCD is no longer busy
+lcd_wait:
+    PHA
+    LDA #%00000000      ; port B is an input
+    STA DDRB
+lcd_busy:
+    LDA #RW
+    STA PORTA
+    LDA #(RW | E)
+    STA PORTA
+    LDA PORTB
+    AND #%10000000      ; busy flag
+    BNE lcd_busy
+
+    LDA #RW
+    STA PORTA
+    LDA #%11111111      ; port B is an output again
+    STA DDRB
+    PLA
+    RTS
+
+lcd_instruction:
+    JSR lcd_wait
+    STA PORTB
+    LDA #$00            ; clear RS, RW and E
+    STA PORTA
+    LDA #E              ; pulse E to send the instruction
+    STA PORTA
+    LDA #$00
+    STA PORTA
+    RTS
+
+print_char:
+    JSR lcd_wait
+    STA PORTB
+    LDA #RS             ; select the data register
+    STA PORTA
+    LDA #(RS | E)       ; pulse E to send the data
+    STA PORTA
+    LDA #RS
+    STA PORTA
+    RTS
+
+    .segment "RODATA"
+
+message:
+    .asciiz "Hello, world!"
+
+    .segment "VECTORS"
+
+    .word $0000         ; NMI
+    .word reset         ; RESET
+    .word $0000         ; IRQ
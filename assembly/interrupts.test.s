@@ -0,0 +1,125 @@
+; Tests for hardware interrupts
+; IRQ, NMI, RTI and the handlers in the standard ROM
+
+.include "test.inc"
+
+; A held IRQ line is ignored while interrupts are disabled
+    SEI
+    LDA #$01
+    STA IRQ_LINE
+    NOP
+    NOP
+    LDA #$00
+    STA IRQ_LINE
+
+    VRFY    :+
+    JMP     :++
+
+:   TestStart   $01
+    TestAddress IRQ_COUNT, $00
+    TestAddress NMI_COUNT, $00
+    TestInterruptSet
+    TestEnd
+
+; Install our own IRQ handler, and enable interrupts. The instruction
+; following CLI is executed before the interrupt is taken.
+:   LDA #<irq_handler
+    STA IRQ_VECTOR
+    LDA #>irq_handler
+    STA IRQ_VECTOR+1
+
+    LDA #$01
+    STA IRQ_LINE
+    LDX #$00
+    LDY #$00
+    CLI
+    LDX #$42        ; this runs before the handler
+    SEI
+
+    VRFY    :+
+    JMP     :++
+
+:   TestStart   $02
+    TestAddress IRQ_COUNT, $01
+    TestAddress r1, $42         ; X as seen by the handler
+    TestAddress r2, %00000100   ; I was set in the handler
+    TestAddress r3, $00         ; Y as seen by the handler
+    TestX       $42
+    TestY       $00             ; Y is restored by the ROM
+    TestEnd
+
+; NMI is taken even if interrupts are disabled, and only once per edge
+:   LDA #<nmi_handler
+    STA NMI_VECTOR
+    LDA #>nmi_handler
+    STA NMI_VECTOR+1
+
+    SEI
+    LDA #$01
+    STA NMI_LINE
+    NOP
+    NOP             ; line is still held, this must not trigger again
+    LDA #$00
+    STA NMI_LINE
+
+    VRFY    :+
+    JMP     :++
+
+:   TestStart   $03
+    TestAddress NMI_COUNT, $01
+    TestAddress a1, $01
+    TestAddress IRQ_COUNT, $01
+    TestInterruptSet
+    TestEnd
+
+; A new edge triggers another NMI
+:   LDA #$01
+    STA NMI_LINE
+    LDA #$00
+    STA NMI_LINE
+    LDA #$01
+    STA NMI_LINE
+    LDA #$00
+    STA NMI_LINE
+
+    VRFY    :+
+    JMP     :++
+
+:   TestStart   $04
+    TestAddress NMI_COUNT, $03
+    TestAddress a1, $03
+    TestEnd
+
+; End of all tests
+:   HALT
+
+; Our own IRQ handler, called from the ROM. Releases the IRQ line
+; and records some state
+irq_handler:
+    STX r1
+    STY r3
+    StStatus r2, %00000100
+    LDA #$00
+    STA IRQ_LINE
+    LDY #$ff        ; the ROM restores Y
+    RTS
+
+nmi_handler:
+    INC a1
+    RTS
+
+    ; In case we keep going here
+    FAIL
+    FAIL
+
+.data
+; Some result variables to prevent needing too many test blocks
+    r1: .byte $de
+    r2: .byte $ad
+    r3: .byte $be
+    r4: .byte $af
+
+    a1: .byte $00
+    a2: .byte $ad
+    a3: .byte $be
+    a4: .byte $ef
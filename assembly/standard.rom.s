@@ -1,6 +1,15 @@
 ; A basic ROM for the 6502, consisting mainly of NMI/BRK/IRQ vectors
-; TODO: Add interrupt handlers
     .setcpu "6502"
+
+; RAM locations used by the ROM. Programs can install their own interrupt
+; handlers by pointing these vectors at them. Handlers end with RTS, the ROM
+; takes care of saving and restoring registers and of the RTI.
+IRQ_VECTOR  = $0200
+NMI_VECTOR  = $0202
+; Number of interrupts serviced since reset
+IRQ_COUNT   = $0204
+NMI_COUNT   = $0205
+
     .segment "OS"
 
 reset:
@@ -12,15 +21,42 @@ reset:
     CLD
     CLC
     CLV
-    ; Set the registers to zero
+    ; Point the user vectors at the empty handler
+    LDA #<no_handler
+    STA IRQ_VECTOR
+    STA NMI_VECTOR
+    LDA #>no_handler
+    STA IRQ_VECTOR+1
+    STA NMI_VECTOR+1
+    ; Set the registers and interrupt counters to zero
     LDY #$00
     LDX #$00
     LDA #$00
+    STA IRQ_COUNT
+    STA NMI_COUNT
 
     BRK
 
 nmi:
-    NOP
+    ; Save the state of the registers
+    PHA
+    TXA
+    PHA
+    TYA
+    PHA
+
+    INC NMI_COUNT
+    JSR call_nmi_vector
+
+    ; restore the state of the registers
+    PLA
+    TAY
+    PLA
+    TAX
+    PLA
+
+    ; return
+    RTI
 
 irq:
     ; Save the state of the registers
@@ -30,6 +66,9 @@ irq:
     TYA
     PHA
 
+    INC IRQ_COUNT
+    JSR call_irq_vector
+
     ; restore the state of the registers
     PLA
     TAY
@@ -40,6 +79,16 @@ irq:
     ; return
     RTI
 
+; There is no indirect JSR, so these are called with JSR
+call_nmi_vector:
+    JMP (NMI_VECTOR)
+
+call_irq_vector:
+    JMP (IRQ_VECTOR)
+
+no_handler:
+    RTS
+
     .segment "VECTORS"
 
     .word nmi
@@ -0,0 +1,196 @@
+; Wozmon, the Apple I monitor by Steve Wozniak (1976)
+;
+; Commands, typed as hex addresses:
+;   FF00        examine a location
+;   FF00.FF1F   examine a range
+;   0300: A9 01 store bytes, starting at an address
+;   0300R       run from an address
+    .setcpu "6502"
+
+XAML    = $24           ; last opened location
+XAMH    = $25
+STL     = $26           ; store address
+STH     = $27
+L       = $28           ; hex value being parsed
+H       = $29
+YSAV    = $2A           ; Y register saved during parsing
+MODE    = $2B           ; $00 = examine, $74 = store, $AE = block examine
+
+IN      = $0200         ; input buffer
+
+KBD     = $D010         ; PIA keyboard data
+KBDCR   = $D011         ; PIA keyboard control
+DSP     = $D012         ; PIA display data
+DSPCR   = $D013         ; PIA display control
+
+    .segment "OS"
+
+reset:
+    CLD
+    CLI
+    LDY #$7F            ; mask for the display data direction register
+    STY DSP
+    LDA #$A7            ; CA1/CB1 on the rising edge, select the data registers
+    STA KBDCR
+    STA DSPCR
+
+notcr:
+    CMP #'_'+$80        ; rubout?
+    BEQ backspace
+    CMP #$9B            ; escape?
+    BEQ escape
+    INY                 ; advance the text index
+    BPL nextchar        ; auto escape when the line gets too long
+
+escape:
+    LDA #$DC            ; backslash
+    JSR echo
+
+getline:
+    LDA #$8D            ; carriage return
+    JSR echo
+    LDY #$01            ; start a new line
+backspace:
+    DEY
+    BMI getline         ; beyond the start of the line, start again
+
+nextchar:
+    LDA KBDCR           ; wait for a key
+    BPL nextchar
+    LDA KBD
+    STA IN,Y            ; add it to the buffer
+    JSR echo
+    CMP #$8D            ; carriage return?
+    BNE notcr
+
+    LDY #$FF            ; reset the text index
+    LDA #$00            ; examine mode
+    TAX
+setstor:
+    ASL                 ; store mode: $74 after shifting the ":"
+setmode:
+    STA MODE
+blskip:
+    INY                 ; next character
+nextitem:
+    LDA IN,Y
+    CMP #$8D            ; end of the line?
+    BEQ getline
+    CMP #'.'+$80
+    BCC blskip          ; skip delimiters
+    BEQ setmode         ; block examine mode
+    CMP #':'+$80
+    BEQ setstor         ; store mode
+    CMP #'R'+$80
+    BEQ run
+    STX L               ; clear the value being parsed
+    STX H
+    STY YSAV
+
+nexthex:
+    LDA IN,Y            ; get a character to convert
+    EOR #$B0            ; map the digits 0-9 to $00-$09
+    CMP #$0A
+    BCC dig
+    ADC #$88            ; map the letters A-F to $FA-$FF
+    CMP #$FA
+    BCC nothex
+dig:
+    ASL                 ; hex digit to the most significant nibble
+    ASL
+    ASL
+    ASL
+    LDX #$04            ; shift count
+hexshift:
+    ASL                 ; shift the digit into L and H
+    ROL L
+    ROL H
+    DEX
+    BNE hexshift
+    INY                 ; next character
+    BNE nexthex
+
+nothex:
+    CPY YSAV            ; no hex digits at all?
+    BEQ escape
+    BIT MODE
+    BVC notstor         ; bit 6 is only set in store mode
+    LDA L               ; store the parsed byte
+    STA (STL,X)
+    INC STL
+    BNE nextitem
+    INC STH
+tonextitem:
+    JMP nextitem
+
+run:
+    JMP (XAML)
+
+notstor:
+    BMI xamnext         ; block examine
+    LDX #$02
+setadr:
+    LDA L-1,X           ; copy the parsed address to the store and examine addresses
+    STA STL-1,X
+    STA XAML-1,X
+    DEX
+    BNE setadr
+
+nxtprnt:
+    BNE prdata          ; print the address unless it's not needed
+    LDA #$8D
+    JSR echo
+    LDA XAMH
+    JSR prbyte
+    LDA XAML
+    JSR prbyte
+    LDA #':'+$80
+    JSR echo
+
+prdata:
+    LDA #$A0            ; space
+    JSR echo
+    LDA (XAML,X)
+    JSR prbyte
+
+xamnext:
+    STX MODE            ; back to examine mode
+    LDA XAML            ; compare the examine address to the parsed value
+    CMP L
+    LDA XAMH
+    SBC H
+    BCS tonextitem      ; done with the block
+    INC XAML
+    BNE mod8chk
+    INC XAMH
+mod8chk:
+    LDA XAML            ; start a new line every 8 bytes
+    AND #$07
+    BPL nxtprnt
+
+prbyte:
+    PHA                 ; print A as two hex digits
+    LSR
+    LSR
+    LSR
+    LSR
+    JSR prhex
+    PLA
+prhex:
+    AND #$0F
+    ORA #'0'+$80
+    CMP #$BA            ; a digit?
+    BCC echo
+    ADC #$06            ; make it a letter
+
+echo:
+    BIT DSP             ; wait until the display is ready
+    BMI echo
+    STA DSP
+    RTS
+
+    .segment "VECTORS"
+
+    .word $0F00         ; NMI
+    .word reset         ; RESET
+    .word $0000         ; IRQ
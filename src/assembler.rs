@@ -0,0 +1,1196 @@
+// A 6502 assembler for a subset of the ca65 syntax
+
+mod expression;
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+use crate::computer::cpu::instruction::{decode_instruction, AddressMode, Instruction};
+use crate::computer::symbols::SymbolTable;
+use crate::loader::Program;
+use expression::{evaluate, is_symbol_char, is_symbol_start, Context, Value};
+
+/*
+ * Enough of ca65 to assemble the sources in assembly/, without the cc65
+ * toolchain:
+ *
+ *   labels         name:, @local: (until the next label), : (anonymous)
+ *   equates        NAME = expression, and NAME .set expression to change it later
+ *   instructions   all those known to decode_instruction. Operands that are
+ *                  known to fit in a byte use zero page addressing, unless
+ *                  prefixed with a: (absolute). Forward references are absolute.
+ *   data           .byte .word .addr .dbyt .res .asciiz
+ *   placement      .org, .segment "NAME", .code .rodata .data .bss .zeropage
+ *   source         .include, .macro/.endmacro with .local, .if .ifdef .ifndef
+ *                  .ifblank .ifnblank .else .endif
+ *   ignored        .setcpu .export .import .exportzp .importzp .global
+ *
+ * Assembling happens in two passes. The first finds the size of everything,
+ * after which the segments are placed. The second produces the code.
+ *
+ * Segments without a fixed address follow each other from the origin:
+ * CODE, RODATA, DATA and BSS first, then the others in order of appearance.
+ * BSS and ZEROPAGE take up space, but have no contents. ZEROPAGE starts at 0.
+ */
+
+const DEFAULT_ORIGIN: u16 = 0x1000;
+const SEGMENT_ORDER: [&str; 4] = ["CODE", "RODATA", "DATA", "BSS"];
+const UNINITIALISED_SEGMENTS: [&str; 2] = ["BSS", "ZEROPAGE"];
+// Guard against recursive macros and includes
+const MAX_DEPTH: usize = 64;
+
+#[derive(Clone)]
+pub struct Assembler {
+    include_paths: Vec<PathBuf>,
+    origin: u16,
+    segment_addresses: HashMap<String, u16>,
+    // Symbols that exist before assembling, like those of a loaded ROM
+    predefined: HashMap<String, u16>,
+}
+
+// The result of assembling: the code, and the addresses of its labels
+#[derive(Debug)]
+pub struct Assembly {
+    pub program: Program,
+    pub symbols: SymbolTable,
+}
+
+impl Default for Assembler {
+    fn default() -> Self {
+        Self {
+            include_paths: Vec::new(),
+            origin: DEFAULT_ORIGIN,
+            segment_addresses: HashMap::from([("ZEROPAGE".to_string(), 0)]),
+            predefined: HashMap::new(),
+        }
+    }
+}
+
+// Opcodes by instruction and address mode, the reverse of decode_instruction.
+// Should two opcodes decode the same, the lowest one is used.
+fn opcodes() -> &'static HashMap<(Instruction, AddressMode), u8> {
+    static OPCODES: OnceLock<HashMap<(Instruction, AddressMode), u8>> = OnceLock::new();
+    OPCODES.get_or_init(|| {
+        (0..=255u8).rev()
+            .filter_map(|opcode| decode_instruction(opcode).map(|(i, mode, _)| ((i, mode), opcode)))
+            .collect()
+    })
+}
+
+impl Assembler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Where to look for included files, after the directory of the including file
+    pub fn with_include_path(mut self, path: &Path) -> Self {
+        self.include_paths.push(path.to_path_buf());
+        self
+    }
+
+    // Where segments without a fixed address start
+    pub fn with_origin(mut self, origin: u16) -> Self {
+        self.origin = origin;
+        self
+    }
+
+    // Put a segment at a fixed address
+    pub fn with_segment(mut self, name: &str, address: u16) -> Self {
+        self.segment_addresses.insert(name.to_string(), address);
+        self
+    }
+
+    // Make the symbols in a symbol table available to the source, apart from
+    // names with more than one address
+    pub fn with_symbols(mut self, symbols: &SymbolTable) -> Self {
+        self.predefined.extend(symbols.iter()
+            .filter(|(_, name)| symbols.address_of(name).is_some())
+            .map(|(address, name)| (name.to_string(), address)));
+        self
+    }
+
+    pub fn assemble_file(&self, file_name: &Path) -> Result<Assembly, String> {
+        let source = std::fs::read_to_string(file_name)
+            .map_err(|e| format!("Was not able to read {}: {}", file_name.display(), e))?;
+        self.assemble_source(&source, file_name)
+    }
+
+    pub fn assemble(&self, source: &str) -> Result<Assembly, String> {
+        self.assemble_source(source, Path::new("<source>"))
+    }
+
+    // Assemble a single line at an address, like the line assembler of a monitor
+    pub fn assemble_at(&self, address: u16, line: &str) -> Result<Vec<u8>, String> {
+        let assembly = self.clone()
+            .with_origin(address)
+            .assemble_source(line, Path::new("line"))
+            .map_err(|e| e.strip_prefix("line:1: ").map(str::to_string).unwrap_or(e))?;
+        let (start, image) = assembly.program.to_image(0);
+        if !image.is_empty() && start != address {
+            return Err("The line must be assembled at its address".to_string());
+        }
+        Ok(image)
+    }
+
+    fn assemble_source(&self, source: &str, file_name: &Path) -> Result<Assembly, String> {
+        let mut first = Pass::new(self, None);
+        first.run(source, file_name)?;
+        let layout = first.layout();
+        let mut second = Pass::new(self, Some(first.results(&layout)));
+        second.segment_bases = layout;
+        second.run(source, file_name)?;
+        Ok(second.assembly())
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Macro {
+    parameters: Vec<String>,
+    body: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum SymbolValue {
+    Known(i64),
+    // A label in a segment that isn't placed yet, at an offset
+    Pending(usize, u32),
+}
+
+#[derive(Debug)]
+struct Segment {
+    name: String,
+    // Bytes in the part of the segment that is placed with it
+    size: u32,
+    // The address after an .org, which takes the rest of the segment out of placement
+    org: Option<u32>,
+}
+
+// What the first pass found out, for the second one
+struct FirstPassResults {
+    symbols: HashMap<String, i64>,
+    anonymous: Vec<i64>,
+    zeropage: Vec<bool>,
+}
+
+// The state while assembling within a conditional block
+struct Condition {
+    active: bool,
+    // Whether any of the branches was taken, so .else isn't
+    taken: bool,
+}
+
+struct Pass<'a> {
+    assembler: &'a Assembler,
+    previous: Option<FirstPassResults>,
+
+    symbols: HashMap<String, SymbolValue>,
+    labels: Vec<(String, i64)>,
+    anonymous: Vec<SymbolValue>,
+    // For each instruction that can use zero page addressing, whether it does
+    zeropage: Vec<bool>,
+    macros: HashMap<String, Macro>,
+    segments: Vec<Segment>,
+    segment_bases: HashMap<usize, u16>,
+    current_segment: usize,
+    // The last normal label, that @local labels belong to
+    scope: String,
+    conditions: Vec<Condition>,
+    defining: Option<(String, Macro)>,
+    local_counter: usize,
+    program: Program,
+}
+
+impl<'a> Pass<'a> {
+    fn new(assembler: &'a Assembler, previous: Option<FirstPassResults>) -> Self {
+        Self {
+            assembler,
+            previous,
+            symbols: assembler.predefined.iter()
+                .map(|(name, address)| (name.clone(), SymbolValue::Known(*address as i64)))
+                .collect(),
+            labels: Vec::new(),
+            anonymous: Vec::new(),
+            zeropage: Vec::new(),
+            macros: HashMap::new(),
+            segments: vec![Segment { name: "CODE".to_string(), size: 0, org: None }],
+            segment_bases: HashMap::new(),
+            current_segment: 0,
+            scope: String::new(),
+            conditions: Vec::new(),
+            defining: None,
+            local_counter: 0,
+            program: Program { segments: Vec::new(), run_address: None },
+        }
+    }
+
+    fn is_final(&self) -> bool {
+        self.previous.is_some()
+    }
+
+    fn run(&mut self, source: &str, file_name: &Path) -> Result<(), String> {
+        let lines: Vec<String> = source.lines().map(str::to_string).collect();
+        self.process_lines(&lines, file_name, 0)?;
+        if self.defining.is_some() {
+            return Err(format!("{}: .macro without .endmacro", file_name.display()));
+        }
+        if !self.conditions.is_empty() {
+            return Err(format!("{}: .if without .endif", file_name.display()));
+        }
+        Ok(())
+    }
+
+    fn process_lines(&mut self, lines: &[String], file_name: &Path, depth: usize) -> Result<(), String> {
+        if depth > MAX_DEPTH {
+            return Err("Too many nested includes or macros".to_string());
+        }
+        for (i, line) in lines.iter().enumerate() {
+            self.process_line(line, file_name, depth)
+                .map_err(|e| format!("{}:{}: {}", file_name.display(), i + 1, e))?;
+        }
+        Ok(())
+    }
+
+    fn process_line(&mut self, line: &str, file_name: &Path, depth: usize) -> Result<(), String> {
+        let line = strip_comment(line);
+        let (first_word, rest) = split_word(line.trim());
+        let directive = first_word.to_lowercase();
+
+        // Collect the lines of a macro being defined
+        if let Some((name, mut definition)) = self.defining.take() {
+            if directive == ".endmacro" || directive == ".endmac" {
+                self.macros.insert(name, definition);
+            } else {
+                definition.body.push(line.to_string());
+                self.defining = Some((name, definition));
+            }
+            return Ok(());
+        }
+
+        // Conditional assembly
+        let active = self.conditions.last().is_none_or(|c| c.active);
+        match directive.as_str() {
+            ".if" | ".ifdef" | ".ifndef" | ".ifblank" | ".ifnblank" => {
+                let condition = active && self.condition(&directive, rest)?;
+                self.conditions.push(Condition { active: condition, taken: condition || !active });
+                return Ok(());
+            }
+            ".else" => {
+                let condition = self.conditions.last_mut().ok_or(".else without .if")?;
+                condition.active = !condition.taken;
+                condition.taken = true;
+                return Ok(());
+            }
+            ".endif" => {
+                self.conditions.pop().ok_or(".endif without .if")?;
+                return Ok(());
+            }
+            _ if !active => return Ok(()),
+            _ => {}
+        }
+
+        let mut statement = line.trim();
+
+        // Labels
+        if let Some(rest) = statement.strip_prefix(':').filter(|r| !r.starts_with(['+', '-'])) {
+            let value = self.location_value();
+            self.anonymous.push(value);
+            statement = rest.trim();
+        } else {
+            let name_length = statement.chars().take_while(|c| is_symbol_char(*c)).count();
+            let after_name = &statement[name_length..];
+            if name_length > 0 && after_name.starts_with(':') && !after_name.starts_with(":=") {
+                let name = &statement[..name_length];
+                self.define_label(name)?;
+                statement = after_name[1..].trim();
+            }
+        }
+
+        // Equates
+        if let Some((name, expression)) = split_equate(statement) {
+            let name = self.qualify(name);
+            let value = self.evaluate(expression)?;
+            return match value {
+                Some(value) => self.define(&name, SymbolValue::Known(value)),
+                // Unknown in the first pass, to be worked out in the second
+                None => Ok(()),
+            };
+        }
+
+        let (word, operand) = split_word(statement);
+        if word.is_empty() {
+            return Ok(());
+        }
+
+        // Variables, which can be set again
+        let (second_word, expression) = split_word(operand);
+        if second_word.eq_ignore_ascii_case(".set") {
+            let name = self.qualify(word);
+            if let Some(value) = self.evaluate(expression)? {
+                self.symbols.insert(name, SymbolValue::Known(value));
+            }
+            return Ok(());
+        }
+        if word.starts_with('.') {
+            self.directive(&word.to_lowercase(), operand, file_name, depth)
+        } else if let Some(definition) = self.macros.get(word).cloned() {
+            if depth > MAX_DEPTH {
+                return Err("Too many nested macros".to_string());
+            }
+            // Errors are reported at the line using the macro
+            for line in self.expand(&definition, operand) {
+                self.process_line(&line, file_name, depth + 1)
+                    .map_err(|e| format!("in macro {}: {}", word, e))?;
+            }
+            Ok(())
+        } else {
+            self.instruction(word, operand)
+        }
+    }
+
+    fn condition(&self, directive: &str, operand: &str) -> Result<bool, String> {
+        Ok(match directive {
+            ".ifblank" => operand.trim().is_empty(),
+            ".ifnblank" => !operand.trim().is_empty(),
+            // Only symbols defined so far count, so both passes agree
+            ".ifdef" => self.symbols.contains_key(&self.qualify(operand.trim())),
+            ".ifndef" => !self.symbols.contains_key(&self.qualify(operand.trim())),
+            _ => self.evaluate(operand)?.ok_or("Condition must be known in the first pass")? != 0,
+        })
+    }
+
+    fn directive(&mut self, directive: &str, operand: &str, file_name: &Path, depth: usize) -> Result<(), String> {
+        match directive {
+            ".byte" | ".byt" | ".asciiz" => {
+                let mut bytes = Vec::new();
+                for item in split_arguments(operand) {
+                    match parse_string(&item) {
+                        Some(text) => bytes.extend(text.bytes()),
+                        None => bytes.push(self.byte(&item)?),
+                    }
+                }
+                if directive == ".asciiz" {
+                    bytes.push(0);
+                }
+                self.emit(&bytes)
+            }
+            ".word" | ".addr" | ".dbyt" => {
+                for item in split_arguments(operand) {
+                    let word = self.word(&item)?;
+                    match directive {
+                        ".dbyt" => self.emit(&word.to_be_bytes())?,
+                        _ => self.emit(&word.to_le_bytes())?,
+                    }
+                }
+                Ok(())
+            }
+            ".res" => {
+                let arguments = split_arguments(operand);
+                let count = self.evaluate(arguments.first().ok_or(".res needs a size")?)?
+                    .ok_or("The size of .res must be known in the first pass")?;
+                let fill = match arguments.get(1) {
+                    Some(fill) => self.byte(fill)?,
+                    None => 0,
+                };
+                if !(0..=0x10000).contains(&count) {
+                    return Err(format!("Invalid size {} for .res", count));
+                }
+                self.emit(&vec![fill; count as usize])
+            }
+            ".org" => {
+                let address = self.evaluate(operand)?.ok_or("The address of .org must be known in the first pass")?;
+                if !(0..=0xffff).contains(&address) {
+                    return Err(format!("Invalid address ${:x} for .org", address));
+                }
+                self.segments[self.current_segment].org = Some(address as u32);
+                Ok(())
+            }
+            ".segment" => {
+                let name = parse_string(operand.trim()).ok_or(".segment needs a name in quotes")?;
+                self.select_segment(&name);
+                Ok(())
+            }
+            ".code" | ".rodata" | ".data" | ".bss" | ".zeropage" => {
+                self.select_segment(&directive[1..].to_uppercase());
+                Ok(())
+            }
+            ".include" => {
+                let name = parse_string(operand.trim()).ok_or(".include needs a file name in quotes")?;
+                let path = self.find_include(&name, file_name)?;
+                let source = std::fs::read_to_string(&path)
+                    .map_err(|e| format!("Was not able to read {}: {}", path.display(), e))?;
+                let lines: Vec<String> = source.lines().map(str::to_string).collect();
+                self.process_lines(&lines, &path, depth + 1)
+            }
+            ".macro" | ".mac" => {
+                let (name, parameters) = split_word(operand);
+                if name.is_empty() {
+                    return Err(".macro needs a name".to_string());
+                }
+                let parameters = split_arguments(parameters);
+                self.defining = Some((name.to_string(), Macro { parameters, body: Vec::new() }));
+                Ok(())
+            }
+            ".local" => Err(".local outside of a macro".to_string()),
+            ".setcpu" => match parse_string(operand.trim()).as_deref() {
+                Some("6502") => Ok(()),
+                _ => Err(format!("Unsupported CPU {}", operand.trim())),
+            },
+            ".export" | ".exportzp" | ".import" | ".importzp" | ".global" | ".globalzp" | ".debuginfo" => Ok(()),
+            _ => Err(format!("Unknown directive {}", directive)),
+        }
+    }
+
+    fn instruction(&mut self, mnemonic: &str, operand: &str) -> Result<(), String> {
+        let instruction = Instruction::from_str(&mnemonic.to_uppercase())
+            .map_err(|_| format!("Unknown instruction {}", mnemonic))?;
+        let has = |mode| opcodes().contains_key(&(instruction, mode));
+        let (form, expression) = parse_operand(operand.trim());
+
+        let mode = match form {
+            Form::Implied if has(AddressMode::Implied) => AddressMode::Implied,
+            Form::Implied | Form::Accumulator => AddressMode::Accumulator,
+            Form::Immediate => AddressMode::Immediate,
+            Form::Indirect if has(AddressMode::Indirect) => AddressMode::Indirect,
+            Form::IndirectX => AddressMode::IndirectX,
+            Form::IndirectY => AddressMode::IndirectY,
+            Form::Address(_, Index::None) if has(AddressMode::Relative) => AddressMode::Relative,
+            Form::Indirect => self.address_mode(has, expression, Index::None, false)?,
+            Form::Address(force_absolute, index) => self.address_mode(has, expression, index, force_absolute)?,
+        };
+        let opcode = *opcodes().get(&(instruction, mode))
+            .ok_or_else(|| format!("{} does not support {:?} addressing", mnemonic, mode))?;
+
+        // Only the size matters in the first pass
+        let value = match mode.operand_size() {
+            0 => None,
+            _ if !self.is_final() => None,
+            _ => self.evaluate(expression)?,
+        };
+        let mut bytes = vec![opcode];
+        if let Some(value) = value {
+            match mode {
+                AddressMode::Relative => {
+                    let address = self.location_value_known().ok_or("Unknown address for branch")?;
+                    let offset = value - (address + 2);
+                    if !(-128..=127).contains(&offset) {
+                        return Err(format!("Branch target is {} bytes away, too far", offset));
+                    }
+                    bytes.push(offset as u8);
+                }
+                AddressMode::Immediate => bytes.push(to_byte(value)?),
+                _ if mode.operand_size() == 1 => {
+                    if !(0..=0xff).contains(&value) {
+                        return Err(format!("Zero page address ${:x} out of range", value));
+                    }
+                    bytes.push(value as u8);
+                }
+                _ => bytes.extend(to_word(value)?.to_le_bytes()),
+            }
+        } else {
+            bytes.resize(1 + mode.operand_size() as usize, 0);
+        }
+        self.emit(&bytes)
+    }
+
+    // Choose between zero page and absolute addressing
+    fn address_mode(&mut self, has: impl Fn(AddressMode) -> bool, expression: &str, index: Index, force_absolute: bool)
+        -> Result<AddressMode, String>
+    {
+        let (zeropage, absolute) = match index {
+            Index::None => (AddressMode::Zeropage, AddressMode::Absolute),
+            Index::X => (AddressMode::ZeropageX, AddressMode::AbsoluteX),
+            Index::Y => (AddressMode::ZeropageY, AddressMode::AbsoluteY),
+        };
+        if !has(zeropage) || force_absolute {
+            return Ok(absolute);
+        }
+        if !has(absolute) {
+            return Ok(zeropage);
+        }
+        let use_zeropage = match &self.previous {
+            Some(previous) => previous.zeropage.get(self.zeropage.len()).copied().unwrap_or(false),
+            None => self.evaluate(expression)?.is_some_and(|v| (0..=0xff).contains(&v)),
+        };
+        self.zeropage.push(use_zeropage);
+        Ok(if use_zeropage { zeropage } else { absolute })
+    }
+
+    fn expand(&mut self, definition: &Macro, operand: &str) -> Vec<String> {
+        let arguments = split_arguments(operand);
+        let mut replacements: HashMap<String, String> = definition.parameters.iter()
+            .enumerate()
+            .map(|(i, parameter)| (parameter.clone(), arguments.get(i).cloned().unwrap_or_default()))
+            .collect();
+
+        let mut body = Vec::new();
+        for line in &definition.body {
+            let (word, names) = split_word(line.trim());
+            if word.eq_ignore_ascii_case(".local") {
+                for name in split_arguments(names) {
+                    self.local_counter += 1;
+                    replacements.insert(name.clone(), format!("__{}_{}", name, self.local_counter));
+                }
+            } else {
+                body.push(line.clone());
+            }
+        }
+        body.iter().map(|line| substitute(line, &replacements)).collect()
+    }
+
+    fn find_include(&self, name: &str, file_name: &Path) -> Result<PathBuf, String> {
+        let directory = file_name.parent().unwrap_or(Path::new(""));
+        std::iter::once(directory.to_path_buf())
+            .chain(self.assembler.include_paths.iter().cloned())
+            .map(|path| path.join(name))
+            .find(|path| path.exists())
+            .ok_or_else(|| format!("Include file {} not found", name))
+    }
+
+    fn select_segment(&mut self, name: &str) {
+        self.current_segment = match self.segments.iter().position(|s| s.name == name) {
+            Some(index) => index,
+            None => {
+                self.segments.push(Segment { name: name.to_string(), size: 0, org: None });
+                self.segments.len() - 1
+            }
+        };
+    }
+
+    // Symbols
+
+    fn qualify(&self, name: &str) -> String {
+        match name.starts_with('@') {
+            true => format!("{}{}", self.scope, name),
+            false => name.to_string(),
+        }
+    }
+
+    fn define_label(&mut self, name: &str) -> Result<(), String> {
+        if !is_symbol_start(name.chars().next().unwrap_or(' ')) {
+            return Err(format!("Invalid label '{}'", name));
+        }
+        if !name.starts_with('@') {
+            self.scope = name.to_string();
+        }
+        let name = self.qualify(name);
+        let value = self.location_value();
+        if let (SymbolValue::Known(address), Some(previous)) = (value, &self.previous) {
+            // Anything that changes size between the passes moves the labels after it
+            if previous.symbols.get(&name).is_some_and(|a| *a != address) {
+                return Err(format!("Label '{}' moved between passes", name));
+            }
+            self.labels.push((name.clone(), address));
+        }
+        self.define(&name, value)
+    }
+
+    fn define(&mut self, name: &str, value: SymbolValue) -> Result<(), String> {
+        if self.symbols.insert(name.to_string(), value).is_some() {
+            return Err(format!("Symbol '{}' is already defined", name));
+        }
+        Ok(())
+    }
+
+    fn lookup(&self, name: &str) -> Option<i64> {
+        match self.symbols.get(name) {
+            Some(SymbolValue::Known(value)) => Some(*value),
+            Some(SymbolValue::Pending(..)) => None,
+            None => self.previous.as_ref().and_then(|p| p.symbols.get(name).copied()),
+        }
+    }
+
+    // Addresses
+
+    fn location_value(&self) -> SymbolValue {
+        let segment = &self.segments[self.current_segment];
+        match self.location_value_known() {
+            Some(address) => SymbolValue::Known(address),
+            None => SymbolValue::Pending(self.current_segment, segment.size),
+        }
+    }
+
+    fn location_value_known(&self) -> Option<i64> {
+        let segment = &self.segments[self.current_segment];
+        if let Some(address) = segment.org {
+            return Some(address as i64);
+        }
+        self.segment_base(self.current_segment).map(|base| base as i64 + segment.size as i64)
+    }
+
+    fn segment_base(&self, index: usize) -> Option<u16> {
+        self.assembler.segment_addresses.get(&self.segments[index].name)
+            .or_else(|| self.segment_bases.get(&index))
+            .copied()
+    }
+
+    fn emit(&mut self, bytes: &[u8]) -> Result<(), String> {
+        let address = self.location_value_known();
+        let segment = &mut self.segments[self.current_segment];
+        match &mut segment.org {
+            Some(org) => *org += bytes.len() as u32,
+            None => segment.size += bytes.len() as u32,
+        }
+        let has_contents = !UNINITIALISED_SEGMENTS.contains(&segment.name.as_str());
+        if !self.is_final() || !has_contents {
+            return Ok(());
+        }
+        let address = address.ok_or("Unknown address")?;
+        self.program.add(address as u32, bytes)
+    }
+
+    // Place the segments that don't have a fixed address
+    fn layout(&self) -> HashMap<usize, u16> {
+        let mut order: Vec<usize> = SEGMENT_ORDER.iter()
+            .filter_map(|name| self.segments.iter().position(|s| s.name == *name))
+            .collect();
+        order.extend((0..self.segments.len()).filter(|i| !SEGMENT_ORDER.contains(&self.segments[*i].name.as_str())));
+
+        let mut address = self.assembler.origin as u32;
+        let mut bases = HashMap::new();
+        for index in order {
+            if self.assembler.segment_addresses.contains_key(&self.segments[index].name) {
+                continue;
+            }
+            // Let the second pass report segments that don't fit
+            bases.insert(index, address as u16);
+            address += self.segments[index].size;
+        }
+        bases
+    }
+
+    fn results(&self, layout: &HashMap<usize, u16>) -> FirstPassResults {
+        let resolve = |value: &SymbolValue| match *value {
+            SymbolValue::Known(value) => value,
+            SymbolValue::Pending(segment, offset) => {
+                let base = self.assembler.segment_addresses.get(&self.segments[segment].name)
+                    .or_else(|| layout.get(&segment))
+                    .copied()
+                    .unwrap_or(0);
+                base as i64 + offset as i64
+            }
+        };
+        FirstPassResults {
+            symbols: self.symbols.iter().map(|(name, value)| (name.clone(), resolve(value))).collect(),
+            anonymous: self.anonymous.iter().map(resolve).collect(),
+            zeropage: self.zeropage.clone(),
+        }
+    }
+
+    fn assembly(self) -> Assembly {
+        let mut symbols = SymbolTable::new();
+        for (name, address) in &self.labels {
+            symbols.add(name, *address as u16);
+        }
+        // Segments come out in the order they were written to. Sort them, and
+        // join the ones that follow each other.
+        let mut segments = self.program.segments;
+        segments.sort_by_key(|segment| segment.address);
+        let mut program = Program { segments: Vec::new(), run_address: None };
+        for segment in segments {
+            // Everything fit when it was added before
+            let _ = program.add(segment.address as u32, &segment.data);
+        }
+        Assembly { program, symbols }
+    }
+
+    // Values
+
+    fn evaluate(&self, expression: &str) -> Result<Value, String> {
+        evaluate(expression, self)
+    }
+
+    fn byte(&self, expression: &str) -> Result<u8, String> {
+        match self.evaluate(expression)? {
+            Some(value) => to_byte(value),
+            None => Ok(0),
+        }
+    }
+
+    fn word(&self, expression: &str) -> Result<u16, String> {
+        match self.evaluate(expression)? {
+            Some(value) => to_word(value),
+            None => Ok(0),
+        }
+    }
+}
+
+impl Context for Pass<'_> {
+    fn symbol(&self, name: &str) -> Result<Value, String> {
+        let name = self.qualify(name);
+        match self.lookup(&name) {
+            None if self.is_final() => Err(format!("Undefined symbol '{}'", name)),
+            value => Ok(value),
+        }
+    }
+
+    fn anonymous(&self, offset: i32) -> Result<Value, String> {
+        let defined = self.anonymous.len() as i64;
+        let index = if offset > 0 { defined + offset as i64 - 1 } else { defined + offset as i64 };
+        if index < 0 {
+            return Err("No previous anonymous label".to_string());
+        }
+        let index = index as usize;
+        match (self.anonymous.get(index), &self.previous) {
+            (Some(SymbolValue::Known(value)), _) => Ok(Some(*value)),
+            (_, Some(previous)) => previous.anonymous.get(index).copied()
+                .map(Some)
+                .ok_or_else(|| "No next anonymous label".to_string()),
+            _ => Ok(None),
+        }
+    }
+
+    fn current_address(&self) -> Value {
+        self.location_value_known()
+    }
+}
+
+fn to_byte(value: i64) -> Result<u8, String> {
+    match value {
+        -128..=255 => Ok(value as u8),
+        _ => Err(format!("Value {} does not fit in a byte", value)),
+    }
+}
+
+fn to_word(value: i64) -> Result<u16, String> {
+    match value {
+        -32768..=65535 => Ok(value as u16),
+        _ => Err(format!("Value {} does not fit in a word", value)),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Index {
+    None,
+    X,
+    Y,
+}
+
+// The shape of an operand, before knowing its value
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Form {
+    Implied,
+    Accumulator,
+    Immediate,
+    Indirect,
+    IndirectX,
+    IndirectY,
+    // Whether absolute addressing is forced, and the index register
+    Address(bool, Index),
+}
+
+// Work out the form of an operand, and the expression in it
+fn parse_operand(operand: &str) -> (Form, &str) {
+    if operand.is_empty() {
+        return (Form::Implied, operand);
+    }
+    if operand.eq_ignore_ascii_case("a") {
+        return (Form::Accumulator, operand);
+    }
+    if let Some(expression) = operand.strip_prefix('#') {
+        return (Form::Immediate, expression.trim());
+    }
+    let (address, index) = match split_top_level(operand) {
+        Some((address, index)) if index.trim().eq_ignore_ascii_case("x") => (address.trim(), Index::X),
+        Some((address, index)) if index.trim().eq_ignore_ascii_case("y") => (address.trim(), Index::Y),
+        _ => (operand, Index::None),
+    };
+    if let Some(inner) = enclosed(address) {
+        match (split_top_level(inner), index) {
+            (Some((inner, x)), Index::None) if x.trim().eq_ignore_ascii_case("x") => {
+                return (Form::IndirectX, inner.trim());
+            }
+            (None, Index::Y) => return (Form::IndirectY, inner.trim()),
+            (None, Index::None) => return (Form::Indirect, inner.trim()),
+            _ => {}
+        }
+    }
+    match address.strip_prefix("a:") {
+        Some(expression) => (Form::Address(true, index), expression.trim()),
+        None => (Form::Address(false, index), address.strip_prefix("z:").unwrap_or(address).trim()),
+    }
+}
+
+// The inside of text in parentheses, if the parentheses enclose all of it
+fn enclosed(text: &str) -> Option<&str> {
+    let inner = text.strip_prefix('(')?.strip_suffix(')')?;
+    let mut depth = 0;
+    for c in inner.chars() {
+        match c {
+            '(' => depth += 1,
+            ')' if depth == 0 => return None,
+            ')' => depth -= 1,
+            _ => {}
+        }
+    }
+    Some(inner)
+}
+
+// Split at the last comma that's not in parentheses or quotes
+fn split_top_level(text: &str) -> Option<(&str, &str)> {
+    let mut depth = 0;
+    let mut quote = None;
+    let mut split = None;
+    for (i, c) in text.char_indices() {
+        match (c, quote) {
+            ('"' | '\'', None) => quote = Some(c),
+            (_, Some(q)) if c == q => quote = None,
+            (_, Some(_)) => {}
+            ('(', None) => depth += 1,
+            (')', None) => depth -= 1,
+            (',', None) if depth == 0 => split = Some(i),
+            _ => {}
+        }
+    }
+    split.map(|i| (&text[..i], &text[i + 1..]))
+}
+
+// Split a list of arguments at commas that are not in parentheses or quotes
+fn split_arguments(text: &str) -> Vec<String> {
+    let mut arguments = Vec::new();
+    let mut rest = text.trim();
+    while let Some((first, last)) = split_top_level(rest) {
+        arguments.insert(0, last.trim().to_string());
+        rest = first;
+    }
+    if !rest.trim().is_empty() || !arguments.is_empty() {
+        arguments.insert(0, rest.trim().to_string());
+    }
+    arguments
+}
+
+fn split_word(text: &str) -> (&str, &str) {
+    match text.find(char::is_whitespace) {
+        Some(i) => (&text[..i], text[i..].trim()),
+        None => (text, ""),
+    }
+}
+
+// "NAME = expression" or "NAME := expression"
+fn split_equate(statement: &str) -> Option<(&str, &str)> {
+    let (name, expression) = statement.split_once('=')?;
+    let name = name.trim().trim_end_matches(':').trim();
+    let mut chars = name.chars();
+    let valid = chars.next().is_some_and(is_symbol_start) && chars.all(is_symbol_char);
+    valid.then_some((name, expression.trim()))
+}
+
+fn parse_string(text: &str) -> Option<String> {
+    text.strip_prefix('"')?.strip_suffix('"').map(str::to_string)
+}
+
+fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+    for (i, c) in line.char_indices() {
+        match (c, quote) {
+            (';', None) => return &line[..i],
+            ('"', None) => quote = Some('"'),
+            // Only a quote with a character and a closing quote is a character constant
+            ('\'', None) if line[i..].chars().nth(2) == Some('\'') => quote = Some('\''),
+            (_, Some(q)) if c == q => quote = None,
+            _ => {}
+        }
+    }
+    line
+}
+
+// Replace whole symbols in a line, leaving strings alone
+fn substitute(line: &str, replacements: &HashMap<String, String>) -> String {
+    let mut result = String::new();
+    let mut chars = line.char_indices().peekable();
+    let mut in_string = false;
+    while let Some((i, c)) = chars.next() {
+        if c == '"' {
+            in_string = !in_string;
+        }
+        if in_string || !is_symbol_start(c) {
+            result.push(c);
+            continue;
+        }
+        let mut end = i + c.len_utf8();
+        while let Some((j, c)) = chars.peek().copied() {
+            if !is_symbol_char(c) {
+                break;
+            }
+            end = j + c.len_utf8();
+            chars.next();
+        }
+        let word = &line[i..end];
+        result.push_str(replacements.get(word).map_or(word, String::as_str));
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assemble(source: &str) -> Vec<u8> {
+        let assembly = Assembler::new().assemble(source).unwrap_or_else(|e| panic!("{}", e));
+        assert_eq!(assembly.program.segments.len(), 1);
+        assert_eq!(assembly.program.segments[0].address, DEFAULT_ORIGIN);
+        assembly.program.segments[0].data.clone()
+    }
+
+    #[test]
+    fn address_modes() {
+        let source = "
+            LDA #$10
+            LDA $10
+            LDA $10,X
+            LDA $1234
+            LDA $1234,X
+            LDA $1234, y
+            LDA ($10,X)
+            LDA ($10),Y
+            LDX $10,Y
+            JMP ($1234)
+            ASL
+            ASL A
+            LDA a:$10
+            NOP";
+        assert_eq!(assemble(source), vec![
+            0xa9, 0x10, 0xa5, 0x10, 0xb5, 0x10, 0xad, 0x34, 0x12, 0xbd, 0x34, 0x12, 0xb9, 0x34, 0x12,
+            0xa1, 0x10, 0xb1, 0x10, 0xb6, 0x10, 0x6c, 0x34, 0x12, 0x0a, 0x0a, 0xad, 0x10, 0x00, 0xea,
+        ]);
+    }
+
+    // Every documented opcode, as listed in the 6502 instruction set reference
+    // (https://www.masswerk.at/6502/6502_instruction_set.html), by mode:
+    // immediate, zero page, zero page X/Y, absolute, absolute X, absolute Y,
+    // (indirect,X), (indirect),Y; then accumulator, implied, relative and indirect
+    const REFERENCE: &[(&str, &[u8])] = &[
+        ("ADC #$44", &[0x69, 0x44]), ("ADC $44", &[0x65, 0x44]), ("ADC $44,X", &[0x75, 0x44]),
+        ("ADC $4400", &[0x6d, 0x00, 0x44]), ("ADC $4400,X", &[0x7d, 0x00, 0x44]),
+        ("ADC $4400,Y", &[0x79, 0x00, 0x44]), ("ADC ($44,X)", &[0x61, 0x44]), ("ADC ($44),Y", &[0x71, 0x44]),
+        ("AND #$44", &[0x29, 0x44]), ("AND $44", &[0x25, 0x44]), ("AND $44,X", &[0x35, 0x44]),
+        ("AND $4400", &[0x2d, 0x00, 0x44]), ("AND $4400,X", &[0x3d, 0x00, 0x44]),
+        ("AND $4400,Y", &[0x39, 0x00, 0x44]), ("AND ($44,X)", &[0x21, 0x44]), ("AND ($44),Y", &[0x31, 0x44]),
+        ("ASL A", &[0x0a]), ("ASL $44", &[0x06, 0x44]), ("ASL $44,X", &[0x16, 0x44]),
+        ("ASL $4400", &[0x0e, 0x00, 0x44]), ("ASL $4400,X", &[0x1e, 0x00, 0x44]),
+        ("BCC $1012", &[0x90, 0x10]), ("BCS $1012", &[0xb0, 0x10]), ("BEQ $1012", &[0xf0, 0x10]),
+        ("BMI $1012", &[0x30, 0x10]), ("BNE $1012", &[0xd0, 0x10]), ("BPL $1012", &[0x10, 0x10]),
+        ("BVC $1012", &[0x50, 0x10]), ("BVS $1012", &[0x70, 0x10]),
+        ("BIT $44", &[0x24, 0x44]), ("BIT $4400", &[0x2c, 0x00, 0x44]),
+        ("BRK", &[0x00]),
+        ("CLC", &[0x18]), ("CLD", &[0xd8]), ("CLI", &[0x58]), ("CLV", &[0xb8]),
+        ("CMP #$44", &[0xc9, 0x44]), ("CMP $44", &[0xc5, 0x44]), ("CMP $44,X", &[0xd5, 0x44]),
+        ("CMP $4400", &[0xcd, 0x00, 0x44]), ("CMP $4400,X", &[0xdd, 0x00, 0x44]),
+        ("CMP $4400,Y", &[0xd9, 0x00, 0x44]), ("CMP ($44,X)", &[0xc1, 0x44]), ("CMP ($44),Y", &[0xd1, 0x44]),
+        ("CPX #$44", &[0xe0, 0x44]), ("CPX $44", &[0xe4, 0x44]), ("CPX $4400", &[0xec, 0x00, 0x44]),
+        ("CPY #$44", &[0xc0, 0x44]), ("CPY $44", &[0xc4, 0x44]), ("CPY $4400", &[0xcc, 0x00, 0x44]),
+        ("DEC $44", &[0xc6, 0x44]), ("DEC $44,X", &[0xd6, 0x44]),
+        ("DEC $4400", &[0xce, 0x00, 0x44]), ("DEC $4400,X", &[0xde, 0x00, 0x44]),
+        ("DEX", &[0xca]), ("DEY", &[0x88]),
+        ("EOR #$44", &[0x49, 0x44]), ("EOR $44", &[0x45, 0x44]), ("EOR $44,X", &[0x55, 0x44]),
+        ("EOR $4400", &[0x4d, 0x00, 0x44]), ("EOR $4400,X", &[0x5d, 0x00, 0x44]),
+        ("EOR $4400,Y", &[0x59, 0x00, 0x44]), ("EOR ($44,X)", &[0x41, 0x44]), ("EOR ($44),Y", &[0x51, 0x44]),
+        ("INC $44", &[0xe6, 0x44]), ("INC $44,X", &[0xf6, 0x44]),
+        ("INC $4400", &[0xee, 0x00, 0x44]), ("INC $4400,X", &[0xfe, 0x00, 0x44]),
+        ("INX", &[0xe8]), ("INY", &[0xc8]),
+        ("JMP $4400", &[0x4c, 0x00, 0x44]), ("JMP ($4400)", &[0x6c, 0x00, 0x44]),
+        ("JSR $4400", &[0x20, 0x00, 0x44]),
+        ("LDA #$44", &[0xa9, 0x44]), ("LDA $44", &[0xa5, 0x44]), ("LDA $44,X", &[0xb5, 0x44]),
+        ("LDA $4400", &[0xad, 0x00, 0x44]), ("LDA $4400,X", &[0xbd, 0x00, 0x44]),
+        ("LDA $4400,Y", &[0xb9, 0x00, 0x44]), ("LDA ($44,X)", &[0xa1, 0x44]), ("LDA ($44),Y", &[0xb1, 0x44]),
+        ("LDX #$44", &[0xa2, 0x44]), ("LDX $44", &[0xa6, 0x44]), ("LDX $44,Y", &[0xb6, 0x44]),
+        ("LDX $4400", &[0xae, 0x00, 0x44]), ("LDX $4400,Y", &[0xbe, 0x00, 0x44]),
+        ("LDY #$44", &[0xa0, 0x44]), ("LDY $44", &[0xa4, 0x44]), ("LDY $44,X", &[0xb4, 0x44]),
+        ("LDY $4400", &[0xac, 0x00, 0x44]), ("LDY $4400,X", &[0xbc, 0x00, 0x44]),
+        ("LSR A", &[0x4a]), ("LSR $44", &[0x46, 0x44]), ("LSR $44,X", &[0x56, 0x44]),
+        ("LSR $4400", &[0x4e, 0x00, 0x44]), ("LSR $4400,X", &[0x5e, 0x00, 0x44]),
+        ("NOP", &[0xea]),
+        ("ORA #$44", &[0x09, 0x44]), ("ORA $44", &[0x05, 0x44]), ("ORA $44,X", &[0x15, 0x44]),
+        ("ORA $4400", &[0x0d, 0x00, 0x44]), ("ORA $4400,X", &[0x1d, 0x00, 0x44]),
+        ("ORA $4400,Y", &[0x19, 0x00, 0x44]), ("ORA ($44,X)", &[0x01, 0x44]), ("ORA ($44),Y", &[0x11, 0x44]),
+        ("PHA", &[0x48]), ("PHP", &[0x08]), ("PLA", &[0x68]), ("PLP", &[0x28]),
+        ("ROL A", &[0x2a]), ("ROL $44", &[0x26, 0x44]), ("ROL $44,X", &[0x36, 0x44]),
+        ("ROL $4400", &[0x2e, 0x00, 0x44]), ("ROL $4400,X", &[0x3e, 0x00, 0x44]),
+        ("ROR A", &[0x6a]), ("ROR $44", &[0x66, 0x44]), ("ROR $44,X", &[0x76, 0x44]),
+        ("ROR $4400", &[0x6e, 0x00, 0x44]), ("ROR $4400,X", &[0x7e, 0x00, 0x44]),
+        ("RTI", &[0x40]), ("RTS", &[0x60]),
+        ("SBC #$44", &[0xe9, 0x44]), ("SBC $44", &[0xe5, 0x44]), ("SBC $44,X", &[0xf5, 0x44]),
+        ("SBC $4400", &[0xed, 0x00, 0x44]), ("SBC $4400,X", &[0xfd, 0x00, 0x44]),
+        ("SBC $4400,Y", &[0xf9, 0x00, 0x44]), ("SBC ($44,X)", &[0xe1, 0x44]), ("SBC ($44),Y", &[0xf1, 0x44]),
+        ("SEC", &[0x38]), ("SED", &[0xf8]), ("SEI", &[0x78]),
+        ("STA $44", &[0x85, 0x44]), ("STA $44,X", &[0x95, 0x44]),
+        ("STA $4400", &[0x8d, 0x00, 0x44]), ("STA $4400,X", &[0x9d, 0x00, 0x44]),
+        ("STA $4400,Y", &[0x99, 0x00, 0x44]), ("STA ($44,X)", &[0x81, 0x44]), ("STA ($44),Y", &[0x91, 0x44]),
+        ("STX $44", &[0x86, 0x44]), ("STX $44,Y", &[0x96, 0x44]), ("STX $4400", &[0x8e, 0x00, 0x44]),
+        ("STY $44", &[0x84, 0x44]), ("STY $44,X", &[0x94, 0x44]), ("STY $4400", &[0x8c, 0x00, 0x44]),
+        ("TAX", &[0xaa]), ("TAY", &[0xa8]), ("TSX", &[0xba]),
+        ("TXA", &[0x8a]), ("TXS", &[0x9a]), ("TYA", &[0x98]),
+    ];
+
+    #[test]
+    fn reference_opcodes() {
+        assert_eq!(REFERENCE.len(), 151);
+        let assembler = Assembler::new();
+        for (line, bytes) in REFERENCE {
+            assert_eq!(assembler.assemble_at(0x1000, line).as_deref(), Ok(*bytes), "{}", line);
+            // And the CPU decodes them the same
+            let (instruction, mode, _) = decode_instruction(bytes[0]).unwrap();
+            assert!(line.starts_with(&instruction.to_string()), "{}", line);
+            assert_eq!(1 + mode.operand_size() as usize, bytes.len(), "{}", line);
+        }
+    }
+
+    #[test]
+    fn labels() {
+        let source = "
+        ZP = $20
+        start:  LDX #<data
+                LDA ZP
+        @loop:  DEX
+                BNE @loop
+                JMP later
+        :       BEQ :-
+                BCC :+
+                LDA data+1
+        :       RTS
+        later:  STA ZP,X
+        data:   .word start, later";
+        assert_eq!(assemble(source), vec![
+            0xa2, 0x14, 0xa5, 0x20, 0xca, 0xd0, 0xfd, 0x4c, 0x12, 0x10, 0xf0, 0xfe, 0x90, 0x03,
+            0xad, 0x15, 0x10, 0x60, 0x95, 0x20, 0x00, 0x10, 0x12, 0x10,
+        ]);
+
+        let assembly = Assembler::new().assemble(source).unwrap();
+        assert_eq!(assembly.symbols.address_of("start"), Some(0x1000));
+        assert_eq!(assembly.symbols.address_of("start@loop"), Some(0x1004));
+        assert_eq!(assembly.symbols.address_of("data"), Some(0x1014));
+    }
+
+    #[test]
+    fn data() {
+        let source = "
+            .byte 1, $ff, -1, \"AB\", 'c'
+            .word $1234
+            .dbyt $1234
+            .asciiz \"hi; there\" ; a comment
+            .res 2, $ea";
+        assert_eq!(assemble(source), vec![
+            1, 0xff, 0xff, b'A', b'B', b'c', 0x34, 0x12, 0x12, 0x34,
+            b'h', b'i', b';', b' ', b't', b'h', b'e', b'r', b'e', 0, 0xea, 0xea,
+        ]);
+    }
+
+    #[test]
+    fn macros_and_conditions() {
+        let source = "
+        DEFINED = 1
+        VALUE .set 1
+        VALUE .set VALUE + 1
+        .macro Store address, value
+            .ifnblank value
+            LDA #value
+            .else
+            LDA #0
+            .endif
+            STA address
+        .endmacro
+        .macro Wait
+            .local loop
+        loop: DEX
+            BNE loop
+        .endmacro
+            Store $10, 5
+            Store $11
+            Wait
+            Wait
+        .ifdef DEFINED
+            .byte 1
+        .endif
+        .if 2 > 3
+            .byte 2
+        .endif
+            .byte VALUE";
+        assert_eq!(assemble(source), vec![
+            0xa9, 0x05, 0x85, 0x10, 0xa9, 0x00, 0x85, 0x11, 0xca, 0xd0, 0xfd, 0xca, 0xd0, 0xfd, 0x01, 0x02,
+        ]);
+    }
+
+    #[test]
+    fn segments() {
+        let source = "
+            .segment \"VECTORS\"
+            .word reset
+            .code
+        reset: LDA value
+            RTS
+            .data
+        value: .byte 7
+            .bss
+        buffer: .res 4
+            .rodata
+            .byte 9
+            .zeropage
+        pointer: .res 2
+            .code
+            LDA pointer
+            LDA buffer";
+        let assembly = Assembler::new()
+            .with_origin(0x8000)
+            .with_segment("VECTORS", 0xfffc)
+            .assemble(source)
+            .unwrap();
+        let segments: Vec<(u16, Vec<u8>)> = assembly.program.segments.iter()
+            .map(|s| (s.address, s.data.clone()))
+            .collect();
+        assert_eq!(segments, vec![
+            (0x8000, vec![0xad, 0x0a, 0x80, 0x60, 0xa5, 0x00, 0xad, 0x0b, 0x80, 0x09, 0x07]),
+            (0xfffc, vec![0x00, 0x80]),
+        ]);
+        assert_eq!(assembly.symbols.address_of("pointer"), Some(0x0000));
+        assert_eq!(assembly.symbols.address_of("buffer"), Some(0x800b));
+    }
+
+    #[test]
+    fn org() {
+        let assembly = Assembler::new().assemble(".org $0300\nstart: JMP start").unwrap();
+        assert_eq!(assembly.program.segments[0].address, 0x0300);
+        assert_eq!(assembly.program.segments[0].data, vec![0x4c, 0x00, 0x03]);
+    }
+
+    #[test]
+    fn line_at_address() {
+        let mut symbols = SymbolTable::new();
+        symbols.add("echo", 0xffef);
+        let assembler = Assembler::new().with_symbols(&symbols);
+        assert_eq!(assembler.assemble_at(0x0300, "JSR echo"), Ok(vec![0x20, 0xef, 0xff]));
+        assert_eq!(assembler.assemble_at(0x0300, "BNE $0300"), Ok(vec![0xd0, 0xfe]));
+        assert_eq!(assembler.assemble_at(0x0300, "lda #'A'"), Ok(vec![0xa9, 0x41]));
+        assert_eq!(assembler.assemble_at(0x0300, ""), Ok(vec![]));
+        assert_eq!(assembler.assemble_at(0x0300, "LDA"), Err("LDA does not support Accumulator addressing".to_string()));
+        assert!(assembler.assemble_at(0x0300, ".org $2000\nNOP").is_err());
+    }
+
+    #[test]
+    fn errors() {
+        let error = |source| Assembler::new().assemble(source).unwrap_err();
+        assert!(error("LDA #$100").contains("<source>:1"));
+        assert!(error("\n  FOO").contains("<source>:2: Unknown instruction FOO"));
+        assert!(error("JMP nowhere").contains("Undefined symbol 'nowhere'"));
+        assert!(error("x: NOP\nx: NOP").contains("already defined"));
+        assert!(error("BNE far\n.res 200\nfar: RTS").contains("too far"));
+        assert!(error("STX $1234,X").contains("does not support"));
+        assert!(error(".macro X\nNOP").contains("without .endmacro"));
+        assert!(error(".include \"missing.inc\"").contains("not found"));
+    }
+
+    #[test]
+    fn assembly_sources() {
+        // The ROM that the assembly tests run with
+        let rom = Assembler::new()
+            .with_segment("OS", 0xff00)
+            .with_segment("VECTORS", 0xfffa)
+            .assemble_file(Path::new("assembly/standard.rom.s"))
+            .unwrap();
+        assert_eq!(rom.symbols.address_of("reset"), Some(0xff00));
+        assert_eq!(rom.program.segments.last().unwrap().address, 0xfffa);
+
+        let test = Assembler::new().assemble_file(Path::new("assembly/framework.test.s")).unwrap();
+        assert_eq!(test.program.segments[0].address, DEFAULT_ORIGIN);
+    }
+}
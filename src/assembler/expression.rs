@@ -0,0 +1,300 @@
+// Expressions in operands and directives, in ca65 syntax
+
+/*
+ * Supported:
+ *
+ *   numbers        $ff (hex), %1010 (binary), 42 (decimal), 'A' (character)
+ *   symbols        reset, @loop (local to the last label)
+ *   anonymous      :+ :++ (next labels), :- :-- (previous labels)
+ *   current pc     *
+ *   unary          - ~ ! < (low byte) > (high byte) .lobyte() .hibyte()
+ *   binary         * / .mod & | ^ << >> + - = <> < > <= >= && ||
+ *
+ * Values can be unknown while assembling, like labels that are defined
+ * further on. The result is then None, rather than an error.
+ */
+
+pub(crate) type Value = Option<i64>;
+
+// What expressions can refer to
+pub(crate) trait Context {
+    fn symbol(&self, name: &str) -> Result<Value, String>;
+    // Anonymous labels, 1 being the next and -1 the previous one
+    fn anonymous(&self, offset: i32) -> Result<Value, String>;
+    fn current_address(&self) -> Value;
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(i64),
+    Symbol(String),
+    Anonymous(i32),
+    Operator(&'static str),
+}
+
+// Longest operators first, so they're matched before their prefixes
+const OPERATORS: [&str; 20] = [
+    "<<", ">>", "<=", ">=", "<>", "&&", "||", ".mod", ".lobyte", ".hibyte",
+    "+", "-", "*", "/", "&", "|", "^", "~", "!", "=",
+];
+const SINGLE_OPERATORS: [&str; 4] = ["<", ">", "(", ")"];
+
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let chars: Vec<char> = text.chars().collect();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let rest: String = chars[i..].iter().collect();
+        let take_while = |start: usize, f: fn(char) -> bool| {
+            chars[start..].iter().take_while(|c| f(**c)).count()
+        };
+        if c.is_whitespace() {
+            i += 1;
+        } else if c == '$' {
+            let n = take_while(i + 1, |c| c.is_ascii_hexdigit());
+            let digits: String = chars[i + 1..i + 1 + n].iter().collect();
+            let value = i64::from_str_radix(&digits, 16).map_err(|_| format!("Invalid number '${}'", digits))?;
+            tokens.push(Token::Number(value));
+            i += 1 + n;
+        } else if c == '%' && chars.get(i + 1).is_some_and(|c| *c == '0' || *c == '1') {
+            let n = take_while(i + 1, |c| c == '0' || c == '1');
+            let digits: String = chars[i + 1..i + 1 + n].iter().collect();
+            tokens.push(Token::Number(i64::from_str_radix(&digits, 2).map_err(|e| e.to_string())?));
+            i += 1 + n;
+        } else if c.is_ascii_digit() {
+            let n = take_while(i, |c| c.is_ascii_digit());
+            let digits: String = chars[i..i + n].iter().collect();
+            tokens.push(Token::Number(digits.parse().map_err(|_| format!("Invalid number '{}'", digits))?));
+            i += n;
+        } else if c == '\'' {
+            match (chars.get(i + 1), chars.get(i + 2)) {
+                (Some(c), Some('\'')) => tokens.push(Token::Number(*c as i64)),
+                _ => return Err("Invalid character constant".to_string()),
+            }
+            i += 3;
+        } else if c == ':' {
+            let n = take_while(i + 1, |c| c == '+' || c == '-');
+            let signs = &chars[i + 1..i + 1 + n];
+            if n == 0 || signs.iter().any(|s| *s != signs[0]) {
+                return Err("Invalid anonymous label reference".to_string());
+            }
+            tokens.push(Token::Anonymous(if signs[0] == '+' { n as i32 } else { -(n as i32) }));
+            i += 1 + n;
+        } else if let Some(operator) = OPERATORS.iter().find(|o| rest.to_lowercase().starts_with(*o)) {
+            tokens.push(Token::Operator(operator));
+            i += operator.len();
+        } else if let Some(operator) = SINGLE_OPERATORS.iter().find(|o| rest.starts_with(*o)) {
+            tokens.push(Token::Operator(operator));
+            i += 1;
+        } else if is_symbol_start(c) {
+            let n = take_while(i, is_symbol_char);
+            tokens.push(Token::Symbol(chars[i..i + n].iter().collect()));
+            i += n;
+        } else {
+            return Err(format!("Unexpected '{}' in expression", c));
+        }
+    }
+    Ok(tokens)
+}
+
+pub(crate) fn is_symbol_start(c: char) -> bool {
+    c.is_ascii_alphabetic() || c == '_' || c == '@'
+}
+
+pub(crate) fn is_symbol_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '@'
+}
+
+// Binary operators by precedence, lowest first
+const PRECEDENCE: [&[&str]; 7] = [
+    &["||"],
+    &["&&"],
+    &["=", "<>", "<", ">", "<=", ">="],
+    &["|", "^"],
+    &["&", "<<", ">>"],
+    &["+", "-"],
+    &["*", "/", ".mod"],
+];
+
+struct Parser<'a> {
+    tokens: Vec<Token>,
+    position: usize,
+    context: &'a dyn Context,
+}
+
+pub(crate) fn evaluate(text: &str, context: &dyn Context) -> Result<Value, String> {
+    let mut parser = Parser { tokens: tokenize(text)?, position: 0, context };
+    if parser.tokens.is_empty() {
+        return Err("Missing expression".to_string());
+    }
+    let value = parser.binary(0)?;
+    match parser.tokens.get(parser.position) {
+        None => Ok(value),
+        Some(token) => Err(format!("Unexpected {:?} in expression '{}'", token, text)),
+    }
+}
+
+impl Parser<'_> {
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn peek_operator(&self) -> Option<&'static str> {
+        match self.tokens.get(self.position) {
+            Some(Token::Operator(operator)) => Some(operator),
+            _ => None,
+        }
+    }
+
+    fn binary(&mut self, level: usize) -> Result<Value, String> {
+        if level == PRECEDENCE.len() {
+            return self.unary();
+        }
+        let mut left = self.binary(level + 1)?;
+        while let Some(operator) = self.peek_operator().filter(|o| PRECEDENCE[level].contains(o)) {
+            self.position += 1;
+            let right = self.binary(level + 1)?;
+            left = match (left, right) {
+                (Some(l), Some(r)) => Some(apply(operator, l, r)?),
+                _ => None,
+            };
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Value, String> {
+        let operator = match self.peek_operator() {
+            Some(operator @ ("-" | "~" | "!" | "<" | ">" | ".lobyte" | ".hibyte")) => operator,
+            _ => return self.primary(),
+        };
+        self.position += 1;
+        let value = self.unary()?;
+        Ok(value.map(|v| match operator {
+            "-" => -v,
+            "~" => !v,
+            "!" => (v == 0) as i64,
+            "<" | ".lobyte" => v & 0xff,
+            _ => (v >> 8) & 0xff,
+        }))
+    }
+
+    fn primary(&mut self) -> Result<Value, String> {
+        match self.next() {
+            Some(Token::Number(n)) => Ok(Some(n)),
+            Some(Token::Symbol(name)) => self.context.symbol(&name),
+            Some(Token::Anonymous(offset)) => self.context.anonymous(offset),
+            Some(Token::Operator("*")) => Ok(self.context.current_address()),
+            Some(Token::Operator("(")) => {
+                let value = self.binary(0)?;
+                match self.next() {
+                    Some(Token::Operator(")")) => Ok(value),
+                    _ => Err("Missing ')'".to_string()),
+                }
+            }
+            Some(token) => Err(format!("Unexpected {:?} in expression", token)),
+            None => Err("Incomplete expression".to_string()),
+        }
+    }
+}
+
+fn apply(operator: &str, left: i64, right: i64) -> Result<i64, String> {
+    Ok(match operator {
+        "+" => left.wrapping_add(right),
+        "-" => left.wrapping_sub(right),
+        "*" => left.wrapping_mul(right),
+        "/" | ".mod" if right == 0 => return Err("Division by zero".to_string()),
+        "/" => left / right,
+        ".mod" => left % right,
+        "&" => left & right,
+        "|" => left | right,
+        "^" => left ^ right,
+        "<<" => left.wrapping_shl(right as u32),
+        ">>" => left.wrapping_shr(right as u32),
+        "=" => (left == right) as i64,
+        "<>" => (left != right) as i64,
+        "<" => (left < right) as i64,
+        ">" => (left > right) as i64,
+        "<=" => (left <= right) as i64,
+        ">=" => (left >= right) as i64,
+        "&&" => (left != 0 && right != 0) as i64,
+        _ => (left != 0 || right != 0) as i64,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct TestContext;
+
+    impl Context for TestContext {
+        fn symbol(&self, name: &str) -> Result<Value, String> {
+            match name {
+                "start" => Ok(Some(0x1234)),
+                "later" => Ok(None),
+                _ => Err(format!("Undefined symbol '{}'", name)),
+            }
+        }
+
+        fn anonymous(&self, offset: i32) -> Result<Value, String> {
+            Ok(Some(0x2000 + offset as i64))
+        }
+
+        fn current_address(&self) -> Value {
+            Some(0x1000)
+        }
+    }
+
+    fn eval(text: &str) -> Result<Value, String> {
+        evaluate(text, &TestContext)
+    }
+
+    #[test]
+    fn numbers() {
+        assert_eq!(eval("$ff"), Ok(Some(255)));
+        assert_eq!(eval("%1010"), Ok(Some(10)));
+        assert_eq!(eval("42"), Ok(Some(42)));
+        assert_eq!(eval("'A'"), Ok(Some(65)));
+        assert_eq!(eval("'_'+$80"), Ok(Some(0xdf)));
+    }
+
+    #[test]
+    fn operators() {
+        assert_eq!(eval("1 + 2 * 3"), Ok(Some(7)));
+        assert_eq!(eval("(1 + 2) * 3"), Ok(Some(9)));
+        assert_eq!(eval("-1"), Ok(Some(-1)));
+        assert_eq!(eval("<start"), Ok(Some(0x34)));
+        assert_eq!(eval(">start"), Ok(Some(0x12)));
+        assert_eq!(eval(".hibyte(start+$100)"), Ok(Some(0x13)));
+        assert_eq!(eval("start+1"), Ok(Some(0x1235)));
+        assert_eq!(eval("1 << 4 | 1"), Ok(Some(0x11)));
+        assert_eq!(eval("7 .mod 4"), Ok(Some(3)));
+        assert_eq!(eval("3 > 2 && 1 <> 1"), Ok(Some(0)));
+        assert_eq!(eval("~0 & $ff"), Ok(Some(0xff)));
+        assert_eq!(eval("* + 3"), Ok(Some(0x1003)));
+        assert_eq!(eval("*"), Ok(Some(0x1000)));
+    }
+
+    #[test]
+    fn symbols() {
+        assert_eq!(eval(":+"), Ok(Some(0x2001)));
+        assert_eq!(eval(":--"), Ok(Some(0x1ffe)));
+        assert_eq!(eval("later + 1"), Ok(None));
+        assert!(eval("nowhere").is_err());
+    }
+
+    #[test]
+    fn errors() {
+        assert!(eval("").is_err());
+        assert!(eval("(1 + 2").is_err());
+        assert!(eval("1 +").is_err());
+        assert!(eval("1 2").is_err());
+        assert!(eval("1 / 0").is_err());
+        assert!(eval(":+-").is_err());
+        assert!(eval("$").is_err());
+        assert!(eval("#1").is_err());
+    }
+}
@@ -0,0 +1,88 @@
+use m6502::assembler::Assembler;
+use m6502::binutils::parse_address;
+
+use clap::Parser;
+use color_eyre::{eyre::eyre, Result};
+
+use std::fmt::Write;
+use std::path::PathBuf;
+
+// Assemble a source file into a binary image
+#[derive(Parser)]
+struct AsmCli {
+    /// Source file, in ca65 syntax
+    source: PathBuf,
+    /// Binary to write, covering everything from the lowest to the highest address used
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+    /// Where segments without a fixed address start
+    #[arg(long, value_parser = parse_address, default_value = "1000")]
+    origin: u16,
+    /// Put a segment at a fixed address, as in VECTORS=fffa
+    #[arg(short, long, value_parser = parse_segment)]
+    segment: Vec<(String, u16)>,
+    /// Directory to look for included files
+    #[arg(short = 'I', long)]
+    include: Vec<PathBuf>,
+    /// Write the labels to this file, in the format of ld65 -Ln
+    #[arg(short, long)]
+    labels: Option<PathBuf>,
+    /// Value for the gaps between segments
+    #[arg(long, value_parser = parse_byte, default_value = "0")]
+    fill: u8,
+}
+
+fn parse_segment(s: &str) -> Result<(String, u16), String> {
+    let (name, address) = s.split_once('=').ok_or("Expected NAME=ADDRESS")?;
+    Ok((name.to_string(), parse_address(address)?))
+}
+
+fn parse_byte(s: &str) -> Result<u8, String> {
+    u8::try_from(parse_address(s)?).map_err(|_| format!("Invalid byte '{}'", s))
+}
+
+fn main() -> Result<()> {
+    color_eyre::install()?;
+    let _ = env_logger::builder()
+        .format_timestamp(None)
+        .filter_level(log::LevelFilter::Info)
+        .parse_default_env()
+        .try_init();
+
+    let cli = AsmCli::parse();
+
+    let mut assembler = Assembler::new().with_origin(cli.origin);
+    for (name, address) in &cli.segment {
+        assembler = assembler.with_segment(name, *address);
+    }
+    for path in &cli.include {
+        assembler = assembler.with_include_path(path);
+    }
+    let assembly = assembler.assemble_file(&cli.source).map_err(|e| eyre!(e))?;
+
+    let (start, image) = assembly.program.to_image(cli.fill);
+    let output = cli.output.unwrap_or_else(|| cli.source.with_extension("bin"));
+    std::fs::write(&output, &image)?;
+    println!("{}: {} bytes at ${:04x}", output.display(), image.len(), start);
+
+    if let Some(labels_file) = cli.labels {
+        let mut labels = String::new();
+        for (address, name) in assembly.symbols.iter() {
+            writeln!(labels, "al {:06X} .{}", address, name)?;
+        }
+        std::fs::write(labels_file, labels)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cli() {
+        use clap::CommandFactory;
+        AsmCli::command().debug_assert();
+    }
+}
@@ -1,8 +1,229 @@
 use m6502::proxy::ComputerProxy;
 use m6502::binutils::*;
+use m6502::computer::Stop;
+use m6502::computer::machines::Machine;
+use m6502::monitor::{self, Command, FileKind};
+use m6502::scripting::Script;
+use m6502::watch::Watch;
 
 use clap::Parser;
-use color_eyre::Result;
+use color_eyre::{eyre::eyre, Result};
+
+#[derive(Parser)]
+struct DebugCli {
+    #[command(flatten)]
+    cli: Cli,
+    /// Monitor command to run, like in the TUI's command palette, instead of running the machine.
+    /// Can be given more than once, like -x "break loop" -x run -x "mem buffer"
+    #[arg(short = 'x', long = "command")]
+    commands: Vec<String>,
+    /// Stop running after about this many clock cycles, without waiting for the clock.
+    /// Interactive machines like the Apple I otherwise run until they're killed.
+    #[arg(long)]
+    max_cycles: Option<u32>,
+}
+
+// How much the mem and dis commands show
+const MEMORY_LINES: u16 = 4;
+const DISASSEMBLY_LINES: usize = 8;
+
+// Run until something stops the computer, and the script's callbacks don't say to keep going.
+// Returns None when the cycle limit is reached. Cycles are counted again after each callback.
+fn run(proxy: &mut ComputerProxy, script: &mut Option<Script>, max_cycles: Option<u32>) -> Result<Option<Stop>, String> {
+    loop {
+        let stop = match max_cycles {
+            Some(cycles) => match proxy.run_cycles(cycles) {
+                Some(stop) => stop,
+                None => return Ok(None),
+            },
+            None => proxy.run(),
+        };
+        let keep_going = match script.as_mut() {
+            Some(script) => proxy.handle_stop(script, stop)?,
+            None => false,
+        };
+        if !keep_going {
+            return Ok(Some(stop));
+        }
+    }
+}
+
+fn describe_stop(proxy: &ComputerProxy, stop: Stop) -> String {
+    match stop {
+        Stop::Halted => "halted".to_string(),
+        Stop::Breakpoint(address) => format!("breakpoint at {}", proxy.address_to_string(address)),
+        Stop::Exit(value) => format!("exited with ${:02x}", value),
+        Stop::Watchpoint(access) => format!(
+            "{:?} of ${:02x} at {}",
+            access.kind, access.value, proxy.address_to_string(access.address)
+        ),
+        Stop::Step => "stopped".to_string(),
+        Stop::Paused => "paused".to_string(),
+    }
+}
+
+fn show_registers(proxy: &ComputerProxy) -> String {
+    let state = &proxy.cpu_state;
+    format!("PC={} A=${:02x} X=${:02x} Y=${:02x} SP=${:02x} P=${:02x}",
+        proxy.address_to_string(state.program_counter),
+        state.accumulator, state.x_index, state.y_index, state.stack_pointer, state.status.as_byte())
+}
+
+fn show_memory(proxy: &ComputerProxy, address: u16) -> String {
+    let lines: Vec<String> = (0..MEMORY_LINES)
+        .map(|line| {
+            let start = address.wrapping_add(line * 16);
+            let bytes = proxy.peek_memory(start, 16);
+            let hex: Vec<String> = bytes.iter()
+                .map(|byte| byte.map_or("--".to_string(), |byte| format!("{:02x}", byte)))
+                .collect();
+            let text: String = bytes.iter()
+                .map(|byte| match byte {
+                    Some(byte) if byte.is_ascii_graphic() || *byte == b' ' => *byte as char,
+                    Some(_) => '.',
+                    None => ' ',
+                })
+                .collect();
+            format!("{:04x}: {}  {}", start, hex.join(" "), text)
+        })
+        .collect();
+    lines.join("\n")
+}
+
+// Do what a monitor command says, like the TUI does. Returns what to show.
+fn execute(
+    proxy: &mut ComputerProxy,
+    script: &mut Option<Script>,
+    max_cycles: Option<u32>,
+    command: Command,
+) -> Result<String, String> {
+    let stopped = |proxy: &mut ComputerProxy, stop: Stop| {
+        proxy.update();
+        format!("Stopped: {}\n{}", describe_stop(proxy, stop), show_registers(proxy))
+    };
+    let ran = |proxy: &mut ComputerProxy, stop: Option<Stop>| match stop {
+        Some(stop) => stopped(proxy, stop),
+        None => {
+            proxy.update();
+            format!("Stopped: cycle limit reached\n{}", show_registers(proxy))
+        }
+    };
+    match command {
+        Command::Run => {
+            let stop = run(proxy, script, max_cycles)?;
+            Ok(ran(proxy, stop))
+        }
+        Command::Step(count) => {
+            let mut stop = Stop::Step;
+            for _ in 0..count {
+                stop = proxy.step_instruction();
+                if stop != Stop::Step {
+                    break;
+                }
+            }
+            Ok(stopped(proxy, stop))
+        }
+        Command::Over => {
+            let stop = proxy.step_over().unwrap_or(Stop::Step);
+            Ok(stopped(proxy, stop))
+        }
+        Command::Out => {
+            let stop = proxy.step_out().unwrap_or(Stop::Step);
+            Ok(stopped(proxy, stop))
+        }
+        Command::Next => {
+            let stop = proxy.step_line().unwrap_or(Stop::Step);
+            Ok(stopped(proxy, stop))
+        }
+        Command::Until(location) => {
+            let address = proxy.resolve_address(&location)?;
+            proxy.run_to(address);
+            let stop = run(proxy, script, max_cycles)?;
+            Ok(ran(proxy, stop))
+        }
+        Command::Reset => {
+            proxy.reset();
+            proxy.update();
+            Ok(show_registers(proxy))
+        }
+        Command::Break(location) => {
+            let address = proxy.resolve_address(&location)?;
+            if !proxy.is_breakpoint(address) {
+                proxy.toggle_breakpoint(address);
+            }
+            Ok(format!("Breakpoint at ${:04x}", address))
+        }
+        Command::Delete(location) => {
+            let address = proxy.resolve_address(&location)?;
+            if !proxy.is_breakpoint(address) {
+                return Err(format!("There is no breakpoint at {}", location));
+            }
+            proxy.toggle_breakpoint(address);
+            Ok(format!("Removed the breakpoint at ${:04x}", address))
+        }
+        Command::Set(register, value) => {
+            proxy.set_register(register, &value)?;
+            proxy.update();
+            Ok(show_registers(proxy))
+        }
+        Command::Memory(location) => {
+            let location = location.ok_or("'mem' needs a location here")?;
+            Ok(show_memory(proxy, proxy.resolve_address(&location)?))
+        }
+        Command::Disassemble(location) => {
+            let address = proxy.resolve_address(&location)?;
+            let lines: Vec<String> = proxy.disassemble_lines(address, DISASSEMBLY_LINES).into_iter()
+                .map(|(address, text)| format!("{:04x}: {}", address, text))
+                .collect();
+            Ok(lines.join("\n"))
+        }
+        Command::Poke(location, bytes) => {
+            let address = proxy.resolve_address(&location)?;
+            proxy.write_memory(address, &bytes);
+            Ok(format!("{} bytes written at ${:04x}", bytes.len(), address))
+        }
+        Command::Assemble(location, line) => {
+            let address = proxy.resolve_address(&location)?;
+            let size = proxy.assemble_at(address, &line)?;
+            Ok(format!("{} bytes written at ${:04x}", size, address))
+        }
+        // Without a TUI to keep watching, show the value once
+        Command::Watch(Some(text)) => {
+            let watch = Watch::parse(&text)?;
+            Ok(format!("{} = {}", watch, proxy.evaluate_watch(&watch)?))
+        }
+        Command::Load(_, None, _) => Err("'load' needs a file here".to_string()),
+        Command::Load(FileKind::Rom, Some(file), _) => {
+            proxy.load_rom(&file)?;
+            Ok(format!("Loaded {}", file.display()))
+        }
+        Command::Load(FileKind::Program, Some(file), address) => {
+            let run_address = proxy.load_program(&file, address)?;
+            Ok(format!("Loaded {}, to run from ${:04x}", file.display(), run_address))
+        }
+        Command::Load(FileKind::Symbols, Some(file), _) => {
+            let count = proxy.load_symbols(&file)?;
+            Ok(format!("Loaded {} symbols from {}", count, file.display()))
+        }
+        Command::Load(FileKind::State, Some(file), _) => {
+            proxy.load_state(&file)?;
+            Ok(format!("Loaded {}", file.display()))
+        }
+        Command::SaveState(file) => {
+            proxy.save_state(&file)?;
+            Ok(format!("Saved {}", file.display()))
+        }
+        Command::Help => {
+            let lines: Vec<String> = monitor::HELP.iter()
+                .map(|(command, text)| format!("{:<44} {}", command, text))
+                .collect();
+            Ok(lines.join("\n"))
+        }
+        Command::Pause | Command::Watch(None) | Command::Console | Command::Hide(_) | Command::Show(_)
+        | Command::Log => Err("That command only works in the TUI".to_string()),
+        Command::Quit => Ok(String::new()),
+    }
+}
 
 fn main() -> Result<()> {
     color_eyre::install()?;
@@ -15,16 +236,41 @@ fn main() -> Result<()> {
         .parse_default_env()
         .try_init();
 
-    let cli = Cli::parse();
+    let DebugCli { cli, commands, max_cycles } = DebugCli::parse();
+
+    let mut computer = build_computer(cli.clone()).map_err(|e| eyre!(e))?;
+    let mut script = run_script(&cli, &mut computer).map_err(|e| eyre!(e))?;
+    let mut app = ComputerProxy::new(&mut computer);
 
-    let computer = build_computer(cli.clone());
+    if !commands.is_empty() {
+        for line in commands {
+            let command = Command::parse(&line).map_err(|e| eyre!(e))?;
+            if command == Command::Quit {
+                break;
+            }
+            let output = execute(&mut app, &mut script, max_cycles, command).map_err(|e| eyre!("{}: {}", line, e))?;
+            if !output.is_empty() {
+                println!("{}", output);
+            }
+        }
+        return Ok(());
+    }
+
+    // Run the machine, unless there is nothing to run
+    if cli.program_file.is_some() || cli.config.is_some() || cli.machine != Machine::Standard {
+        let stop = run(&mut app, &mut script, max_cycles).map_err(|e| eyre!(e))?;
+        let reason = stop.map_or("cycle limit reached".to_string(), |stop| describe_stop(&app, stop));
+        println!("Stopped: {}", reason);
+    }
     // TODO Start the computer in a separate thread, with the correct
     // communication stuff done
 
-    let app = ComputerProxy::new(&computer);
-
     let items: Vec<String> = app.get_execution_history().iter()
-        .map(|x| format!("{:04x}: {}", x.0, x.1)).collect();
+        .flat_map(|x| {
+            let label = app.symbol_at(x.0).map(|name| format!("{}:", name));
+            label.into_iter().chain([format!("{:04x}: {}", x.0, x.1)])
+        })
+        .collect();
     println!("Execution history:");
     for item in items {
         println!("{}", item);
@@ -43,6 +289,6 @@ mod tests {
     #[test]
     fn test_cli() {
         use clap::CommandFactory;
-        Cli::command().debug_assert();
+        DebugCli::command().debug_assert();
     }
-}
\ No newline at end of file
+}
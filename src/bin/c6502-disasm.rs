@@ -0,0 +1,120 @@
+use m6502::binutils::parse_address;
+use m6502::computer::config::CpuVariant;
+use m6502::computer::symbols::SymbolTable;
+use m6502::disassembler::{Disassembler, Style};
+use m6502::loader::{self, Format};
+
+use clap::Parser;
+use color_eyre::{eyre::eyre, Result};
+
+use std::path::PathBuf;
+
+// Disassemble a ROM image or program
+#[derive(Parser)]
+struct DisasmCli {
+    /// ROM image or program: raw binary, Intel HEX, S-records, .prg, .xex or .o65
+    file: PathBuf,
+    /// Where a raw binary starts, defaults to ending at $ffff
+    #[arg(short, long, value_parser = parse_address)]
+    address: Option<u16>,
+    /// Format of the file, if it can't be told from its name or contents
+    #[arg(short, long, value_enum)]
+    format: Option<Format>,
+    /// The processor the code is for
+    #[arg(long, value_enum, default_value_t)]
+    cpu: CpuVariant,
+    /// Symbols to use: ld65 label file (-Ln), VICE label file or ld65 debug info (.dbg)
+    #[arg(short, long)]
+    symbols: Vec<PathBuf>,
+    /// Where code starts, as a symbol or address. The vectors are always followed.
+    #[arg(short, long)]
+    entry: Vec<String>,
+    /// What to write: ca65 source, or a listing with or without the bytes
+    #[arg(long, value_enum, default_value_t)]
+    style: Style,
+    /// File to write to, instead of standard output
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+}
+
+fn main() -> Result<()> {
+    color_eyre::install()?;
+    let _ = env_logger::builder()
+        .format_timestamp(None)
+        .filter_level(log::LevelFilter::Warn)
+        .parse_default_env()
+        .try_init();
+
+    let cli = DisasmCli::parse();
+    let listing = disassemble(&cli).map_err(|e| eyre!(e))?;
+    match cli.output {
+        Some(output) => std::fs::write(output, listing)?,
+        None => print!("{}", listing),
+    }
+
+    Ok(())
+}
+
+// The listing for the file on the command line
+fn disassemble(cli: &DisasmCli) -> Result<String, String> {
+    // The loader reports files that can't be read
+    let size = std::fs::metadata(&cli.file).map_or(0, |m| m.len());
+    let address = cli.address.unwrap_or_else(|| 0x10000u64.saturating_sub(size) as u16);
+    let program = loader::load_file(&cli.file, cli.format, address)?;
+    let (start, image) = program.to_image(0);
+    let end = start as u32 + image.len() as u32;
+
+    let mut symbols = SymbolTable::new();
+    for path in &cli.symbols {
+        symbols.load_file(path)?;
+    }
+
+    let mut disassembler = Disassembler::new(start, image)
+        .with_variant(cli.cpu)
+        .with_symbols(&symbols)
+        .with_vectors();
+    let mut entry_points = Vec::new();
+    for entry in &cli.entry {
+        entry_points.push(symbols.resolve(entry)?);
+    }
+    entry_points.extend(program.run_address);
+    // Without anything else to go on, code starts at the start
+    if entry_points.is_empty() && end <= 0xfffa {
+        entry_points.push(start);
+    }
+    for address in entry_points {
+        disassembler = disassembler.with_entry_point(address);
+    }
+
+    Ok(disassembler.disassemble().listing(cli.style))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cli() {
+        use clap::CommandFactory;
+        DisasmCli::command().debug_assert();
+        assert!(DisasmCli::try_parse_from(["c6502-disasm", "--cpu", "6502", "a.rom"]).is_ok());
+        assert!(DisasmCli::try_parse_from(["c6502-disasm", "--cpu", "65c02", "a.rom"]).is_err());
+    }
+
+    #[test]
+    fn rom_starting_with_erased_bytes() {
+        // Two erased bytes, then reset: JMP reset
+        let mut rom = vec![0xff; 0x100];
+        rom[0x02..0x06].copy_from_slice(&[0x4c, 0x02, 0xff, 0xea]);
+        rom[0xfc] = 0x02;
+        rom[0xfd] = 0xff;
+        for name in ["erased.rom", "erased"] {
+            let file = std::env::temp_dir().join(format!("m6502-test-{}-{}", std::process::id(), name));
+            std::fs::write(&file, &rom).unwrap();
+            let cli = DisasmCli::parse_from(["c6502-disasm", file.to_str().unwrap()]);
+            let listing = disassemble(&cli);
+            std::fs::remove_file(&file).unwrap();
+            assert!(listing.unwrap().contains("JMP reset"), "{}", name);
+        }
+    }
+}
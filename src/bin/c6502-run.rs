@@ -0,0 +1,176 @@
+use m6502::binutils::*;
+use m6502::computer::{Computer, Stop};
+
+use std::ops::RangeInclusive;
+
+use clap::{Parser, ValueEnum};
+use color_eyre::{eyre::eyre, Result};
+
+/*
+ * Run a program without a user interface, as fast as possible, until it
+ * stops. The exit status of the process tells how it stopped:
+ *
+ *   exit port          the value the program wrote to it
+ *   BRK, breakpoint    0, or the value of the register given with --status
+ *   cycle limit        124, like timeout(1)
+ *
+ * Any status from 0 to 255 can come from the program, so a program writing
+ * $7c to the exit port can't be told apart from one running out of cycles by
+ * the status alone. The reason for stopping is shown, unless --quiet is given.
+ */
+const CYCLE_LIMIT_STATUS: i32 = 124;
+
+#[derive(Parser)]
+struct RunCli {
+    #[command(flatten)]
+    cli: Cli,
+    /// Stop after this many clock cycles, with exit status 124
+    #[arg(long)]
+    max_cycles: Option<u32>,
+    /// Show memory when done: START or START-END, as symbols or addresses
+    #[arg(short, long)]
+    dump: Vec<String>,
+    /// Register that holds the exit status, when the program halts or hits a breakpoint
+    #[arg(long, value_enum)]
+    status: Option<Register>,
+    /// Don't show the registers and the reason for stopping
+    #[arg(short, long)]
+    quiet: bool,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Register {
+    A,
+    X,
+    Y,
+}
+
+// How many bytes to show for a dump without an end
+const DUMP_LENGTH: u16 = 0x10;
+
+fn parse_range(computer: &Computer, text: &str) -> Result<RangeInclusive<u16>, String> {
+    let symbols = computer.symbols();
+    match text.split_once('-') {
+        Some((start, end)) => {
+            let (start, end) = (symbols.resolve(start)?, symbols.resolve(end)?);
+            if end < start {
+                return Err(format!("Range '{}' ends before it starts", text));
+            }
+            Ok(start..=end)
+        }
+        None => {
+            let start = symbols.resolve(text)?;
+            Ok(start..=start.saturating_add(DUMP_LENGTH - 1))
+        }
+    }
+}
+
+// Memory is only peeked at, so I/O registers show without being disturbed
+fn show_memory(computer: &Computer, range: RangeInclusive<u16>) {
+    let bytes: Vec<(u16, Option<u8>)> = range.map(|address| (address, computer.peek_memory(address, 1)[0])).collect();
+    for line in bytes.chunks(16) {
+        let hex: Vec<String> = line.iter()
+            .map(|(_, b)| b.map_or("--".to_string(), |b| format!("{:02x}", b)))
+            .collect();
+        let text: String = line.iter()
+            .map(|(_, b)| match b {
+                Some(b) if b.is_ascii_graphic() || *b == b' ' => *b as char,
+                Some(_) => '.',
+                None => ' ',
+            })
+            .collect();
+        println!("{:04x}: {:<47}  {}", line[0].0, hex.join(" "), text);
+    }
+}
+
+fn show_registers(computer: &Computer) {
+    let state = computer.get_cpu_state();
+    let flags: String = "NV-BDIZC".chars().enumerate()
+        .map(|(i, c)| match state.status.as_byte() & (0x80 >> i) != 0 {
+            true => c,
+            false => c.to_ascii_lowercase(),
+        })
+        .collect();
+    println!("PC={} A=${:02x} X=${:02x} Y=${:02x} SP=${:02x} P={}",
+        computer.address_to_string(state.program_counter),
+        state.accumulator, state.x_index, state.y_index, state.stack_pointer, flags);
+}
+
+fn main() -> Result<()> {
+    color_eyre::install()?;
+    let _ = env_logger::builder()
+        .format_timestamp(None)
+        .filter_level(log::LevelFilter::Warn)
+        .parse_default_env()
+        .try_init();
+
+    let run_cli = RunCli::parse();
+    // Nothing here would call the script's callbacks
+    if run_cli.cli.script.is_some() {
+        return Err(eyre!("Scripts only run in c6502-tui and c6502-debug"));
+    }
+    let mut computer = build_computer(run_cli.cli.clone()).map_err(|e| eyre!(e))?;
+    // Check the ranges before running, rather than finding out afterwards
+    let ranges = run_cli.dump.iter()
+        .map(|text| parse_range(&computer, text))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| eyre!(e))?;
+
+    let stop = match run_cli.max_cycles {
+        Some(cycles) => computer.run_cycles(cycles).err(),
+        None => loop {
+            if let Err(stop) = computer.run_cycles(u32::MAX) {
+                break Some(stop);
+            }
+        },
+    };
+
+    let state = computer.get_cpu_state();
+    let register = |register| match register {
+        Register::A => state.accumulator,
+        Register::X => state.x_index,
+        Register::Y => state.y_index,
+    };
+    let (reason, status) = match stop {
+        Some(Stop::Exit(value)) => (format!("exit port written with ${:02x}", value), value as i32),
+        Some(Stop::Breakpoint(address)) => (
+            format!("breakpoint at {}", computer.address_to_string(address)),
+            run_cli.status.map_or(0, register) as i32,
+        ),
+        Some(_) => ("halted".to_string(), run_cli.status.map_or(0, register) as i32),
+        None => ("cycle limit reached".to_string(), CYCLE_LIMIT_STATUS),
+    };
+
+    if !run_cli.quiet {
+        println!("Stopped: {}", reason);
+        show_registers(&computer);
+    }
+    for range in ranges {
+        show_memory(&computer, range);
+    }
+
+    std::process::exit(status);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cli() {
+        use clap::CommandFactory;
+        RunCli::command().debug_assert();
+    }
+
+    #[test]
+    fn ranges() {
+        let mut computer = Computer::new().with_rom(vec![0; 0x100]).build().unwrap();
+        computer.symbols_mut().add("buffer", 0x0200);
+        assert_eq!(parse_range(&computer, "0000-ffff"), Ok(0x0000..=0xffff));
+        assert_eq!(parse_range(&computer, "buffer-$20f"), Ok(0x0200..=0x020f));
+        assert_eq!(parse_range(&computer, "buffer"), Ok(0x0200..=0x020f));
+        assert_eq!(parse_range(&computer, "fff8"), Ok(0xfff8..=0xffff));
+        assert_eq!(parse_range(&computer, "ffff-ffff"), Ok(0xffff..=0xffff));
+        assert!(parse_range(&computer, "0300-0200").is_err());
+    }
+}
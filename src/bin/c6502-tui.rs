@@ -1,8 +1,50 @@
+use std::path::PathBuf;
+
 use m6502::tui::App;
-use m6502::binutils::{build_computer, Cli};
+use m6502::tui::config::TuiConfig;
+use m6502::binutils::{build_computer, run_script, Cli};
+use m6502::computer::devices::ConnectionSpec;
+
+use clap::{Parser, ValueEnum};
+use crossterm::event::{DisableMouseCapture, EnableMouseCapture};
+use color_eyre::{eyre::eyre, Result};
 
-use clap::Parser;
-use color_eyre::Result;
+#[derive(Parser)]
+struct TuiCli {
+    #[command(flatten)]
+    cli: Cli,
+    /// Layout and theme of the panels [default: ~/.config/m6502/tui.toml, if it exists]
+    #[arg(long)]
+    tui_config: Option<PathBuf>,
+}
+
+// The user's configuration directory for the emulator
+fn config_directory() -> Option<PathBuf> {
+    let directory = std::env::var_os("XDG_CONFIG_HOME").map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(directory.join("m6502"))
+}
+
+// The configuration given, or the one in the user's configuration directory
+fn read_config(file: Option<PathBuf>) -> Result<TuiConfig> {
+    let file = file.or_else(|| Some(config_directory()?.join("tui.toml")).filter(|file| file.exists()));
+    match file {
+        Some(file) => TuiConfig::from_file(&file).map_err(|e| eyre!(e)),
+        None => Ok(TuiConfig::default()),
+    }
+}
+
+// Watches are kept with the program, or else with the ROM or machine description,
+// or else in the configuration directory, for the machine
+fn watch_file(cli: &Cli) -> Option<PathBuf> {
+    match [&cli.program_file, &cli.rom_file, &cli.config].into_iter().flatten().next() {
+        Some(file) => Some(file.with_extension("watch")),
+        None => {
+            let machine = cli.machine.to_possible_value()?;
+            Some(config_directory()?.join(format!("{}.watch", machine.get_name())))
+        }
+    }
+}
 
 fn main() -> Result<()> {
     // Some setup
@@ -10,15 +52,34 @@ fn main() -> Result<()> {
     tui_logger::set_default_level(log::LevelFilter::Trace);
     color_eyre::install()?;
 
-    let cli = Cli::parse();
+    let TuiCli { mut cli, tui_config } = TuiCli::parse();
+    let config = read_config(tui_config)?;
+    // The terminal is taken, so serial ports go to the console pane instead
+    if cli.serial == ConnectionSpec::Stdio {
+        cli.serial = ConnectionSpec::Console;
+    }
 
-    let computer = build_computer(cli);
+    let mut computer = build_computer(cli.clone()).map_err(|e| eyre!(e))?;
+    let script = run_script(&cli, &mut computer).map_err(|e| eyre!(e))?;
     // TODO Start the computer in a separate thread, with the correct
     // communication stuff done
 
     let terminal = ratatui::init();
-    let result = App::new(&computer).run(terminal);
+    // The mouse is handy, but the keys do everything too
+    if let Err(e) = crossterm::execute!(std::io::stdout(), EnableMouseCapture) {
+        log::warn!("No mouse: {}", e);
+    }
+    let watch_file = watch_file(&cli);
+    if watch_file.is_none() {
+        log::warn!("Watches won't be saved: there's no program, ROM or configuration directory to keep them with");
+    }
+    let result = App::new(&mut computer)
+        .with_script(script)
+        .with_watch_file(watch_file)
+        .with_config(config)
+        .run(terminal);
     // Ensure we clean up when we exit or in case of an error
+    let _ = crossterm::execute!(std::io::stdout(), DisableMouseCapture);
     ratatui::restore();
 
     // TODO shut down the computer.
@@ -33,6 +94,16 @@ mod tests {
     #[test]
     fn test_cli() {
         use clap::CommandFactory;
-        Cli::command().debug_assert();
+        TuiCli::command().debug_assert();
+    }
+
+    #[test]
+    fn watch_files() {
+        let file = |args: &[&str]| watch_file(&TuiCli::parse_from(args).cli);
+        assert_eq!(file(&["tui", "-r", "a.rom", "-p", "b.bin"]), Some(PathBuf::from("b.watch")));
+        assert_eq!(file(&["tui", "-r", "roms/a.rom"]), Some(PathBuf::from("roms/a.watch")));
+        if let Some(file) = file(&["tui", "-m", "apple1"]) {
+            assert!(file.ends_with("m6502/apple1.watch"));
+        }
     }
 }
\ No newline at end of file
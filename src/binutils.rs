@@ -4,34 +4,165 @@ use std::path::{Path, PathBuf};
 
 use clap::Parser;
 
+use crate::computer::config::{MachineConfig, DEFAULT_LOAD_ADDRESS};
+use crate::computer::devices::{Acia, Connection, ConnectionSpec, Via};
+use crate::computer::devices::connection::ChannelEndpoint;
+use crate::computer::machines::{apple1, ben_eater, Machine};
+pub use crate::computer::symbols::parse_address;
 use crate::computer::Computer;
+use crate::loader::{self, Format};
+use crate::scripting::Script;
 
-pub fn read_bytes_from_file(file_name: &Path) -> Vec<u8> {
-    std::fs::read(file_name).unwrap_or_else(|_| panic!(
-        "Was not able to load bytes from {}", file_name.display()
+pub fn read_bytes_from_file(file_name: &Path) -> Result<Vec<u8>, String> {
+    std::fs::read(file_name).map_err(|e| format!(
+        "Was not able to load bytes from {}: {}", file_name.display(), e
     ))
 }
 
 // Command line parser for all binaries
 #[derive(Parser, Clone)]
 pub struct Cli {
-    #[arg(short, long, default_value = "assembly/standard.rom")]
-    pub rom_file: PathBuf,
+    /// The machine to emulate
+    #[arg(short, long, value_enum, default_value_t)]
+    pub machine: Machine,
+    /// ROM image, defaults to the one belonging to the machine
+    #[arg(short, long)]
+    pub rom_file: Option<PathBuf>,
+    /// Machine description (TOML), instead of a ready made machine
+    #[arg(short, long, conflicts_with_all = ["machine", "rom_file"])]
+    pub config: Option<PathBuf>,
+    /// Program to load: raw binary, Intel HEX, S-records, .prg, .xex or .o65
     #[arg(short, long)]
     pub program_file: Option<PathBuf>,
+    /// Format of the program file, if it can't be told from its name or contents
+    #[arg(short, long, value_enum)]
+    pub format: Option<Format>,
+    /// Map a 6551 ACIA serial port at this address
+    #[arg(long, value_parser = parse_address)]
+    pub acia: Option<u16>,
+    /// Where the ACIA or terminal is connected to: stdio, console (c6502-tui only), pty or unix:<path>
+    #[arg(long, default_value = "stdio")]
+    pub serial: ConnectionSpec,
+    /// Map a 6522 VIA at this address
+    #[arg(long, value_parser = parse_address)]
+    pub via: Option<u16>,
+    /// Symbols to show: ld65 label file (-Ln), VICE label file or ld65 debug info (.dbg)
+    #[arg(short, long)]
+    pub symbols: Vec<PathBuf>,
+    /// Stop running at this symbol or address
+    #[arg(short, long)]
+    pub breakpoint: Vec<String>,
+    /// Map an exit port at this address: stop running when the program writes to it
+    #[arg(long, value_parser = parse_address)]
+    pub exit_port: Option<u16>,
+    /// Rhai script to run once the computer is built
+    #[arg(long)]
+    pub script: Option<PathBuf>,
+}
+
+// Open a connection, keeping the other end of a console
+fn open_connection(spec: &ConnectionSpec, console: &mut Option<ChannelEndpoint>) -> Result<Box<dyn Connection>, String> {
+    // Every serial device is connected as --serial says, and only one can have the console
+    let hint = if *spec == ConnectionSpec::Console && console.is_some() {
+        " (with more than one serial device, use --serial pty or unix:<path>)"
+    } else {
+        ""
+    };
+    spec.open_with_console(console).map_err(|e| format!(
+        "Was not able to open serial connection {}: {}{}", spec, e, hint
+    ))
 }
 
 // Default way to build a computer from command line arguments
-pub fn build_computer(cli: Cli) -> Computer {
-    let rom_data = read_bytes_from_file(&cli.rom_file);
-    let mut computer = Computer::new().with_rom(rom_data).build().unwrap();
+pub fn build_computer(cli: Cli) -> Result<Computer, String> {
+    let mut load_address = DEFAULT_LOAD_ADDRESS;
+    let mut entry_point = None;
+    let mut console = None;
+    let mut builder = if let Some(config_file) = &cli.config {
+        let config = MachineConfig::from_file(config_file)?;
+        load_address = config.load_address;
+        entry_point = config.entry_point;
+        config.builder()?
+    } else {
+        let rom_file = cli.rom_file.clone().unwrap_or_else(|| cli.machine.default_rom());
+        let rom_data = read_bytes_from_file(&rom_file)?;
+        match cli.machine {
+            Machine::Standard => Computer::new().with_rom(rom_data),
+            Machine::Apple1 => apple1::builder(rom_data, open_connection(&cli.serial, &mut console)?),
+            Machine::BenEater => ben_eater::builder(rom_data)?,
+        }
+    };
 
-    if cli.program_file.is_some() {
-        let program = read_bytes_from_file(&cli.program_file.unwrap());
-        computer.load_program(0x1000, &program);
+    if let Some(address) = cli.acia {
+        builder = builder.with_device(address, Box::new(Acia::new(open_connection(&cli.serial, &mut console)?)));
+    }
+    if let Some(address) = cli.via {
+        builder = builder.with_device(address, Box::new(Via::new()));
+    }
+    if let Some(address) = cli.exit_port {
+        builder = builder.with_exit_port(address);
+    }
+    if let Some(console) = console {
+        builder = builder.with_console(console);
     }
 
-    computer
+    let mut computer = builder.build()?;
+
+    for symbol_file in &cli.symbols {
+        computer.load_symbols(symbol_file)?;
+    }
+    for location in &cli.breakpoint {
+        computer.add_breakpoint_at(location)?;
+    }
+
+    if let Some(program_file) = cli.program_file {
+        let program = loader::load_file(&program_file, cli.format, load_address)?;
+        for segment in &program.segments {
+            computer.write_memory(segment.address, &segment.data);
+        }
+        // The address in the file comes first, then the one for raw binaries
+        let run_address = program.run_address
+            .or(entry_point)
+            .unwrap_or(load_address);
+        computer.set_program_counter(run_address);
+    }
+
+    Ok(computer)
+}
+
+// Load and run the script from the command line, if there is one
+pub fn run_script(cli: &Cli, computer: &mut Computer) -> Result<Option<Script>, String> {
+    let Some(file_name) = cli.script.as_ref() else {
+        return Ok(None);
+    };
+    let mut script = Script::from_file(file_name)?;
+    script.run(computer).map_err(|e| format!("{}: {}", file_name.display(), e))?;
+    Ok(Some(script))
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn address_parsing() {
+        assert_eq!(parse_address("$d010"), Ok(0xd010));
+        assert_eq!(parse_address("0x8000"), Ok(0x8000));
+        assert_eq!(parse_address("FFFC"), Ok(0xfffc));
+        assert!(parse_address("10000").is_err());
+        assert!(parse_address("$").is_err());
+        assert!(parse_address("x12").is_err());
+    }
 
+    #[test]
+    fn console_used_twice() {
+        let rom_file = std::env::temp_dir().join(format!("m6502-test-{}-console.rom", std::process::id()));
+        std::fs::write(&rom_file, [0xea; 0x100]).unwrap();
+        let cli = Cli::parse_from([
+            "test", "-m", "apple1", "-r", rom_file.to_str().unwrap(), "--acia", "c000", "--serial", "console",
+        ]);
+        let result = build_computer(cli);
+        std::fs::remove_file(&rom_file).unwrap();
+        assert!(result.unwrap_err().contains("console is already in use"));
+    }
+}
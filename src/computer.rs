@@ -1,21 +1,49 @@
 pub mod cpu;
 pub mod clock;
 pub mod bus;
+pub mod config;
+pub mod debug_info;
+pub mod devices;
 mod inspect;
+pub mod machines;
+mod interrupts;
+mod state;
+pub mod symbols;
 
 use cpu::Cpu;
-use bus::{Bus, Ram};
+use cpu::inspect::CpuState;
+use cpu::instruction::{decode_instruction, Instruction};
+use bus::{Access, AccessKind, Addressable, Bus, Ram, MAX_MEMORY_SIZE};
 use clock::{Clock, TickCount};
+use devices::{ExitPort, Lcd};
+use devices::connection::ChannelEndpoint;
+use interrupts::InterruptLines;
+use debug_info::{DebugInfo, Segment, SourceLocation};
+use symbols::SymbolTable;
+pub use inspect::{StackEntry, StackItem};
 
 use log::info;
-use std::{fmt::Write, path::PathBuf};
+use std::{collections::BTreeSet, fmt::Write, path::{Path, PathBuf}};
 
 const DEFAULT_CLOCK_SPEED: u32 = 1_000_000; // 1 MHz
+// Give up stepping to the next source line, or out of a subroutine, after this many instructions
+const MAX_LINE_STEPS: usize = 1_000_000;
+
+// Memory mapped next to the RAM at 0 and the ROM at the top
+enum MemoryBlock {
+    Ram(Vec<u8>),
+    Rom(Vec<u8>),
+}
 
 pub struct ComputerBuilder {
     clock: Clock,
     rom: Vec<u8>,
     memory_size: usize,
+    extra_memory: Vec<(u16, MemoryBlock)>,
+    devices: Vec<(u16, Box<dyn Addressable>)>,
+    console: Option<ChannelEndpoint>,
+    exit_port: Option<ExitPort>,
+    run_rom_initialisation: bool,
 }
 
 impl Default for ComputerBuilder {
@@ -24,6 +52,11 @@ impl Default for ComputerBuilder {
             clock: Clock::default(),
             rom: Vec::new(),
             memory_size: 0x10000,
+            extra_memory: Vec::new(),
+            devices: Vec::new(),
+            console: None,
+            exit_port: None,
+            run_rom_initialisation: true,
         }
     }
 }
@@ -51,24 +84,97 @@ impl ComputerBuilder {
         Ok(self)
     }
 
+    // A memory size of 0 leaves out the RAM starting at address 0
     pub fn with_memory_size(mut self, memory_size: usize) -> Self {
         self.memory_size = memory_size;
         self
     }
 
+    // Add a block of RAM at the given address. Later blocks take precedence
+    // over earlier ones, and over the RAM at 0 and the ROM at the top.
+    pub fn with_ram(self, address: u16, size: usize) -> Self {
+        self.with_ram_contents(address, vec![0; size])
+    }
+
+    // Add a block of RAM with initial contents
+    pub fn with_ram_contents(mut self, address: u16, data: Vec<u8>) -> Self {
+        self.extra_memory.push((address, MemoryBlock::Ram(data)));
+        self
+    }
+
+    // Add a ROM at the given address, instead of at the top of memory
+    pub fn with_rom_at(mut self, address: u16, data: Vec<u8>) -> Self {
+        self.extra_memory.push((address, MemoryBlock::Rom(data)));
+        self
+    }
+
+    // Don't run the ROM until BRK when building. Needed for ROMs that never
+    // give control back, like a monitor waiting for keyboard input.
+    pub fn without_rom_initialisation(mut self) -> Self {
+        self.run_rom_initialisation = false;
+        self
+    }
+
+    // Map a device on the bus at the given address. Devices are mapped after
+    // RAM and ROM, so they take precedence over any memory at the same address.
+    pub fn with_device(mut self, address: u16, device: Box<dyn Addressable>) -> Self {
+        self.devices.push((address, device));
+        self
+    }
+
+    // Make the other end of a console connection available, for a UI to show.
+    // See ConnectionSpec::open_with_console().
+    pub fn with_console(mut self, console: ChannelEndpoint) -> Self {
+        self.console = Some(console);
+        self
+    }
+
+    // Map an exit port at the given address. Running stops when the
+    // program writes to it.
+    pub fn with_exit_port(mut self, address: u16) -> Self {
+        let exit_port = ExitPort::new();
+        self.devices.push((address, Box::new(exit_port.clone())));
+        self.exit_port = Some(exit_port);
+        self
+    }
+
     pub fn build(self) -> Result<Computer, String> {
         // Do some sanity checks
-        if self.rom.len() < 0x100 {
+        let has_rom_blocks = self.extra_memory.iter()
+            .any(|(_, block)| matches!(block, MemoryBlock::Rom(_)));
+        if (self.rom.is_empty() && !has_rom_blocks) || (!self.rom.is_empty() && self.rom.len() < 0x100) {
             return Err("ROM is too small or not set".to_string());
         }
 
-        // Create memory
-        let memory = Ram::new(self.memory_size);
+        // The ROM at the top of memory, which can be replaced later
+        let rom_size = match self.rom.len() {
+            0 => self.extra_memory.iter()
+                .filter_map(|(address, block)| match block {
+                    MemoryBlock::Rom(data) if *address as usize + data.len() == MAX_MEMORY_SIZE => Some(data.len()),
+                    _ => None,
+                })
+                .next_back()
+                .unwrap_or(0),
+            size => size,
+        };
 
         // Build the bus
-        let bus = Bus::new()
-            .add_ram(memory, 0x0)?
-            .add_rom_at_end(&self.rom)?;
+        let mut bus = Bus::new();
+        if self.memory_size > 0 {
+            bus = bus.add_ram(Ram::new(self.memory_size), 0x0)?;
+        }
+        if !self.rom.is_empty() {
+            bus = bus.add_rom_at_end(&self.rom)?;
+        }
+        for (address, block) in self.extra_memory {
+            bus = match block {
+                MemoryBlock::Ram(data) => bus.add_ram(Ram::from(&data), address)?,
+                MemoryBlock::Rom(data) => bus.add_rom(&data, address)?,
+            };
+        }
+        for (address, device) in self.devices {
+            bus = bus.add_device(device, address)?;
+        }
 
         // Build the Cpu
         let cpu = Cpu::new(bus);
@@ -80,10 +186,22 @@ impl ComputerBuilder {
         let mut computer = Computer {
             cpu,
             clock: self.clock,
+            interrupts: InterruptLines::default(),
+            halted: false,
+            rom_size,
+            console: self.console,
+            exit_port: self.exit_port,
+            symbols: SymbolTable::new(),
+            debug_info: Vec::new(),
+            breakpoints: BTreeSet::new(),
+            temporary_breakpoint: None,
+            pending_stops: Vec::new(),
         };
 
         // TODO This is needed to run the ROM initialisation. Can be removed in the future
-        computer.run();
+        if self.run_rom_initialisation {
+            computer.run();
+        }
 
         Ok(computer)
     }
@@ -94,6 +212,33 @@ pub struct Computer {
     cpu: Cpu,
     //bus: Rc<dyn Addressable>,
     clock: Clock,
+    interrupts: InterruptLines,
+    halted: bool,
+    rom_size: usize,
+    console: Option<ChannelEndpoint>,
+    exit_port: Option<ExitPort>,
+    symbols: SymbolTable,
+    debug_info: Vec<DebugInfo>,
+    breakpoints: BTreeSet<u16>,
+    // Only stops once, and is gone whenever running stops
+    temporary_breakpoint: Option<u16>,
+    // More reasons to stop for the last instruction, last one first
+    pending_stops: Vec<Stop>,
+}
+
+// Why the computer stopped running
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stop {
+    Halted,
+    Breakpoint(u16),
+    // The program wrote this value to the exit port
+    Exit(u8),
+    // The program accessed a watched address
+    Watchpoint(Access),
+    // Done with what was asked, like stepping a line
+    Step,
+    // Stopped by the user
+    Paused,
 }
 
 impl Computer {
@@ -103,20 +248,342 @@ impl Computer {
     }
 }
 
+// An empty computer, with nothing on the bus. It runs into unmapped memory
+// straight away, so it's only good for taking another computer's place.
+impl Default for Computer {
+    fn default() -> Self {
+        Self {
+            cpu: Cpu::new(Bus::new()),
+            clock: Clock::default(),
+            interrupts: InterruptLines::default(),
+            halted: false,
+            rom_size: 0,
+            console: None,
+            exit_port: None,
+            symbols: SymbolTable::new(),
+            debug_info: Vec::new(),
+            breakpoints: BTreeSet::new(),
+            temporary_breakpoint: None,
+            pending_stops: Vec::new(),
+        }
+    }
+}
+
 impl Computer {
-    pub fn run(&mut self) {
+    // Run until the CPU halts or reaches a breakpoint
+    pub fn run(&mut self) -> Stop {
+        if let Some(stop) = self.pending_stops.pop() {
+            return stop;
+        }
         let mut number_of_ticks: TickCount = 1;
         loop {
             self.clock.wait_for_tick(number_of_ticks);
-            match self.cpu.fetch_and_execute() {
+            match self.step() {
                 Some(n) => number_of_ticks = n,
-                None => break,
+                None => return Stop::Halted,
+            }
+            if let Some(stop) = self.check_stop() {
+                return stop;
+            }
+        }
+    }
+
+    // Run for at least the given number of clock cycles, without waiting for the clock.
+    // Returns the number of cycles used, or why it stopped early.
+    pub fn run_cycles(&mut self, cycles: u32) -> Result<u32, Stop> {
+        if let Some(stop) = self.pending_stops.pop() {
+            return Err(stop);
+        }
+        let mut used = 0;
+        while used < cycles {
+            used += self.step().ok_or(Stop::Halted)? as u32;
+            if let Some(stop) = self.check_stop() {
+                return Err(stop);
+            }
+        }
+        Ok(used)
+    }
+
+    // Execute instructions until the program counter reaches a different source
+    // line. Code without source, like a ROM routine that is called, is run through.
+    // Returns the number of cycles used, or why it stopped early.
+    pub fn step_line(&mut self) -> Result<u32, Stop> {
+        let start = self.source_location(self.cpu.get_state().program_counter);
+        self.run_until(|computer, _| {
+            let location = computer.source_location(computer.cpu.get_state().program_counter);
+            location.is_some() && location != start
+        })
+    }
+
+    // Execute an instruction, or a whole subroutine for JSR.
+    // Returns the number of cycles used, or why it stopped early.
+    pub fn step_over(&mut self) -> Result<u32, Stop> {
+        let state = self.cpu.get_state();
+        // Peek, so that I/O registers the program counter points at are left alone
+        let opcode = self.cpu.bus.peek(state.program_counter);
+        if !matches!(opcode.and_then(decode_instruction), Some((Instruction::JSR, _, _))) {
+            return self.run_until(|_, _| true);
+        }
+        // Recursive calls come back to the same address, but deeper in the stack
+        let return_address = state.program_counter.wrapping_add(3);
+        self.run_until(|computer, _| {
+            let now = computer.cpu.get_state();
+            now.program_counter == return_address && now.stack_pointer >= state.stack_pointer
+        })
+    }
+
+    // Run until the current subroutine returns.
+    // Returns the number of cycles used, or why it stopped early.
+    pub fn step_out(&mut self) -> Result<u32, Stop> {
+        let stack_pointer = self.cpu.get_state().stack_pointer;
+        self.run_until(|computer, opcode| {
+            matches!(opcode.and_then(decode_instruction), Some((Instruction::RTS | Instruction::RTI, _, _)))
+                && computer.cpu.get_state().stack_pointer > stack_pointer
+        })
+    }
+
+    // Execute instructions until done says so, given the opcode that was executed last.
+    // The opcode is peeked at, which gives None for unmapped memory.
+    fn run_until(&mut self, done: impl Fn(&Self, Option<u8>) -> bool) -> Result<u32, Stop> {
+        if let Some(stop) = self.pending_stops.pop() {
+            return Err(stop);
+        }
+        let mut used = 0;
+        for _ in 0..MAX_LINE_STEPS {
+            let opcode = self.cpu.bus.peek(self.cpu.get_state().program_counter);
+            used += self.step().ok_or(Stop::Halted)? as u32;
+            if let Some(stop) = self.check_stop() {
+                return Err(stop);
+            }
+            if done(self, opcode) {
+                break;
+            }
+        }
+        Ok(used)
+    }
+
+    // Breakpoints are checked after each instruction, so running again from
+    // a breakpoint executes the instruction there. An instruction can be a
+    // reason to stop more than once, like writing to a watched address and
+    // ending up at a breakpoint: running again reports the next reason.
+    fn check_stop(&mut self) -> Option<Stop> {
+        let mut stops = Vec::new();
+        if let Some(value) = self.exit_port.as_ref().and_then(ExitPort::take) {
+            info!("Exit with ${:02x}", value);
+            stops.push(Stop::Exit(value));
+        }
+        for access in self.cpu.bus.take_accesses() {
+            info!("{:?} of ${:02x} at {}", access.kind, access.value, self.address_to_string(access.address));
+            stops.push(Stop::Watchpoint(access));
+        }
+        let address = self.cpu.get_state().program_counter;
+        if self.breakpoints.contains(&address) || self.temporary_breakpoint == Some(address) {
+            info!("Breakpoint at {}", self.address_to_string(address));
+            stops.push(Stop::Breakpoint(address));
+        }
+        if !stops.is_empty() {
+            self.temporary_breakpoint = None;
+        }
+        self.pending_stops = stops.split_off(stops.len().min(1));
+        self.pending_stops.reverse();
+        stops.pop()
+    }
+
+    // Execute a single instruction, and take any interrupt that is pending at the end of it.
+    // Returns the number of clock cycles used, or None if the CPU halted.
+    pub fn step(&mut self) -> Option<TickCount> {
+        // Only accesses by the CPU count, not those by debuggers looking at memory
+        self.cpu.bus.take_accesses();
+        self.pending_stops.clear();
+        let Some(cycles) = self.cpu.fetch_and_execute() else {
+            self.halted = true;
+            return None;
+        };
+        self.cpu.bus.tick(cycles);
+
+        // The 6502 samples its interrupt lines at the end of each instruction
+        let (irq, nmi) = self.interrupts.poll(&self.cpu.bus);
+        match self.cpu.service_interrupts(irq, nmi) {
+            Some(interrupt_cycles) => {
+                self.cpu.bus.tick(interrupt_cycles);
+                Some(cycles + interrupt_cycles)
             }
+            None => Some(cycles),
         }
     }
 
+    // Hold (true) or release (false) the IRQ line
+    pub fn set_irq(&mut self, level: bool) {
+        self.interrupts.set_irq(level);
+    }
+
+    // Hold (true) or release (false) the NMI line. Only the transition to
+    // held causes an interrupt.
+    pub fn set_nmi(&mut self, level: bool) {
+        self.interrupts.set_nmi(level);
+    }
+
+    // Pulse the NMI line, causing a single interrupt
+    pub fn trigger_nmi(&mut self) {
+        self.interrupts.trigger_nmi();
+    }
+
     pub fn load_program(&mut self, address: u16, program: &[u8]) {
         self.cpu.load_program(address, program);
+        self.pending_stops.clear();
+        self.halted = false;
+    }
+
+    // Write to memory, without touching the CPU
+    pub fn write_memory(&mut self, address: u16, data: &[u8]) {
+        self.cpu.bus.write_bytes(address, data);
+    }
+
+    // Assemble a line of source and write it to memory, using the known symbols.
+    // Returns the number of bytes written.
+    pub fn assemble_at(&mut self, address: u16, line: &str) -> Result<usize, String> {
+        let bytes = crate::assembler::Assembler::new()
+            .with_symbols(&self.symbols)
+            .assemble_at(address, line)?;
+        self.write_memory(address, &bytes);
+        Ok(bytes.len())
+    }
+
+    // Put another ROM at the end of memory, and start it from its reset vector
+    pub fn load_rom(&mut self, rom: &[u8]) -> Result<(), String> {
+        if rom.is_empty() || rom.len() > self.rom_size {
+            return Err(format!("A ROM of {} bytes doesn't fit in the {} bytes of ROM", rom.len(), self.rom_size));
+        }
+        self.write_memory((MAX_MEMORY_SIZE - rom.len()) as u16, rom);
+        self.reset();
+        Ok(())
+    }
+
+    // Start again from the reset vector. Memory and devices are left as they are.
+    pub fn reset(&mut self) {
+        self.cpu.reset();
+        self.interrupts = InterruptLines::default();
+        self.halted = false;
+        self.temporary_breakpoint = None;
+        self.pending_stops.clear();
+    }
+
+    // Change the registers, as for a debugger
+    pub fn set_cpu_state(&mut self, state: &CpuState) {
+        self.cpu.set_state(state);
+        self.pending_stops.clear();
+        self.halted = false;
+    }
+
+    // Continue execution at the given address
+    pub fn set_program_counter(&mut self, address: u16) {
+        self.cpu.set_program_counter(address);
+        self.pending_stops.clear();
+        self.halted = false;
+    }
+
+    // Whether the CPU stopped, and there is nothing left to run
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
+
+    pub fn clock_speed(&self) -> u32 {
+        self.clock.speed()
+    }
+
+    // The LCD connected to one of the devices, if there is one
+    pub fn lcd(&self) -> Option<Lcd> {
+        self.cpu.bus.display()
+    }
+
+    pub fn console(&self) -> Option<&ChannelEndpoint> {
+        self.console.as_ref()
+    }
+
+    pub fn symbols(&self) -> &SymbolTable {
+        &self.symbols
+    }
+
+    pub fn symbols_mut(&mut self) -> &mut SymbolTable {
+        &mut self.symbols
+    }
+
+    // Add the symbols from a label or debug info file. Debug info also
+    // provides the source lines, segments and scopes for its addresses.
+    pub fn load_symbols(&mut self, file_name: &Path) -> Result<usize, String> {
+        let count = self.symbols.load_file(file_name)?;
+        if file_name.extension().is_some_and(|e| e == "dbg") {
+            self.debug_info.push(DebugInfo::from_file(file_name)?);
+        }
+        Ok(count)
+    }
+
+    pub fn add_debug_info(&mut self, debug_info: DebugInfo) {
+        self.debug_info.push(debug_info);
+    }
+
+    // The source line that generated the code or data at an address
+    pub fn source_location(&self, address: u16) -> Option<SourceLocation> {
+        self.debug_info.iter().find_map(|info| info.location_for(address))
+    }
+
+    // The source lines around the one for an address, with their line numbers
+    pub fn source_lines(&self, address: u16, before: usize, after: usize) -> Vec<(usize, String)> {
+        self.debug_info.iter()
+            .find_map(|info| {
+                let location = info.location_for(address)?;
+                let lines = info.source_lines(&location, before, after);
+                Some(lines.into_iter().map(|(n, line)| (n, line.to_string())).collect())
+            })
+            .unwrap_or_default()
+    }
+
+    pub fn segment_for(&self, address: u16) -> Option<&Segment> {
+        self.debug_info.iter().find_map(|info| info.segment_for(address))
+    }
+
+    pub fn scope_for(&self, address: u16) -> Option<String> {
+        self.debug_info.iter().find_map(|info| info.scope_for(address))
+    }
+
+    pub fn add_breakpoint(&mut self, address: u16) {
+        self.breakpoints.insert(address);
+    }
+
+    // Add a breakpoint by symbol, or by address. Returns the address.
+    pub fn add_breakpoint_at(&mut self, location: &str) -> Result<u16, String> {
+        let address = self.symbols.resolve(location)?;
+        self.add_breakpoint(address);
+        Ok(address)
+    }
+
+    // Returns whether there was a breakpoint at the address
+    pub fn remove_breakpoint(&mut self, address: u16) -> bool {
+        self.breakpoints.remove(&address)
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = u16> + '_ {
+        self.breakpoints.iter().copied()
+    }
+
+    // Stop at the address once, as for running to a cursor. The breakpoint is
+    // gone as soon as running stops, for whatever reason.
+    pub fn set_temporary_breakpoint(&mut self, address: u16) {
+        self.temporary_breakpoint = Some(address);
+    }
+
+    // Stop running after an instruction that reads or writes the address
+    pub fn add_watchpoint(&mut self, address: u16, kind: AccessKind) {
+        self.cpu.bus.add_watchpoint(address, kind);
+    }
+
+    // Returns whether there was a watchpoint for the address
+    pub fn remove_watchpoint(&mut self, address: u16, kind: AccessKind) -> bool {
+        self.cpu.bus.remove_watchpoint(address, kind)
+    }
+
+    pub fn watchpoints(&self) -> impl Iterator<Item = (u16, AccessKind)> + '_ {
+        self.cpu.bus.watchpoints()
     }
 
     // Formatting/Display functions
@@ -142,18 +609,16 @@ impl Computer {
 
 #[cfg(test)]
 mod tests {
-    use std::{path::Path, process::{Command, Stdio}};
-    use log::{debug, info};
+    use std::path::Path;
+    use log::debug;
 
     use test_log::test;
     use test_case::test_case;
-    use std::sync::Once;
 
     use clock::Clock;
 
-    static MAKE_ASSEMBLY: Once = Once::new();
-
     use super::*;
+    use crate::assembler::Assembler;
 
     #[test]
     fn construction() {
@@ -173,6 +638,7 @@ mod tests {
     #[test_case("add_with_carry"; "add with carry")]
     #[test_case("comparison"; "comparison instructions")]
     #[test_case("other"; "other instructions")]
+    #[test_case("interrupts"; "hardware interrupts")]
     fn assembly(test_name: &str) {
         // Set up
         let _ = env_logger::builder()
@@ -180,11 +646,10 @@ mod tests {
             .format_timestamp(None)
             .format_target(false)
             .try_init();
-        MAKE_ASSEMBLY.call_once(build_assembly);
 
         // Do the work
         let mut computer = create_test_computer();
-        let file_name = format!("assembly/{}.test", test_name);
+        let file_name = format!("assembly/{}.test.s", test_name);
         let program = read_program(file_name.as_str());
         // NOTE: See assembly/test.cfg for this value
         let start_address = 0x1000;
@@ -193,36 +658,434 @@ mod tests {
         computer.run();
     }
 
+    // A minimal ROM: reset enables interrupts and halts with BRK. The IRQ
+    // handler increments $10, the NMI handler increments $11.
+    fn interrupt_test_rom() -> Vec<u8> {
+        let mut rom = vec![0xea; 0x100];
+        let code: [(usize, &[u8]); 4] = [
+            (0x00, &[0xa2, 0xff, 0x9a, 0x58, 0x00]), // LDX #$ff, TXS, CLI, BRK
+            (0x10, &[0xe6, 0x10, 0x40]),             // irq: INC $10, RTI
+            (0x20, &[0xe6, 0x11, 0x40]),             // nmi: INC $11, RTI
+            (0xfa, &[0x20, 0xff, 0x00, 0xff, 0x10, 0xff]), // vectors
+        ];
+        for (offset, bytes) in code {
+            rom[offset..offset + bytes.len()].copy_from_slice(bytes);
+        }
+        rom
+    }
+
+    fn create_interrupt_test_computer() -> Computer {
+        let mut computer = Computer::new()
+            .with_rom(interrupt_test_rom())
+            .with_clock(Clock::new(clock::ClockMode::Speedy))
+            .build()
+            .unwrap();
+        // A row of NOPs to execute
+        computer.load_program(0x1000, &[0xea; 0x10]);
+        computer
+    }
+
+    #[test]
+    fn irq_line() {
+        let mut computer = create_interrupt_test_computer();
+
+        computer.step();
+        assert_eq!(computer.get_cpu_state().program_counter, 0x1001);
+
+        // The interrupt is taken at the end of the next instruction
+        computer.set_irq(true);
+        assert_eq!(computer.step(), Some(2 + 7));
+        assert_eq!(computer.get_cpu_state().program_counter, 0xff10);
+        assert!(computer.get_cpu_state().status.irq_disable);
+
+        // Run the handler. The line is still held, but interrupts are disabled
+        computer.step();
+        assert_eq!(computer.cpu.bus.read_byte(0x0010), 1);
+        // Acknowledge the interrupt before returning from it
+        computer.set_irq(false);
+        computer.step();
+        assert_eq!(computer.get_cpu_state().program_counter, 0x1002);
+        assert!(!computer.get_cpu_state().status.irq_disable);
+
+        computer.step();
+        assert_eq!(computer.get_cpu_state().program_counter, 0x1003);
+        assert_eq!(computer.cpu.bus.read_byte(0x0010), 1);
+    }
+
+    #[test]
+    fn irq_line_is_level_triggered() {
+        let mut computer = create_interrupt_test_computer();
+
+        // If the line is not released, the handler is entered again after RTI
+        computer.set_irq(true);
+        for _ in 0..3 {
+            computer.step(); // NOP or RTI, followed by the interrupt
+            assert_eq!(computer.get_cpu_state().program_counter, 0xff10);
+            computer.step(); // INC
+        }
+        assert_eq!(computer.cpu.bus.read_byte(0x0010), 3);
+    }
+
+    #[test]
+    fn nmi_line() {
+        let mut computer = create_interrupt_test_computer();
+        // SEI, followed by NOPs
+        computer.load_program(0x1000, &[0x78, 0xea, 0xea, 0xea, 0xea, 0xea, 0xea, 0xea]);
+        computer.step();
+
+        // NMI can not be masked, and has priority over IRQ
+        computer.set_irq(true);
+        computer.set_nmi(true);
+        computer.step();
+        assert_eq!(computer.get_cpu_state().program_counter, 0xff20);
+        computer.step();
+        computer.step();
+        assert_eq!(computer.get_cpu_state().program_counter, 0x1002);
+        assert_eq!(computer.cpu.bus.read_byte(0x0011), 1);
+
+        // The line is still held, which must not cause another interrupt
+        computer.step();
+        assert_eq!(computer.get_cpu_state().program_counter, 0x1003);
+
+        // But a new edge does
+        computer.set_nmi(false);
+        computer.step();
+        computer.set_nmi(true);
+        computer.step();
+        assert_eq!(computer.get_cpu_state().program_counter, 0xff20);
+        computer.step();
+        computer.step();
+        assert_eq!(computer.get_cpu_state().program_counter, 0x1005);
+
+        // As does a pulse
+        computer.set_nmi(false);
+        computer.trigger_nmi();
+        computer.step();
+        assert_eq!(computer.get_cpu_state().program_counter, 0xff20);
+        computer.step();
+        computer.step();
+        assert_eq!(computer.get_cpu_state().program_counter, 0x1006);
+
+        assert_eq!(computer.cpu.bus.read_byte(0x0011), 3);
+        assert_eq!(computer.cpu.bus.read_byte(0x0010), 0);
+    }
+
+    #[test]
+    fn breakpoints() {
+        let mut computer = create_interrupt_test_computer();
+        computer.symbols_mut().add("loop", 0x1004);
+        computer.add_breakpoint(0x1002);
+        assert_eq!(computer.add_breakpoint_at("loop"), Ok(0x1004));
+        assert!(computer.add_breakpoint_at("nowhere").is_err());
+
+        assert_eq!(computer.run_cycles(100), Err(Stop::Breakpoint(0x1002)));
+        assert_eq!(computer.get_cpu_state().program_counter, 0x1002);
+        // Continuing runs the instruction at the breakpoint
+        assert_eq!(computer.run(), Stop::Breakpoint(0x1004));
+
+        assert!(computer.remove_breakpoint(0x1004));
+        assert!(!computer.remove_breakpoint(0x1004));
+        assert_eq!(computer.breakpoints().collect::<Vec<_>>(), vec![0x1002]);
+        assert_eq!(computer.run_cycles(10), Ok(10));
+    }
+
+    #[test]
+    fn exit_port() {
+        let mut computer = Computer::new()
+            .with_rom(interrupt_test_rom())
+            .with_clock(Clock::new(clock::ClockMode::Speedy))
+            .with_exit_port(0xf000)
+            .build()
+            .unwrap();
+        // NOP, LDA #$2a, STA $f000, NOP
+        computer.load_program(0x1000, &[0xea, 0xa9, 0x2a, 0x8d, 0x00, 0xf0, 0xea]);
+        assert_eq!(computer.run_cycles(100), Err(Stop::Exit(0x2a)));
+        assert_eq!(computer.get_cpu_state().program_counter, 0x1006);
+        assert_eq!(computer.read_memory(0x1001, 2), vec![0xa9, 0x2a]);
+    }
+
+    #[test]
+    fn watchpoints() {
+        let mut computer = create_interrupt_test_computer();
+        // LDA $10, STA $11, NOP
+        computer.load_program(0x1000, &[0xa5, 0x10, 0x85, 0x11, 0xea]);
+        computer.add_watchpoint(0x0010, AccessKind::Read);
+        computer.add_watchpoint(0x0011, AccessKind::Write);
+        computer.add_breakpoint(0x1004);
+        // Looking at memory doesn't count
+        computer.read_memory(0x0010, 2);
+
+        let access = |kind, address| Access { kind, address, value: 0 };
+        assert_eq!(computer.run(), Stop::Watchpoint(access(AccessKind::Read, 0x0010)));
+        assert_eq!(computer.run(), Stop::Watchpoint(access(AccessKind::Write, 0x0011)));
+        // The write and the breakpoint are for the same instruction
+        assert_eq!(computer.run(), Stop::Breakpoint(0x1004));
+        assert!(computer.remove_watchpoint(0x0011, AccessKind::Write));
+        assert_eq!(computer.watchpoints().collect::<Vec<_>>(), vec![(0x0010, AccessKind::Read)]);
+    }
+
+    #[test]
+    fn step_over_and_out() {
+        let mut computer = create_interrupt_test_computer();
+        // JSR sub, NOP; sub: NOP, JSR inner, RTS; inner: RTS
+        computer.load_program(0x1000, &[0x20, 0x10, 0x10, 0xea]);
+        computer.write_memory(0x1010, &[0xea, 0x20, 0x20, 0x10, 0x60]);
+        computer.write_memory(0x1020, &[0x60]);
+        let pc = |computer: &Computer| computer.get_cpu_state().program_counter;
+
+        // JSR, NOP, JSR, RTS and RTS
+        assert_eq!(computer.step_over(), Ok(26));
+        assert_eq!(pc(&computer), 0x1003);
+        assert!(computer.step_over().is_ok());
+        assert_eq!(pc(&computer), 0x1004);
+
+        computer.set_program_counter(0x1000);
+        computer.step();
+        computer.step();
+        assert_eq!(pc(&computer), 0x1011);
+        // Stepping out runs through the inner subroutine
+        assert!(computer.step_out().is_ok());
+        assert_eq!(pc(&computer), 0x1003);
+
+        // Breakpoints still stop stepping
+        computer.set_program_counter(0x1000);
+        computer.add_breakpoint(0x1020);
+        assert_eq!(computer.step_over(), Err(Stop::Breakpoint(0x1020)));
+    }
+
+    // A device full of NOPs, counting how often it is read
+    #[derive(Debug, Default)]
+    struct CountingDevice {
+        reads: std::rc::Rc<std::cell::Cell<u32>>,
+    }
+
+    impl Addressable for CountingDevice {
+        fn size(&self) -> usize {
+            0x10
+        }
+
+        fn read_byte(&self, _address: u16) -> u8 {
+            self.reads.set(self.reads.get() + 1);
+            0xea
+        }
+
+        fn peek(&self, _address: u16) -> Option<u8> {
+            Some(0xea)
+        }
+
+        fn write_byte(&mut self, _address: u16, _byte: u8) {}
+    }
+
+    #[test]
+    fn stepping_through_io() {
+        let device = CountingDevice::default();
+        let reads = device.reads.clone();
+        let mut computer = Computer::new()
+            .with_rom(vec![0xea; 0x100])
+            .with_device(0xd000, Box::new(device))
+            .without_rom_initialisation()
+            .build()
+            .unwrap();
+
+        // Only the CPU reads the opcode, as it would without a debugger
+        computer.set_program_counter(0xd000);
+        computer.step();
+        let cpu_reads = reads.get();
+        computer.set_program_counter(0xd000);
+        reads.set(0);
+        assert!(computer.step_over().is_ok());
+        assert_eq!(reads.get(), cpu_reads);
+    }
+
+    #[test]
+    fn temporary_breakpoint_and_reset() {
+        let mut computer = create_interrupt_test_computer();
+        computer.set_temporary_breakpoint(0x1004);
+        assert_eq!(computer.run(), Stop::Breakpoint(0x1004));
+        // It's gone once it stopped
+        assert_eq!(computer.run_cycles(10), Ok(10));
+
+        computer.reset();
+        let state = computer.get_cpu_state();
+        assert_eq!(state.program_counter, 0xff00);
+        assert!(state.status.irq_disable);
+        assert!(!computer.is_halted());
+    }
+
+    #[test]
+    fn step_by_source_line() {
+        let mut computer = create_interrupt_test_computer();
+        // Line 1 is one NOP, line 2 two of them, line 3 jumps to code without source
+        computer.load_program(0x1000, &[0xea, 0xea, 0xea, 0x4c, 0x20, 0x10]);
+        computer.write_memory(0x1020, &[0xea, 0x4c, 0x06, 0x10]);
+        computer.add_debug_info(debug_info::DebugInfo::parse("file\tid=0,name=\"test.s\"\n\
+            seg\tid=0,name=\"CODE\",start=0x1000,size=7\n\
+            span\tid=0,seg=0,start=0,size=1\n\
+            span\tid=1,seg=0,start=1,size=2\n\
+            span\tid=2,seg=0,start=3,size=3\n\
+            span\tid=3,seg=0,start=6,size=1\n\
+            line\tid=0,file=0,line=1,span=0\n\
+            line\tid=1,file=0,line=2,span=1\n\
+            line\tid=2,file=0,line=3,span=2\n\
+            line\tid=3,file=0,line=4,span=3\n").unwrap());
+
+        let line = |computer: &Computer| computer.source_location(computer.get_cpu_state().program_counter).map(|l| l.line);
+        assert_eq!(line(&computer), Some(1));
+        assert_eq!(computer.step_line(), Ok(2));
+        assert_eq!(line(&computer), Some(2));
+        assert_eq!(computer.step_line(), Ok(4));
+        assert_eq!(line(&computer), Some(3));
+        // Runs through the code without source
+        assert_eq!(computer.step_line(), Ok(3 + 2 + 3));
+        assert_eq!(line(&computer), Some(4));
+        assert_eq!(computer.segment_for(0x1006).map(|s| s.name.as_str()), Some("CODE"));
+    }
+
+    #[test]
+    fn line_assembler() {
+        let mut computer = create_interrupt_test_computer();
+        computer.symbols_mut().add("counter", 0x0010);
+        assert_eq!(computer.assemble_at(0x1000, "INC counter"), Ok(2));
+        assert_eq!(computer.assemble_at(0x1002, "JMP $1000"), Ok(3));
+        assert!(computer.assemble_at(0x1005, "JMP nowhere").is_err());
+        assert_eq!(computer.disassemble(0x1000, 5).into_iter().map(|(_, s)| s).collect::<Vec<_>>(),
+            vec!["INC counter", "JMP $1000"]);
+
+        computer.run_cycles(16).unwrap();
+        assert_eq!(computer.cpu.bus.read_byte(0x0010), 2);
+    }
+
+    #[test]
+    fn symbolic_disassembly() {
+        let mut computer = create_interrupt_test_computer();
+        // JMP loop, LDA $10, BNE loop, STA $0200, X
+        computer.load_program(0x1000, &[0x4c, 0x07, 0x10, 0xa5, 0x10, 0xd0, 0xf9, 0x9d, 0x00, 0x02]);
+        computer.symbols_mut().add("counter", 0x0010);
+        computer.symbols_mut().add("loop", 0x1000);
+
+        let lines: Vec<String> = computer.disassemble(0x1000, 10).into_iter().map(|(_, s)| s).collect();
+        assert_eq!(lines, vec!["JMP $1007", "LDA counter", "BNE loop", "STA $0200, X"]);
+        assert_eq!(computer.address_to_string(0x1000), "loop");
+        assert_eq!(computer.address_to_string(0x1001), "$1001");
+    }
+
+    #[test]
+    fn disassembly_lines() {
+        let mut computer = create_interrupt_test_computer();
+        // LDA #$01, unknown, STA $0200, INX
+        computer.load_program(0x1000, &[0xa9, 0x01, 0x02, 0x8d, 0x00, 0x02, 0xe8]);
+
+        let addresses: Vec<u16> = computer.disassemble_lines(0x1000, 4).into_iter().map(|(a, _)| a).collect();
+        assert_eq!(addresses, vec![0x1000, 0x1002, 0x1003, 0x1006]);
+        assert_eq!(computer.next_instruction(0x1003), 0x1006);
+        assert_eq!(computer.previous_instruction(0x1006), 0x1003);
+        assert_eq!(computer.previous_instruction(0x1002), 0x1000);
+        assert_eq!(computer.previous_instruction(0x0000), 0x0000);
+
+        // Disassembly stops at the end of memory
+        assert_eq!(computer.disassemble_lines(0xffff, 4).len(), 1);
+        assert_eq!(computer.next_instruction(0xffff), 0xffff);
+    }
+
+    #[test]
+    fn stack_contents() {
+        let mut computer = create_interrupt_test_computer();
+        // What JSR $1010 at $1000, then PHP and PHA leave behind
+        computer.load_program(0x1000, &[0x20, 0x10, 0x10]);
+        computer.write_memory(0x01fc, &[0x42, 0x32, 0x02, 0x10]);
+        let mut state = computer.get_cpu_state();
+        state.stack_pointer = 0xfb;
+        state.program_counter = 0x1014;
+        computer.set_cpu_state(&state);
+
+        let items: Vec<(u16, StackItem)> = computer.stack_entries().into_iter().map(|e| (e.address, e.item)).collect();
+        assert_eq!(items, vec![
+            (0x01fc, StackItem::Byte(0x42)),
+            (0x01fd, StackItem::Status(0x32)),
+            (0x01fe, StackItem::ReturnAddress { call: 0x1000 }),
+        ]);
+
+        // An interrupt frame on top of that, after a NOP
+        computer.write_memory(0x1014, &[0xea]);
+        computer.set_nmi(true);
+        computer.step();
+        let entry = computer.stack_entries()[0];
+        assert_eq!(entry.address, 0x01f9);
+        assert!(matches!(entry.item, StackItem::Interrupt { return_address: 0x1015, .. }));
+    }
+
+    #[test]
+    fn replacing_the_rom() {
+        let mut computer = create_test_computer();
+        // A page of numbered bytes, resetting to $ff42
+        let mut rom: Vec<u8> = (0..=0xff).collect();
+        rom[0xfc..].copy_from_slice(&[0x42, 0xff, 0x00, 0xff]);
+        computer.load_rom(&rom).unwrap();
+        assert_eq!(computer.get_cpu_state().program_counter, 0xff42);
+        assert_eq!(computer.read_memory(0xff00, 0x100), rom);
+
+        // The ROM only takes the top page
+        assert!(computer.load_rom(&[]).is_err());
+        assert!(computer.load_rom(&[0xea; 0x300]).is_err());
+        assert_eq!(computer.read_memory(0xfd00, 1), vec![0]);
+    }
+
+    // A device that allows assembly tests to drive the interrupt lines.
+    // Writing a non-zero value to offset 0 holds IRQ, to offset 1 holds NMI.
+    // See IRQ_LINE and NMI_LINE in assembly/test.inc
+    #[derive(Debug, Default)]
+    struct InterruptTrigger {
+        irq: bool,
+        nmi: bool,
+    }
+
+    impl Addressable for InterruptTrigger {
+        fn size(&self) -> usize {
+            2
+        }
+
+        fn read_byte(&self, address: u16) -> u8 {
+            match address {
+                0 => u8::from(self.irq),
+                _ => u8::from(self.nmi),
+            }
+        }
+
+        fn write_byte(&mut self, address: u16, byte: u8) {
+            match address {
+                0 => self.irq = byte != 0,
+                _ => self.nmi = byte != 0,
+            }
+        }
+
+        fn irq(&self) -> bool {
+            self.irq
+        }
+
+        fn nmi(&self) -> bool {
+            self.nmi
+        }
+    }
+
     // Helpers for test functions
     fn create_test_computer() -> Computer {
-        let rom_file_name = Path::new("assembly/standard.rom");
-        let rom = std::fs::read(rom_file_name).unwrap_or_else(|_| panic!(
-            "Was not able to load rom from {}", rom_file_name.display()
-        ));
+        let rom = Assembler::new()
+            .with_segment("OS", 0xff00)
+            .with_segment("VECTORS", 0xfffa)
+            .assemble_file(Path::new("assembly/standard.rom.s"))
+            .unwrap_or_else(|e| panic!("Was not able to assemble the rom: {}", e));
         Computer::new()
-            .with_rom(rom)
+            .with_rom(rom.program.to_image(0).1)
             .with_clock(Clock::new(clock::ClockMode::Speedy))
+            .with_device(0xfe00, Box::new(InterruptTrigger::default()))
             .build()
             .unwrap_or_else(|_| panic!("Was not able to create computer"))
     }
 
     fn read_program(file_name: &str) -> Vec<u8> {
-        let program_file_name = Path::new(file_name);
-        std::fs::read(program_file_name).unwrap_or_else(|_| panic!(
-            "Was not able to load program from {}", program_file_name.display()
-        ))
+        let assembly = Assembler::new()
+            .assemble_file(Path::new(file_name))
+            .unwrap_or_else(|e| panic!("Was not able to assemble {}: {}", file_name, e));
+        assembly.program.to_image(0).1
     }
-
-    fn build_assembly() {
-        info!("Building assembly");
-        let status = Command::new("make")
-            .arg("-C").arg("assembly") // chdir to assembly
-            .arg("-j").arg("8") // run this many in parallel
-            .stdout(Stdio::null())
-            .status()
-            .expect("Make failed to run");
-
-        assert!(status.success(), "Make returned an error. Please run from command line to check")
-    }
-
 }
\ No newline at end of file
@@ -1,6 +1,11 @@
+use std::cell::RefCell;
+use std::collections::BTreeSet;
 use std::fmt::Debug;
 use std::fmt;
 
+use super::clock::TickCount;
+use super::devices::Lcd;
+
 // This function works in this order, because it's the order in which
 // bytes are read from memory (i.e. little endian)
 pub fn lo_hi_to_address(lo: u8, hi: u8) -> u16 {
@@ -21,9 +26,26 @@ struct MappedAddressable {
     addressable: Box<dyn Addressable>,
 }
 
+// A read or write on the bus, as seen by a watchpoint
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum AccessKind {
+    Read,
+    Write,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Access {
+    pub kind: AccessKind,
+    pub address: u16,
+    pub value: u8,
+}
+
 #[derive(Debug, Default)]
 pub struct Bus {
     segments: Vec<MappedAddressable>,
+    watchpoints: BTreeSet<(u16, AccessKind)>,
+    // Accesses to watched addresses, since the last time they were taken
+    accesses: RefCell<Vec<Access>>,
 }
 
 /*
@@ -35,6 +57,9 @@ pub struct Bus {
  *
  * If two address ranges of segments overlap, the last added segment is the one that will
  * be addressed
+ *
+ * The bus also carries the IRQ and NMI lines. Any chip on the bus can pull these,
+ * and the bus reports whether any of them is doing so.
  */
 impl Bus {
     pub fn new() -> Self {
@@ -63,19 +88,71 @@ impl Bus {
         self.add_rom(rom_data, start as u16)
     }
 
-    fn add_addressable<A: Addressable + 'static>(mut self, addressable: A, start: u16, end: u16) -> Result<Self, String> {
+    // Devices (I/O chips) usually only occupy a handful of addresses, so unlike RAM and ROM
+    // they do not need to be aligned with a page boundary
+    pub fn add_device(self, device: Box<dyn Addressable>, start: u16) -> Result<Self, String> {
+        log::info!("Adding device {:?} at 0x{:04x}", device, start);
+        if device.size() == 0 || start as usize + device.size() > MAX_MEMORY_SIZE {
+            return Err(format!("Device of size {:x} does not fit at 0x{:04x}", device.size(), start));
+        }
+        let end = start + (device.size() - 1) as u16;
+        self.map_addressable(device, start, end)
+    }
+
+    fn add_addressable<A: Addressable + 'static>(self, addressable: A, start: u16, end: u16) -> Result<Self, String> {
         log::debug!("Adding addressable of size {:x} at 0x{:04x} to 0x{:04x}", addressable.size(), start, end);
+        if !start.is_multiple_of(0x100) || end % 0x100 != 0xff {
+            return Err("Start and end must be aligned with page boundary".to_string());
+        }
+        self.map_addressable(Box::new(addressable), start, end)
+    }
+
+    // The RAM and ROM blocks, with the address they start at
+    pub fn memory_contents(&self) -> Vec<(u16, &[u8])> {
+        self.segments.iter()
+            .filter_map(|segment| Some((segment.start, segment.addressable.contents()?)))
+            .collect()
+    }
+
+    pub fn memory_contents_mut(&mut self) -> Vec<(u16, &mut [u8])> {
+        self.segments.iter_mut()
+            .filter_map(|segment| Some((segment.start, segment.addressable.contents_mut()?)))
+            .collect()
+    }
+
+    pub fn add_watchpoint(&mut self, address: u16, kind: AccessKind) {
+        self.watchpoints.insert((address, kind));
+    }
+
+    // Returns whether there was a watchpoint
+    pub fn remove_watchpoint(&mut self, address: u16, kind: AccessKind) -> bool {
+        self.watchpoints.remove(&(address, kind))
+    }
+
+    pub fn watchpoints(&self) -> impl Iterator<Item = (u16, AccessKind)> + '_ {
+        self.watchpoints.iter().copied()
+    }
+
+    // The accesses to watched addresses since the last call
+    pub fn take_accesses(&self) -> Vec<Access> {
+        self.accesses.take()
+    }
+
+    fn watch(&self, kind: AccessKind, address: u16, value: u8) {
+        if !self.watchpoints.is_empty() && self.watchpoints.contains(&(address, kind)) {
+            self.accesses.borrow_mut().push(Access { kind, address, value });
+        }
+    }
+
+    fn map_addressable(mut self, addressable: Box<dyn Addressable>, start: u16, end: u16) -> Result<Self, String> {
         if start > end {
             return Err(format!("Start address 0x{:04x} is greater than end address 0x{:04x}", start, end));
         }
-        if start % 0x100 != 0 || end % 0x100 != 0xff {
-            return Err("Start and end must be aligned with page boundary".to_string());
-        }
 
         let segment = MappedAddressable {
             start,
             end,
-            addressable: Box::new(addressable),
+            addressable,
         };
         // Insert at the front, so we don't have to iterate in reverse
         self.segments.insert(0, segment);
@@ -88,17 +165,27 @@ impl Addressable for Bus {
     fn read_byte(&self, address: u16) -> u8 {
         for segment in &self.segments {
             if address >= segment.start && address <= segment.end {
-                return segment.addressable.read_byte(address - segment.start);
+                let value = segment.addressable.read_byte(address - segment.start);
+                self.watch(AccessKind::Read, address, value);
+                return value;
             }
         }
         log::error!("Attempt to read from unmapped memory address 0x{:04x}", address);
         0
     }
 
+    // Nothing is there to answer for unmapped addresses, and watchpoints don't see peeks
+    fn peek(&self, address: u16) -> Option<u8> {
+        self.segments.iter()
+            .find(|segment| address >= segment.start && address <= segment.end)
+            .and_then(|segment| segment.addressable.peek(address - segment.start))
+    }
+
     fn write_byte(&mut self, address: u16, byte: u8) {
         for segment in &mut self.segments {
             if address >= segment.start && address <= segment.end {
                 segment.addressable.write_byte(address - segment.start, byte);
+                self.watch(AccessKind::Write, address, byte);
                 return;
             }
         }
@@ -111,6 +198,24 @@ impl Addressable for Bus {
     fn size(&self) -> usize {
         MAX_MEMORY_SIZE
     }
+
+    fn tick(&mut self, cycles: TickCount) {
+        for segment in &mut self.segments {
+            segment.addressable.tick(cycles);
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.segments.iter().any(|segment| segment.addressable.irq())
+    }
+
+    fn nmi(&self) -> bool {
+        self.segments.iter().any(|segment| segment.addressable.nmi())
+    }
+
+    fn display(&self) -> Option<Lcd> {
+        self.segments.iter().find_map(|segment| segment.addressable.display())
+    }
 }
 
 /*
@@ -126,6 +231,33 @@ pub trait Addressable: Debug {
 
     fn read_byte(&self, address: u16) -> u8;
 
+    // What reading the address would return, without the side effects reading
+    // has on some chips, like clearing flags or taking a received byte. Used to
+    // show memory. Chips with such side effects have to override this.
+    fn peek(&self, address: u16) -> Option<u8> {
+        Some(self.read_byte(address))
+    }
+
+    // The contents of memory, to save and restore the state of the computer.
+    // Devices have none, and keep the state they're in.
+    fn contents(&self) -> Option<&[u8]> {
+        None
+    }
+
+    fn contents_mut(&mut self) -> Option<&mut [u8]> {
+        None
+    }
+
+    // A display connected to this chip, for a UI to show
+    fn display(&self) -> Option<Lcd> {
+        None
+    }
+
+    // Peek at the two bytes at the address, as an address
+    fn peek_address(&self, address: u16) -> Option<u16> {
+        Some(lo_hi_to_address(self.peek(address)?, self.peek(address.wrapping_add(1))?))
+    }
+
     fn read_two_bytes(&self, address: u16) -> [u8; 2] {
         if address == 0xffff {
             log::error!("Attempt to read past end of memory");
@@ -165,6 +297,20 @@ pub trait Addressable: Debug {
         let b = self.read_two_bytes(address);
         lo_hi_to_address(b[0], b[1])
     }
+
+    // Called after every instruction with the number of clock cycles it took, so that
+    // chips with timers or I/O can keep up with the CPU
+    fn tick(&mut self, _cycles: TickCount) {}
+
+    // Is this chip currently asserting the (level triggered) IRQ line?
+    fn irq(&self) -> bool {
+        false
+    }
+
+    // Is this chip currently asserting the (edge triggered) NMI line?
+    fn nmi(&self) -> bool {
+        false
+    }
 }
 
 // TODO add a 'proper' bus implementation with multiple rom and ram regions
@@ -223,6 +369,14 @@ impl Addressable for Ram {
         let offset = usize::from(address);
         self.data[offset..][..bytes.len()].copy_from_slice(bytes);
     }
+
+    fn contents(&self) -> Option<&[u8]> {
+        Some(&self.data)
+    }
+
+    fn contents_mut(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.data)
+    }
 }
 
 // TODO somehow let the user determine how much and which memory to show
@@ -412,6 +566,106 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_watchpoints() -> Result<(), String> {
+        let mut bus = Bus::new().add_ram(Ram::new(0x1000), 0x0000)?;
+        bus.add_watchpoint(0x0010, AccessKind::Write);
+        bus.add_watchpoint(0x0020, AccessKind::Read);
+
+        bus.write_byte(0x0010, 0x42);
+        bus.write_byte(0x0020, 0x43);
+        bus.read_byte(0x0010);
+        assert_eq!(bus.peek(0x0020), Some(0x43));
+        assert_eq!(bus.read_byte(0x0020), 0x43);
+        assert_eq!(bus.take_accesses(), vec![
+            Access { kind: AccessKind::Write, address: 0x0010, value: 0x42 },
+            Access { kind: AccessKind::Read, address: 0x0020, value: 0x43 },
+        ]);
+        assert!(bus.take_accesses().is_empty());
+
+        assert!(bus.remove_watchpoint(0x0010, AccessKind::Write));
+        assert!(!bus.remove_watchpoint(0x0010, AccessKind::Read));
+        bus.write_byte(0x0010, 0x44);
+        assert!(bus.take_accesses().is_empty());
+        assert_eq!(bus.watchpoints().collect::<Vec<_>>(), vec![(0x0020, AccessKind::Read)]);
+        Ok(())
+    }
+
+    #[derive(Debug, Default)]
+    struct TestDevice {
+        register: u8,
+    }
+
+    impl Addressable for TestDevice {
+        fn size(&self) -> usize {
+            4
+        }
+
+        fn read_byte(&self, _address: u16) -> u8 {
+            self.register
+        }
+
+        fn write_byte(&mut self, _address: u16, byte: u8) {
+            self.register = byte;
+        }
+
+        fn irq(&self) -> bool {
+            self.register & 0x01 != 0
+        }
+
+        fn nmi(&self) -> bool {
+            self.register & 0x02 != 0
+        }
+    }
+
+    #[test]
+    fn test_device() -> Result<(), String> {
+        // Devices do not have to be page aligned
+        let mut bus = Bus::new()
+            .add_ram(Ram::new(0x1000), 0x0000)?
+            .add_device(Box::new(TestDevice::default()), 0x0810)?;
+
+        assert!(!bus.irq());
+        assert!(!bus.nmi());
+
+        bus.write_byte(0x0812, 0x01);
+        assert_eq!(0x01, bus.read_byte(0x0810));
+        assert!(bus.irq());
+        assert!(!bus.nmi());
+
+        bus.write_byte(0x0813, 0x02);
+        assert!(!bus.irq());
+        assert!(bus.nmi());
+
+        // Memory around the device is still RAM
+        bus.write_byte(0x080f, 0xaa);
+        bus.write_byte(0x0814, 0xbb);
+        assert_eq!(0xaa, bus.read_byte(0x080f));
+        assert_eq!(0xbb, bus.read_byte(0x0814));
+        assert_eq!(0x02, bus.read_byte(0x0811));
+
+        let device = bus.get_segment_at_start_address(0x0810).unwrap();
+        assert_eq!(0x02, device.read_byte(0));
+
+        // Peeking goes to the device too, and finds nothing where nothing is mapped
+        assert_eq!(bus.peek(0x0811), Some(0x02));
+        assert_eq!(bus.peek(0x0814), Some(0xbb));
+        assert_eq!(bus.peek(0x1000), None);
+
+        // Only memory has contents to save
+        let memory = bus.memory_contents();
+        assert_eq!(memory.len(), 1);
+        assert_eq!((memory[0].0, memory[0].1.len(), memory[0].1[0x0814]), (0x0000, 0x1000, 0xbb));
+
+        Ok(())
+    }
+
+    #[test]
+    fn device_past_end_of_memory() {
+        let result = Bus::new().add_device(Box::new(TestDevice::default()), 0xfffe);
+        assert!(result.is_err());
+    }
+
     #[test]
     fn test_rom() -> Result<(), String> {
         // Create some fake rom images
@@ -2,13 +2,17 @@ use std::time::{Duration, Instant};
 use std::thread;
 use std::fmt::{Debug, Display};
 
+use serde::Deserialize;
+
 use super::DEFAULT_CLOCK_SPEED;
 
 // TODO make this into a type that limits its range, maybe ranged_integer crate once it's mature
 pub type TickCount = u16;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum ClockMode {
+    #[default]
     Normal,
     Speedy,
 }
@@ -47,6 +51,10 @@ impl Clock {
         self.interval = Duration::from_nanos(1_000_000_000/speed as u64);
         self
     }
+
+    pub fn speed(&self) -> u32 {
+        self.speed
+    }
 }
 
 impl Clock {
@@ -0,0 +1,421 @@
+use std::path::{Path, PathBuf};
+
+use clap::ValueEnum;
+use log::info;
+use serde::Deserialize;
+
+use super::clock::{Clock, ClockMode};
+use super::devices::{Acia, Connection, ConnectionSpec, Lcd, Pia, Via};
+use super::devices::connection::ChannelEndpoint;
+use super::machines::apple1;
+use super::{Computer, ComputerBuilder, DEFAULT_CLOCK_SPEED};
+
+/*
+ * Machine configuration
+ *
+ * A TOML description of a computer: its memory map, devices, clock, and where
+ * programs are loaded. For example:
+ *
+ *   load_address = 0x1000
+ *
+ *   [clock]
+ *   speed = 1_000_000
+ *
+ *   [[ram]]
+ *   start = 0x0000
+ *   size = 0x8000
+ *
+ *   [[rom]]
+ *   start = 0xc000
+ *   file = "basic.bin"
+ *   offset = 0x10        # skip a header
+ *
+ *   [[device]]
+ *   type = "acia"
+ *   address = 0x8800
+ *   connection = "pty"
+ *
+ * Memory blocks must start and end on a page boundary. Later blocks take
+ * precedence over earlier ones, and devices over all memory. Relative file
+ * names are taken relative to the configuration file.
+ */
+
+pub const DEFAULT_LOAD_ADDRESS: u16 = 0x1000;
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MachineConfig {
+    #[serde(default)]
+    pub cpu: CpuConfig,
+    #[serde(default)]
+    pub clock: ClockConfig,
+    #[serde(default)]
+    pub ram: Vec<MemoryConfig>,
+    #[serde(default)]
+    pub rom: Vec<MemoryConfig>,
+    #[serde(default, rename = "device")]
+    pub devices: Vec<DeviceConfig>,
+    // Where a raw binary given on the command line is loaded
+    #[serde(default = "default_load_address")]
+    pub load_address: u16,
+    // Where execution of a program starts, if not at the load address and
+    // the program file doesn't say
+    pub entry_point: Option<u16>,
+    // Run the ROM until it executes BRK, before loading a program
+    #[serde(default)]
+    pub rom_initialisation: bool,
+
+    // Where relative file names are taken from
+    #[serde(skip)]
+    base_directory: PathBuf,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CpuConfig {
+    #[serde(default)]
+    pub variant: CpuVariant,
+}
+
+// Only the NMOS 6502 is emulated so far, any other variant is an error
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize, ValueEnum)]
+pub enum CpuVariant {
+    #[default]
+    #[serde(rename = "6502")]
+    #[value(name = "6502")]
+    Mos6502,
+}
+
+impl CpuVariant {
+    // The name ca65 uses in .setcpu
+    pub fn name(&self) -> &'static str {
+        match self {
+            CpuVariant::Mos6502 => "6502",
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ClockConfig {
+    #[serde(default = "default_clock_speed")]
+    pub speed: u32,
+    #[serde(default)]
+    pub mode: ClockMode,
+}
+
+impl Default for ClockConfig {
+    fn default() -> Self {
+        Self {
+            speed: DEFAULT_CLOCK_SPEED,
+            mode: ClockMode::default(),
+        }
+    }
+}
+
+// A block of RAM or ROM. ROM needs a file, RAM needs a size and can be
+// initialised from a file.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MemoryConfig {
+    pub start: u16,
+    pub size: Option<usize>,
+    pub file: Option<PathBuf>,
+    // Where in the file the contents start
+    #[serde(default)]
+    pub offset: usize,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+pub enum DeviceConfig {
+    // 6551 serial port
+    Acia {
+        address: u16,
+        #[serde(default = "default_connection")]
+        connection: ConnectionSpec,
+    },
+    // 6522 VIA, optionally with an LCD connected the Ben Eater way
+    Via {
+        address: u16,
+        lcd: Option<LcdConfig>,
+    },
+    // 6820 PIA, optionally with an Apple I keyboard and display
+    Pia {
+        address: u16,
+        terminal: Option<ConnectionSpec>,
+        #[serde(default = "default_irq")]
+        irq: bool,
+    },
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LcdConfig {
+    pub columns: u8,
+    pub rows: u8,
+}
+
+fn default_load_address() -> u16 {
+    DEFAULT_LOAD_ADDRESS
+}
+
+fn default_clock_speed() -> u32 {
+    DEFAULT_CLOCK_SPEED
+}
+
+fn default_connection() -> ConnectionSpec {
+    ConnectionSpec::Stdio
+}
+
+fn default_irq() -> bool {
+    true
+}
+
+impl MachineConfig {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        toml::from_str(text).map_err(|e| e.to_string())
+    }
+
+    pub fn from_file(file_name: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(file_name)
+            .map_err(|e| format!("Was not able to read {}: {}", file_name.display(), e))?;
+        let mut config = Self::parse(&text)
+            .map_err(|e| format!("Invalid configuration in {}: {}", file_name.display(), e))?;
+        config.base_directory = file_name.parent().map(Path::to_path_buf).unwrap_or_default();
+        Ok(config)
+    }
+
+    pub fn builder(&self) -> Result<ComputerBuilder, String> {
+        let clock = Clock::new(self.clock.mode.clone()).with_clock_speed(self.clock.speed);
+        info!("Configured cpu variant: {:?}", self.cpu.variant);
+
+        // All memory comes from the configuration
+        let mut builder = Computer::new()
+            .with_clock(clock)
+            .with_memory_size(0);
+        for ram in &self.ram {
+            builder = builder.with_ram_contents(ram.start, ram.contents(&self.base_directory, false)?);
+        }
+        for rom in &self.rom {
+            builder = builder.with_rom_at(rom.start, rom.contents(&self.base_directory, true)?);
+        }
+
+        let mut console = None;
+        for device in &self.devices {
+            builder = match device {
+                DeviceConfig::Acia { address, connection } => {
+                    builder.with_device(*address, Box::new(Acia::new(open(connection, &mut console)?)))
+                }
+                DeviceConfig::Via { address, lcd: None } => builder.with_device(*address, Box::new(Via::new())),
+                DeviceConfig::Via { address, lcd: Some(lcd) } => {
+                    let lcd = Lcd::new(lcd.columns, lcd.rows);
+                    let via = Via::new().with_peripheral(Box::new(lcd));
+                    builder.with_device(*address, Box::new(via))
+                }
+                DeviceConfig::Pia { address, terminal, irq } => {
+                    let mut pia = Pia::new();
+                    if let Some(terminal) = terminal {
+                        pia = pia.with_peripheral(Box::new(apple1::Terminal::new(open(terminal, &mut console)?)));
+                    }
+                    if !irq {
+                        pia = pia.without_irq();
+                    }
+                    builder.with_device(*address, Box::new(pia))
+                }
+            };
+        }
+
+        if let Some(console) = console {
+            builder = builder.with_console(console);
+        }
+        if !self.rom_initialisation {
+            builder = builder.without_rom_initialisation();
+        }
+        Ok(builder)
+    }
+}
+
+fn open(spec: &ConnectionSpec, console: &mut Option<ChannelEndpoint>) -> Result<Box<dyn Connection>, String> {
+    spec.open_with_console(console).map_err(|e| format!("Was not able to open connection {}: {}", spec, e))
+}
+
+impl MemoryConfig {
+    // The contents of the block, read from its file, and padded to its size
+    fn contents(&self, base_directory: &Path, rom: bool) -> Result<Vec<u8>, String> {
+        let mut data = match &self.file {
+            Some(file) => {
+                let path = base_directory.join(file);
+                let data = std::fs::read(&path)
+                    .map_err(|e| format!("Was not able to load {}: {}", path.display(), e))?;
+                if self.offset > data.len() {
+                    return Err(format!("Offset {:x} is past the end of {}", self.offset, path.display()));
+                }
+                data[self.offset..].to_vec()
+            }
+            None if rom => return Err(format!("ROM at 0x{:04x} needs a file", self.start)),
+            None => Vec::new(),
+        };
+        let size = match self.size {
+            Some(size) => size,
+            None if rom => data.len(),
+            None => return Err(format!("RAM at 0x{:04x} needs a size", self.start)),
+        };
+        if size == 0 || self.start as usize + size > 0x10000 {
+            return Err(format!("Memory of size {:x} does not fit at 0x{:04x}", size, self.start));
+        }
+        data.resize(size, 0);
+        Ok(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn defaults() {
+        let config = MachineConfig::parse("").unwrap();
+        assert_eq!(config.cpu.variant, CpuVariant::Mos6502);
+        assert_eq!(config.clock.speed, DEFAULT_CLOCK_SPEED);
+        assert_eq!(config.clock.mode, ClockMode::Normal);
+        assert_eq!(config.load_address, DEFAULT_LOAD_ADDRESS);
+        assert_eq!(config.entry_point, None);
+        assert!(config.ram.is_empty() && config.rom.is_empty() && config.devices.is_empty());
+    }
+
+    #[test]
+    fn full_config() {
+        let config = MachineConfig::parse(r#"
+            load_address = 0x0800
+            entry_point = 0x0810
+
+            [cpu]
+            variant = "6502"
+
+            [clock]
+            speed = 2_000_000
+            mode = "speedy"
+
+            [[ram]]
+            start = 0x0000
+            size = 0x8000
+
+            [[rom]]
+            start = 0xe000
+            file = "monitor.bin"
+            offset = 2
+
+            [[device]]
+            type = "acia"
+            address = 0x8800
+            connection = "unix:/tmp/acia"
+
+            [[device]]
+            type = "via"
+            address = 0x6000
+            lcd = { columns = 20, rows = 4 }
+
+            [[device]]
+            type = "pia"
+            address = 0xd010
+            terminal = "stdio"
+            irq = false
+        "#).unwrap();
+
+        assert_eq!(config.load_address, 0x0800);
+        assert_eq!(config.entry_point, Some(0x0810));
+        assert_eq!(config.clock.mode, ClockMode::Speedy);
+        assert_eq!(config.ram[0].size, Some(0x8000));
+        assert_eq!(config.rom[0].offset, 2);
+        assert!(matches!(&config.devices[0],
+            DeviceConfig::Acia { address: 0x8800, connection: ConnectionSpec::UnixSocket(_) }));
+        assert!(matches!(&config.devices[1],
+            DeviceConfig::Via { address: 0x6000, lcd: Some(LcdConfig { columns: 20, rows: 4 }) }));
+        assert!(matches!(&config.devices[2],
+            DeviceConfig::Pia { address: 0xd010, terminal: Some(ConnectionSpec::Stdio), irq: false }));
+    }
+
+    #[test]
+    fn example_configs() {
+        for text in [
+            include_str!("../../machines/standard.toml"),
+            include_str!("../../machines/replica1.toml"),
+            include_str!("../../machines/ben_eater.toml"),
+        ] {
+            let config = MachineConfig::parse(text).unwrap();
+            assert!(!config.ram.is_empty() && !config.rom.is_empty());
+        }
+    }
+
+    #[test]
+    fn invalid_configs() {
+        assert!(MachineConfig::parse("[cpu]\nvariant = \"z80\"").is_err());
+        let error = MachineConfig::parse("[cpu]\nvariant = \"65c02\"").unwrap_err();
+        assert!(error.contains("unknown variant `65c02`, expected `6502`"), "{}", error);
+        assert!(MachineConfig::parse("[[device]]\ntype = \"sid\"\naddress = 0xd400").is_err());
+        assert!(MachineConfig::parse("[[device]]\ntype = \"acia\"\naddress = 0x8800\nconnection = \"tcp\"").is_err());
+        assert!(MachineConfig::parse("memory = 0x10000").is_err());
+        assert!(MachineConfig::parse("[[ram]]\nstart = 0x10000\nsize = 1").is_err());
+    }
+
+    #[test]
+    fn memory_contents() {
+        let directory = std::env::temp_dir().join(format!("m6502-config-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        std::fs::write(directory.join("data.bin"), [1, 2, 3, 4]).unwrap();
+
+        let block = |size, file: Option<&str>, offset| MemoryConfig {
+            start: 0xff00,
+            size,
+            file: file.map(PathBuf::from),
+            offset,
+        };
+        assert_eq!(block(None, Some("data.bin"), 1).contents(&directory, true), Ok(vec![2, 3, 4]));
+        assert_eq!(block(Some(6), Some("data.bin"), 0).contents(&directory, false), Ok(vec![1, 2, 3, 4, 0, 0]));
+        assert_eq!(block(Some(2), None, 0).contents(&directory, false), Ok(vec![0, 0]));
+        assert!(block(None, None, 0).contents(&directory, true).is_err());
+        assert!(block(None, None, 0).contents(&directory, false).is_err());
+        assert!(block(None, Some("data.bin"), 5).contents(&directory, true).is_err());
+        assert!(block(Some(0x200), None, 0).contents(&directory, false).is_err());
+
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn build_from_config() {
+        let directory = std::env::temp_dir().join(format!("m6502-build-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        // A ROM that only has a reset vector pointing at $c000
+        let mut rom = vec![0xea; 0x100];
+        rom[0xfc] = 0x00;
+        rom[0xfd] = 0xc0;
+        std::fs::write(directory.join("test.rom"), &rom).unwrap();
+        let file_name = directory.join("machine.toml");
+        std::fs::write(&file_name, r#"
+            [[ram]]
+            start = 0x0000
+            size = 0x4000
+
+            [[ram]]
+            start = 0xc000
+            size = 0x100
+
+            [[rom]]
+            start = 0xff00
+            file = "test.rom"
+
+            [[device]]
+            type = "via"
+            address = 0x6000
+            lcd = { columns = 16, rows = 2 }
+        "#).unwrap();
+
+        let config = MachineConfig::from_file(&file_name).unwrap();
+        let computer = config.builder().unwrap().build().unwrap();
+        assert_eq!(computer.get_cpu_state().program_counter, 0xc000);
+        assert!(computer.lcd().is_some());
+
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
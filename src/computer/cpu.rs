@@ -1,5 +1,5 @@
 pub mod inspect;
-mod instruction;
+pub mod instruction;
 pub mod status;
 
 use inspect::ExecutedInstruction;
@@ -20,6 +20,9 @@ const NMI_ADDRESS: u16 = 0xfffa;
 const RESET_ADDRESS: u16 = 0xfffc;
 const IRQ_ADDRESS: u16 = 0xfffe;
 
+// Number of cycles the CPU takes to enter an interrupt handler
+const INTERRUPT_CYCLES: TickCount = 7;
+
 /*
  * For much of the information used here, see
  * https://www.masswerk.at/6502/6502_instruction_set.html
@@ -40,13 +43,19 @@ pub struct Cpu {
     program_counter: u16,
     status: Status,
 
+    // The value of the interrupt disable flag when the IRQ line was last polled.
+    // CLI, SEI and PLP change the flag after the poll, so the instruction following
+    // them still sees the old value.
+    irq_disable_at_poll: bool,
+
     // For debugging and display
     execution_history: CircularBuffer<16, ExecutedInstruction>,
 }
 
 impl Cpu {
     pub fn new(bus: Bus) -> Self {
-        let program_counter = bus.read_address(RESET_ADDRESS);
+        // An empty bus has no reset vector, so start at 0
+        let program_counter = bus.peek_address(RESET_ADDRESS).unwrap_or_default();
         Self {
             bus,
             accumulator: 0,
@@ -57,11 +66,19 @@ impl Cpu {
 
             program_counter,
             status: Status::default(),
+            irq_disable_at_poll: false,
 
             execution_history: CircularBuffer::new(),
         }
     }
 
+    // What the reset line does: continue at the reset vector, with interrupts disabled
+    pub fn reset(&mut self) {
+        self.program_counter = self.bus.read_address(RESET_ADDRESS);
+        self.stack_pointer = 0xfd;
+        self.status.irq_disable = true;
+    }
+
     pub fn load_program(&mut self, address: u16, program: &[u8]) {
         // TODO put in some better safeguards for a sensible address
         assert!(address > 0x200 && address < 0xfdff);
@@ -72,6 +89,10 @@ impl Cpu {
         debug!("Setting program counter to {:04x}", self.program_counter);
     }
 
+    pub fn set_program_counter(&mut self, address: u16) {
+        self.program_counter = address;
+    }
+
     // Run for one clock cycle
     pub fn fetch_and_execute(&mut self) -> Option<TickCount> {
         // Read a byte
@@ -125,7 +146,12 @@ impl Cpu {
                 self.program_counter += 1 + operand_size;
 
                 // update the state of memory and CPU
+                let irq_disable = self.status.irq_disable;
                 self.execute_instruction(instruction, operand);
+                self.irq_disable_at_poll = match instruction {
+                    Instruction::CLI | Instruction::SEI | Instruction::PLP => irq_disable,
+                    _ => self.status.irq_disable,
+                };
 
                 // FIXME This is here to stop us from running too long. Need to fix
                 if self.program_counter > NMI_ADDRESS {
@@ -438,11 +464,12 @@ impl Cpu {
             Instruction::JSR => {
                 match operand {
                     Operand::Address(address) => {
-                        // program counter already points to next instruction
-                        let return_address = self.program_counter;
+                        // program counter already points to next instruction. Like the
+                        // real thing, push the address before it, high byte first.
+                        let return_address = self.program_counter.wrapping_sub(1);
                         let bytes = address_to_bytes(return_address);
-                        self.push_stack(bytes[0]);
                         self.push_stack(bytes[1]);
+                        self.push_stack(bytes[0]);
                         self.program_counter = address;
                     },
                     _ => illegal_opcode(instruction, operand),
@@ -572,10 +599,10 @@ impl Cpu {
                 self.return_from_interrupt();
             },
             Instruction::RTS => {
-                let hi = self.pull_stack();
                 let lo = self.pull_stack();
+                let hi = self.pull_stack();
                 let address = lo_hi_to_address(lo, hi);
-                self.program_counter = address;
+                self.program_counter = address.wrapping_add(1);
             },
             Instruction::SBC => {
                 match operand {
@@ -665,35 +692,57 @@ impl Cpu {
         self.program_counter = self.bus.read_address(NMI_ADDRESS);
     }
 
-    #[allow(dead_code)]
+    // Called between instructions with the state of the interrupt lines. NMI has
+    // priority over IRQ. Returns the number of cycles used if an interrupt was taken.
+    pub fn service_interrupts(&mut self, irq: bool, nmi: bool) -> Option<TickCount> {
+        if nmi {
+            debug!("{:04x}: NMI", self.program_counter);
+            self.execute_nmi();
+            Some(INTERRUPT_CYCLES)
+        } else if irq && !self.irq_disable_at_poll {
+            debug!("{:04x}: IRQ", self.program_counter);
+            self.enter_interrupt(IRQ_ADDRESS);
+            Some(INTERRUPT_CYCLES)
+        } else {
+            None
+        }
+    }
+
     pub fn execute_nmi(&mut self) {
-        self.prepare_for_hardware_interrupt();
-        self.program_counter = self.bus.read_address(NMI_ADDRESS);
+        self.enter_interrupt(NMI_ADDRESS);
     }
 
-    #[allow(dead_code)]
     pub fn execute_irq(&mut self) {
         if self.status.irq_disable {
             return;
         }
+        self.enter_interrupt(IRQ_ADDRESS);
+    }
+
+    fn enter_interrupt(&mut self, vector: u16) {
         self.prepare_for_hardware_interrupt();
-        self.program_counter = self.bus.read_address(IRQ_ADDRESS);
+        self.program_counter = self.bus.read_address(vector);
     }
 
-    #[allow(dead_code)]
     fn prepare_for_hardware_interrupt(&mut self) {
         let address_bytes = address_to_bytes(self.program_counter);
         self.push_stack(address_bytes[1]); // high byte
         self.push_stack(address_bytes[0]); // low byte
-        self.push_stack(self.status.as_byte());
+        // Hardware interrupts push the status with the break bit clear
+        let mut status = self.status;
+        status.brk = false;
+        status.ignored = true;
+        self.push_stack(status.as_byte());
         self.status.brk = false;
+        self.status.irq_disable = true;
+        self.irq_disable_at_poll = true;
     }
 
     fn return_from_interrupt(&mut self) {
         let status = self.pull_stack();
         let low = self.pull_stack();
         let high = self.pull_stack();
-        self.program_counter = lo_hi_to_address(high, low);
+        self.program_counter = lo_hi_to_address(low, high);
         self.status = Status::from_byte(status);
         // Ensure brk is always false after a hardware restore
         self.status.brk = false;
@@ -784,17 +833,15 @@ impl Cpu {
     }
 
     // FIXME Implement decimal mode
-    // FIXME This panics if value == 0xff and carry is not set
     fn subtract_with_carry(&mut self, value: u8) {
         let a = self.accumulator;
         let c = u8::from(self.status.carry); // either 0 or 1
 
         let new_a = a.wrapping_add(!value).wrapping_add(c);
 
-        self.status.carry = a >= value;
-        // self.status.carry = new_a > a
-        //             || (new_a < a && c == 1)
-        //             || (value == 0xff && c == 0);
+        // Subtraction adds the complement, so carry is that addition's carry out:
+        // clear when there was a borrow, including the one coming in
+        self.status.carry = a as u16 + (!value) as u16 + c as u16 > 0xff;
         self.status.overflow = (a ^ new_a) & 0x80 != 0 && (a ^ value) & 0x80 != 0;
 
         self.set_accumulator(new_a);
@@ -847,4 +894,160 @@ pub mod tests {
             assert_eq!(byte, data);
         }
     }
+
+    #[test]
+    fn hardware_interrupt() {
+        let mut cpu = create_test_cpu();
+        cpu.program_counter = 0x1234;
+        cpu.stack_pointer = 0xff;
+        cpu.status.irq_disable = false;
+        cpu.irq_disable_at_poll = false;
+        cpu.status.carry = true;
+
+        assert_eq!(cpu.service_interrupts(true, false), Some(INTERRUPT_CYCLES));
+        assert_eq!(cpu.program_counter, cpu.bus.read_address(IRQ_ADDRESS));
+        assert!(cpu.status.irq_disable);
+        assert!(!cpu.status.brk);
+        // Return address and status (with break clear) on the stack
+        assert_eq!(cpu.peek_stack(3), 0x12);
+        assert_eq!(cpu.peek_stack(2), 0x34);
+        assert_eq!(cpu.peek_stack(1), 0b0010_0001);
+
+        // While in the handler, the IRQ line is ignored
+        assert_eq!(cpu.service_interrupts(true, false), None);
+
+        cpu.return_from_interrupt();
+        assert_eq!(cpu.program_counter, 0x1234);
+        assert_eq!(cpu.stack_pointer, 0xff);
+        assert!(!cpu.status.irq_disable);
+        assert!(cpu.status.carry);
+    }
+
+    #[test]
+    fn load_absolute_y() {
+        let mut cpu = create_test_cpu();
+        // LDA $2000,Y, twice: once within the page, once into the next
+        cpu.load_program(0x1000, &[0xb9, 0x00, 0x20, 0xb9, 0xf0, 0x20]);
+        cpu.bus.write_byte(0x2005, 0x42);
+        cpu.bus.write_byte(0x2105, 0x84);
+        cpu.y_index = 0x05;
+
+        cpu.fetch_and_execute();
+        assert_eq!(cpu.accumulator, 0x42);
+        assert_eq!(cpu.program_counter, 0x1003);
+
+        cpu.y_index = 0x15;
+        cpu.fetch_and_execute();
+        assert_eq!(cpu.accumulator, 0x84);
+        assert!(cpu.status.negative);
+        assert_eq!(cpu.program_counter, 0x1006);
+    }
+
+    #[test]
+    fn subroutine_return_address() {
+        let mut cpu = create_test_cpu();
+        // JSR $1010, and RTS there
+        cpu.load_program(0x1000, &[0x20, 0x10, 0x10]);
+        cpu.bus.write_byte(0x1010, 0x60);
+        cpu.stack_pointer = 0xff;
+
+        // Like a real 6502, the address of the last byte of the JSR, high byte first
+        cpu.fetch_and_execute();
+        assert_eq!(cpu.program_counter, 0x1010);
+        assert_eq!(cpu.peek_stack(2), 0x10);
+        assert_eq!(cpu.peek_stack(1), 0x02);
+
+        cpu.fetch_and_execute();
+        assert_eq!(cpu.program_counter, 0x1003);
+        assert_eq!(cpu.stack_pointer, 0xff);
+    }
+
+    #[test]
+    fn return_to_pushed_address() {
+        let mut cpu = create_test_cpu();
+        // Jump tables push the target minus one and RTS to it:
+        // LDA #$20, PHA, LDA #$ff, PHA, RTS
+        cpu.load_program(0x1000, &[0xa9, 0x20, 0x48, 0xa9, 0xff, 0x48, 0x60]);
+        cpu.stack_pointer = 0xff;
+        for _ in 0..5 {
+            cpu.fetch_and_execute();
+        }
+        assert_eq!(cpu.program_counter, 0x2100);
+        assert_eq!(cpu.stack_pointer, 0xff);
+    }
+
+    #[test]
+    fn subtract_with_borrow() {
+        let mut cpu = create_test_cpu();
+        // $0100 - $0001, a byte at a time: SEC, LDA #$00, SBC #$01, LDA #$01, SBC #$00
+        cpu.load_program(0x1000, &[0x38, 0xa9, 0x00, 0xe9, 0x01, 0xa9, 0x01, 0xe9, 0x00]);
+        cpu.fetch_and_execute();
+        cpu.fetch_and_execute();
+        cpu.fetch_and_execute();
+        assert_eq!(cpu.accumulator, 0xff);
+        assert!(!cpu.status.carry);
+        cpu.fetch_and_execute();
+        cpu.fetch_and_execute();
+        assert_eq!(cpu.accumulator, 0x00);
+        assert!(cpu.status.carry);
+
+        // Equal values with a borrow coming in: SBC #$05 with A = $05 and carry clear
+        cpu.load_program(0x1000, &[0xa9, 0x05, 0xe9, 0x05]);
+        cpu.status.carry = false;
+        cpu.fetch_and_execute();
+        cpu.fetch_and_execute();
+        assert_eq!(cpu.accumulator, 0xff);
+        assert!(!cpu.status.carry);
+    }
+
+    #[test]
+    fn nested_subroutines() {
+        let mut cpu = create_test_cpu();
+        // JSR $1010; there JSR $1020, which returns twice
+        cpu.load_program(0x1000, &[0x20, 0x10, 0x10]);
+        cpu.bus.write_bytes(0x1010, &[0x20, 0x20, 0x10, 0x60]);
+        cpu.bus.write_byte(0x1020, 0x60);
+        cpu.stack_pointer = 0xff;
+        cpu.fetch_and_execute();
+        cpu.fetch_and_execute();
+        assert_eq!(cpu.peek_stack(4), 0x10);
+        assert_eq!(cpu.peek_stack(3), 0x02);
+        assert_eq!(cpu.peek_stack(2), 0x10);
+        assert_eq!(cpu.peek_stack(1), 0x12);
+        cpu.fetch_and_execute();
+        assert_eq!(cpu.program_counter, 0x1013);
+        cpu.fetch_and_execute();
+        assert_eq!(cpu.program_counter, 0x1003);
+    }
+
+    #[test]
+    fn nmi_ignores_interrupt_disable() {
+        let mut cpu = create_test_cpu();
+        cpu.program_counter = 0x1000;
+        cpu.status.irq_disable = true;
+        cpu.irq_disable_at_poll = true;
+
+        assert_eq!(cpu.service_interrupts(true, false), None);
+        assert_eq!(cpu.program_counter, 0x1000);
+
+        assert_eq!(cpu.service_interrupts(true, true), Some(INTERRUPT_CYCLES));
+        assert_eq!(cpu.program_counter, cpu.bus.read_address(NMI_ADDRESS));
+    }
+
+    #[test]
+    fn interrupt_latency_after_cli() {
+        let mut cpu = create_test_cpu();
+        // CLI, NOP
+        cpu.load_program(0x1000, &[0x58, 0xea]);
+        cpu.status.irq_disable = true;
+
+        // The instruction after CLI still runs before the IRQ is taken
+        cpu.fetch_and_execute();
+        assert!(!cpu.status.irq_disable);
+        assert_eq!(cpu.service_interrupts(true, false), None);
+
+        cpu.fetch_and_execute();
+        assert_eq!(cpu.program_counter, 0x1002);
+        assert_eq!(cpu.service_interrupts(true, false), Some(INTERRUPT_CYCLES));
+    }
 }
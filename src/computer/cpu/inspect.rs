@@ -1,5 +1,7 @@
 use super::*;
+use crate::computer::symbols::SymbolTable;
 
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
 pub struct CpuState {
     pub accumulator: u8,
     pub x_index: u8,
@@ -22,6 +24,15 @@ impl Cpu {
             status: self.status,
         }
     }
+
+    pub fn set_state(&mut self, state: &CpuState) {
+        self.accumulator = state.accumulator;
+        self.x_index = state.x_index;
+        self.y_index = state.y_index;
+        self.stack_pointer = state.stack_pointer;
+        self.program_counter = state.program_counter;
+        self.status = state.status;
+    }
 }
 
 #[derive(Debug)]
@@ -48,25 +59,29 @@ impl ExecutedInstruction {
         InstructionOption::Some(self.instruction, self.address_mode, self.operand_bytes)
     }
 
-    fn disassemble(&self) -> (u16, String) {
-        (self.address, format!("{}", self.as_instruction_option()))
+    fn disassemble(&self, symbols: &SymbolTable) -> (u16, String) {
+        (self.address, self.as_instruction_option().format(self.address, symbols))
     }
 }
 
 enum InstructionOption {
     Some(instruction::Instruction, instruction::AddressMode, [u8; 2]),
     None(u8),
+    // Nothing is mapped at the address
+    Unmapped,
 }
 
-impl std::fmt::Display for InstructionOption {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+impl InstructionOption {
+    // Show the instruction at the given address, with operands by name where possible
+    fn format(&self, address: u16, symbols: &SymbolTable) -> String {
         match self {
             InstructionOption::Some(instruction, address_mode, operand_bytes) => {
-                write!(f, "{instruction} {}", address_mode.debug_format(operand_bytes))
+                format!("{instruction} {}", address_mode.symbolic_format(operand_bytes, address, symbols))
             }
             InstructionOption::None(opcode) => {
-                write!(f, "U{:02x}", opcode)
+                format!("U{:02x}", opcode)
             }
+            InstructionOption::Unmapped => "--".to_string(),
         }
     }
 }
@@ -74,11 +89,18 @@ impl std::fmt::Display for InstructionOption {
 // Formatting/Display functions for the CPU type
 impl Cpu {
 
+    // Peeks at memory rather than reading it, so disassembling doesn't disturb I/O chips
     fn get_instruction(&self, address: u16) -> InstructionOption {
-        let opcode = self.bus.read_byte(address);
+        let Some(opcode) = self.bus.peek(address) else {
+            return InstructionOption::Unmapped;
+        };
         match instruction::decode_instruction(opcode) {
             Some((instruction, address_mode, _)) => {
-                let operand_bytes = self.bus.read_two_bytes(address + 1);
+                // Peek at them one by one: at the end of memory, the operand wraps around
+                let operand_bytes = [
+                    self.bus.peek(address.wrapping_add(1)).unwrap_or_default(),
+                    self.bus.peek(address.wrapping_add(2)).unwrap_or_default(),
+                ];
                 InstructionOption::Some(instruction, address_mode, operand_bytes)
             }
             None => {
@@ -88,25 +110,33 @@ impl Cpu {
     }
 
     // TODO should these be in computer::inspect?`
-    fn stringify_opcode(&self, address: u16) -> String {
-        format!{"{}", self.get_instruction(address)}
+    fn stringify_opcode(&self, address: u16, symbols: &SymbolTable) -> String {
+        self.get_instruction(address).format(address, symbols)
     }
 
-    pub fn address_opcode_to_string(&self, address: u16) -> String {
-        self.stringify_opcode(address)
+    pub fn address_opcode_to_string(&self, address: u16, symbols: &SymbolTable) -> String {
+        self.stringify_opcode(address, symbols)
     }
 
-    pub fn disassemble(&self, start_address: u16, length: u16) -> Vec<(u16, String)> {
+    // Length in bytes of the instruction at the address. Unknown opcodes take one byte.
+    pub fn instruction_length(&self, address: u16) -> u16 {
+        match self.get_instruction(address) {
+            InstructionOption::Some(_, address_mode, _) => 1 + address_mode.operand_size(),
+            InstructionOption::None(_) | InstructionOption::Unmapped => 1,
+        }
+    }
+
+    pub fn disassemble(&self, start_address: u16, length: u16, symbols: &SymbolTable) -> Vec<(u16, String)> {
         let mut result = Vec::new();
         let mut i = 0;
         while i < length {
             let instruction = self.get_instruction(start_address + i);
-            result.push((start_address + i, format!("{instruction}")));
+            result.push((start_address + i, instruction.format(start_address + i, symbols)));
             match instruction {
                 InstructionOption::Some(_, address_mode, _) => {
                     i += 1 + address_mode.operand_size()
                 },
-                InstructionOption::None(_) => {
+                InstructionOption::None(_) | InstructionOption::Unmapped => {
                     break
                 },
             };
@@ -114,8 +144,8 @@ impl Cpu {
         result
     }
 
-    pub fn get_execution_history(&self) -> Vec<(u16, String)> {
-        self.execution_history.iter().map(|x| x.disassemble()).collect()
+    pub fn get_execution_history(&self, symbols: &SymbolTable) -> Vec<(u16, String)> {
+        self.execution_history.iter().map(|x| x.disassemble(symbols)).collect()
     }
 
     pub fn show_registers<W: fmt::Write>(&self, b: &mut W) -> Result<(), fmt::Error> {
@@ -148,9 +178,9 @@ impl Cpu {
         write!(
             b,
             "\t\t{:04x} {:04x} {:04x}",
-            self.bus.read_address(NMI_ADDRESS),
-            self.bus.read_address(RESET_ADDRESS),
-            self.bus.read_address(IRQ_ADDRESS)
+            self.bus.peek_address(NMI_ADDRESS).unwrap_or_default(),
+            self.bus.peek_address(RESET_ADDRESS).unwrap_or_default(),
+            self.bus.peek_address(IRQ_ADDRESS).unwrap_or_default()
         )?;
 
         writeln!(b)?;
@@ -162,7 +192,7 @@ impl Cpu {
     }
 
     pub fn show_reset_memory<W: fmt::Write>(&self, b: &mut W) -> Result<(), fmt::Error> {
-        let reset_address = self.bus.read_address(RESET_ADDRESS);
+        let reset_address = self.bus.peek_address(RESET_ADDRESS).unwrap_or_default();
         self.show_memory(b, reset_address)
     }
 
@@ -194,8 +224,10 @@ impl Cpu {
                 write!(b, "{color_red}")?;
             }
 
-            let byte = self.bus.read_byte(address);
-            write!(b, " {:02X}", byte)?;
+            match self.bus.peek(address) {
+                Some(byte) => write!(b, " {:02X}", byte)?,
+                None => write!(b, " --")?,
+            }
 
             if address == focal_address {
                 write!(b, "{color_reset}")?;
@@ -1,5 +1,7 @@
+use crate::computer::symbols::SymbolTable;
+
 // Possible address modes for the above instructions
-#[derive(Clone, Copy, Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum AddressMode {
     Accumulator, // OPC A	    operand is AC (implied single byte instruction)
     Absolute,    // OPC $LLHH	operand is address $HHLL
@@ -61,6 +63,30 @@ impl AddressMode {
             AddressMode::ZeropageY => format!("${:02x}, Y", bytes[0]),
         }
     }
+
+    // Like debug_format, but using the name of the address the operand refers to,
+    // if it has one. Branch targets are relative to the address of the instruction.
+    pub fn symbolic_format(&self, bytes: &[u8; 2], address: u16, symbols: &SymbolTable) -> String {
+        let target = match self {
+            AddressMode::Absolute | AddressMode::AbsoluteX | AddressMode::AbsoluteY
+                | AddressMode::Indirect => u16::from_le_bytes(*bytes),
+            AddressMode::IndirectX | AddressMode::IndirectY | AddressMode::Zeropage
+                | AddressMode::ZeropageX | AddressMode::ZeropageY => bytes[0] as u16,
+            AddressMode::Relative => address.wrapping_add(2).wrapping_add(bytes[0] as i8 as u16),
+            _ => return self.debug_format(bytes),
+        };
+        let Some(name) = symbols.name_for(target) else {
+            return self.debug_format(bytes);
+        };
+        match self {
+            AddressMode::AbsoluteX | AddressMode::ZeropageX => format!("{name}, X"),
+            AddressMode::AbsoluteY | AddressMode::ZeropageY => format!("{name}, Y"),
+            AddressMode::Indirect => format!("({name})"),
+            AddressMode::IndirectX => format!("({name}, X)"),
+            AddressMode::IndirectY => format!("({name}), Y"),
+            _ => name.to_string(),
+        }
+    }
 }
 
 impl Operand {
@@ -273,7 +299,7 @@ pub const fn decode_instruction(op_code: u8) -> Option<(Instruction, AddressMode
         0xb6 => Some((Instruction::LDX, AddressMode::ZeropageY, 4)),
         0xb7 => None,
         0xb8 => Some((Instruction::CLV, AddressMode::Implied, 2)),
-        0xb9 => Some((Instruction::LDA, AddressMode::Immediate, 2)),
+        0xb9 => Some((Instruction::LDA, AddressMode::AbsoluteY, 4)),
         0xba => Some((Instruction::TSX, AddressMode::Implied, 2)),
         0xbb => None,
         0xbc => Some((Instruction::LDY, AddressMode::AbsoluteX, 4)),
@@ -363,7 +389,7 @@ pub const fn decode_instruction(op_code: u8) -> Option<(Instruction, AddressMode
 
 // The Instructions that the COU can execute
 #[allow(clippy::upper_case_acronyms)]
-#[derive(Copy, Clone, Debug, strum_macros::Display)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, strum_macros::Display, strum_macros::EnumString)]
 pub enum Instruction {
     ADC, // add with carry
     AND, // and (with accumulator)
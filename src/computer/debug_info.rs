@@ -0,0 +1,377 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+/*
+ * Debug info, as written by ld65 --dbgfile
+ *
+ * The file consists of lines with a record type, and comma separated
+ * key=value fields, like:
+ *
+ *   file   id=0,name="hello.s",size=512,mtime=0x66A0B2C1,mod=0
+ *   seg    id=0,name="CODE",start=0x008000,size=0x0040,addrsize=absolute,type=ro
+ *   span   id=3,seg=0,start=12,size=3
+ *   line   id=7,file=0,line=21,span=3
+ *   scope  id=1,name="print",mod=0,type=scope,size=9,parent=0,span=4+5
+ *
+ * Spans are address ranges within a segment. Lines and scopes refer to the
+ * spans they generated code or data for.
+ */
+
+// Line types. Macro expansions also refer to the line that invoked them.
+const LINE_TYPE_ASSEMBLER: u32 = 0;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceLocation {
+    pub file: String,
+    pub line: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Segment {
+    pub name: String,
+    pub start: u16,
+    pub size: u32,
+}
+
+#[derive(Debug)]
+struct SourceFile {
+    name: String,
+    // The source itself, if it could be found
+    lines: Option<Vec<String>>,
+}
+
+#[derive(Debug)]
+struct Scope {
+    name: String,
+    parent: Option<usize>,
+    ranges: Vec<(u16, u32)>,
+}
+
+// A line that generated code, with the address range it covers
+#[derive(Debug, Clone, Copy)]
+struct LineRange {
+    file: usize,
+    line: usize,
+    line_type: u32,
+    size: u32,
+}
+
+#[derive(Debug, Default)]
+pub struct DebugInfo {
+    files: HashMap<usize, SourceFile>,
+    segments: Vec<Segment>,
+    scopes: HashMap<usize, Scope>,
+    // The best line for each address
+    lines: HashMap<u16, LineRange>,
+}
+
+// Whether a file holds ld65 debug info, rather than a label file
+pub fn is_debug_info(file_name: &Path, text: &str) -> bool {
+    file_name.extension().is_some_and(|e| e == "dbg") || text.starts_with("version\t")
+}
+
+// Split a line of debug info into its key=value fields, removing quotes.
+// Quoted values may contain commas.
+pub(crate) fn fields(text: &str) -> HashMap<&str, &str> {
+    let mut fields = HashMap::new();
+    let mut rest = text;
+    while let Some((key, value)) = rest.split_once('=') {
+        let (value, remainder) = match value.strip_prefix('"') {
+            Some(quoted) => {
+                let end = quoted.find('"').unwrap_or(quoted.len());
+                let remainder = quoted[end..].trim_start_matches('"');
+                (&quoted[..end], remainder.strip_prefix(',').unwrap_or(remainder))
+            }
+            None => value.split_once(',').unwrap_or((value, "")),
+        };
+        fields.insert(key, value);
+        rest = remainder;
+    }
+    fields
+}
+
+fn number(fields: &HashMap<&str, &str>, key: &str) -> Result<u32, String> {
+    let value = fields.get(key).ok_or_else(|| format!("missing field '{}'", key))?;
+    match value.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => value.parse(),
+    }
+    .map_err(|_| format!("invalid number '{}' for '{}'", value, key))
+}
+
+fn optional_number(fields: &HashMap<&str, &str>, key: &str) -> Result<Option<u32>, String> {
+    match fields.contains_key(key) {
+        true => number(fields, key).map(Some),
+        false => Ok(None),
+    }
+}
+
+// Span lists look like "4+5+9"
+fn span_ids(fields: &HashMap<&str, &str>) -> Result<Vec<usize>, String> {
+    match fields.get("span") {
+        None => Ok(Vec::new()),
+        Some(spans) => spans.split('+')
+            .map(|id| id.parse().map_err(|_| format!("invalid span '{}'", id)))
+            .collect(),
+    }
+}
+
+impl DebugInfo {
+    // Read debug info, and the source files it refers to. These are looked
+    // for next to the debug info, and relative to the current directory.
+    pub fn from_file(file_name: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(file_name)
+            .map_err(|e| format!("Was not able to read {}: {}", file_name.display(), e))?;
+        let mut info = Self::parse(&text).map_err(|e| format!("{}: {}", file_name.display(), e))?;
+        let directory = file_name.parent().unwrap_or(Path::new(""));
+        for file in info.files.values_mut() {
+            file.lines = std::fs::read_to_string(directory.join(&file.name))
+                .or_else(|_| std::fs::read_to_string(&file.name))
+                .ok()
+                .map(|source| source.lines().map(str::to_string).collect());
+        }
+        Ok(info)
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut info = Self::default();
+        // Segments and spans by id, to resolve the lines and scopes that refer to them
+        let mut segments = HashMap::new();
+        let mut spans = HashMap::new();
+        let mut lines = Vec::new();
+        let mut scopes = Vec::new();
+
+        for (i, line) in text.lines().enumerate() {
+            let Some((record, rest)) = line.split_once('\t') else {
+                continue;
+            };
+            let fields = fields(rest);
+            let id = || number(&fields, "id").map(|id| id as usize);
+            let result: Result<(), String> = (|| {
+                match record {
+                    "file" => {
+                        let name = fields.get("name").unwrap_or(&"").to_string();
+                        info.files.insert(id()?, SourceFile { name, lines: None });
+                    }
+                    "seg" => {
+                        let start = number(&fields, "start")?;
+                        let segment = Segment {
+                            name: fields.get("name").unwrap_or(&"").to_string(),
+                            start: u16::try_from(start).map_err(|_| "segment outside of memory".to_string())?,
+                            size: number(&fields, "size")?,
+                        };
+                        segments.insert(id()?, segment.clone());
+                        info.segments.push(segment);
+                    }
+                    "span" => {
+                        let (segment, start, size) =
+                            (number(&fields, "seg")?, number(&fields, "start")?, number(&fields, "size")?);
+                        spans.insert(id()?, (segment as usize, start, size));
+                    }
+                    "line" => {
+                        let range = LineRange {
+                            file: number(&fields, "file")? as usize,
+                            line: number(&fields, "line")? as usize,
+                            line_type: optional_number(&fields, "type")?.unwrap_or(LINE_TYPE_ASSEMBLER),
+                            size: 0,
+                        };
+                        lines.push((range, span_ids(&fields)?));
+                    }
+                    "scope" => {
+                        let scope = Scope {
+                            name: fields.get("name").unwrap_or(&"").to_string(),
+                            parent: optional_number(&fields, "parent")?.map(|p| p as usize),
+                            ranges: Vec::new(),
+                        };
+                        scopes.push((id()?, scope, span_ids(&fields)?));
+                    }
+                    _ => {}
+                }
+                Ok(())
+            })();
+            result.map_err(|e| format!("Line {}: {}", i + 1, e))?;
+        }
+
+        // Turn spans into absolute address ranges
+        let range = |span: &usize| -> Result<(u16, u32), String> {
+            let (segment, start, size) = spans.get(span).ok_or_else(|| format!("unknown span {}", span))?;
+            let segment: &Segment = segments.get(segment).ok_or_else(|| format!("unknown segment {}", segment))?;
+            Ok((segment.start.wrapping_add(*start as u16), *size))
+        };
+
+        for (line, span_ids) in lines {
+            for span in &span_ids {
+                let (start, size) = range(span)?;
+                let line = LineRange { size, ..line };
+                for offset in 0..size {
+                    let address = start.wrapping_add(offset as u16);
+                    let better = match info.lines.get(&address) {
+                        None => true,
+                        Some(current) => line.is_better_than(current),
+                    };
+                    if better {
+                        info.lines.insert(address, line);
+                    }
+                }
+            }
+        }
+        for (id, mut scope, span_ids) in scopes {
+            scope.ranges = span_ids.iter().map(range).collect::<Result<_, _>>()?;
+            info.scopes.insert(id, scope);
+        }
+        Ok(info)
+    }
+
+    // The source line that generated the code or data at an address
+    pub fn location_for(&self, address: u16) -> Option<SourceLocation> {
+        let line = self.lines.get(&address)?;
+        Some(SourceLocation {
+            file: self.files.get(&line.file)?.name.clone(),
+            line: line.line,
+        })
+    }
+
+    // The text of a source line, counting from 1
+    pub fn source_line(&self, location: &SourceLocation) -> Option<&str> {
+        self.source_lines(location, 0, 0).into_iter().next().map(|(_, text)| text)
+    }
+
+    // The source lines around a location, with their line numbers
+    pub fn source_lines(&self, location: &SourceLocation, before: usize, after: usize) -> Vec<(usize, &str)> {
+        let file = self.files.values().find(|file| file.name == location.file);
+        let Some(lines) = file.and_then(|file| file.lines.as_ref()) else {
+            return Vec::new();
+        };
+        let first = location.line.saturating_sub(before).max(1);
+        let last = (location.line + after).min(lines.len());
+        (first..=last).map(|n| (n, lines[n - 1].as_str())).collect()
+    }
+
+    pub fn segment_for(&self, address: u16) -> Option<&Segment> {
+        self.segments.iter()
+            .find(|segment| (address as u32).wrapping_sub(segment.start as u32) < segment.size)
+    }
+
+    // The innermost named scope containing an address, as in "outer::inner"
+    pub fn scope_for(&self, address: u16) -> Option<String> {
+        let contains = |scope: &Scope| scope.ranges.iter()
+            .any(|(start, size)| (address as u32).wrapping_sub(*start as u32) < *size);
+        let size = |scope: &Scope| scope.ranges.iter().map(|(_, size)| size).sum::<u32>();
+        let (mut id, _) = self.scopes.iter()
+            .filter(|(_, scope)| !scope.name.is_empty() && contains(scope))
+            .min_by_key(|(_, scope)| size(scope))?;
+
+        let mut names = Vec::new();
+        while let Some(scope) = self.scopes.get(id) {
+            if !scope.name.is_empty() {
+                names.push(scope.name.as_str());
+            }
+            match &scope.parent {
+                Some(parent) => id = parent,
+                None => break,
+            }
+        }
+        names.reverse();
+        Some(names.join("::"))
+    }
+}
+
+impl LineRange {
+    // Lines in the assembler source come before macro definitions, and
+    // lines for a single instruction before those spanning a whole block
+    fn is_better_than(&self, other: &LineRange) -> bool {
+        let own_type = self.line_type == LINE_TYPE_ASSEMBLER;
+        let other_type = other.line_type == LINE_TYPE_ASSEMBLER;
+        if own_type != other_type {
+            return own_type;
+        }
+        self.size < other.size
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DEBUG_INFO: &str = "version\tmajor=2,minor=0\n\
+        info\tcsym=0,file=2,lib=0,line=6,mod=1,scope=2,seg=2,span=5,sym=2,type=4\n\
+        file\tid=0,name=\"hello.s\",size=200,mtime=0x66A0B2C1,mod=0\n\
+        file\tid=1,name=\"macros, and more.inc\",size=50,mtime=0x66A0B2C1,mod=0\n\
+        mod\tid=0,name=\"hello.o\",file=0\n\
+        seg\tid=0,name=\"CODE\",start=0x008000,size=0x000A,addrsize=absolute,type=ro,oname=\"hello.rom\",ooffs=0\n\
+        seg\tid=1,name=\"DATA\",start=0x000200,size=0x0004,addrsize=absolute,type=rw,oname=\"hello.rom\",ooffs=16\n\
+        span\tid=0,seg=0,start=0,size=2\n\
+        span\tid=1,seg=0,start=2,size=3\n\
+        span\tid=2,seg=0,start=5,size=5\n\
+        span\tid=3,seg=1,start=0,size=4\n\
+        span\tid=4,seg=0,start=0,size=10\n\
+        line\tid=0,file=0,line=3,span=0\n\
+        line\tid=1,file=0,line=4,span=1\n\
+        line\tid=2,file=1,line=2,type=2,count=1,span=2\n\
+        line\tid=3,file=0,line=5,span=2\n\
+        line\tid=4,file=0,line=9,span=3\n\
+        line\tid=5,file=0,line=1,span=4\n\
+        scope\tid=0,name=\"\",mod=0,size=10,span=4\n\
+        scope\tid=1,name=\"print\",mod=0,type=scope,size=5,parent=0,span=2\n\
+        sym\tid=0,name=\"reset\",addrsize=absolute,size=1,scope=0,def=0,val=0x8000,seg=0,type=lab\n";
+
+    fn location(line: usize) -> Option<SourceLocation> {
+        Some(SourceLocation { file: "hello.s".to_string(), line })
+    }
+
+    #[test]
+    fn field_parsing() {
+        let fields = fields("id=1,name=\"a, b\",size=50,type=\"x\"");
+        assert_eq!(fields.get("id"), Some(&"1"));
+        assert_eq!(fields.get("name"), Some(&"a, b"));
+        assert_eq!(fields.get("size"), Some(&"50"));
+        assert_eq!(fields.get("type"), Some(&"x"));
+    }
+
+    #[test]
+    fn lines() {
+        let info = DebugInfo::parse(DEBUG_INFO).unwrap();
+        assert_eq!(info.location_for(0x8000), location(3));
+        assert_eq!(info.location_for(0x8001), location(3));
+        assert_eq!(info.location_for(0x8002), location(4));
+        // The macro invocation, not the line in the macro
+        assert_eq!(info.location_for(0x8006), location(5));
+        assert_eq!(info.location_for(0x0203), location(9));
+        assert_eq!(info.location_for(0x800a), None);
+    }
+
+    #[test]
+    fn source() {
+        let mut info = DebugInfo::parse(DEBUG_INFO).unwrap();
+        let source = ["; hello", "", "reset:  LDX #0", "        LDA #1", "        PRINT", "", "", "", "x: .word 0"];
+        info.files.get_mut(&0).unwrap().lines = Some(source.iter().map(|s| s.to_string()).collect());
+
+        let here = location(4).unwrap();
+        assert_eq!(info.source_line(&here), Some("        LDA #1"));
+        assert_eq!(info.source_lines(&here, 5, 1), vec![
+            (1, "; hello"), (2, ""), (3, "reset:  LDX #0"), (4, "        LDA #1"), (5, "        PRINT"),
+        ]);
+        assert_eq!(info.source_lines(&location(9).unwrap(), 0, 3), vec![(9, "x: .word 0")]);
+        // Files that weren't found have no source
+        let elsewhere = SourceLocation { file: "macros, and more.inc".to_string(), line: 2 };
+        assert_eq!(info.source_line(&elsewhere), None);
+    }
+
+    #[test]
+    fn segments_and_scopes() {
+        let info = DebugInfo::parse(DEBUG_INFO).unwrap();
+        assert_eq!(info.segment_for(0x8009).map(|s| s.name.as_str()), Some("CODE"));
+        assert_eq!(info.segment_for(0x0200).map(|s| s.name.as_str()), Some("DATA"));
+        assert_eq!(info.segment_for(0x800a), None);
+
+        assert_eq!(info.scope_for(0x8005), Some("print".to_string()));
+        assert_eq!(info.scope_for(0x8000), None);
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(DebugInfo::parse("span\tid=0,seg=0,start=0\n").is_err());
+        assert!(DebugInfo::parse("line\tid=0,file=0,line=1,span=3\n").is_err());
+        assert!(DebugInfo::parse("seg\tid=0,name=\"X\",start=0x10000,size=1\n").is_err());
+    }
+}
@@ -0,0 +1,15 @@
+pub mod acia;
+pub mod connection;
+pub mod exit_port;
+pub mod hd44780;
+pub mod pia;
+pub mod ports;
+pub mod via;
+
+pub use acia::Acia;
+pub use connection::{Connection, ConnectionSpec};
+pub use exit_port::ExitPort;
+pub use hd44780::Lcd;
+pub use pia::Pia;
+pub use ports::{Pins, PortPeripheral};
+pub use via::Via;
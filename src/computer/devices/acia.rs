@@ -0,0 +1,257 @@
+use std::cell::Cell;
+
+use super::connection::Connection;
+use crate::computer::bus::Addressable;
+use crate::computer::clock::TickCount;
+
+/*
+ * MOS 6551 ACIA (Asynchronous Communications Interface Adapter)
+ *
+ * A serial port, occupying four addresses:
+ *
+ *   0: data      read: received byte, write: byte to transmit
+ *   1: status    read: status, write: programmed reset
+ *   2: command
+ *   3: control
+ *
+ * Transmission is instantaneous, so the transmit data register is always empty.
+ * A received byte is picked up from the connection whenever the receive data
+ * register has been read. Baud rate, word length and parity settings in the
+ * control register are stored, but don't affect anything.
+ */
+
+// Status register bits
+const STATUS_IRQ: u8 = 0b1000_0000;
+const STATUS_TDRE: u8 = 0b0001_0000; // transmit data register empty
+const STATUS_RDRF: u8 = 0b0000_1000; // receive data register full
+const STATUS_OVERRUN: u8 = 0b0000_0100;
+
+// Command register bits
+const COMMAND_PARITY: u8 = 0b1110_0000;
+const COMMAND_ECHO: u8 = 0b0001_0000;
+const COMMAND_TRANSMIT: u8 = 0b0000_1100;
+const COMMAND_TRANSMIT_IRQ: u8 = 0b0000_0100;
+const COMMAND_RECEIVE_IRQ_DISABLE: u8 = 0b0000_0010;
+const COMMAND_DTR: u8 = 0b0000_0001;
+
+const REGISTER_DATA: u16 = 0;
+const REGISTER_STATUS: u16 = 1;
+const REGISTER_COMMAND: u16 = 2;
+const REGISTER_CONTROL: u16 = 3;
+
+#[derive(Debug)]
+pub struct Acia {
+    connection: Box<dyn Connection>,
+
+    receive_data: Cell<u8>,
+    // Reading data or status has side effects, hence the Cell
+    status: Cell<u8>,
+    command: u8,
+    control: u8,
+}
+
+impl Acia {
+    pub fn new(connection: Box<dyn Connection>) -> Self {
+        Self {
+            connection,
+            receive_data: Cell::new(0),
+            status: Cell::new(STATUS_TDRE),
+            command: COMMAND_RECEIVE_IRQ_DISABLE,
+            control: 0,
+        }
+    }
+
+    fn enabled(&self) -> bool {
+        self.command & COMMAND_DTR != 0
+    }
+
+    fn receive_irq_enabled(&self) -> bool {
+        self.enabled() && self.command & COMMAND_RECEIVE_IRQ_DISABLE == 0
+    }
+
+    fn transmit_irq_enabled(&self) -> bool {
+        self.enabled() && self.command & COMMAND_TRANSMIT == COMMAND_TRANSMIT_IRQ
+    }
+
+    fn set_status(&self, bits: u8) {
+        self.status.set(self.status.get() | bits);
+    }
+
+    fn clear_status(&self, bits: u8) {
+        self.status.set(self.status.get() & !bits);
+    }
+
+    fn transmit(&mut self, byte: u8) {
+        self.connection.send(byte);
+        // The byte is gone immediately, so the register is empty again
+        if self.transmit_irq_enabled() {
+            self.set_status(STATUS_IRQ);
+        }
+    }
+}
+
+impl Addressable for Acia {
+    fn size(&self) -> usize {
+        4
+    }
+
+    fn read_byte(&self, address: u16) -> u8 {
+        match address {
+            REGISTER_DATA => {
+                self.clear_status(STATUS_RDRF | STATUS_OVERRUN);
+                self.receive_data.get()
+            }
+            REGISTER_STATUS => {
+                let status = self.status.get();
+                self.clear_status(STATUS_IRQ);
+                status
+            }
+            REGISTER_COMMAND => self.command,
+            _ => self.control,
+        }
+    }
+
+    fn peek(&self, address: u16) -> Option<u8> {
+        Some(match address {
+            REGISTER_DATA => self.receive_data.get(),
+            REGISTER_STATUS => self.status.get(),
+            REGISTER_COMMAND => self.command,
+            _ => self.control,
+        })
+    }
+
+    fn write_byte(&mut self, address: u16, byte: u8) {
+        match address {
+            REGISTER_DATA => self.transmit(byte),
+            REGISTER_STATUS => {
+                // Programmed reset: leaves parity and control alone
+                self.command &= COMMAND_PARITY;
+                self.command |= COMMAND_RECEIVE_IRQ_DISABLE;
+                self.clear_status(STATUS_OVERRUN);
+            }
+            REGISTER_COMMAND => self.command = byte,
+            REGISTER_CONTROL => self.control = byte,
+            _ => unreachable!(),
+        }
+    }
+
+    fn tick(&mut self, _cycles: TickCount) {
+        // Only take a byte from the host when there is room for it, so nothing gets lost
+        if self.status.get() & STATUS_RDRF != 0 {
+            return;
+        }
+        if let Some(byte) = self.connection.receive() {
+            self.receive_data.set(byte);
+            self.set_status(STATUS_RDRF);
+            if self.receive_irq_enabled() {
+                self.set_status(STATUS_IRQ);
+            }
+            if self.command & COMMAND_ECHO != 0 {
+                self.connection.send(byte);
+            }
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.status.get() & STATUS_IRQ != 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::computer::devices::connection::{ChannelConnection, ChannelEndpoint};
+
+    fn create_acia() -> (Acia, ChannelEndpoint) {
+        let (connection, endpoint) = ChannelConnection::new();
+        (Acia::new(Box::new(connection)), endpoint)
+    }
+
+    #[test]
+    fn transmit() {
+        let (mut acia, endpoint) = create_acia();
+        assert_eq!(acia.read_byte(REGISTER_STATUS) & STATUS_TDRE, STATUS_TDRE);
+
+        acia.write_byte(REGISTER_DATA, b'H');
+        acia.write_byte(REGISTER_DATA, b'i');
+        assert_eq!(endpoint.receiver.try_recv(), Ok(b'H'));
+        assert_eq!(endpoint.receiver.try_recv(), Ok(b'i'));
+        assert_eq!(acia.read_byte(REGISTER_STATUS) & STATUS_TDRE, STATUS_TDRE);
+        assert!(!acia.irq());
+    }
+
+    #[test]
+    fn receive() {
+        let (mut acia, endpoint) = create_acia();
+        acia.tick(1);
+        assert_eq!(acia.read_byte(REGISTER_STATUS) & STATUS_RDRF, 0);
+
+        endpoint.sender.send(b'a').unwrap();
+        endpoint.sender.send(b'b').unwrap();
+        acia.tick(1);
+        assert_eq!(acia.read_byte(REGISTER_STATUS) & STATUS_RDRF, STATUS_RDRF);
+        // The second byte waits until the first one has been read
+        acia.tick(1);
+        assert_eq!(acia.read_byte(REGISTER_DATA), b'a');
+        assert_eq!(acia.read_byte(REGISTER_STATUS) & STATUS_RDRF, 0);
+
+        acia.tick(1);
+        assert_eq!(acia.read_byte(REGISTER_DATA), b'b');
+        // Interrupts are disabled after reset
+        assert!(!acia.irq());
+    }
+
+    #[test]
+    fn receive_interrupt() {
+        let (mut acia, endpoint) = create_acia();
+        // DTR on, receiver interrupts enabled
+        acia.write_byte(REGISTER_COMMAND, 0b0000_1001);
+
+        acia.tick(1);
+        assert!(!acia.irq());
+
+        endpoint.sender.send(b'x').unwrap();
+        acia.tick(1);
+        assert!(acia.irq());
+
+        // Looking at the registers takes nothing
+        assert_eq!(acia.peek(REGISTER_DATA), Some(b'x'));
+        assert_eq!(acia.peek(REGISTER_STATUS).unwrap() & STATUS_IRQ, STATUS_IRQ);
+        assert!(acia.irq());
+
+        // Reading the status register acknowledges the interrupt
+        let status = acia.read_byte(REGISTER_STATUS);
+        assert_eq!(status & (STATUS_IRQ | STATUS_RDRF), STATUS_IRQ | STATUS_RDRF);
+        assert!(!acia.irq());
+        assert_eq!(acia.read_byte(REGISTER_DATA), b'x');
+    }
+
+    #[test]
+    fn transmit_interrupt() {
+        let (mut acia, _endpoint) = create_acia();
+        acia.write_byte(REGISTER_COMMAND, 0b0000_0111);
+        acia.write_byte(REGISTER_DATA, b'!');
+        assert!(acia.irq());
+        acia.read_byte(REGISTER_STATUS);
+        assert!(!acia.irq());
+    }
+
+    #[test]
+    fn echo() {
+        let (mut acia, endpoint) = create_acia();
+        acia.write_byte(REGISTER_COMMAND, 0b0001_1011);
+        endpoint.sender.send(b'e').unwrap();
+        acia.tick(1);
+        assert_eq!(endpoint.receiver.try_recv(), Ok(b'e'));
+    }
+
+    #[test]
+    fn programmed_reset() {
+        let (mut acia, _endpoint) = create_acia();
+        acia.write_byte(REGISTER_COMMAND, 0b0110_1001);
+        acia.write_byte(REGISTER_CONTROL, 0x1f);
+        acia.write_byte(REGISTER_STATUS, 0x00);
+        assert_eq!(acia.read_byte(REGISTER_COMMAND), 0b0110_0010);
+        assert_eq!(acia.read_byte(REGISTER_CONTROL), 0x1f);
+    }
+}
@@ -0,0 +1,437 @@
+use std::fmt::{self, Debug};
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use serde::Deserialize;
+
+/*
+ * Connections
+ *
+ * A connection is the host side of an emulated serial line or terminal. Devices
+ * send bytes to the host through it, and pick up whatever the host has sent when
+ * they are ready for it. Receiving never blocks: input is gathered by a background
+ * thread and queued until the device asks for it.
+ */
+pub trait Connection: Debug {
+    // The next byte sent by the host, if there is one
+    fn receive(&mut self) -> Option<u8>;
+
+    // Send a byte to the host
+    fn send(&mut self, byte: u8);
+}
+
+// The kinds of connection that can be requested on the command line
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum ConnectionSpec {
+    Stdio,
+    // The console of a UI, such as the one in c6502-tui
+    Console,
+    Pty,
+    UnixSocket(PathBuf),
+}
+
+impl FromStr for ConnectionSpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "stdio" => Ok(ConnectionSpec::Stdio),
+            "console" => Ok(ConnectionSpec::Console),
+            "pty" => Ok(ConnectionSpec::Pty),
+            _ => match s.strip_prefix("unix:") {
+                Some(path) if !path.is_empty() => Ok(ConnectionSpec::UnixSocket(PathBuf::from(path))),
+                _ => Err(format!("Unknown connection '{}', expected stdio, console, pty or unix:<path>", s)),
+            },
+        }
+    }
+}
+
+impl TryFrom<String> for ConnectionSpec {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl fmt::Display for ConnectionSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConnectionSpec::Stdio => write!(f, "stdio"),
+            ConnectionSpec::Console => write!(f, "console"),
+            ConnectionSpec::Pty => write!(f, "pty"),
+            ConnectionSpec::UnixSocket(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+impl ConnectionSpec {
+    pub fn open(&self) -> io::Result<Box<dyn Connection>> {
+        Ok(match self {
+            ConnectionSpec::Stdio => Box::new(StdioConnection::new()),
+            ConnectionSpec::Console => {
+                return Err(io::Error::new(io::ErrorKind::Unsupported, "the console needs a UI to show it"))
+            }
+            ConnectionSpec::Pty => Box::new(PtyConnection::new()?),
+            ConnectionSpec::UnixSocket(path) => Box::new(UnixSocketConnection::new(path.clone())?),
+        })
+    }
+
+    // Open the connection, like open(). The other end of a console is put in
+    // console, for a UI to use. There can only be one.
+    pub fn open_with_console(&self, console: &mut Option<ChannelEndpoint>) -> io::Result<Box<dyn Connection>> {
+        match self {
+            ConnectionSpec::Console if console.is_some() => {
+                Err(io::Error::new(io::ErrorKind::AlreadyExists, "the console is already in use"))
+            }
+            ConnectionSpec::Console => {
+                let (connection, endpoint) = ChannelConnection::new();
+                *console = Some(endpoint);
+                Ok(Box::new(connection))
+            }
+            _ => self.open(),
+        }
+    }
+}
+
+// Read bytes from the given reader on a background thread, until it runs dry
+fn spawn_reader<R: Read + Send + 'static>(mut reader: R, sender: Sender<u8>) {
+    thread::spawn(move || {
+        let mut buffer = [0u8; 256];
+        loop {
+            match reader.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(n) => {
+                    for byte in &buffer[..n] {
+                        if sender.send(*byte).is_err() {
+                            return;
+                        }
+                    }
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    log::debug!("Connection closed: {}", e);
+                    break;
+                }
+            }
+        }
+    });
+}
+
+fn try_receive(receiver: &Receiver<u8>) -> Option<u8> {
+    match receiver.try_recv() {
+        Ok(byte) => Some(byte),
+        Err(TryRecvError::Empty) | Err(TryRecvError::Disconnected) => None,
+    }
+}
+
+// The terminal the emulator runs in. Line endings typed by the user are
+// translated into the carriage returns 6502 software expects.
+pub struct StdioConnection {
+    input: Receiver<u8>,
+}
+
+impl StdioConnection {
+    pub fn new() -> Self {
+        let (sender, input) = mpsc::channel();
+        spawn_reader(io::stdin(), sender);
+        Self { input }
+    }
+}
+
+impl Default for StdioConnection {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Debug for StdioConnection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "stdio")
+    }
+}
+
+impl Connection for StdioConnection {
+    fn receive(&mut self) -> Option<u8> {
+        try_receive(&self.input).map(|byte| if byte == b'\n' { b'\r' } else { byte })
+    }
+
+    fn send(&mut self, byte: u8) {
+        let mut stdout = io::stdout();
+        let byte = if byte == b'\r' { b'\n' } else { byte };
+        let _ = stdout.write_all(&[byte]);
+        let _ = stdout.flush();
+    }
+}
+
+// A pseudo terminal. Connect a terminal program (screen, minicom, ...) to the
+// slave device, whose name is logged when the connection is opened.
+pub struct PtyConnection {
+    master: File,
+    input: Receiver<u8>,
+    slave_name: PathBuf,
+    // Keep the slave open, so reads don't fail while no terminal is connected
+    _slave: File,
+}
+
+impl PtyConnection {
+    pub fn new() -> io::Result<Self> {
+        use nix::sys::termios;
+
+        let pty = nix::pty::openpty(None, None)?;
+        let mut attributes = termios::tcgetattr(&pty.slave)?;
+        termios::cfmakeraw(&mut attributes);
+        termios::tcsetattr(&pty.slave, termios::SetArg::TCSANOW, &attributes)?;
+        let slave_name = nix::unistd::ttyname(&pty.slave)?;
+        log::info!("Serial connection available on {}", slave_name.display());
+
+        let master = File::from(pty.master);
+        let (sender, input) = mpsc::channel();
+        spawn_reader(master.try_clone()?, sender);
+
+        Ok(Self {
+            master,
+            input,
+            slave_name,
+            _slave: File::from(pty.slave),
+        })
+    }
+
+    pub fn slave_name(&self) -> &PathBuf {
+        &self.slave_name
+    }
+}
+
+impl Debug for PtyConnection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "pty {}", self.slave_name.display())
+    }
+}
+
+impl Connection for PtyConnection {
+    fn receive(&mut self) -> Option<u8> {
+        try_receive(&self.input)
+    }
+
+    fn send(&mut self, byte: u8) {
+        let _ = self.master.write_all(&[byte]);
+    }
+}
+
+// A Unix domain socket the emulator listens on. One client at a time can
+// connect; output sent while nobody is connected is dropped.
+pub struct UnixSocketConnection {
+    path: PathBuf,
+    client: Arc<Mutex<Option<UnixStream>>>,
+    input: Receiver<u8>,
+}
+
+impl UnixSocketConnection {
+    pub fn new(path: PathBuf) -> io::Result<Self> {
+        // Remove any stale socket left behind by a previous run, but nothing else
+        match std::fs::symlink_metadata(&path) {
+            Ok(metadata) if metadata.file_type().is_socket() => std::fs::remove_file(&path)?,
+            Ok(_) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!("{} is there already, and isn't a socket", path.display()),
+                ));
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        let listener = UnixListener::bind(&path)?;
+        log::info!("Serial connection listening on {}", path.display());
+
+        let client: Arc<Mutex<Option<UnixStream>>> = Arc::new(Mutex::new(None));
+        let (sender, input) = mpsc::channel();
+
+        let accepted = client.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                log::info!("Serial client connected");
+                if let Ok(reader) = stream.try_clone() {
+                    *accepted.lock().unwrap() = Some(stream);
+                    spawn_reader(reader, sender.clone());
+                }
+            }
+        });
+
+        Ok(Self { path, client, input })
+    }
+}
+
+impl Debug for UnixSocketConnection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unix:{}", self.path.display())
+    }
+}
+
+impl Connection for UnixSocketConnection {
+    fn receive(&mut self) -> Option<u8> {
+        try_receive(&self.input)
+    }
+
+    fn send(&mut self, byte: u8) {
+        let mut client = self.client.lock().unwrap();
+        if let Some(stream) = client.as_mut() {
+            if stream.write_all(&[byte]).is_err() {
+                log::info!("Serial client disconnected");
+                *client = None;
+            }
+        }
+    }
+}
+
+impl Drop for UnixSocketConnection {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+// A connection to another part of the emulator, such as a console in the UI,
+// or a test. The other side uses the returned ChannelEndpoint.
+#[derive(Debug)]
+pub struct ChannelConnection {
+    input: Receiver<u8>,
+    output: Sender<u8>,
+}
+
+#[derive(Debug)]
+pub struct ChannelEndpoint {
+    pub sender: Sender<u8>,
+    pub receiver: Receiver<u8>,
+}
+
+impl ChannelConnection {
+    pub fn new() -> (Self, ChannelEndpoint) {
+        let (to_device, input) = mpsc::channel();
+        let (output, from_device) = mpsc::channel();
+        (
+            Self { input, output },
+            ChannelEndpoint { sender: to_device, receiver: from_device },
+        )
+    }
+}
+
+impl Connection for ChannelConnection {
+    fn receive(&mut self) -> Option<u8> {
+        try_receive(&self.input)
+    }
+
+    fn send(&mut self, byte: u8) {
+        let _ = self.output.send(byte);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    fn receive_with_timeout(connection: &mut dyn Connection) -> Option<u8> {
+        let start = Instant::now();
+        while start.elapsed() < Duration::from_secs(2) {
+            if let Some(byte) = connection.receive() {
+                return Some(byte);
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+        None
+    }
+
+    #[test]
+    fn parse_spec() {
+        assert_eq!("stdio".parse(), Ok(ConnectionSpec::Stdio));
+        assert_eq!("pty".parse(), Ok(ConnectionSpec::Pty));
+        assert_eq!("console".parse(), Ok(ConnectionSpec::Console));
+        assert_eq!("unix:/tmp/acia".parse(), Ok(ConnectionSpec::UnixSocket(PathBuf::from("/tmp/acia"))));
+        assert!("unix:".parse::<ConnectionSpec>().is_err());
+        assert!("serial".parse::<ConnectionSpec>().is_err());
+    }
+
+    #[test]
+    fn console_connection() {
+        let mut console = None;
+        let mut connection = ConnectionSpec::Console.open_with_console(&mut console).unwrap();
+        let endpoint = console.as_ref().unwrap();
+        endpoint.sender.send(b'A').unwrap();
+        assert_eq!(connection.receive(), Some(b'A'));
+
+        assert!(ConnectionSpec::Console.open_with_console(&mut console).is_err());
+        assert!(ConnectionSpec::Console.open().is_err());
+    }
+
+    #[test]
+    fn channel_connection() {
+        let (mut connection, endpoint) = ChannelConnection::new();
+        assert_eq!(connection.receive(), None);
+
+        endpoint.sender.send(b'A').unwrap();
+        assert_eq!(connection.receive(), Some(b'A'));
+        assert_eq!(connection.receive(), None);
+
+        connection.send(b'B');
+        assert_eq!(endpoint.receiver.try_recv(), Ok(b'B'));
+    }
+
+    #[test]
+    fn unix_socket_connection() {
+        let path = std::env::temp_dir().join(format!("m6502-test-{}.sock", std::process::id()));
+        let mut connection = UnixSocketConnection::new(path.clone()).unwrap();
+
+        let mut client = UnixStream::connect(&path).unwrap();
+        client.write_all(b"x").unwrap();
+        assert_eq!(receive_with_timeout(&mut connection), Some(b'x'));
+
+        connection.send(b'y');
+        let mut buffer = [0u8; 1];
+        client.read_exact(&mut buffer).unwrap();
+        assert_eq!(buffer[0], b'y');
+
+        drop(connection);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn unix_socket_over_stale_socket_only() {
+        let path = std::env::temp_dir().join(format!("m6502-test-{}-stale.sock", std::process::id()));
+        // A socket left behind is replaced
+        std::mem::forget(UnixListener::bind(&path).unwrap());
+        let connection = UnixSocketConnection::new(path.clone()).unwrap();
+        drop(connection);
+
+        // Anything else is left alone
+        std::fs::write(&path, b"keep").unwrap();
+        let result = UnixSocketConnection::new(path.clone());
+        assert_eq!(result.err().map(|e| e.kind()), Some(io::ErrorKind::AlreadyExists));
+        assert_eq!(std::fs::read(&path).unwrap(), b"keep");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn pty_connection() {
+        let mut connection = PtyConnection::new().unwrap();
+        let mut slave = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(connection.slave_name())
+            .unwrap();
+
+        slave.write_all(b"z").unwrap();
+        assert_eq!(receive_with_timeout(&mut connection), Some(b'z'));
+
+        connection.send(b'w');
+        let mut buffer = [0u8; 1];
+        slave.read_exact(&mut buffer).unwrap();
+        assert_eq!(buffer[0], b'w');
+    }
+}
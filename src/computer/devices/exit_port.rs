@@ -0,0 +1,57 @@
+use std::cell::Cell;
+use std::rc::Rc;
+
+use crate::computer::bus::Addressable;
+
+/*
+ * Exit port
+ *
+ * Not a real chip: a single byte on the bus that a program can write to,
+ * to tell whoever runs it that it's done, and with which result. Clones
+ * share the same value, so the computer can see what the program wrote.
+ */
+#[derive(Debug, Clone, Default)]
+pub struct ExitPort {
+    value: Rc<Cell<Option<u8>>>,
+}
+
+impl ExitPort {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // The value written since the last call, if any
+    pub fn take(&self) -> Option<u8> {
+        self.value.take()
+    }
+}
+
+impl Addressable for ExitPort {
+    fn size(&self) -> usize {
+        1
+    }
+
+    fn read_byte(&self, _address: u16) -> u8 {
+        self.value.get().unwrap_or(0)
+    }
+
+    fn write_byte(&mut self, _address: u16, byte: u8) {
+        self.value.set(Some(byte));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shared_value() {
+        let port = ExitPort::new();
+        let mut device = port.clone();
+        assert_eq!(port.take(), None);
+        device.write_byte(0, 3);
+        assert_eq!(device.read_byte(0), 3);
+        assert_eq!(port.take(), Some(3));
+        assert_eq!(port.take(), None);
+    }
+}
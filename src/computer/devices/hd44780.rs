@@ -0,0 +1,503 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use super::ports::{Pins, PortPeripheral};
+
+/*
+ * Hitachi HD44780 character LCD controller
+ *
+ * Display data RAM holds 80 characters. In two line mode, the first line starts
+ * at address $00 and the second at $40, each 40 characters long. The display
+ * shows a window of these, moved by display shifts.
+ *
+ * Instructions (RS low):
+ *
+ *   $01 clear display              $10 cursor/display shift (S/C, R/L)
+ *   $02 return home                $20 function set (DL, N, F)
+ *   $04 entry mode (I/D, S)        $40 set CGRAM address
+ *   $08 display control (D, C, B)  $80 set DDRAM address
+ *
+ * Everything executes instantly, so the busy flag is never set.
+ */
+
+const DDRAM_SIZE: usize = 0x80;
+const CGRAM_SIZE: usize = 0x40;
+const LINE_LENGTH: u8 = 40;
+const SECOND_LINE: u8 = 0x40;
+
+#[derive(Debug)]
+pub struct Hd44780 {
+    columns: u8,
+    rows: u8,
+
+    ddram: [u8; DDRAM_SIZE],
+    cgram: [u8; CGRAM_SIZE],
+    address: u8,
+    cgram_selected: bool,
+
+    increment: bool,
+    shift_on_write: bool,
+    display_on: bool,
+    cursor_on: bool,
+    blink_on: bool,
+    // How far the display window has been shifted to the left
+    shift: u8,
+    eight_bit: bool,
+    two_lines: bool,
+}
+
+impl Hd44780 {
+    pub fn new(columns: u8, rows: u8) -> Self {
+        // The state after the internal reset at power up
+        Self {
+            columns,
+            rows,
+            ddram: [b' '; DDRAM_SIZE],
+            cgram: [0; CGRAM_SIZE],
+            address: 0,
+            cgram_selected: false,
+            increment: true,
+            shift_on_write: false,
+            display_on: false,
+            cursor_on: false,
+            blink_on: false,
+            shift: 0,
+            eight_bit: true,
+            two_lines: false,
+        }
+    }
+
+    pub fn write_instruction(&mut self, value: u8) {
+        match value.leading_zeros() {
+            7 => {
+                self.ddram = [b' '; DDRAM_SIZE];
+                self.address = 0;
+                self.cgram_selected = false;
+                self.increment = true;
+                self.shift = 0;
+            }
+            6 => {
+                self.address = 0;
+                self.cgram_selected = false;
+                self.shift = 0;
+            }
+            5 => {
+                self.increment = value & 0b10 != 0;
+                self.shift_on_write = value & 0b01 != 0;
+            }
+            4 => {
+                self.display_on = value & 0b100 != 0;
+                self.cursor_on = value & 0b010 != 0;
+                self.blink_on = value & 0b001 != 0;
+            }
+            3 => {
+                let right = value & 0b0100 != 0;
+                if value & 0b1000 != 0 {
+                    self.shift_display(right);
+                } else {
+                    self.move_address(right);
+                }
+            }
+            2 => {
+                self.eight_bit = value & 0b1_0000 != 0;
+                self.two_lines = value & 0b0_1000 != 0;
+            }
+            1 => {
+                self.address = value & 0x3f;
+                self.cgram_selected = true;
+            }
+            0 => {
+                self.address = value & 0x7f;
+                self.cgram_selected = false;
+            }
+            // A zero byte does nothing
+            _ => {}
+        }
+    }
+
+    pub fn write_data(&mut self, value: u8) {
+        if self.cgram_selected {
+            self.cgram[self.address as usize % CGRAM_SIZE] = value;
+        } else {
+            self.ddram[self.address as usize % DDRAM_SIZE] = value;
+            if self.shift_on_write {
+                self.shift_display(!self.increment);
+            }
+        }
+        self.move_address(self.increment);
+    }
+
+    // Busy flag (always clear) and the address counter
+    pub fn read_status(&self) -> u8 {
+        self.address & 0x7f
+    }
+
+    pub fn read_data(&mut self) -> u8 {
+        let value = if self.cgram_selected {
+            self.cgram[self.address as usize % CGRAM_SIZE]
+        } else {
+            self.ddram[self.address as usize % DDRAM_SIZE]
+        };
+        self.move_address(self.increment);
+        value
+    }
+
+    fn move_address(&mut self, forward: bool) {
+        if self.cgram_selected {
+            self.address = if forward { self.address + 1 } else { self.address.wrapping_sub(1) } & 0x3f;
+            return;
+        }
+        self.address = if self.two_lines {
+            match (forward, self.address) {
+                (true, 0x27) => SECOND_LINE,
+                (true, 0x67) => 0x00,
+                (false, 0x00) => 0x67,
+                (false, SECOND_LINE) => 0x27,
+                (true, a) => a + 1,
+                (false, a) => a - 1,
+            }
+        } else {
+            match (forward, self.address) {
+                (true, 0x4f) => 0x00,
+                (false, 0x00) => 0x4f,
+                (true, a) => a + 1,
+                (false, a) => a - 1,
+            }
+        };
+    }
+
+    fn shift_display(&mut self, right: bool) {
+        // Shifting the display right moves the window left
+        self.shift = if right {
+            (self.shift + LINE_LENGTH - 1) % LINE_LENGTH
+        } else {
+            (self.shift + 1) % LINE_LENGTH
+        };
+    }
+
+    // The DDRAM address shown at a position on the display
+    fn address_at(&self, row: u8, column: u8) -> Option<u8> {
+        if self.two_lines {
+            // Four line displays continue lines one and two after the visible columns
+            let line_start = if row.is_multiple_of(2) { 0 } else { SECOND_LINE };
+            let offset = (row / 2) * self.columns;
+            Some(line_start + (offset + column + self.shift) % LINE_LENGTH)
+        } else if row == 0 {
+            Some((column + self.shift) % (2 * LINE_LENGTH))
+        } else {
+            None
+        }
+    }
+
+    pub fn columns(&self) -> u8 {
+        self.columns
+    }
+
+    pub fn rows(&self) -> u8 {
+        self.rows
+    }
+
+    pub fn display_on(&self) -> bool {
+        self.display_on
+    }
+
+    pub fn blink_on(&self) -> bool {
+        self.blink_on
+    }
+
+    // The character codes on the display, one vector per row
+    pub fn lines(&self) -> Vec<Vec<u8>> {
+        (0..self.rows)
+            .map(|row| {
+                (0..self.columns)
+                    .map(|column| match self.address_at(row, column) {
+                        Some(address) if self.display_on => self.ddram[address as usize],
+                        _ => b' ',
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    // Where the cursor is shown, as (row, column), if it's visible
+    pub fn cursor(&self) -> Option<(u8, u8)> {
+        if !self.display_on || !(self.cursor_on || self.blink_on) || self.cgram_selected {
+            return None;
+        }
+        (0..self.rows)
+            .flat_map(|row| (0..self.columns).map(move |column| (row, column)))
+            .find(|(row, column)| self.address_at(*row, *column) == Some(self.address))
+    }
+}
+
+// Translate a character code of the standard (A00) character ROM
+pub fn to_char(code: u8) -> char {
+    match code {
+        // Custom characters from CGRAM
+        0x00..=0x0f => '▒',
+        0x5c => '¥',
+        0x7e => '→',
+        0x7f => '←',
+        0x20..=0x7d => code as char,
+        // Katakana, in the same order as the half width forms in Unicode
+        0xa1..=0xdf => char::from_u32(0xff61 + (code - 0xa1) as u32).unwrap_or('?'),
+        0xa0 => ' ',
+        0xff => '█',
+        _ => '?',
+    }
+}
+
+/*
+ * An LCD module, connected to a 6522 VIA the way the Ben Eater kit does:
+ * data bus on port B, and the control lines on port A.
+ *
+ *   PA7: E     PA6: RW     PA5: RS
+ *
+ * The controller latches data on the falling edge of E. Clones share the same
+ * display, so the UI can show what the program wrote.
+ */
+const PIN_E: u8 = 0b1000_0000;
+const PIN_RW: u8 = 0b0100_0000;
+const PIN_RS: u8 = 0b0010_0000;
+
+#[derive(Debug, Clone)]
+pub struct Lcd {
+    controller: Rc<RefCell<Hd44780>>,
+    enable: bool,
+    // In four bit mode, the high nibble of a byte being written
+    high_nibble: Option<u8>,
+    // In four bit mode, whether the next read transfers the low nibble
+    read_low_nibble: bool,
+    // What the controller drives onto the data bus during a read
+    read_value: Option<u8>,
+}
+
+impl Lcd {
+    pub fn new(columns: u8, rows: u8) -> Self {
+        Self {
+            controller: Rc::new(RefCell::new(Hd44780::new(columns, rows))),
+            enable: false,
+            high_nibble: None,
+            read_low_nibble: false,
+            read_value: None,
+        }
+    }
+
+    pub fn controller(&self) -> std::cell::Ref<'_, Hd44780> {
+        self.controller.borrow()
+    }
+
+    // The text on the display
+    pub fn text(&self) -> Vec<String> {
+        self.controller().lines().iter()
+            .map(|line| line.iter().map(|&code| to_char(code)).collect())
+            .collect()
+    }
+
+    fn write(&mut self, register_select: bool, data: u8) {
+        let mut controller = self.controller.borrow_mut();
+        let value = if controller.eight_bit {
+            data
+        } else {
+            // Only D4-D7 are used, high nibble first
+            match self.high_nibble.take() {
+                None => {
+                    self.high_nibble = Some(data & 0xf0);
+                    return;
+                }
+                Some(high) => high | (data >> 4),
+            }
+        };
+        if register_select {
+            controller.write_data(value);
+        } else {
+            controller.write_instruction(value);
+        }
+    }
+
+    // The value is put on the bus when E goes high, and the read completes when it goes low
+    fn start_read(&mut self, register_select: bool) {
+        let mut controller = self.controller.borrow_mut();
+        let value = if register_select {
+            // Only peek here, the address counter moves when the read completes
+            let address = controller.address;
+            let value = controller.read_data();
+            controller.address = address;
+            value
+        } else {
+            controller.read_status()
+        };
+        self.read_value = Some(if controller.eight_bit {
+            value
+        } else if self.read_low_nibble {
+            value << 4
+        } else {
+            value & 0xf0
+        });
+    }
+
+    fn end_read(&mut self, register_select: bool) {
+        self.read_value = None;
+        let mut controller = self.controller.borrow_mut();
+        let complete = controller.eight_bit || self.read_low_nibble;
+        if !controller.eight_bit {
+            self.read_low_nibble = !self.read_low_nibble;
+        }
+        if complete && register_select {
+            controller.read_data();
+        }
+    }
+}
+
+impl PortPeripheral for Lcd {
+    fn display(&self) -> Option<Lcd> {
+        Some(self.clone())
+    }
+
+    fn update(&mut self, outputs: &Pins) {
+        let enable = outputs.port_a & PIN_E != 0;
+        let read = outputs.port_a & PIN_RW != 0;
+        let register_select = outputs.port_a & PIN_RS != 0;
+
+        match (self.enable, enable) {
+            (false, true) if read => self.start_read(register_select),
+            (true, false) if read => self.end_read(register_select),
+            (true, false) => {
+                self.read_low_nibble = false;
+                self.write(register_select, outputs.port_b);
+            }
+            _ => {}
+        }
+        self.enable = enable;
+    }
+
+    fn inputs(&mut self) -> Pins {
+        Pins {
+            port_b: self.read_value.unwrap_or(0xff),
+            ..Default::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_string(lcd: &mut Hd44780, text: &str) {
+        for byte in text.bytes() {
+            lcd.write_data(byte);
+        }
+    }
+
+    fn text(lcd: &Hd44780) -> Vec<String> {
+        lcd.lines().iter().map(|line| line.iter().map(|&c| to_char(c)).collect()).collect()
+    }
+
+    fn initialised() -> Hd44780 {
+        let mut lcd = Hd44780::new(16, 2);
+        lcd.write_instruction(0x38); // 8 bit, 2 lines
+        lcd.write_instruction(0x0e); // display and cursor on
+        lcd.write_instruction(0x06); // increment, no shift
+        lcd.write_instruction(0x01); // clear
+        lcd
+    }
+
+    #[test]
+    fn display_off_after_reset() {
+        let mut lcd = Hd44780::new(16, 2);
+        write_string(&mut lcd, "Hidden");
+        assert_eq!(text(&lcd), vec![" ".repeat(16), " ".repeat(16)]);
+        assert_eq!(lcd.cursor(), None);
+    }
+
+    #[test]
+    fn write_lines() {
+        let mut lcd = initialised();
+        write_string(&mut lcd, "Hello, world!");
+        lcd.write_instruction(0x80 | 0x40);
+        write_string(&mut lcd, "6502");
+        assert_eq!(text(&lcd), vec!["Hello, world!   ", "6502            "]);
+        assert_eq!(lcd.cursor(), Some((1, 4)));
+        assert_eq!(lcd.read_status(), 0x44);
+
+        lcd.write_instruction(0x02);
+        assert_eq!(lcd.cursor(), Some((0, 0)));
+        assert_eq!(lcd.read_data(), b'H');
+    }
+
+    #[test]
+    fn line_wrap() {
+        let mut lcd = initialised();
+        write_string(&mut lcd, &"x".repeat(40));
+        write_string(&mut lcd, "2nd");
+        assert_eq!(lcd.read_status(), 0x43);
+        assert_eq!(text(&lcd)[1], "2nd             ");
+    }
+
+    #[test]
+    fn shift_display() {
+        let mut lcd = initialised();
+        write_string(&mut lcd, "abcdefghijklmnopq");
+        lcd.write_instruction(0x18); // shift display left
+        assert_eq!(text(&lcd)[0], "bcdefghijklmnopq");
+        lcd.write_instruction(0x1c); // and right again
+        lcd.write_instruction(0x1c);
+        assert_eq!(text(&lcd)[0], " abcdefghijklmno");
+    }
+
+    #[test]
+    fn custom_characters() {
+        let mut lcd = initialised();
+        lcd.write_instruction(0x40);
+        write_string(&mut lcd, "\x0e\x11");
+        assert_eq!(lcd.cgram[..2], [0x0e, 0x11]);
+        lcd.write_instruction(0x80);
+        lcd.write_data(0x00);
+        assert_eq!(text(&lcd)[0].chars().next(), Some('▒'));
+    }
+
+    // Drive the LCD through the port pins, like a 6502 program would
+    fn pulse(lcd: &mut Lcd, control: u8, data: u8) -> u8 {
+        lcd.update(&Pins { port_a: control, port_b: data, ..Default::default() });
+        lcd.update(&Pins { port_a: control | PIN_E, port_b: data, ..Default::default() });
+        let value = lcd.inputs().port_b;
+        lcd.update(&Pins { port_a: control, port_b: data, ..Default::default() });
+        value
+    }
+
+    #[test]
+    fn eight_bit_interface() {
+        let mut lcd = Lcd::new(16, 2);
+        for instruction in [0x38, 0x0e, 0x06, 0x01] {
+            pulse(&mut lcd, 0, instruction);
+        }
+        for byte in b"Hi" {
+            pulse(&mut lcd, PIN_RS, *byte);
+        }
+        assert_eq!(lcd.text()[0], "Hi              ");
+        // Busy flag and address
+        assert_eq!(pulse(&mut lcd, PIN_RW, 0xff), 0x02);
+
+        let display = lcd.clone();
+        pulse(&mut lcd, PIN_RS, b'!');
+        assert_eq!(display.text()[0], "Hi!             ");
+    }
+
+    #[test]
+    fn four_bit_interface() {
+        let mut lcd = Lcd::new(16, 2);
+        // Function set: 4 bit, still in 8 bit mode
+        pulse(&mut lcd, 0, 0x20);
+        for instruction in [0x28u8, 0x0e, 0x06, 0x01] {
+            pulse(&mut lcd, 0, instruction & 0xf0);
+            pulse(&mut lcd, 0, instruction << 4);
+        }
+        pulse(&mut lcd, PIN_RS, b'A' & 0xf0);
+        pulse(&mut lcd, PIN_RS, b'A' << 4);
+        assert_eq!(lcd.text()[0], "A               ");
+
+        let high = pulse(&mut lcd, PIN_RW, 0xff);
+        let low = pulse(&mut lcd, PIN_RW, 0xff);
+        assert_eq!(high | (low >> 4), 0x01);
+    }
+}
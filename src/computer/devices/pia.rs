@@ -0,0 +1,463 @@
+use std::cell::RefCell;
+
+use super::ports::{Pins, PortPeripheral};
+use crate::computer::bus::Addressable;
+use crate::computer::clock::TickCount;
+
+/*
+ * Motorola 6820/6821 PIA (Peripheral Interface Adapter)
+ *
+ * Two 8 bit I/O ports, each with a data direction register and two control
+ * lines, occupying four addresses:
+ *
+ *   0: PRA or DDRA     2: PRB or DDRB
+ *   1: CRA             3: CRB
+ *
+ * Bit 2 of a control register selects whether the port address reaches the
+ * data direction register (0) or the peripheral register (1). The other bits:
+ *
+ *   0: C1 interrupt enable         6: C2 interrupt flag (read only)
+ *   1: C1 active on rising edge    7: C1 interrupt flag (read only)
+ *   3-5: C2 mode
+ *
+ * Reading a peripheral register clears both interrupt flags of that side.
+ */
+
+const CR_C1_IRQ_ENABLE: u8 = 0b0000_0001;
+const CR_C1_POSITIVE: u8 = 0b0000_0010;
+const CR_PERIPHERAL_REGISTER: u8 = 0b0000_0100;
+const CR_C2_BITS: u8 = 0b0011_1000;
+const CR_C2_FLAG: u8 = 0b0100_0000;
+const CR_C1_FLAG: u8 = 0b1000_0000;
+
+const REGISTER_PORT_A: u16 = 0;
+const REGISTER_CONTROL_A: u16 = 1;
+const REGISTER_PORT_B: u16 = 2;
+const REGISTER_CONTROL_B: u16 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ControlMode {
+    Input { irq_enable: bool, positive_edge: bool },
+    // Goes low on a read of PRA / write of PRB, and high again on the active C1 edge
+    Handshake,
+    // Goes low for one cycle on a read of PRA / write of PRB
+    Pulse,
+    Manual(bool),
+}
+
+impl ControlMode {
+    fn from_control(control: u8) -> Self {
+        match (control & CR_C2_BITS) >> 3 {
+            0b000 => ControlMode::Input { irq_enable: false, positive_edge: false },
+            0b001 => ControlMode::Input { irq_enable: true, positive_edge: false },
+            0b010 => ControlMode::Input { irq_enable: false, positive_edge: true },
+            0b011 => ControlMode::Input { irq_enable: true, positive_edge: true },
+            0b100 => ControlMode::Handshake,
+            0b101 => ControlMode::Pulse,
+            0b110 => ControlMode::Manual(false),
+            _ => ControlMode::Manual(true),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct State {
+    ora: u8,
+    orb: u8,
+    ddra: u8,
+    ddrb: u8,
+    cra: u8,
+    crb: u8,
+
+    // Control line outputs in pulse and handshake mode
+    ca2_out: bool,
+    cb2_out: bool,
+
+    inputs: Pins,
+    outputs: Pins,
+    peripherals: Vec<Box<dyn PortPeripheral>>,
+}
+
+#[derive(Debug)]
+pub struct Pia {
+    // Reading from a PIA has side effects (e.g. clearing interrupt flags)
+    state: RefCell<State>,
+    // Not every machine connects the interrupt outputs to the CPU
+    irq_connected: bool,
+}
+
+impl Default for Pia {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Pia {
+    pub fn new() -> Self {
+        Self {
+            state: RefCell::new(State {
+                ora: 0,
+                orb: 0,
+                ddra: 0,
+                ddrb: 0,
+                cra: 0,
+                crb: 0,
+                ca2_out: true,
+                cb2_out: true,
+                inputs: Pins::default(),
+                outputs: Pins::default(),
+                peripherals: Vec::new(),
+            }),
+            irq_connected: true,
+        }
+    }
+
+    pub fn with_peripheral(self, peripheral: Box<dyn PortPeripheral>) -> Self {
+        {
+            let mut state = self.state.borrow_mut();
+            state.peripherals.push(peripheral);
+            state.update_outputs(true);
+        }
+        self
+    }
+
+    // Leave IRQA and IRQB unconnected, so the PIA never interrupts the CPU
+    pub fn without_irq(mut self) -> Self {
+        self.irq_connected = false;
+        self
+    }
+}
+
+impl State {
+    fn read(&mut self, register: u16) -> u8 {
+        let value = self.peek(register);
+        match register {
+            REGISTER_PORT_A if self.cra & CR_PERIPHERAL_REGISTER != 0 => {
+                self.cra &= !(CR_C1_FLAG | CR_C2_FLAG);
+                if self.c2_handshake(ControlMode::from_control(self.cra)) {
+                    self.ca2_out = false;
+                    self.update_outputs(false);
+                }
+            }
+            REGISTER_PORT_B if self.crb & CR_PERIPHERAL_REGISTER != 0 => {
+                self.crb &= !(CR_C1_FLAG | CR_C2_FLAG);
+            }
+            _ => {}
+        }
+        value
+    }
+
+    // What a read returns, without clearing flags or a handshake
+    fn peek(&self, register: u16) -> u8 {
+        match register {
+            REGISTER_PORT_A if self.cra & CR_PERIPHERAL_REGISTER != 0 => self.outputs.port_a & self.inputs.port_a,
+            REGISTER_PORT_A => self.ddra,
+            REGISTER_CONTROL_A => self.cra,
+            REGISTER_PORT_B if self.crb & CR_PERIPHERAL_REGISTER != 0 => {
+                (self.orb & self.ddrb) | (self.inputs.port_b & !self.ddrb)
+            }
+            REGISTER_PORT_B => self.ddrb,
+            _ => self.crb,
+        }
+    }
+
+    fn write(&mut self, register: u16, value: u8) {
+        match register {
+            REGISTER_PORT_A if self.cra & CR_PERIPHERAL_REGISTER != 0 => self.ora = value,
+            REGISTER_PORT_A => self.ddra = value,
+            REGISTER_CONTROL_A => self.cra = (self.cra & (CR_C1_FLAG | CR_C2_FLAG)) | (value & 0x3f),
+            REGISTER_PORT_B if self.crb & CR_PERIPHERAL_REGISTER != 0 => {
+                self.orb = value;
+                if self.c2_handshake(ControlMode::from_control(self.crb)) {
+                    self.cb2_out = false;
+                    // Make sure the peripheral sees every write, even of the same value
+                    self.update_outputs(true);
+                    return;
+                }
+            }
+            REGISTER_PORT_B => self.ddrb = value,
+            REGISTER_CONTROL_B => self.crb = (self.crb & (CR_C1_FLAG | CR_C2_FLAG)) | (value & 0x3f),
+            _ => unreachable!(),
+        }
+        self.update_outputs(false);
+    }
+
+    fn c2_handshake(&self, mode: ControlMode) -> bool {
+        matches!(mode, ControlMode::Handshake | ControlMode::Pulse)
+    }
+
+    fn irq_a(&self) -> bool {
+        irq_asserted(self.cra)
+    }
+
+    fn irq_b(&self) -> bool {
+        irq_asserted(self.crb)
+    }
+
+    fn compute_outputs(&self) -> Pins {
+        Pins {
+            port_a: (self.ora & self.ddra) | !self.ddra,
+            port_b: (self.orb & self.ddrb) | !self.ddrb,
+            ca1: true,
+            ca2: c2_output(self.cra, self.ca2_out),
+            cb1: true,
+            cb2: c2_output(self.crb, self.cb2_out),
+        }
+    }
+
+    fn update_outputs(&mut self, force: bool) {
+        let outputs = self.compute_outputs();
+        if force || outputs != self.outputs {
+            self.outputs = outputs;
+            for peripheral in &mut self.peripherals {
+                peripheral.update(&outputs);
+            }
+        }
+    }
+
+    // Read the pins driven by peripherals, and act on any edges of the control lines
+    fn sample_inputs(&mut self) {
+        let mut inputs = Pins::default();
+        for peripheral in &mut self.peripherals {
+            inputs = inputs.and(&peripheral.inputs());
+        }
+        let previous = self.inputs;
+        self.inputs = inputs;
+
+        if control_edge(self.cra, previous.ca1, inputs.ca1, &mut self.ca2_out) {
+            self.cra |= CR_C1_FLAG;
+        }
+        if c2_edge(self.cra, previous.ca2, inputs.ca2) {
+            self.cra |= CR_C2_FLAG;
+        }
+        if control_edge(self.crb, previous.cb1, inputs.cb1, &mut self.cb2_out) {
+            self.crb |= CR_C1_FLAG;
+        }
+        if c2_edge(self.crb, previous.cb2, inputs.cb2) {
+            self.crb |= CR_C2_FLAG;
+        }
+        self.update_outputs(false);
+    }
+
+    // Pulses only last a cycle
+    fn end_pulses(&mut self) {
+        if ControlMode::from_control(self.cra) == ControlMode::Pulse {
+            self.ca2_out = true;
+        }
+        if ControlMode::from_control(self.crb) == ControlMode::Pulse {
+            self.cb2_out = true;
+        }
+        self.update_outputs(false);
+    }
+}
+
+fn irq_asserted(control: u8) -> bool {
+    let c1 = control & CR_C1_FLAG != 0 && control & CR_C1_IRQ_ENABLE != 0;
+    let c2 = control & CR_C2_FLAG != 0
+        && matches!(ControlMode::from_control(control), ControlMode::Input { irq_enable: true, .. });
+    c1 || c2
+}
+
+fn c2_output(control: u8, handshake_level: bool) -> bool {
+    match ControlMode::from_control(control) {
+        ControlMode::Input { .. } => true,
+        ControlMode::Manual(level) => level,
+        _ => handshake_level,
+    }
+}
+
+// Whether C1 made its active transition. In handshake mode that also restores C2.
+fn control_edge(control: u8, previous: bool, current: bool, c2_out: &mut bool) -> bool {
+    let positive = control & CR_C1_POSITIVE != 0;
+    if previous != current && current == positive {
+        if ControlMode::from_control(control) == ControlMode::Handshake {
+            *c2_out = true;
+        }
+        return true;
+    }
+    false
+}
+
+// Whether C2, as an input, made its active transition
+fn c2_edge(control: u8, previous: bool, current: bool) -> bool {
+    match ControlMode::from_control(control) {
+        ControlMode::Input { positive_edge, .. } => previous != current && current == positive_edge,
+        _ => false,
+    }
+}
+
+impl Addressable for Pia {
+    fn size(&self) -> usize {
+        4
+    }
+
+    fn read_byte(&self, address: u16) -> u8 {
+        self.state.borrow_mut().read(address)
+    }
+
+    fn peek(&self, address: u16) -> Option<u8> {
+        Some(self.state.borrow().peek(address))
+    }
+
+    fn write_byte(&mut self, address: u16, byte: u8) {
+        self.state.get_mut().write(address, byte);
+    }
+
+    fn tick(&mut self, _cycles: TickCount) {
+        let state = self.state.get_mut();
+        state.end_pulses();
+        state.sample_inputs();
+    }
+
+    fn irq(&self) -> bool {
+        let state = self.state.borrow();
+        self.irq_connected && (state.irq_a() || state.irq_b())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    // A peripheral that records what the PIA outputs, and lets tests set the inputs
+    #[derive(Debug, Default)]
+    struct TestPeripheral {
+        outputs: SharedPins,
+        updates: Rc<Cell<usize>>,
+        inputs: SharedPins,
+    }
+
+    impl PortPeripheral for TestPeripheral {
+        fn update(&mut self, outputs: &Pins) {
+            self.outputs.set(Some(*outputs));
+            self.updates.set(self.updates.get() + 1);
+        }
+
+        fn inputs(&mut self) -> Pins {
+            self.inputs.get().unwrap_or_default()
+        }
+    }
+
+    type SharedPins = Rc<Cell<Option<Pins>>>;
+
+    fn create_pia() -> (Pia, SharedPins, SharedPins) {
+        let peripheral = TestPeripheral::default();
+        let outputs = peripheral.outputs.clone();
+        let inputs = peripheral.inputs.clone();
+        (Pia::new().with_peripheral(Box::new(peripheral)), outputs, inputs)
+    }
+
+    #[test]
+    fn data_direction_and_ports() {
+        let (mut pia, outputs, inputs) = create_pia();
+        // After reset, the port address reaches the data direction register
+        pia.write_byte(REGISTER_PORT_B, 0x0f);
+        pia.write_byte(REGISTER_CONTROL_B, CR_PERIPHERAL_REGISTER);
+        assert_eq!(pia.read_byte(REGISTER_CONTROL_B), CR_PERIPHERAL_REGISTER);
+
+        pia.write_byte(REGISTER_PORT_B, 0x5a);
+        assert_eq!(outputs.get().unwrap().port_b, 0xfa);
+
+        inputs.set(Some(Pins { port_b: 0x3c, ..Default::default() }));
+        pia.tick(1);
+        assert_eq!(pia.read_byte(REGISTER_PORT_B), 0x3a);
+
+        pia.write_byte(REGISTER_CONTROL_B, 0);
+        assert_eq!(pia.read_byte(REGISTER_PORT_B), 0x0f);
+    }
+
+    #[test]
+    fn c1_interrupt() {
+        let (mut pia, _outputs, inputs) = create_pia();
+        pia.write_byte(REGISTER_CONTROL_A, CR_PERIPHERAL_REGISTER | CR_C1_POSITIVE);
+        inputs.set(Some(Pins { ca1: false, ..Default::default() }));
+        pia.tick(1);
+        assert_eq!(pia.read_byte(REGISTER_CONTROL_A) & CR_C1_FLAG, 0);
+
+        inputs.set(Some(Pins { port_a: 0xc1, ca1: true, ..Default::default() }));
+        pia.tick(1);
+        assert_eq!(pia.read_byte(REGISTER_CONTROL_A) & CR_C1_FLAG, CR_C1_FLAG);
+        // The flag is set, but interrupts are not enabled
+        assert!(!pia.irq());
+
+        pia.write_byte(REGISTER_CONTROL_A, CR_PERIPHERAL_REGISTER | CR_C1_POSITIVE | CR_C1_IRQ_ENABLE);
+        assert!(pia.irq());
+        // Peeking at the port doesn't count as reading it
+        assert_eq!(pia.peek(REGISTER_PORT_A), Some(0xc1));
+        assert!(pia.irq());
+        // Writing the control register leaves the flag alone, reading the port clears it
+        assert_eq!(pia.read_byte(REGISTER_PORT_A), 0xc1);
+        assert!(!pia.irq());
+        assert_eq!(pia.read_byte(REGISTER_CONTROL_A) & CR_C1_FLAG, 0);
+    }
+
+    #[test]
+    fn c2_input_interrupt() {
+        let (mut pia, _outputs, inputs) = create_pia();
+        // CB2 input, interrupt on the falling edge
+        pia.write_byte(REGISTER_CONTROL_B, CR_PERIPHERAL_REGISTER | 0b0000_1000);
+        inputs.set(Some(Pins { cb2: false, ..Default::default() }));
+        pia.tick(1);
+        assert_eq!(pia.read_byte(REGISTER_CONTROL_B) & CR_C2_FLAG, CR_C2_FLAG);
+        assert!(pia.irq());
+        pia.read_byte(REGISTER_PORT_B);
+        assert!(!pia.irq());
+    }
+
+    #[test]
+    fn irq_not_connected() {
+        let (pia, _outputs, inputs) = create_pia();
+        let mut pia = pia.without_irq();
+        pia.write_byte(REGISTER_CONTROL_A, CR_PERIPHERAL_REGISTER | CR_C1_IRQ_ENABLE);
+        inputs.set(Some(Pins { ca1: false, ..Default::default() }));
+        pia.tick(1);
+        assert_eq!(pia.read_byte(REGISTER_CONTROL_A) & CR_C1_FLAG, CR_C1_FLAG);
+        assert!(!pia.irq());
+    }
+
+    #[test]
+    fn ca2_read_handshake() {
+        let (mut pia, outputs, inputs) = create_pia();
+        // CA2 handshake, CA1 active on the rising edge
+        pia.write_byte(REGISTER_CONTROL_A, 0b0010_0110);
+        assert!(outputs.get().unwrap().ca2);
+
+        pia.read_byte(REGISTER_PORT_A);
+        assert!(!outputs.get().unwrap().ca2);
+
+        inputs.set(Some(Pins { ca1: false, ..Default::default() }));
+        pia.tick(1);
+        assert!(!outputs.get().unwrap().ca2);
+        inputs.set(Some(Pins::default()));
+        pia.tick(1);
+        assert!(outputs.get().unwrap().ca2);
+    }
+
+    #[test]
+    fn cb2_write_pulse_and_manual() {
+        let peripheral = TestPeripheral::default();
+        let outputs = peripheral.outputs.clone();
+        let updates = peripheral.updates.clone();
+        let mut pia = Pia::new().with_peripheral(Box::new(peripheral));
+
+        // CB2 pulse mode
+        pia.write_byte(REGISTER_CONTROL_B, 0b0010_1100);
+        let before = updates.get();
+        pia.write_byte(REGISTER_PORT_B, 0x00);
+        assert!(!outputs.get().unwrap().cb2);
+        pia.tick(1);
+        assert!(outputs.get().unwrap().cb2);
+        // Writing the same value again is still seen by the peripheral
+        pia.write_byte(REGISTER_PORT_B, 0x00);
+        assert!(!outputs.get().unwrap().cb2);
+        assert_eq!(updates.get(), before + 3);
+
+        // Manual output
+        pia.write_byte(REGISTER_CONTROL_B, 0b0011_0100);
+        assert!(!outputs.get().unwrap().cb2);
+        pia.write_byte(REGISTER_CONTROL_B, 0b0011_1100);
+        assert!(outputs.get().unwrap().cb2);
+    }
+}
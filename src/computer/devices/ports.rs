@@ -0,0 +1,68 @@
+use std::fmt::Debug;
+
+use super::Lcd;
+
+/*
+ * Parallel ports
+ *
+ * The VIA and the PIA both have two 8 bit ports, each with two control lines.
+ * Whatever is connected to them (a keyboard, a display, ...) implements
+ * PortPeripheral, and sees the levels on the pins.
+ */
+
+// The levels on the external pins of a port chip. Pins nobody drives read high.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Pins {
+    pub port_a: u8,
+    pub port_b: u8,
+    pub ca1: bool,
+    pub ca2: bool,
+    pub cb1: bool,
+    pub cb2: bool,
+}
+
+impl Default for Pins {
+    fn default() -> Self {
+        Self {
+            port_a: 0xff,
+            port_b: 0xff,
+            ca1: true,
+            ca2: true,
+            cb1: true,
+            cb2: true,
+        }
+    }
+}
+
+impl Pins {
+    // Combine the pins driven by two parties. The lines are pulled up, so
+    // whoever drives a pin low wins.
+    pub(crate) fn and(&self, other: &Pins) -> Pins {
+        Pins {
+            port_a: self.port_a & other.port_a,
+            port_b: self.port_b & other.port_b,
+            ca1: self.ca1 && other.ca1,
+            ca2: self.ca2 && other.ca2,
+            cb1: self.cb1 && other.cb1,
+            cb2: self.cb2 && other.cb2,
+        }
+    }
+}
+
+// Something connected to the pins of a VIA or PIA
+pub trait PortPeripheral: Debug {
+    // Called whenever the chip changes the levels it drives. Pins that the chip
+    // is not driving (inputs) are reported as high.
+    fn update(&mut self, outputs: &Pins);
+
+    // The levels this peripheral drives onto the pins. Pins it does not drive
+    // should be left high.
+    fn inputs(&mut self) -> Pins {
+        Pins::default()
+    }
+
+    // The display this peripheral is, for a UI to show
+    fn display(&self) -> Option<Lcd> {
+        None
+    }
+}
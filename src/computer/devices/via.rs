@@ -0,0 +1,899 @@
+use std::cell::RefCell;
+
+use super::ports::{Pins, PortPeripheral};
+use super::Lcd;
+use crate::computer::bus::Addressable;
+use crate::computer::clock::TickCount;
+
+/*
+ * MOS 6522 VIA (Versatile Interface Adapter)
+ *
+ * Two 8 bit I/O ports with data direction registers and handshake lines,
+ * two 16 bit timers, a shift register and interrupt logic, occupying 16 addresses:
+ *
+ *   0: ORB/IRB     4: T1C-L    8: T2C-L    12: PCR
+ *   1: ORA/IRA     5: T1C-H    9: T2C-H    13: IFR
+ *   2: DDRB        6: T1L-L   10: SR       14: IER
+ *   3: DDRA        7: T1L-H   11: ACR      15: ORA/IRA (no handshake)
+ *
+ * The timers and shift register are stepped one clock cycle at a time, so that
+ * programs relying on cycle counts see the same timing as on real hardware.
+ * Devices connected to the ports implement PortPeripheral.
+ */
+
+// Interrupt flag (and enable) register bits
+const IRQ_CA2: u8 = 0b0000_0001;
+const IRQ_CA1: u8 = 0b0000_0010;
+const IRQ_SR: u8 = 0b0000_0100;
+const IRQ_CB2: u8 = 0b0000_1000;
+const IRQ_CB1: u8 = 0b0001_0000;
+const IRQ_T2: u8 = 0b0010_0000;
+const IRQ_T1: u8 = 0b0100_0000;
+const IRQ_ANY: u8 = 0b1000_0000;
+
+// Auxiliary control register bits
+const ACR_PA_LATCH: u8 = 0b0000_0001;
+const ACR_PB_LATCH: u8 = 0b0000_0010;
+const ACR_SR_MODE: u8 = 0b0001_1100;
+const ACR_T2_COUNT_PULSES: u8 = 0b0010_0000;
+const ACR_T1_FREE_RUN: u8 = 0b0100_0000;
+const ACR_T1_PB7: u8 = 0b1000_0000;
+
+// Peripheral control register bits
+const PCR_CA1_POSITIVE: u8 = 0b0000_0001;
+const PCR_CB1_POSITIVE: u8 = 0b0001_0000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Register {
+    Orb,
+    Ora,
+    Ddrb,
+    Ddra,
+    T1CounterLow,
+    T1CounterHigh,
+    T1LatchLow,
+    T1LatchHigh,
+    T2CounterLow,
+    T2CounterHigh,
+    Shift,
+    Acr,
+    Pcr,
+    Ifr,
+    Ier,
+    OraNoHandshake,
+}
+
+impl From<u16> for Register {
+    fn from(address: u16) -> Self {
+        match address & 0x0f {
+            0 => Register::Orb,
+            1 => Register::Ora,
+            2 => Register::Ddrb,
+            3 => Register::Ddra,
+            4 => Register::T1CounterLow,
+            5 => Register::T1CounterHigh,
+            6 => Register::T1LatchLow,
+            7 => Register::T1LatchHigh,
+            8 => Register::T2CounterLow,
+            9 => Register::T2CounterHigh,
+            10 => Register::Shift,
+            11 => Register::Acr,
+            12 => Register::Pcr,
+            13 => Register::Ifr,
+            14 => Register::Ier,
+            _ => Register::OraNoHandshake,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ControlMode {
+    Input { positive_edge: bool, independent: bool },
+    Handshake,
+    Pulse,
+    Manual(bool),
+}
+
+impl ControlMode {
+    fn from_bits(bits: u8) -> Self {
+        match bits & 0b111 {
+            0b000 => ControlMode::Input { positive_edge: false, independent: false },
+            0b001 => ControlMode::Input { positive_edge: false, independent: true },
+            0b010 => ControlMode::Input { positive_edge: true, independent: false },
+            0b011 => ControlMode::Input { positive_edge: true, independent: true },
+            0b100 => ControlMode::Handshake,
+            0b101 => ControlMode::Pulse,
+            0b110 => ControlMode::Manual(false),
+            _ => ControlMode::Manual(true),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ShiftMode {
+    Disabled,
+    InT2,
+    InClock,
+    InExternal,
+    OutFreeRunning,
+    OutT2,
+    OutClock,
+    OutExternal,
+}
+
+impl ShiftMode {
+    fn from_acr(acr: u8) -> Self {
+        match (acr & ACR_SR_MODE) >> 2 {
+            0b000 => ShiftMode::Disabled,
+            0b001 => ShiftMode::InT2,
+            0b010 => ShiftMode::InClock,
+            0b011 => ShiftMode::InExternal,
+            0b100 => ShiftMode::OutFreeRunning,
+            0b101 => ShiftMode::OutT2,
+            0b110 => ShiftMode::OutClock,
+            _ => ShiftMode::OutExternal,
+        }
+    }
+
+    fn shifts_out(&self) -> bool {
+        matches!(self, ShiftMode::OutFreeRunning | ShiftMode::OutT2 | ShiftMode::OutClock | ShiftMode::OutExternal)
+    }
+
+    fn external_clock(&self) -> bool {
+        matches!(self, ShiftMode::InExternal | ShiftMode::OutExternal)
+    }
+}
+
+#[derive(Debug)]
+struct State {
+    orb: u8,
+    ora: u8,
+    ddrb: u8,
+    ddra: u8,
+    // Port values latched on a CA1/CB1 transition, when latching is enabled
+    ira_latch: u8,
+    irb_latch: u8,
+
+    t1_counter: u16,
+    t1_latch: u16,
+    // A one shot timer only interrupts once after being started
+    t1_armed: bool,
+    // In free running mode, the counter is reloaded the cycle after it passes zero
+    t1_reload: bool,
+    pb7: bool,
+
+    t2_counter: u16,
+    t2_latch_low: u8,
+    t2_armed: bool,
+
+    shift_register: u8,
+    shift_bits_left: u8,
+    shift_timer: u16,
+    cb2_shift_out: bool,
+
+    acr: u8,
+    pcr: u8,
+    ifr: u8,
+    ier: u8,
+
+    // Control line outputs in pulse and handshake mode
+    ca2_out: bool,
+    cb2_out: bool,
+    ca2_pulse: bool,
+    cb2_pulse: bool,
+
+    inputs: Pins,
+    outputs: Pins,
+    peripherals: Vec<Box<dyn PortPeripheral>>,
+}
+
+#[derive(Debug)]
+pub struct Via {
+    // Reading from a VIA has side effects (e.g. clearing interrupt flags)
+    state: RefCell<State>,
+}
+
+impl Default for Via {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Via {
+    pub fn new() -> Self {
+        Self {
+            state: RefCell::new(State {
+                orb: 0,
+                ora: 0,
+                ddrb: 0,
+                ddra: 0,
+                ira_latch: 0,
+                irb_latch: 0,
+                t1_counter: 0xffff,
+                t1_latch: 0xffff,
+                t1_armed: false,
+                t1_reload: false,
+                pb7: true,
+                t2_counter: 0xffff,
+                t2_latch_low: 0xff,
+                t2_armed: false,
+                shift_register: 0,
+                shift_bits_left: 0,
+                shift_timer: 0,
+                cb2_shift_out: true,
+                acr: 0,
+                pcr: 0,
+                ifr: 0,
+                ier: 0,
+                ca2_out: true,
+                cb2_out: true,
+                ca2_pulse: false,
+                cb2_pulse: false,
+                inputs: Pins::default(),
+                outputs: Pins::default(),
+                peripherals: Vec::new(),
+            }),
+        }
+    }
+
+    pub fn with_peripheral(self, peripheral: Box<dyn PortPeripheral>) -> Self {
+        {
+            let mut state = self.state.borrow_mut();
+            state.peripherals.push(peripheral);
+            state.update_outputs(true);
+        }
+        self
+    }
+}
+
+impl State {
+    fn read(&mut self, register: Register) -> u8 {
+        match register {
+            Register::Orb => {
+                self.sample_inputs();
+                self.clear_flags(IRQ_CB1 | self.cb2_clear_flag());
+            }
+            Register::Ora => {
+                self.sample_inputs();
+                self.clear_flags(IRQ_CA1 | self.ca2_clear_flag());
+                self.ca2_handshake();
+            }
+            Register::OraNoHandshake => self.sample_inputs(),
+            Register::T1CounterLow => self.clear_flags(IRQ_T1),
+            Register::T2CounterLow => self.clear_flags(IRQ_T2),
+            Register::Shift => self.start_shift(),
+            _ => {}
+        }
+        self.peek(register)
+    }
+
+    // What a read returns, without clearing flags, a handshake or starting a shift
+    fn peek(&self, register: Register) -> u8 {
+        match register {
+            Register::Orb => {
+                let pins = if self.acr & ACR_PB_LATCH != 0 { self.irb_latch } else { self.inputs.port_b };
+                // Output pins read back the output register, not the pins
+                (self.orb & self.ddrb) | (pins & !self.ddrb)
+            }
+            Register::Ora | Register::OraNoHandshake => self.read_port_a(),
+            Register::Ddrb => self.ddrb,
+            Register::Ddra => self.ddra,
+            Register::T1CounterLow => self.t1_counter as u8,
+            Register::T1CounterHigh => (self.t1_counter >> 8) as u8,
+            Register::T1LatchLow => self.t1_latch as u8,
+            Register::T1LatchHigh => (self.t1_latch >> 8) as u8,
+            Register::T2CounterLow => self.t2_counter as u8,
+            Register::T2CounterHigh => (self.t2_counter >> 8) as u8,
+            Register::Shift => self.shift_register,
+            Register::Acr => self.acr,
+            Register::Pcr => self.pcr,
+            Register::Ifr => {
+                if self.irq() {
+                    self.ifr | IRQ_ANY
+                } else {
+                    self.ifr
+                }
+            }
+            Register::Ier => self.ier | IRQ_ANY,
+        }
+    }
+
+    fn write(&mut self, register: Register, value: u8) {
+        match register {
+            Register::Orb => {
+                self.orb = value;
+                self.clear_flags(IRQ_CB1 | self.cb2_clear_flag());
+                self.cb2_handshake();
+            }
+            Register::Ora => {
+                self.ora = value;
+                self.clear_flags(IRQ_CA1 | self.ca2_clear_flag());
+                self.ca2_handshake();
+            }
+            Register::OraNoHandshake => self.ora = value,
+            Register::Ddrb => self.ddrb = value,
+            Register::Ddra => self.ddra = value,
+            Register::T1CounterLow | Register::T1LatchLow => {
+                self.t1_latch = (self.t1_latch & 0xff00) | value as u16;
+            }
+            Register::T1CounterHigh => {
+                self.t1_latch = (self.t1_latch & 0x00ff) | ((value as u16) << 8);
+                self.t1_counter = self.t1_latch;
+                self.t1_armed = true;
+                self.t1_reload = false;
+                self.clear_flags(IRQ_T1);
+                if self.acr & ACR_T1_PB7 != 0 {
+                    self.pb7 = false;
+                }
+            }
+            Register::T1LatchHigh => {
+                self.t1_latch = (self.t1_latch & 0x00ff) | ((value as u16) << 8);
+                self.clear_flags(IRQ_T1);
+            }
+            Register::T2CounterLow => self.t2_latch_low = value,
+            Register::T2CounterHigh => {
+                self.t2_counter = ((value as u16) << 8) | self.t2_latch_low as u16;
+                self.t2_armed = true;
+                self.clear_flags(IRQ_T2);
+            }
+            Register::Shift => {
+                self.shift_register = value;
+                self.start_shift();
+            }
+            Register::Acr => {
+                self.acr = value;
+                if self.acr & ACR_T1_PB7 == 0 {
+                    self.pb7 = true;
+                }
+            }
+            Register::Pcr => self.pcr = value,
+            Register::Ifr => self.ifr &= !(value & !IRQ_ANY),
+            Register::Ier => {
+                if value & IRQ_ANY != 0 {
+                    self.ier |= value & !IRQ_ANY;
+                } else {
+                    self.ier &= !value;
+                }
+            }
+        }
+        self.update_outputs(false);
+    }
+
+    fn read_port_a(&self) -> u8 {
+        // Port A reads the pins, even those set up as outputs
+        if self.acr & ACR_PA_LATCH != 0 {
+            self.ira_latch
+        } else {
+            self.outputs.port_a & self.inputs.port_a
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.ifr & self.ier & !IRQ_ANY != 0
+    }
+
+    fn set_flags(&mut self, flags: u8) {
+        self.ifr |= flags;
+    }
+
+    fn clear_flags(&mut self, flags: u8) {
+        self.ifr &= !flags;
+    }
+
+    fn ca2_mode(&self) -> ControlMode {
+        ControlMode::from_bits(self.pcr >> 1)
+    }
+
+    fn cb2_mode(&self) -> ControlMode {
+        ControlMode::from_bits(self.pcr >> 5)
+    }
+
+    // Accessing the port clears the CA2/CB2 flag, unless it is an independent interrupt input
+    fn ca2_clear_flag(&self) -> u8 {
+        match self.ca2_mode() {
+            ControlMode::Input { independent: true, .. } => 0,
+            _ => IRQ_CA2,
+        }
+    }
+
+    fn cb2_clear_flag(&self) -> u8 {
+        match self.cb2_mode() {
+            ControlMode::Input { independent: true, .. } => 0,
+            _ => IRQ_CB2,
+        }
+    }
+
+    // CA2 goes low when port A is read or written in handshake or pulse mode
+    fn ca2_handshake(&mut self) {
+        match self.ca2_mode() {
+            ControlMode::Handshake => self.ca2_out = false,
+            ControlMode::Pulse => {
+                self.ca2_out = false;
+                self.ca2_pulse = true;
+            }
+            _ => {}
+        }
+        self.update_outputs(false);
+    }
+
+    // CB2 only does a handshake on writes to port B
+    fn cb2_handshake(&mut self) {
+        match self.cb2_mode() {
+            ControlMode::Handshake => self.cb2_out = false,
+            ControlMode::Pulse => {
+                self.cb2_out = false;
+                self.cb2_pulse = true;
+            }
+            _ => {}
+        }
+    }
+
+    fn compute_outputs(&self) -> Pins {
+        let mut port_b = (self.orb & self.ddrb) | !self.ddrb;
+        if self.acr & ACR_T1_PB7 != 0 {
+            port_b = (port_b & 0x7f) | if self.pb7 { 0x80 } else { 0 };
+        }
+        let shift_mode = ShiftMode::from_acr(self.acr);
+        Pins {
+            port_a: (self.ora & self.ddra) | !self.ddra,
+            port_b,
+            ca1: true,
+            ca2: match self.ca2_mode() {
+                ControlMode::Input { .. } => true,
+                ControlMode::Manual(level) => level,
+                _ => self.ca2_out,
+            },
+            cb1: true,
+            cb2: if shift_mode.shifts_out() {
+                self.cb2_shift_out
+            } else {
+                match self.cb2_mode() {
+                    ControlMode::Input { .. } => true,
+                    ControlMode::Manual(level) => level,
+                    _ => self.cb2_out,
+                }
+            },
+        }
+    }
+
+    fn update_outputs(&mut self, force: bool) {
+        let outputs = self.compute_outputs();
+        if force || outputs != self.outputs {
+            self.outputs = outputs;
+            for peripheral in &mut self.peripherals {
+                peripheral.update(&outputs);
+            }
+        }
+    }
+
+    // Read the pins driven by peripherals, and act on any edges of the control lines
+    fn sample_inputs(&mut self) {
+        let mut inputs = Pins::default();
+        for peripheral in &mut self.peripherals {
+            inputs = inputs.and(&peripheral.inputs());
+        }
+        let previous = self.inputs;
+        self.inputs = inputs;
+
+        // CA1
+        let ca1_positive = self.pcr & PCR_CA1_POSITIVE != 0;
+        if previous.ca1 != inputs.ca1 && inputs.ca1 == ca1_positive {
+            self.set_flags(IRQ_CA1);
+            self.ira_latch = self.outputs.port_a & inputs.port_a;
+            if self.ca2_mode() == ControlMode::Handshake {
+                self.ca2_out = true;
+            }
+        }
+        // CA2 as an input
+        if let ControlMode::Input { positive_edge, .. } = self.ca2_mode() {
+            if previous.ca2 != inputs.ca2 && inputs.ca2 == positive_edge {
+                self.set_flags(IRQ_CA2);
+            }
+        }
+        // CB1
+        let cb1_positive = self.pcr & PCR_CB1_POSITIVE != 0;
+        if previous.cb1 != inputs.cb1 {
+            if inputs.cb1 == cb1_positive {
+                self.set_flags(IRQ_CB1);
+                self.irb_latch = (self.orb & self.ddrb) | (inputs.port_b & !self.ddrb);
+                if self.cb2_mode() == ControlMode::Handshake {
+                    self.cb2_out = true;
+                }
+            }
+            // With an external clock, data shifts out on the falling edge and in on the rising edge
+            let shift_mode = ShiftMode::from_acr(self.acr);
+            if shift_mode.external_clock() && inputs.cb1 != shift_mode.shifts_out() {
+                self.shift();
+            }
+        }
+        // CB2 as an input
+        if !ShiftMode::from_acr(self.acr).shifts_out() {
+            if let ControlMode::Input { positive_edge, .. } = self.cb2_mode() {
+                if previous.cb2 != inputs.cb2 && inputs.cb2 == positive_edge {
+                    self.set_flags(IRQ_CB2);
+                }
+            }
+        }
+        // Timer 2 can count negative edges on PB6
+        if self.acr & ACR_T2_COUNT_PULSES != 0
+                && previous.port_b & 0x40 != 0 && inputs.port_b & 0x40 == 0 {
+            self.decrement_t2();
+        }
+        self.update_outputs(false);
+    }
+
+    fn start_shift(&mut self) {
+        self.clear_flags(IRQ_SR);
+        self.shift_bits_left = 8;
+        self.shift_timer = self.shift_period();
+    }
+
+    // Cycles between two bits being shifted
+    fn shift_period(&self) -> u16 {
+        match ShiftMode::from_acr(self.acr) {
+            ShiftMode::InClock | ShiftMode::OutClock => 2,
+            _ => 2 * (self.t2_latch_low as u16 + 2),
+        }
+    }
+
+    fn shift(&mut self) {
+        let mode = ShiftMode::from_acr(self.acr);
+        if mode == ShiftMode::Disabled || (self.shift_bits_left == 0 && mode != ShiftMode::OutFreeRunning) {
+            return;
+        }
+        if mode.shifts_out() {
+            // Data recirculates: bit 7 goes out on CB2, and back in as bit 0
+            self.cb2_shift_out = self.shift_register & 0x80 != 0;
+            self.shift_register = self.shift_register.rotate_left(1);
+        } else {
+            self.shift_register = (self.shift_register << 1) | u8::from(self.inputs.cb2);
+        }
+        if mode == ShiftMode::OutFreeRunning {
+            return;
+        }
+        self.shift_bits_left -= 1;
+        if self.shift_bits_left == 0 {
+            self.set_flags(IRQ_SR);
+        }
+    }
+
+    fn decrement_t2(&mut self) {
+        let (counter, underflow) = self.t2_counter.overflowing_sub(1);
+        self.t2_counter = counter;
+        if underflow && self.t2_armed {
+            self.t2_armed = false;
+            self.set_flags(IRQ_T2);
+        }
+    }
+
+    // Advance the timers and shift register by a single clock cycle
+    fn cycle(&mut self) {
+        // Timer 1
+        if self.t1_reload {
+            self.t1_reload = false;
+            self.t1_counter = self.t1_latch;
+        } else {
+            let (counter, underflow) = self.t1_counter.overflowing_sub(1);
+            self.t1_counter = counter;
+            if underflow {
+                if self.acr & ACR_T1_FREE_RUN != 0 {
+                    self.set_flags(IRQ_T1);
+                    self.pb7 = !self.pb7;
+                    self.t1_reload = true;
+                } else if self.t1_armed {
+                    self.t1_armed = false;
+                    self.set_flags(IRQ_T1);
+                    self.pb7 = true;
+                }
+            }
+        }
+
+        // Timer 2, unless it's counting pulses
+        if self.acr & ACR_T2_COUNT_PULSES == 0 {
+            self.decrement_t2();
+        }
+
+        // Shift register under control of timer 2 or the system clock
+        let shift_mode = ShiftMode::from_acr(self.acr);
+        if !shift_mode.external_clock() && shift_mode != ShiftMode::Disabled
+                && (self.shift_bits_left > 0 || shift_mode == ShiftMode::OutFreeRunning) {
+            self.shift_timer = self.shift_timer.saturating_sub(1);
+            if self.shift_timer == 0 {
+                self.shift_timer = self.shift_period();
+                self.shift();
+            }
+        }
+
+        // Pulse mode holds CA2/CB2 low for a single cycle
+        if self.ca2_pulse {
+            self.ca2_pulse = false;
+        } else if self.ca2_mode() == ControlMode::Pulse {
+            self.ca2_out = true;
+        }
+        if self.cb2_pulse {
+            self.cb2_pulse = false;
+        } else if self.cb2_mode() == ControlMode::Pulse {
+            self.cb2_out = true;
+        }
+    }
+}
+
+impl Addressable for Via {
+    fn size(&self) -> usize {
+        16
+    }
+
+    fn read_byte(&self, address: u16) -> u8 {
+        self.state.borrow_mut().read(Register::from(address))
+    }
+
+    fn peek(&self, address: u16) -> Option<u8> {
+        Some(self.state.borrow().peek(Register::from(address)))
+    }
+
+    fn display(&self) -> Option<Lcd> {
+        self.state.borrow().peripherals.iter().find_map(|peripheral| peripheral.display())
+    }
+
+    fn write_byte(&mut self, address: u16, byte: u8) {
+        self.state.get_mut().write(Register::from(address), byte);
+    }
+
+    fn tick(&mut self, cycles: TickCount) {
+        let state = self.state.get_mut();
+        for _ in 0..cycles {
+            state.cycle();
+        }
+        state.sample_inputs();
+    }
+
+    fn irq(&self) -> bool {
+        self.state.borrow().irq()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    // A peripheral that records what the VIA outputs, and lets tests set the inputs
+    #[derive(Debug, Default)]
+    struct TestPeripheral {
+        outputs: SharedPins,
+        inputs: SharedPins,
+    }
+
+    impl PortPeripheral for TestPeripheral {
+        fn update(&mut self, outputs: &Pins) {
+            self.outputs.set(Some(*outputs));
+        }
+
+        fn inputs(&mut self) -> Pins {
+            self.inputs.get().unwrap_or_default()
+        }
+    }
+
+    type SharedPins = Rc<Cell<Option<Pins>>>;
+
+    fn create_via() -> (Via, SharedPins, SharedPins) {
+        let peripheral = TestPeripheral::default();
+        let outputs = peripheral.outputs.clone();
+        let inputs = peripheral.inputs.clone();
+        (Via::new().with_peripheral(Box::new(peripheral)), outputs, inputs)
+    }
+
+    fn register(r: Register) -> u16 {
+        r as u16
+    }
+
+    #[test]
+    fn ports_and_data_direction() {
+        let (mut via, outputs, inputs) = create_via();
+
+        // Lower nibble of port B as output
+        via.write_byte(register(Register::Ddrb), 0x0f);
+        via.write_byte(register(Register::Orb), 0x5a);
+        assert_eq!(outputs.get().unwrap().port_b, 0xfa);
+
+        // Inputs show up on the input pins only
+        inputs.set(Some(Pins { port_b: 0x3c, ..Default::default() }));
+        assert_eq!(via.read_byte(register(Register::Orb)), 0x3a);
+
+        via.write_byte(register(Register::Ddra), 0xff);
+        via.write_byte(register(Register::Ora), 0x81);
+        assert_eq!(outputs.get().unwrap().port_a, 0x81);
+        assert_eq!(via.read_byte(register(Register::Ddra)), 0xff);
+        assert_eq!(via.read_byte(register(Register::OraNoHandshake)), 0x81);
+    }
+
+    #[test]
+    fn timer1_one_shot() {
+        let (mut via, _, _) = create_via();
+        via.write_byte(register(Register::Ier), IRQ_ANY | IRQ_T1);
+        via.write_byte(register(Register::T1CounterLow), 10);
+        via.write_byte(register(Register::T1CounterHigh), 0);
+        assert_eq!(via.read_byte(register(Register::T1CounterLow)), 10);
+
+        via.tick(10);
+        assert!(!via.irq());
+        assert_eq!(via.read_byte(register(Register::T1CounterLow)), 0);
+        via.tick(1);
+        assert!(via.irq());
+        assert_eq!(via.read_byte(register(Register::Ifr)), IRQ_ANY | IRQ_T1);
+
+        // Peeking at the low counter leaves the interrupt, reading it clears it
+        assert_eq!(via.peek(register(Register::T1CounterLow)), Some(0xff));
+        assert!(via.irq());
+        via.read_byte(register(Register::T1CounterLow));
+        assert!(!via.irq());
+
+        // One shot: no further interrupts
+        via.tick(TickCount::MAX);
+        via.tick(TickCount::MAX);
+        assert!(!via.irq());
+    }
+
+    #[test]
+    fn timer1_free_running() {
+        let (mut via, outputs, _) = create_via();
+        via.write_byte(register(Register::Acr), ACR_T1_FREE_RUN | ACR_T1_PB7);
+        via.write_byte(register(Register::Ier), IRQ_ANY | IRQ_T1);
+        via.write_byte(register(Register::T1CounterLow), 8);
+        via.write_byte(register(Register::T1CounterHigh), 0);
+        assert_eq!(outputs.get().unwrap().port_b & 0x80, 0);
+
+        // The period is N + 2 cycles
+        for i in 0..3 {
+            via.tick(if i == 0 { 9 } else { 10 });
+            assert!(via.irq(), "No interrupt in period {}", i);
+            via.write_byte(register(Register::Ifr), IRQ_T1);
+            assert!(!via.irq());
+            via.tick(0); // refresh the outputs
+            let pb7 = outputs.get().unwrap().port_b & 0x80;
+            assert_eq!(pb7 != 0, i % 2 == 0, "PB7 not toggled in period {}", i);
+        }
+    }
+
+    #[test]
+    fn timer2_one_shot() {
+        let (mut via, _, _) = create_via();
+        via.write_byte(register(Register::Ier), IRQ_ANY | IRQ_T2);
+        via.write_byte(register(Register::T2CounterLow), 0x00);
+        via.write_byte(register(Register::T2CounterHigh), 0x01);
+
+        via.tick(0x100);
+        assert!(!via.irq());
+        via.tick(1);
+        assert!(via.irq());
+        assert_eq!(via.read_byte(register(Register::T2CounterHigh)), 0xff);
+        via.read_byte(register(Register::T2CounterLow));
+        assert!(!via.irq());
+    }
+
+    #[test]
+    fn timer2_pulse_counting() {
+        let (mut via, _, inputs) = create_via();
+        via.write_byte(register(Register::Acr), ACR_T2_COUNT_PULSES);
+        via.write_byte(register(Register::Ier), IRQ_ANY | IRQ_T2);
+        via.write_byte(register(Register::T2CounterLow), 2);
+        via.write_byte(register(Register::T2CounterHigh), 0);
+
+        // Clock cycles don't count
+        via.tick(100);
+        assert_eq!(via.read_byte(register(Register::T2CounterLow)), 2);
+
+        for _ in 0..3 {
+            inputs.set(Some(Pins { port_b: 0xbf, ..Default::default() }));
+            via.tick(1);
+            inputs.set(Some(Pins::default()));
+            via.tick(1);
+        }
+        assert!(via.irq());
+    }
+
+    #[test]
+    fn interrupt_enable() {
+        let (mut via, _, _) = create_via();
+        via.write_byte(register(Register::Ier), IRQ_ANY | IRQ_T1 | IRQ_T2);
+        assert_eq!(via.read_byte(register(Register::Ier)), IRQ_ANY | IRQ_T1 | IRQ_T2);
+        via.write_byte(register(Register::Ier), IRQ_T2);
+        assert_eq!(via.read_byte(register(Register::Ier)), IRQ_ANY | IRQ_T1);
+
+        // A disabled flag is still set, but does not cause an interrupt
+        via.write_byte(register(Register::T2CounterLow), 1);
+        via.write_byte(register(Register::T2CounterHigh), 0);
+        via.tick(5);
+        assert_eq!(via.read_byte(register(Register::Ifr)), IRQ_T2);
+        assert!(!via.irq());
+    }
+
+    #[test]
+    fn shift_out_under_clock() {
+        let (mut via, outputs, _) = create_via();
+        via.write_byte(register(Register::Acr), 0b110 << 2);
+        via.write_byte(register(Register::Ier), IRQ_ANY | IRQ_SR);
+        via.write_byte(register(Register::Shift), 0b1010_0000);
+
+        let mut bits = Vec::new();
+        for _ in 0..8 {
+            via.tick(2);
+            bits.push(outputs.get().unwrap().cb2);
+        }
+        assert_eq!(bits, vec![true, false, true, false, false, false, false, false]);
+        assert!(via.irq());
+        // The data recirculates
+        assert_eq!(via.read_byte(register(Register::Shift)), 0b1010_0000);
+        assert!(!via.irq());
+    }
+
+    #[test]
+    fn shift_in_external_clock() {
+        let (mut via, _, inputs) = create_via();
+        via.write_byte(register(Register::Acr), 0b011 << 2);
+        via.read_byte(register(Register::Shift));
+
+        for bit in [true, true, false, false, true, false, true, true] {
+            inputs.set(Some(Pins { cb1: false, cb2: bit, ..Default::default() }));
+            via.tick(1);
+            inputs.set(Some(Pins { cb1: true, cb2: bit, ..Default::default() }));
+            via.tick(1);
+        }
+        assert_eq!(via.read_byte(register(Register::Ifr)) & IRQ_SR, IRQ_SR);
+        assert_eq!(via.read_byte(register(Register::Shift)), 0b1100_1011);
+    }
+
+    #[test]
+    fn ca1_interrupt_and_latch() {
+        let (mut via, _, inputs) = create_via();
+        via.write_byte(register(Register::Acr), ACR_PA_LATCH);
+        via.write_byte(register(Register::Ier), IRQ_ANY | IRQ_CA1);
+
+        // Negative edge by default
+        inputs.set(Some(Pins { port_a: 0x42, ca1: false, ..Default::default() }));
+        via.tick(1);
+        assert!(via.irq());
+        inputs.set(Some(Pins { port_a: 0x99, ca1: true, ..Default::default() }));
+        via.tick(1);
+
+        // Reading port A returns the latched value and clears the interrupt
+        assert_eq!(via.read_byte(register(Register::Ora)), 0x42);
+        assert!(!via.irq());
+    }
+
+    #[test]
+    fn ca2_handshake() {
+        let (mut via, outputs, inputs) = create_via();
+        // CA2 handshake output, CA1 positive edge
+        via.write_byte(register(Register::Pcr), 0b0000_1001);
+        assert!(outputs.get().unwrap().ca2);
+
+        via.write_byte(register(Register::Ora), 0x12);
+        assert!(!outputs.get().unwrap().ca2);
+
+        // The peripheral acknowledges on CA1
+        inputs.set(Some(Pins { ca1: false, ..Default::default() }));
+        via.tick(1);
+        assert!(!outputs.get().unwrap().ca2);
+        inputs.set(Some(Pins::default()));
+        via.tick(1);
+        assert!(outputs.get().unwrap().ca2);
+    }
+
+    #[test]
+    fn cb2_pulse_and_manual() {
+        let (mut via, outputs, _) = create_via();
+        via.write_byte(register(Register::Pcr), 0b1010_0000);
+        via.write_byte(register(Register::Orb), 0x00);
+        assert!(!outputs.get().unwrap().cb2);
+        via.tick(1);
+        via.tick(1);
+        assert!(outputs.get().unwrap().cb2);
+
+        via.write_byte(register(Register::Pcr), 0b1100_0000);
+        assert!(!outputs.get().unwrap().cb2);
+        via.write_byte(register(Register::Pcr), 0b1110_0000);
+        assert!(outputs.get().unwrap().cb2);
+    }
+}
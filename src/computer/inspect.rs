@@ -2,35 +2,154 @@ use super::*;
 use super::bus::Addressable;
 use super::cpu::inspect::CpuState;
 
+// How far back previous_instruction() starts disassembling
+const PREVIOUS_INSTRUCTION_SEARCH: u16 = 16;
+
+// What bytes on the stack probably are. Nothing on the stack says what it is,
+// so this is a guess, from what the bytes look like.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StackItem {
+    // Pushed by JSR, at the given address
+    ReturnAddress { call: u16 },
+    // Pushed by a hardware interrupt: the status, and where it returns to
+    Interrupt { status: u8, return_address: u16 },
+    // Pushed by PHP or BRK
+    Status(u8),
+    Byte(u8),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StackEntry {
+    // The lowest address the item takes
+    pub address: u16,
+    pub item: StackItem,
+}
+
+impl StackItem {
+    pub fn size(&self) -> u16 {
+        match self {
+            StackItem::ReturnAddress { .. } => 2,
+            StackItem::Interrupt { .. } => 3,
+            StackItem::Status(_) | StackItem::Byte(_) => 1,
+        }
+    }
+}
+
+const JSR_OPCODE: u8 = 0x20;
+
 impl Computer {
     pub fn get_cpu_state(&self) -> CpuState {
         self.cpu.get_state()
     }
 
     pub fn get_execution_history(&self) -> Vec<(u16, String)> {
-        self.cpu.get_execution_history()
+        self.cpu.get_execution_history(&self.symbols)
     }
 
     // Returns a vector of lines representing memory.
     // start has to be aligned with line_length
-    pub fn get_memory_lines(&self, start: u16, n_lines: u16, line_length: u16) -> Vec<(u16, Vec<u8>)> {
-        assert!(start % line_length == 0);
+    pub fn get_memory_lines(&self, start: u16, n_lines: u16, line_length: u16) -> Vec<(u16, Vec<Option<u8>>)> {
+        assert!(start.is_multiple_of(line_length));
         let mut lines = Vec::new();
         for i in 0..n_lines {
             let mut line = Vec::new();
             for j in 0..line_length {
-                line.push(self.cpu.bus.read_byte(start + i * line_length + j));
+                line.push(self.cpu.bus.peek(start + i * line_length + j));
             }
             lines.push((start + i * line_length, line));
         }
         lines
     }
 
+    pub fn read_memory(&self, address: u16, length: u16) -> Vec<u8> {
+        (0..length).map(|i| self.cpu.bus.read_byte(address.wrapping_add(i))).collect()
+    }
+
+    // Memory as it would read, without the side effects reading has on I/O
+    // chips, to show it. Unmapped addresses are None.
+    pub fn peek_memory(&self, address: u16, length: u16) -> Vec<Option<u8>> {
+        (0..length).map(|i| self.cpu.bus.peek(address.wrapping_add(i))).collect()
+    }
+
     pub fn address_opcode_to_string(&self, address: u16) -> String {
-        self.cpu.address_opcode_to_string(address)
+        self.cpu.address_opcode_to_string(address, &self.symbols)
     }
 
     pub fn disassemble(&self, start_address: u16, length: u16) -> Vec<(u16, String)> {
-        self.cpu.disassemble(start_address, length)
+        self.cpu.disassemble(start_address, length, &self.symbols)
+    }
+
+    // Disassemble a number of instructions, stopping at the end of memory.
+    // Unlike disassemble(), this carries on past unknown opcodes.
+    pub fn disassemble_lines(&self, start_address: u16, count: usize) -> Vec<(u16, String)> {
+        let mut lines = Vec::with_capacity(count);
+        let mut address = start_address as u32;
+        while lines.len() < count && address <= 0xffff {
+            lines.push((address as u16, self.address_opcode_to_string(address as u16)));
+            address += self.cpu.instruction_length(address as u16) as u32;
+        }
+        lines
+    }
+
+    // The address of the instruction after the one at the address
+    pub fn next_instruction(&self, address: u16) -> u16 {
+        address.saturating_add(self.cpu.instruction_length(address))
+    }
+
+    // The address of the instruction before the one at the address. Code can't
+    // be disassembled backwards, so this looks for the earliest address a bit
+    // before it, from which disassembling ends up at the address.
+    pub fn previous_instruction(&self, address: u16) -> u16 {
+        for start in address.saturating_sub(PREVIOUS_INSTRUCTION_SEARCH)..address {
+            let mut current = start;
+            loop {
+                let next = current as u32 + self.cpu.instruction_length(current) as u32;
+                if next == address as u32 {
+                    return current;
+                }
+                if next > address as u32 {
+                    break;
+                }
+                current = next as u16;
+            }
+        }
+        address.saturating_sub(1)
+    }
+
+    // The contents of the stack, from the top (the stack pointer) to the bottom ($01ff).
+    // Only peeks at memory, so showing it doesn't disturb I/O chips.
+    pub fn stack_entries(&self) -> Vec<StackEntry> {
+        let bus = &self.cpu.bus;
+        let mut entries = Vec::new();
+        let mut address = 0x0100 + self.cpu.get_state().stack_pointer as u16 + 1;
+        while address <= 0x01ff {
+            // Without memory on the stack page, there's nothing to show
+            let Some(byte) = bus.peek(address) else {
+                break;
+            };
+            let word = |address: u16| if address < 0x01ff { bus.peek_address(address) } else { None };
+            // JSR pushes the address of its last byte
+            let call = word(address).map(|word| word.wrapping_sub(2));
+            let item = match call {
+                Some(call) if bus.peek(call) == Some(JSR_OPCODE) => StackItem::ReturnAddress { call },
+                // Interrupts push the status with the break bit clear, which PHP never does
+                _ => match (byte & 0x30, word(address + 1)) {
+                    (0x20, Some(return_address)) => StackItem::Interrupt { status: byte, return_address },
+                    (0x30, _) => StackItem::Status(byte),
+                    _ => StackItem::Byte(byte),
+                },
+            };
+            entries.push(StackEntry { address, item });
+            address += item.size();
+        }
+        entries
+    }
+
+    // An address by name if it has one, or in hexadecimal otherwise
+    pub fn address_to_string(&self, address: u16) -> String {
+        match self.symbols.name_for(address) {
+            Some(name) => name.to_string(),
+            None => format!("${:04x}", address),
+        }
     }
 }
@@ -0,0 +1,100 @@
+use super::bus::Addressable;
+
+/*
+ * Interrupt lines
+ *
+ * The 6502 has two interrupt inputs. IRQ is level triggered: as long as anything
+ * holds the line, the CPU will keep taking the interrupt whenever the interrupt
+ * disable flag is clear. NMI is edge triggered: the CPU takes the interrupt once
+ * when the line goes from inactive to active, and then not again until the line
+ * has been released.
+ *
+ * Both lines are wired-OR: they can be driven by chips on the bus, or by callers
+ * outside the emulated machine through the Computer API.
+ */
+#[derive(Debug, Default)]
+pub struct InterruptLines {
+    // Lines held by callers outside of the bus
+    external_irq: bool,
+    external_nmi: bool,
+
+    // Level of the NMI line at the last poll, used to detect edges
+    nmi_level: bool,
+    // An NMI edge that has been seen, but not yet serviced
+    nmi_pending: bool,
+}
+
+impl InterruptLines {
+    pub fn set_irq(&mut self, level: bool) {
+        self.external_irq = level;
+    }
+
+    pub fn set_nmi(&mut self, level: bool) {
+        self.external_nmi = level;
+    }
+
+    // Generate a single NMI edge, without leaving the line held
+    pub fn trigger_nmi(&mut self) {
+        self.nmi_pending = true;
+    }
+
+    // Sample the lines, returning the (irq, nmi) pair the CPU should act on.
+    // Any NMI edge is consumed by this call.
+    pub fn poll<A: Addressable>(&mut self, bus: &A) -> (bool, bool) {
+        let irq = self.external_irq || bus.irq();
+
+        let nmi_level = self.external_nmi || bus.nmi();
+        if nmi_level && !self.nmi_level {
+            self.nmi_pending = true;
+        }
+        self.nmi_level = nmi_level;
+
+        let nmi = self.nmi_pending;
+        self.nmi_pending = false;
+        (irq, nmi)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::computer::bus::UnconnectedBus;
+
+    #[test]
+    fn irq_is_level_triggered() {
+        let bus = UnconnectedBus {};
+        let mut lines = InterruptLines::default();
+
+        assert_eq!(lines.poll(&bus), (false, false));
+        lines.set_irq(true);
+        assert_eq!(lines.poll(&bus), (true, false));
+        assert_eq!(lines.poll(&bus), (true, false));
+        lines.set_irq(false);
+        assert_eq!(lines.poll(&bus), (false, false));
+    }
+
+    #[test]
+    fn nmi_is_edge_triggered() {
+        let bus = UnconnectedBus {};
+        let mut lines = InterruptLines::default();
+
+        lines.set_nmi(true);
+        assert_eq!(lines.poll(&bus), (false, true));
+        // Holding the line does not trigger again
+        assert_eq!(lines.poll(&bus), (false, false));
+        lines.set_nmi(false);
+        assert_eq!(lines.poll(&bus), (false, false));
+        lines.set_nmi(true);
+        assert_eq!(lines.poll(&bus), (false, true));
+    }
+
+    #[test]
+    fn nmi_pulse() {
+        let bus = UnconnectedBus {};
+        let mut lines = InterruptLines::default();
+
+        lines.trigger_nmi();
+        assert_eq!(lines.poll(&bus), (false, true));
+        assert_eq!(lines.poll(&bus), (false, false));
+    }
+}
@@ -0,0 +1,29 @@
+pub mod apple1;
+pub mod ben_eater;
+
+use std::path::PathBuf;
+
+use clap::ValueEnum;
+
+// Ready made machines, with their memory layout and devices
+#[derive(Debug, Clone, Copy, PartialEq, Default, ValueEnum)]
+pub enum Machine {
+    // RAM everywhere, and a small ROM with interrupt handlers at the top
+    #[default]
+    Standard,
+    // Apple I / Replica-1, running Wozmon
+    Apple1,
+    // Ben Eater's breadboard computer, with an LCD
+    BenEater,
+}
+
+impl Machine {
+    // The ROM to use when none is given on the command line
+    pub fn default_rom(&self) -> PathBuf {
+        PathBuf::from(match self {
+            Machine::Standard => "assembly/standard.rom",
+            Machine::Apple1 => "assembly/wozmon.rom",
+            Machine::BenEater => "assembly/ben_eater.rom",
+        })
+    }
+}
@@ -0,0 +1,214 @@
+use crate::computer::clock::{Clock, ClockMode};
+use crate::computer::devices::{Connection, Pia, Pins, PortPeripheral};
+use crate::computer::{Computer, ComputerBuilder};
+
+/*
+ * Apple I / Replica-1
+ *
+ * 32K of RAM at $0000, 4K of RAM at $E000 (where Integer BASIC is usually
+ * loaded), and Wozmon in ROM at $FF00. The keyboard and display are connected
+ * to a 6820 PIA at $D010:
+ *
+ *   $D010 KBD      keyboard data, bit 7 always set
+ *   $D011 KBDCR    bit 7 set when a key has been pressed (CA1)
+ *   $D012 DSP      display data, bit 7 set while the display is busy
+ *   $D013 DSPCR
+ *
+ * The PIA interrupt lines are not connected.
+ */
+
+pub const PIA_ADDRESS: u16 = 0xd010;
+const RAM_SIZE: usize = 0x8000;
+const EXTRA_RAM_ADDRESS: u16 = 0xe000;
+const EXTRA_RAM_SIZE: usize = 0x1000;
+const CLOCK_SPEED: u32 = 1_022_727;
+
+pub fn builder(rom: Vec<u8>, terminal: Box<dyn Connection>) -> ComputerBuilder {
+    // A larger ROM (e.g. with BASIC included) takes the place of the RAM at $E000
+    let extra_ram = rom.len() <= EXTRA_RAM_SIZE;
+    let pia = Pia::new()
+        .with_peripheral(Box::new(Terminal::new(terminal)))
+        .without_irq();
+
+    let mut builder = Computer::new()
+        .with_clock(Clock::new(ClockMode::Normal).with_clock_speed(CLOCK_SPEED))
+        .with_memory_size(RAM_SIZE)
+        .with_rom(rom)
+        .with_device(PIA_ADDRESS, Box::new(pia))
+        .without_rom_initialisation();
+    if extra_ram {
+        builder = builder.with_ram(EXTRA_RAM_ADDRESS, EXTRA_RAM_SIZE);
+    }
+    builder
+}
+
+// The keyboard and video terminal, as seen through the PIA.
+//
+// A key is presented on port A with a strobe on CA1. The next key is only
+// taken from the connection after the program has read port A, which it
+// signals through the CA2 read handshake Wozmon sets up.
+// Writing to port B pulls CB2 low, upon which the character is sent to the
+// connection, and acknowledged with a pulse on CB1.
+#[derive(Debug)]
+pub struct Terminal {
+    connection: Box<dyn Connection>,
+    key: u8,
+    key_pending: bool,
+    acknowledge: bool,
+    outputs: Pins,
+}
+
+impl Terminal {
+    pub fn new(connection: Box<dyn Connection>) -> Self {
+        Self {
+            connection,
+            key: 0x80,
+            key_pending: false,
+            acknowledge: false,
+            outputs: Pins::default(),
+        }
+    }
+}
+
+// The Apple I keyboard only has upper case, and sets bit 7
+fn translate_key(byte: u8) -> u8 {
+    let key = match byte {
+        b'\n' => b'\r',
+        // Backspace and delete, Wozmon uses _ as rubout
+        0x08 | 0x7f => b'_',
+        _ => byte.to_ascii_uppercase(),
+    };
+    key | 0x80
+}
+
+impl PortPeripheral for Terminal {
+    fn update(&mut self, outputs: &Pins) {
+        if self.outputs.ca2 && !outputs.ca2 {
+            self.key_pending = false;
+        }
+        if self.outputs.cb2 && !outputs.cb2 {
+            self.connection.send(outputs.port_b & 0x7f);
+            self.acknowledge = true;
+        }
+        self.outputs = *outputs;
+    }
+
+    fn inputs(&mut self) -> Pins {
+        let mut strobe = false;
+        if !self.key_pending {
+            if let Some(byte) = self.connection.receive() {
+                self.key = translate_key(byte);
+                self.key_pending = true;
+                strobe = true;
+            }
+        }
+        Pins {
+            port_a: self.key,
+            ca1: strobe,
+            // The display is never busy
+            port_b: 0x7f,
+            cb1: std::mem::take(&mut self.acknowledge),
+            ..Default::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+    use crate::assembler::Assembler;
+    use crate::computer::bus::Addressable;
+    use crate::computer::devices::connection::ChannelConnection;
+
+    // Set up the PIA like Wozmon does, and echo every key to the display
+    fn echo_rom() -> Vec<u8> {
+        let mut rom = vec![0u8; 0x100];
+        let program = [
+            0xa0, 0x7f,             // LDY #$7f
+            0x8c, 0x12, 0xd0,       // STY DSP
+            0xa9, 0xa7,             // LDA #$a7
+            0x8d, 0x11, 0xd0,       // STA KBDCR
+            0x8d, 0x13, 0xd0,       // STA DSPCR
+            0xad, 0x11, 0xd0,       // loop: LDA KBDCR
+            0x10, 0xfb,             // BPL loop
+            0xad, 0x10, 0xd0,       // LDA KBD
+            0x2c, 0x12, 0xd0,       // echo: BIT DSP
+            0x30, 0xfb,             // BMI echo
+            0x8d, 0x12, 0xd0,       // STA DSP
+            0x4c, 0x0d, 0xff,       // JMP loop
+        ];
+        rom[..program.len()].copy_from_slice(&program);
+        rom[0xfc] = 0x00;
+        rom[0xfd] = 0xff;
+        rom
+    }
+
+    #[test]
+    fn translate_keys() {
+        assert_eq!(translate_key(b'a'), b'A' | 0x80);
+        assert_eq!(translate_key(b'1'), b'1' | 0x80);
+        assert_eq!(translate_key(b'\n'), 0x8d);
+        assert_eq!(translate_key(0x7f), b'_' | 0x80);
+    }
+
+    #[test]
+    fn keyboard_and_display() {
+        let (connection, endpoint) = ChannelConnection::new();
+        let mut computer = builder(echo_rom(), Box::new(connection)).build().unwrap();
+
+        // Repeated keys must show up as often as they were typed
+        for byte in b"woz  1\r" {
+            endpoint.sender.send(*byte).unwrap();
+        }
+        for _ in 0..1000 {
+            computer.step();
+        }
+        let output: Vec<u8> = endpoint.receiver.try_iter().collect();
+        assert_eq!(output, b"WOZ  1\r");
+    }
+
+    // The real thing, assembled like with ld65 and wozmon.rom.cfg
+    fn wozmon_rom() -> Vec<u8> {
+        let assembly = Assembler::new()
+            .with_segment("OS", 0xff00)
+            .with_segment("VECTORS", 0xfffa)
+            .assemble_file(Path::new("assembly/wozmon.rom.s"))
+            .unwrap();
+        let (start, image) = assembly.program.to_image(0);
+        assert_eq!((start, image.len()), (0xff00, 0x100));
+        image
+    }
+
+    #[test]
+    fn wozmon() {
+        let (connection, endpoint) = ChannelConnection::new();
+        let mut computer = builder(wozmon_rom(), Box::new(connection)).build().unwrap();
+        let mut session = |input: &str| {
+            for byte in input.bytes() {
+                endpoint.sender.send(byte).unwrap();
+            }
+            computer.run_cycles(200_000).unwrap();
+            String::from_utf8(endpoint.receiver.try_iter().collect()).unwrap()
+        };
+
+        // Wozmon starts with a backslash, then echoes what is typed
+        assert_eq!(session("FF00\r"), "\\\rFF00\r\rFF00: D8\r");
+        assert_eq!(session("FF00.FF0F\r"), "FF00.FF0F\r\rFF00: D8 58 A0 7F 8C 12 D0 A9\rFF08: A7 8D 11 D0 8D 13 D0 C9\r");
+        // Storing shows the old contents of the first location
+        assert_eq!(session("0300: A9 01\r"), "0300: A9 01\r\r0300: 00\r");
+        assert_eq!(session("0300.0301\r"), "0300.0301\r\r0300: A9 01\r");
+    }
+
+    #[test]
+    fn memory_layout() {
+        let (connection, _endpoint) = ChannelConnection::new();
+        let mut computer = builder(echo_rom(), Box::new(connection)).build().unwrap();
+        let bus = &mut computer.cpu.bus;
+        assert_eq!(bus.read_byte(0xff00), 0xa0);
+        for address in [0x0000, 0x7fff, 0xe000, 0xefff] {
+            bus.write_byte(address, 0x42);
+            assert_eq!(bus.read_byte(address), 0x42);
+        }
+    }
+}
@@ -0,0 +1,92 @@
+use crate::computer::devices::{Lcd, Via};
+use crate::computer::{Computer, ComputerBuilder};
+
+/*
+ * Ben Eater's breadboard 6502 computer
+ *
+ * 16K of RAM at $0000, a 6522 VIA at $6000 and 32K of ROM at $8000. A 16x2
+ * character LCD is connected to the VIA: its data bus on port B, and E, RW
+ * and RS on PA7, PA6 and PA5.
+ */
+
+pub const VIA_ADDRESS: u16 = 0x6000;
+const RAM_SIZE: usize = 0x4000;
+const ROM_SIZE: usize = 0x8000;
+const LCD_COLUMNS: u8 = 16;
+const LCD_ROWS: u8 = 2;
+
+pub fn builder(rom: Vec<u8>) -> Result<ComputerBuilder, String> {
+    if rom.len() > ROM_SIZE {
+        return Err(format!("ROM of size {:x} does not fit in {:x} bytes", rom.len(), ROM_SIZE));
+    }
+    let lcd = Lcd::new(LCD_COLUMNS, LCD_ROWS);
+    let via = Via::new().with_peripheral(Box::new(lcd));
+
+    Ok(Computer::new()
+        .with_memory_size(RAM_SIZE)
+        .with_rom(rom)
+        .with_device(VIA_ADDRESS, Box::new(via))
+        .without_rom_initialisation())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The first program from the videos, writing to the LCD in 8 bit mode
+    fn hello_rom() -> Vec<u8> {
+        let mut rom = vec![0xea; ROM_SIZE];
+        let mut program = vec![
+            0xa9, 0xff,             // LDA #$ff
+            0x8d, 0x02, 0x60,       // STA DDRB
+            0xa9, 0xe0,             // LDA #$e0
+            0x8d, 0x03, 0x60,       // STA DDRA
+        ];
+        let instruction = |value: u8| vec![
+            0xa9, value,            // LDA #value
+            0x8d, 0x00, 0x60,       // STA PORTB
+            0xa9, 0x00,             // LDA #0
+            0x8d, 0x01, 0x60,       // STA PORTA
+            0xa9, 0x80,             // LDA #E
+            0x8d, 0x01, 0x60,       // STA PORTA
+            0xa9, 0x00,             // LDA #0
+            0x8d, 0x01, 0x60,       // STA PORTA
+        ];
+        let data = |value: u8| vec![
+            0xa9, value,            // LDA #value
+            0x8d, 0x00, 0x60,       // STA PORTB
+            0xa9, 0x20,             // LDA #RS
+            0x8d, 0x01, 0x60,       // STA PORTA
+            0xa9, 0xa0,             // LDA #(RS | E)
+            0x8d, 0x01, 0x60,       // STA PORTA
+            0xa9, 0x20,             // LDA #RS
+            0x8d, 0x01, 0x60,       // STA PORTA
+        ];
+        for value in [0x38, 0x0e, 0x06] {
+            program.extend(instruction(value));
+        }
+        for byte in b"Hello, world!" {
+            program.extend(data(*byte));
+        }
+        let end = 0x8000 + program.len() as u16;
+        program.extend([0x4c, end as u8, (end >> 8) as u8]); // JMP *
+        rom[..program.len()].copy_from_slice(&program);
+        rom[0x7ffc] = 0x00;
+        rom[0x7ffd] = 0x80;
+        rom
+    }
+
+    #[test]
+    fn hello_world() {
+        let mut computer = builder(hello_rom()).unwrap().build().unwrap();
+        computer.run_cycles(10_000).unwrap();
+        let lcd = computer.lcd().unwrap();
+        assert_eq!(lcd.text(), vec!["Hello, world!   ", "                "]);
+        assert_eq!(lcd.controller().cursor(), Some((0, 13)));
+    }
+
+    #[test]
+    fn rom_too_large() {
+        assert!(builder(vec![0; ROM_SIZE + 1]).is_err());
+    }
+}
@@ -0,0 +1,140 @@
+// Saving and restoring the state of a computer: its registers and memory
+
+use std::path::Path;
+
+use super::Computer;
+use super::cpu::inspect::CpuState;
+use super::cpu::status::Status;
+
+/*
+ * A state file starts with "M6502 state" and a version byte, followed by the
+ * registers: PC (low byte first), A, X, Y, SP and the status flags. Then, for
+ * each block of RAM or ROM, the address it starts at (low byte first), its
+ * size (four bytes, low byte first) and its contents.
+ *
+ * Devices aren't saved, they keep the state they're in. A state can only be
+ * loaded into a computer with the same memory layout.
+ */
+const MAGIC: &[u8] = b"M6502 state\x01";
+const REGISTERS_SIZE: usize = 7;
+
+impl Computer {
+    pub fn save_state(&self, file_name: &Path) -> Result<(), String> {
+        std::fs::write(file_name, self.state_bytes())
+            .map_err(|e| format!("Was not able to write {}: {}", file_name.display(), e))
+    }
+
+    pub fn load_state(&mut self, file_name: &Path) -> Result<(), String> {
+        let bytes = std::fs::read(file_name)
+            .map_err(|e| format!("Was not able to read {}: {}", file_name.display(), e))?;
+        self.restore_state_bytes(&bytes)
+            .map_err(|e| format!("Was not able to load {}: {}", file_name.display(), e))
+    }
+
+    fn state_bytes(&self) -> Vec<u8> {
+        let state = self.get_cpu_state();
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&state.program_counter.to_le_bytes());
+        bytes.extend_from_slice(&[
+            state.accumulator,
+            state.x_index,
+            state.y_index,
+            state.stack_pointer,
+            state.status.as_byte(),
+        ]);
+        for (start, contents) in self.cpu.bus.memory_contents() {
+            bytes.extend_from_slice(&start.to_le_bytes());
+            bytes.extend_from_slice(&(contents.len() as u32).to_le_bytes());
+            bytes.extend_from_slice(contents);
+        }
+        bytes
+    }
+
+    fn restore_state_bytes(&mut self, bytes: &[u8]) -> Result<(), String> {
+        let truncated = || "the state is cut short".to_string();
+        let rest = bytes.strip_prefix(MAGIC).ok_or("it isn't a saved state")?;
+        let (registers, mut rest) = rest.split_at_checked(REGISTERS_SIZE).ok_or_else(truncated)?;
+
+        // Check all of it before changing anything
+        let mut blocks = Vec::new();
+        while !rest.is_empty() {
+            let (header, data) = rest.split_at_checked(6).ok_or_else(truncated)?;
+            let start = u16::from_le_bytes([header[0], header[1]]);
+            let size = u32::from_le_bytes([header[2], header[3], header[4], header[5]]) as usize;
+            let (contents, next) = data.split_at_checked(size).ok_or_else(truncated)?;
+            blocks.push((start, contents));
+            rest = next;
+        }
+        let mut memory = self.cpu.bus.memory_contents_mut();
+        let same_layout = memory.len() == blocks.len() && memory.iter().zip(&blocks)
+            .all(|((start, memory), (block_start, contents))| start == block_start && memory.len() == contents.len());
+        if !same_layout {
+            return Err("the memory of this computer is laid out differently".to_string());
+        }
+        for ((_, memory), (_, contents)) in memory.iter_mut().zip(blocks) {
+            memory.copy_from_slice(contents);
+        }
+
+        self.set_cpu_state(&CpuState {
+            program_counter: u16::from_le_bytes([registers[0], registers[1]]),
+            accumulator: registers[2],
+            x_index: registers[3],
+            y_index: registers[4],
+            stack_pointer: registers[5],
+            status: Status::from_byte(registers[6]),
+        });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_computer(memory_size: usize) -> Computer {
+        Computer::new()
+            .with_rom(vec![0; 0x100])
+            .with_memory_size(memory_size)
+            .without_rom_initialisation()
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn saving_and_loading() {
+        let mut computer = create_computer(0x8000);
+        computer.write_memory(0x0200, &[1, 2, 3]);
+        computer.write_memory(0xfffc, &[0x00, 0x02]);
+        let state = CpuState {
+            program_counter: 0x0201,
+            accumulator: 0x11,
+            x_index: 0x22,
+            y_index: 0x33,
+            stack_pointer: 0xf0,
+            status: Status::from_byte(0b1100_0011),
+        };
+        computer.set_cpu_state(&state);
+        let bytes = computer.state_bytes();
+
+        computer.write_memory(0x0200, &[0, 0, 0]);
+        computer.write_memory(0xfffc, &[0x00, 0xff]);
+        computer.reset();
+        computer.restore_state_bytes(&bytes).unwrap();
+        assert_eq!(computer.read_memory(0x0200, 3), vec![1, 2, 3]);
+        assert_eq!(computer.read_memory(0xfffc, 2), vec![0x00, 0x02]);
+        assert_eq!(computer.get_cpu_state(), state);
+    }
+
+    #[test]
+    fn invalid_states() {
+        let mut computer = create_computer(0x8000);
+        let bytes = computer.state_bytes();
+        computer.write_memory(0x0200, &[1]);
+
+        assert!(computer.restore_state_bytes(b"M6502").is_err());
+        assert!(computer.restore_state_bytes(&bytes[..bytes.len() - 1]).is_err());
+        assert!(create_computer(0x4000).restore_state_bytes(&bytes).is_err());
+        // Nothing changed
+        assert_eq!(computer.read_memory(0x0200, 1), vec![1]);
+    }
+}
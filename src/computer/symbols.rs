@@ -0,0 +1,239 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+
+use super::debug_info::{fields, is_debug_info};
+
+/*
+ * Symbol table
+ *
+ * Names for addresses, loaded from:
+ *
+ *   label files    ld65 -Ln and VICE .vs files, with lines like "al C:ff00 .reset"
+ *   debug info     ld65 --dbgfile, using its sym lines for labels and
+ *                  absolute equates (e.g. "PORTB = $6000")
+ *
+ * An address can have several names. The first one loaded is shown, unless
+ * it's one of the linker generated names starting with "__". A name can also
+ * have several addresses, like local labels used in more than one scope. Such
+ * a name is shown at each of them, but can't be used to give an address.
+ */
+
+#[derive(Debug, Default, Clone)]
+pub struct SymbolTable {
+    names: BTreeMap<u16, Vec<String>>,
+    addresses: HashMap<String, Vec<u16>>,
+}
+
+// Parse a 16 bit address, written in hexadecimal with an optional $ or 0x prefix
+pub fn parse_address(s: &str) -> Result<u16, String> {
+    let digits = s.strip_prefix('$')
+        .or_else(|| s.strip_prefix("0x"))
+        .unwrap_or(s);
+    u16::from_str_radix(digits, 16).map_err(|e| format!("Invalid address '{}': {}", s, e))
+}
+
+impl SymbolTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, name: &str, address: u16) {
+        let addresses = self.addresses.entry(name.to_string()).or_default();
+        if addresses.contains(&address) {
+            return;
+        }
+        addresses.push(address);
+        let names = self.names.entry(address).or_default();
+        // Prefer names written by people over ones generated by the linker
+        if names.first().is_some_and(|first| first.starts_with("__")) && !name.starts_with("__") {
+            names.insert(0, name.to_string());
+        } else {
+            names.push(name.to_string());
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.addresses.values().map(Vec::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.addresses.is_empty()
+    }
+
+    // The name to show for an address
+    pub fn name_for(&self, address: u16) -> Option<&str> {
+        self.names.get(&address)
+            .and_then(|names| names.first())
+            .map(String::as_str)
+    }
+
+    // All symbols, in order of address
+    pub fn iter(&self) -> impl Iterator<Item = (u16, &str)> {
+        self.names.iter()
+            .flat_map(|(address, names)| names.iter().map(|name| (*address, name.as_str())))
+    }
+
+    // The address of a name, if it has only one
+    pub fn address_of(&self, name: &str) -> Option<u16> {
+        match self.addresses_of(name) {
+            [address] => Some(*address),
+            _ => None,
+        }
+    }
+
+    // All addresses with this name, in the order they were added
+    pub fn addresses_of(&self, name: &str) -> &[u16] {
+        self.addresses.get(name).map_or(&[], Vec::as_slice)
+    }
+
+    // An address given by name, or as a hexadecimal number
+    pub fn resolve(&self, text: &str) -> Result<u16, String> {
+        match self.addresses_of(text) {
+            [address] => Ok(*address),
+            [] => parse_address(text).map_err(|_| format!("Unknown symbol or address '{}'", text)),
+            addresses => {
+                let addresses: Vec<String> = addresses.iter().map(|a| format!("${:04x}", a)).collect();
+                Err(format!("Symbol '{}' is at more than one address: {}", text, addresses.join(", ")))
+            }
+        }
+    }
+
+    // Load symbols from a label or debug info file. Returns the number of symbols read.
+    pub fn load_file(&mut self, file_name: &Path) -> Result<usize, String> {
+        let text = std::fs::read_to_string(file_name)
+            .map_err(|e| format!("Was not able to read {}: {}", file_name.display(), e))?;
+        let count = if is_debug_info(file_name, &text) {
+            self.parse_debug_info(&text)
+        } else {
+            self.parse_labels(&text)
+        }
+        .map_err(|e| format!("{}: {}", file_name.display(), e))?;
+        log::info!("Loaded {} symbols from {}", count, file_name.display());
+        Ok(count)
+    }
+
+    // ld65 -Ln and VICE label files
+    pub fn parse_labels(&mut self, text: &str) -> Result<usize, String> {
+        let mut count = 0;
+        for (i, line) in text.lines().enumerate() {
+            let mut words = line.split_whitespace();
+            match words.next() {
+                Some("al" | "add_label") => {}
+                // Other monitor commands, like breakpoints, don't define symbols
+                _ => continue,
+            }
+            let (Some(address), Some(name)) = (words.next(), words.next()) else {
+                return Err(format!("Line {}: expected an address and a name", i + 1));
+            };
+            // Addresses may have a memory space prefix, and are padded to 24 bits by ld65
+            let address = address.split_once(':').map_or(address, |(_, a)| a);
+            let address = u32::from_str_radix(address, 16)
+                .ok()
+                .and_then(|a| u16::try_from(a).ok())
+                .ok_or_else(|| format!("Line {}: invalid address '{}'", i + 1, address))?;
+            self.add(name.strip_prefix('.').unwrap_or(name), address);
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    // The sym lines of ld65 debug info
+    pub fn parse_debug_info(&mut self, text: &str) -> Result<usize, String> {
+        let mut count = 0;
+        for (i, line) in text.lines().enumerate() {
+            let Some(text) = line.strip_prefix("sym\t") else {
+                continue;
+            };
+            let fields = fields(text);
+            let wanted = match fields.get("type").copied() {
+                Some("lab") => true,
+                Some("equ") => fields.get("addrsize") == Some(&"absolute"),
+                _ => false,
+            };
+            let (Some(name), Some(value)) = (fields.get("name"), fields.get("val")) else {
+                continue;
+            };
+            if !wanted {
+                continue;
+            }
+            let address = value.strip_prefix("0x")
+                .and_then(|v| u16::from_str_radix(v, 16).ok())
+                .ok_or_else(|| format!("Line {}: invalid value '{}'", i + 1, value))?;
+            self.add(name, address);
+            count += 1;
+        }
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn add_and_look_up() {
+        let mut symbols = SymbolTable::new();
+        symbols.add("__OS_LOAD__", 0xff00);
+        symbols.add("reset", 0xff00);
+        symbols.add("start", 0xff00);
+        symbols.add("irq", 0xff40);
+        symbols.add("irq", 0xff50);
+        symbols.add("irq", 0xff40);
+
+        assert_eq!(symbols.len(), 5);
+        assert_eq!(symbols.name_for(0xff00), Some("reset"));
+        assert_eq!(symbols.name_for(0xff40), Some("irq"));
+        assert_eq!(symbols.name_for(0xff50), Some("irq"));
+        assert_eq!(symbols.name_for(0x1234), None);
+        assert_eq!(symbols.address_of("start"), Some(0xff00));
+        assert_eq!(symbols.address_of("irq"), None);
+        assert_eq!(symbols.addresses_of("irq"), &[0xff40, 0xff50]);
+        assert_eq!(symbols.iter().map(|(_, name)| name).collect::<Vec<_>>(),
+            vec!["reset", "__OS_LOAD__", "start", "irq", "irq"]);
+
+        assert_eq!(symbols.resolve("start"), Ok(0xff00));
+        assert_eq!(symbols.resolve("$1000"), Ok(0x1000));
+        assert!(symbols.resolve("nowhere").is_err());
+        assert_eq!(symbols.resolve("irq"),
+            Err("Symbol 'irq' is at more than one address: $ff40, $ff50".to_string()));
+    }
+
+    #[test]
+    fn label_file() {
+        let mut symbols = SymbolTable::new();
+        let text = "al 00FF00 .reset\n\
+                    al 00FF2B .nmi\n\
+                    al C:1000 .loop\n\
+                    add_label 0200 IRQ_VECTOR\n\
+                    break 1000\n";
+        assert_eq!(symbols.parse_labels(text), Ok(4));
+        assert_eq!(symbols.address_of("reset"), Some(0xff00));
+        assert_eq!(symbols.address_of("nmi"), Some(0xff2b));
+        assert_eq!(symbols.address_of("loop"), Some(0x1000));
+        assert_eq!(symbols.address_of("IRQ_VECTOR"), Some(0x0200));
+
+        // Local labels come back in every scope that uses them
+        assert_eq!(symbols.parse_labels("al C:1020 .loop\n"), Ok(1));
+        assert_eq!(symbols.addresses_of("loop"), &[0x1000, 0x1020]);
+        assert_eq!(symbols.name_for(0x1020), Some("loop"));
+
+        assert!(symbols.parse_labels("al 10000 .far\n").is_err());
+        assert!(symbols.parse_labels("al 1000\n").is_err());
+    }
+
+    #[test]
+    fn debug_info() {
+        let mut symbols = SymbolTable::new();
+        let text = "version\tmajor=2,minor=0\n\
+                    sym\tid=0,name=\"reset\",addrsize=absolute,size=1,scope=0,def=5,ref=9,val=0xFF00,seg=0,type=lab\n\
+                    sym\tid=1,name=\"PORTB\",addrsize=absolute,scope=0,def=1,val=0x6000,type=equ\n\
+                    sym\tid=2,name=\"E\",addrsize=zeropage,scope=0,def=2,val=0x80,type=equ\n\
+                    sym\tid=3,name=\"@loop\",addrsize=absolute,size=1,scope=0,def=8,val=0xFF05,seg=0,type=lab\n\
+                    sym\tid=4,name=\"message\",addrsize=absolute,size=14,scope=0,def=9,type=imp\n";
+        assert_eq!(symbols.parse_debug_info(text), Ok(3));
+        assert_eq!(symbols.address_of("reset"), Some(0xff00));
+        assert_eq!(symbols.address_of("PORTB"), Some(0x6000));
+        assert_eq!(symbols.address_of("@loop"), Some(0xff05));
+        assert_eq!(symbols.address_of("E"), None);
+    }
+}
@@ -0,0 +1,479 @@
+// A disassembler that follows the flow of the code
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+use clap::ValueEnum;
+
+use crate::computer::config::CpuVariant;
+use crate::computer::cpu::instruction::{decode_instruction, AddressMode, Instruction};
+use crate::computer::symbols::SymbolTable;
+
+/*
+ * Starting from the entry points (and the reset, NMI and IRQ vectors, if
+ * asked for), instructions are decoded and followed:
+ *
+ *   branches           both the target and the next instruction
+ *   JSR                the subroutine, and the instruction after it
+ *   JMP                only the target. For JMP (indirect), the target is
+ *                      read from memory, if the pointer is in the image.
+ *   RTS, RTI, BRK      nothing further
+ *
+ * Everything that isn't reached this way is data. Targets of jumps and
+ * branches get labels (Lxxxx), as do data that instructions refer to (Dxxxx),
+ * unless a symbol names them already.
+ */
+
+const VECTORS_ADDRESS: u32 = 0xfffa;
+
+// How to write out a disassembly
+#[derive(Debug, Default, Clone, Copy, PartialEq, ValueEnum)]
+pub enum Style {
+    // Source that ca65 can assemble again
+    #[default]
+    Ca65,
+    // Addresses and instructions
+    Plain,
+    // Addresses, bytes and instructions
+    Hex,
+}
+
+// What a byte in the image turned out to be
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    Data,
+    Opcode,
+    Operand,
+    // A vector: an address in two bytes
+    Word,
+    WordHigh,
+}
+
+pub struct Disassembler {
+    start: u16,
+    bytes: Vec<u8>,
+    entry_points: Vec<u16>,
+    vectors: bool,
+    symbols: SymbolTable,
+    variant: CpuVariant,
+}
+
+// A line in the output: an instruction, or some data
+#[derive(Debug, Clone, PartialEq)]
+pub struct Line {
+    pub address: u16,
+    pub bytes: Vec<u8>,
+    pub label: Option<String>,
+    // The instruction or directive, as in "LDA #$10" or ".byte $01, $02"
+    pub text: String,
+}
+
+#[derive(Debug)]
+pub struct Disassembly {
+    pub lines: Vec<Line>,
+    // The given symbols, and the generated labels
+    pub symbols: SymbolTable,
+    start: u16,
+    variant: CpuVariant,
+}
+
+impl Disassembler {
+    // Disassemble an image of memory that starts at an address
+    pub fn new(start: u16, bytes: Vec<u8>) -> Self {
+        let mut bytes = bytes;
+        bytes.truncate(0x10000 - start as usize);
+        Self {
+            start,
+            bytes,
+            entry_points: Vec::new(),
+            vectors: false,
+            symbols: SymbolTable::new(),
+            variant: CpuVariant::default(),
+        }
+    }
+
+    pub fn with_variant(mut self, variant: CpuVariant) -> Self {
+        self.variant = variant;
+        self
+    }
+
+    pub fn with_entry_point(mut self, address: u16) -> Self {
+        self.entry_points.push(address);
+        self
+    }
+
+    // Start from the NMI, reset and IRQ vectors, if the image includes them
+    pub fn with_vectors(mut self) -> Self {
+        self.vectors = true;
+        self
+    }
+
+    // Use these names for addresses, instead of generated labels. Names with
+    // more than one address would make the source ambiguous, so they're left out.
+    pub fn with_symbols(mut self, symbols: &SymbolTable) -> Self {
+        for (address, name) in symbols.iter() {
+            if symbols.address_of(name).is_some() {
+                self.symbols.add(name, address);
+            }
+        }
+        self
+    }
+
+    fn contains(&self, address: u32) -> bool {
+        address >= self.start as u32 && address < self.start as u32 + self.bytes.len() as u32
+    }
+
+    fn byte(&self, address: u32) -> u8 {
+        self.bytes[(address - self.start as u32) as usize]
+    }
+
+    fn word(&self, address: u32) -> Option<u16> {
+        match self.contains(address) && self.contains(address + 1) {
+            true => Some(u16::from_le_bytes([self.byte(address), self.byte(address + 1)])),
+            false => None,
+        }
+    }
+
+    pub fn disassemble(&self) -> Disassembly {
+        let mut kinds = vec![Kind::Data; self.bytes.len()];
+        let mut code_labels = BTreeSet::new();
+        let mut data_labels = BTreeSet::new();
+        let mut symbols = self.symbols.clone();
+
+        let mut work: Vec<u16> = self.entry_points.clone();
+        if self.vectors && self.contains(VECTORS_ADDRESS) && self.contains(0xffff) {
+            for (i, name) in ["nmi", "reset", "irq"].iter().enumerate() {
+                let address = VECTORS_ADDRESS + 2 * i as u32;
+                kinds[(address - self.start as u32) as usize] = Kind::Word;
+                kinds[(address + 1 - self.start as u32) as usize] = Kind::WordHigh;
+                let target = self.word(address).unwrap_or_default();
+                if self.contains(target as u32) && symbols.name_for(target).is_none() {
+                    symbols.add(name, target);
+                }
+                work.push(target);
+            }
+        }
+        for address in &work {
+            code_labels.insert(*address);
+        }
+
+        while let Some(address) = work.pop() {
+            let mut address = address as u32;
+            loop {
+                if !self.contains(address) || kinds[(address - self.start as u32) as usize] != Kind::Data {
+                    break;
+                }
+                let Some((instruction, mode, _)) = decode_instruction(self.byte(address)) else {
+                    break;
+                };
+                let size = 1 + mode.operand_size() as u32;
+                let next = address + size;
+                // Instructions that run off the image, or into other code, are data
+                if !self.contains(next - 1)
+                    || (address + 1..next).any(|a| kinds[(a - self.start as u32) as usize] != Kind::Data)
+                {
+                    break;
+                }
+                kinds[(address - self.start as u32) as usize] = Kind::Opcode;
+                for a in address + 1..next {
+                    kinds[(a - self.start as u32) as usize] = Kind::Operand;
+                }
+
+                let operand = match size {
+                    2 => self.byte(address + 1) as u16,
+                    3 => u16::from_le_bytes([self.byte(address + 1), self.byte(address + 2)]),
+                    _ => 0,
+                };
+                let mut follow = |target: u16| {
+                    code_labels.insert(target);
+                    work.push(target);
+                };
+                match (instruction, mode) {
+                    (_, AddressMode::Relative) => {
+                        follow((next as u16).wrapping_add(operand as i8 as u16));
+                    }
+                    (Instruction::JSR, _) => follow(operand),
+                    (Instruction::JMP, AddressMode::Absolute) => {
+                        follow(operand);
+                        break;
+                    }
+                    (Instruction::JMP, _) => {
+                        if let Some(target) = self.word(operand as u32) {
+                            follow(target);
+                        }
+                        data_labels.insert(operand);
+                        break;
+                    }
+                    (Instruction::RTS | Instruction::RTI | Instruction::BRK, _) => break,
+                    (_, AddressMode::Immediate | AddressMode::Implied | AddressMode::Accumulator) => {}
+                    _ => {
+                        data_labels.insert(operand);
+                    }
+                }
+                address = next;
+            }
+        }
+
+        // Name what's referred to in the image, except halfway into instructions
+        for address in code_labels.iter().chain(&data_labels) {
+            let index = (*address as u32).wrapping_sub(self.start as u32) as usize;
+            if !self.contains(*address as u32) || matches!(kinds[index], Kind::Operand | Kind::WordHigh) {
+                continue;
+            }
+            if symbols.name_for(*address).is_none() {
+                let prefix = if kinds[index] == Kind::Opcode { "L" } else { "D" };
+                symbols.add(&format!("{}{:04X}", prefix, address), *address);
+            }
+        }
+
+        Disassembly {
+            lines: self.lines(&kinds, &symbols),
+            symbols,
+            start: self.start,
+            variant: self.variant,
+        }
+    }
+
+    fn lines(&self, kinds: &[Kind], symbols: &SymbolTable) -> Vec<Line> {
+        let mut lines = Vec::new();
+        let mut index = 0;
+        while index < self.bytes.len() {
+            let address = self.start.wrapping_add(index as u16);
+            let label = symbols.name_for(address).map(str::to_string);
+            let (size, text) = match kinds[index] {
+                Kind::Opcode => {
+                    let (instruction, mode, _) = decode_instruction(self.bytes[index]).unwrap();
+                    let size = 1 + mode.operand_size() as usize;
+                    let mut operand = [0; 2];
+                    operand[..size - 1].copy_from_slice(&self.bytes[index + 1..index + size]);
+                    (size, format_instruction(instruction, mode, &operand, address, symbols))
+                }
+                Kind::Word => {
+                    let target = u16::from_le_bytes([self.bytes[index], self.bytes[index + 1]]);
+                    (2, format!(".word {}", address_name(target, symbols)))
+                }
+                _ => {
+                    // Data runs until the next code or label, at most 8 bytes to a line
+                    let mut size = 1;
+                    while size < 8
+                        && index + size < self.bytes.len()
+                        && matches!(kinds[index + size], Kind::Data | Kind::Operand)
+                        && symbols.name_for(address.wrapping_add(size as u16)).is_none()
+                    {
+                        size += 1;
+                    }
+                    let bytes: Vec<String> = self.bytes[index..index + size].iter()
+                        .map(|b| format!("${:02x}", b))
+                        .collect();
+                    (size, format!(".byte {}", bytes.join(", ")))
+                }
+            };
+            lines.push(Line {
+                address,
+                bytes: self.bytes[index..index + size].to_vec(),
+                label,
+                text,
+            });
+            index += size;
+        }
+        lines
+    }
+}
+
+fn address_name(address: u16, symbols: &SymbolTable) -> String {
+    match symbols.name_for(address) {
+        Some(name) => name.to_string(),
+        None => format!("${:04x}", address),
+    }
+}
+
+// Format an instruction so that it assembles to the same bytes again
+fn format_instruction(instruction: Instruction, mode: AddressMode, operand: &[u8; 2], address: u16, symbols: &SymbolTable) -> String {
+    let text = match mode {
+        // Branches outside the image need their target, not the offset
+        AddressMode::Relative => {
+            address_name(address.wrapping_add(2).wrapping_add(operand[0] as i8 as u16), symbols)
+        }
+        AddressMode::Absolute | AddressMode::AbsoluteX | AddressMode::AbsoluteY => {
+            let text = mode.symbolic_format(operand, address, symbols);
+            // Assemblers use zero page addressing where they can
+            match u16::from_le_bytes(*operand) < 0x100 {
+                true => format!("a:{}", text),
+                false => text,
+            }
+        }
+        _ => mode.symbolic_format(operand, address, symbols),
+    };
+    format!("{} {}", instruction, text).trim_end().to_string()
+}
+
+impl Disassembly {
+    pub fn listing(&self, style: Style) -> String {
+        if style == Style::Ca65 {
+            return self.to_source();
+        }
+        let mut listing = String::new();
+        for line in &self.lines {
+            if let Some(label) = &line.label {
+                let _ = writeln!(listing, "{}:", label);
+            }
+            match style {
+                Style::Hex => {
+                    let bytes: Vec<String> = line.bytes.iter().map(|b| format!("{:02x}", b)).collect();
+                    let _ = writeln!(listing, "{:04x}  {:<24}  {}", line.address, bytes.join(" "), line.text);
+                }
+                _ => {
+                    let _ = writeln!(listing, "{:04x}  {}", line.address, line.text);
+                }
+            }
+        }
+        listing
+    }
+
+    // Source for ca65 (or the built-in assembler) that assembles to the same image
+    pub fn to_source(&self) -> String {
+        let mut source = String::new();
+        let _ = writeln!(source, "; Disassembled by c6502\n    .setcpu \"{}\"\n", self.variant.name());
+
+        // Symbols that aren't labels are defined up front: those outside of the
+        // image, other names for a label, and addresses in the middle of a line
+        let defined: BTreeSet<&str> = self.lines.iter().filter_map(|l| l.label.as_deref()).collect();
+        let mut equates = BTreeMap::new();
+        for (address, name) in self.symbols.iter() {
+            if !defined.contains(name) {
+                equates.entry(name).or_insert(address);
+            }
+        }
+        for (name, address) in &equates {
+            let _ = writeln!(source, "{:<15} = ${:04x}", name, address);
+        }
+        if !equates.is_empty() {
+            source.push('\n');
+        }
+
+        let _ = writeln!(source, "    .org ${:04x}\n", self.start);
+        for line in &self.lines {
+            if let Some(label) = &line.label {
+                let _ = writeln!(source, "{}:", label);
+            }
+            let _ = writeln!(source, "    {}", line.text);
+        }
+        source
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::Assembler;
+
+    const SOURCE: &str = "
+            .org $f000
+        start:
+            LDX #$00
+        loop:
+            LDA message,X
+            BEQ done
+            JSR print
+            INX
+            BNE loop
+        done:
+            JMP (pointer)
+        print:
+            STA $0200
+            LDA a:$0010
+            RTS
+        message:
+            .byte \"Hi\", 0, $ff
+        pointer:
+            .word start
+        ";
+
+    fn image() -> Vec<u8> {
+        Assembler::new().assemble(SOURCE).unwrap().program.to_image(0).1
+    }
+
+    #[test]
+    fn follows_code() {
+        let disassembly = Disassembler::new(0xf000, image()).with_entry_point(0xf000).disassemble();
+        let lines: Vec<(Option<&str>, &str)> = disassembly.lines.iter()
+            .map(|l| (l.label.as_deref(), l.text.as_str()))
+            .collect();
+        assert_eq!(lines, vec![
+            (Some("LF000"), "LDX #$00"),
+            (Some("LF002"), "LDA DF017, X"),
+            (None, "BEQ LF00D"),
+            (None, "JSR LF010"),
+            (None, "INX"),
+            (None, "BNE LF002"),
+            (Some("LF00D"), "JMP (DF01B)"),
+            (Some("LF010"), "STA $0200"),
+            (None, "LDA a:$0010"),
+            (None, "RTS"),
+            (Some("DF017"), ".byte $48, $69, $00, $ff"),
+            (Some("DF01B"), ".byte $00, $f0"),
+        ]);
+        assert_eq!(disassembly.lines[1].bytes, vec![0xbd, 0x17, 0xf0]);
+    }
+
+    #[test]
+    fn vectors_and_symbols() {
+        let mut rom = vec![0xea; 0x100];
+        rom[0x00] = 0x40; // nmi: RTI
+        rom[0x10] = 0x4c; // reset: JMP reset
+        rom[0x11] = 0x10;
+        rom[0x12] = 0xff;
+        rom[0x20] = 0x20; // irq: JSR echo, RTI
+        rom[0x21] = 0xef;
+        rom[0x22] = 0xff;
+        rom[0x23] = 0x40;
+        rom[0x30] = 0x02; // undefined opcode, never reached
+        rom[0xef] = 0x60; // echo: RTS
+        rom[0xfa..].copy_from_slice(&[0x00, 0xff, 0x10, 0xff, 0x20, 0xff]);
+        let mut symbols = SymbolTable::new();
+        symbols.add("echo", 0xffef);
+        symbols.add("KBD", 0xd010);
+
+        let disassembly = Disassembler::new(0xff00, rom).with_vectors().with_symbols(&symbols).disassemble();
+        let text = |address: u16| disassembly.lines.iter()
+            .find(|l| l.address == address)
+            .map(|l| (l.label.clone(), l.text.clone()));
+        assert_eq!(text(0xff00), Some((Some("nmi".to_string()), "RTI".to_string())));
+        assert_eq!(text(0xff10), Some((Some("reset".to_string()), "JMP reset".to_string())));
+        assert_eq!(text(0xff20), Some((Some("irq".to_string()), "JSR echo".to_string())));
+        assert_eq!(text(0xff2c), Some((None, ".byte $ea, $ea, $ea, $ea, $02, $ea, $ea, $ea".to_string())));
+        assert_eq!(text(0xffef), Some((Some("echo".to_string()), "RTS".to_string())));
+        assert_eq!(text(0xfffa), Some((None, ".word nmi".to_string())));
+        assert_eq!(text(0xfffc), Some((None, ".word reset".to_string())));
+    }
+
+    #[test]
+    fn source_reassembles() {
+        let mut symbols = SymbolTable::new();
+        symbols.add("screen", 0x0200);
+        // The operand of the first instruction, like self-modifying code would use
+        symbols.add("count", 0xf001);
+        let disassembly = Disassembler::new(0xf000, image())
+            .with_entry_point(0xf000)
+            .with_symbols(&symbols)
+            .disassemble();
+        let source = disassembly.to_source();
+        assert!(source.contains("screen          = $0200\n"));
+        assert!(source.contains("    STA screen\n"));
+        assert!(source.contains("count           = $f001\n"));
+
+        let assembly = Assembler::new().assemble(&source).unwrap();
+        assert_eq!(assembly.program.to_image(0), (0xf000, image()));
+    }
+
+    #[test]
+    fn listing_styles() {
+        let disassembly = Disassembler::new(0xf000, image()).with_entry_point(0xf000).disassemble();
+        let plain = disassembly.listing(Style::Plain);
+        assert!(plain.starts_with("LF000:\nf000  LDX #$00\nLF002:\nf002  LDA DF017, X\n"));
+        let hex = disassembly.listing(Style::Hex);
+        assert!(hex.contains("\nf002  bd 17 f0                  LDA DF017, X\n"));
+        assert!(hex.contains("\nf01b  00 f0                     .byte $00, $f0\n"));
+        assert!(disassembly.listing(Style::Ca65).contains(".setcpu \"6502\""));
+    }
+}
@@ -1,4 +1,10 @@
+pub mod assembler;
 pub mod binutils;
 pub mod computer;
+pub mod disassembler;
+pub mod loader;
+pub mod monitor;
 pub mod proxy;
+pub mod scripting;
 pub mod tui;
+pub mod watch;
@@ -0,0 +1,539 @@
+// Loading programs from the common 6502 object file formats
+
+use std::path::Path;
+
+use clap::ValueEnum;
+
+/*
+ * Supported formats:
+ *
+ *   raw    plain binary, loaded at a given address
+ *   ihex   Intel HEX, with start address records
+ *   srec   Motorola S-records, with termination (start address) records
+ *   prg    Commodore program: 2 byte load address, optionally a BASIC SYS line
+ *   xex    Atari executable: $FFFF header and segments, run address in RUNAD
+ *   o65    André Fachat's relocatable format as written by ld65. Segments are
+ *          loaded at the addresses they were linked for, not relocated.
+ *
+ * The format is taken from the file extension, or failing that from the
+ * first bytes of the file. Files that start like an Atari executable but
+ * don't load as one are taken to be raw.
+ */
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum Format {
+    Raw,
+    Ihex,
+    Srec,
+    Prg,
+    Xex,
+    O65,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Segment {
+    pub address: u16,
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Program {
+    pub segments: Vec<Segment>,
+    // Where execution starts, if the file says so
+    pub run_address: Option<u16>,
+}
+
+impl Program {
+    pub(crate) fn add(&mut self, address: u32, data: &[u8]) -> Result<(), String> {
+        if address as usize + data.len() > 0x10000 {
+            return Err(format!("Data at 0x{:x} does not fit in memory", address));
+        }
+        // Extend the previous segment if this continues it
+        if let Some(last) = self.segments.last_mut() {
+            if last.address as usize + last.data.len() == address as usize {
+                last.data.extend_from_slice(data);
+                return Ok(());
+            }
+        }
+        if !data.is_empty() {
+            self.segments.push(Segment { address: address as u16, data: data.to_vec() });
+        }
+        Ok(())
+    }
+
+    // All segments as one block of memory, from the lowest to the highest
+    // address used, with the gaps filled. Returns the start address and the block.
+    pub fn to_image(&self, fill: u8) -> (u16, Vec<u8>) {
+        let Some(start) = self.segments.iter().map(|s| s.address).min() else {
+            return (0, Vec::new());
+        };
+        let end = self.segments.iter()
+            .map(|s| s.address as usize + s.data.len())
+            .max()
+            .unwrap_or(start as usize);
+        let mut image = vec![fill; end - start as usize];
+        for segment in &self.segments {
+            let offset = (segment.address - start) as usize;
+            image[offset..offset + segment.data.len()].copy_from_slice(&segment.data);
+        }
+        (start, image)
+    }
+}
+
+impl Format {
+    // The format the file name says, if it says one
+    pub fn from_extension(file_name: &Path) -> Option<Format> {
+        let extension = file_name.extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_ascii_lowercase());
+        match extension.as_deref() {
+            Some("hex" | "ihx" | "ihex") => Some(Format::Ihex),
+            Some("srec" | "s19" | "s28" | "s37" | "mot") => Some(Format::Srec),
+            Some("prg") => Some(Format::Prg),
+            Some("xex") => Some(Format::Xex),
+            Some("o65") => Some(Format::O65),
+            Some("bin" | "rom") => Some(Format::Raw),
+            _ => None,
+        }
+    }
+
+    pub fn detect(file_name: &Path, bytes: &[u8]) -> Format {
+        if let Some(format) = Format::from_extension(file_name) {
+            return format;
+        }
+        if bytes.starts_with(&O65_MAGIC) {
+            Format::O65
+        } else if bytes.starts_with(&[0xff, 0xff]) {
+            Format::Xex
+        } else if bytes.starts_with(b":") && is_text(bytes) {
+            Format::Ihex
+        } else if bytes.starts_with(b"S") && bytes.get(1).is_some_and(u8::is_ascii_digit) && is_text(bytes) {
+            Format::Srec
+        } else {
+            Format::Raw
+        }
+    }
+}
+
+fn is_text(bytes: &[u8]) -> bool {
+    bytes.iter().all(|b| b.is_ascii_graphic() || b.is_ascii_whitespace())
+}
+
+// Load a program from a file, in the given format or the detected one.
+// Raw binaries are loaded at load_address.
+pub fn load_file(file_name: &Path, format: Option<Format>, load_address: u16) -> Result<Program, String> {
+    let bytes = std::fs::read(file_name)
+        .map_err(|e| format!("Was not able to load {}: {}", file_name.display(), e))?;
+    let guessed = format.is_none() && Format::from_extension(file_name).is_none();
+    let format = format.unwrap_or_else(|| Format::detect(file_name, &bytes));
+    log::info!("Loading {} as {:?}", file_name.display(), format);
+    let result = match parse(format, &bytes, load_address) {
+        // Raw images often start with erased $ff bytes, which look like an Atari header
+        Err(e) if guessed && format == Format::Xex => {
+            log::info!("Loading {} as {:?} instead: {}", file_name.display(), Format::Raw, e);
+            parse(Format::Raw, &bytes, load_address)
+        }
+        result => result,
+    };
+    result.map_err(|e| format!("Was not able to load {}: {}", file_name.display(), e))
+}
+
+pub fn parse(format: Format, bytes: &[u8], load_address: u16) -> Result<Program, String> {
+    match format {
+        Format::Raw => {
+            let mut program = Program { segments: Vec::new(), run_address: None };
+            program.add(load_address as u32, bytes)?;
+            Ok(program)
+        }
+        Format::Ihex => parse_ihex(text(bytes)?),
+        Format::Srec => parse_srec(text(bytes)?),
+        Format::Prg => parse_prg(bytes),
+        Format::Xex => parse_xex(bytes),
+        Format::O65 => parse_o65(bytes),
+    }
+}
+
+fn text(bytes: &[u8]) -> Result<&str, String> {
+    std::str::from_utf8(bytes).map_err(|_| "File is not text".to_string())
+}
+
+fn hex_bytes(line: &str, line_number: usize) -> Result<Vec<u8>, String> {
+    if !line.len().is_multiple_of(2) {
+        return Err(format!("Line {}: odd number of hex digits", line_number));
+    }
+    // Go by bytes, as slicing the text could split a character
+    line.as_bytes()
+        .chunks(2)
+        .map(|pair| {
+            let digits = std::str::from_utf8(pair).ok().filter(|_| pair.iter().all(u8::is_ascii_hexdigit));
+            digits.and_then(|digits| u8::from_str_radix(digits, 16).ok())
+                .ok_or_else(|| format!("Line {}: invalid hex '{}'", line_number, String::from_utf8_lossy(pair)))
+        })
+        .collect()
+}
+
+fn parse_ihex(text: &str) -> Result<Program, String> {
+    let mut program = Program { segments: Vec::new(), run_address: None };
+    let mut base: u32 = 0;
+    for (i, line) in text.lines().enumerate().map(|(i, l)| (i + 1, l.trim())) {
+        if line.is_empty() {
+            continue;
+        }
+        let Some(record) = line.strip_prefix(':') else {
+            return Err(format!("Line {}: record does not start with ':'", i));
+        };
+        let bytes = hex_bytes(record, i)?;
+        if bytes.len() < 5 || bytes.len() != bytes[0] as usize + 5 {
+            return Err(format!("Line {}: wrong record length", i));
+        }
+        if bytes.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)) != 0 {
+            return Err(format!("Line {}: checksum error", i));
+        }
+        let address = u16::from_be_bytes([bytes[1], bytes[2]]) as u32;
+        let data = &bytes[4..bytes.len() - 1];
+        match bytes[3] {
+            0x00 => program.add(base + address, data)?,
+            0x01 => break,
+            // Extended segment address
+            0x02 if data.len() == 2 => base = (u16::from_be_bytes([data[0], data[1]]) as u32) << 4,
+            // Start segment address (CS:IP)
+            0x03 if data.len() == 4 => {
+                let segment = u16::from_be_bytes([data[0], data[1]]) as u32;
+                let offset = u16::from_be_bytes([data[2], data[3]]) as u32;
+                program.run_address = Some(address_16(segment * 16 + offset, i)?);
+            }
+            // Extended linear address
+            0x04 if data.len() == 2 => base = (u16::from_be_bytes([data[0], data[1]]) as u32) << 16,
+            // Start linear address
+            0x05 if data.len() == 4 => {
+                program.run_address = Some(address_16(u32::from_be_bytes([data[0], data[1], data[2], data[3]]), i)?);
+            }
+            record_type => return Err(format!("Line {}: unsupported record type {:02x}", i, record_type)),
+        }
+    }
+    Ok(program)
+}
+
+fn address_16(address: u32, line: usize) -> Result<u16, String> {
+    u16::try_from(address).map_err(|_| format!("Line {}: address 0x{:x} does not fit in 16 bits", line, address))
+}
+
+fn parse_srec(text: &str) -> Result<Program, String> {
+    let mut program = Program { segments: Vec::new(), run_address: None };
+    for (i, line) in text.lines().enumerate().map(|(i, l)| (i + 1, l.trim())) {
+        if line.is_empty() {
+            continue;
+        }
+        let record_type = match line.as_bytes() {
+            [b'S', t, ..] if t.is_ascii_digit() => t - b'0',
+            _ => return Err(format!("Line {}: record does not start with 'S'", i)),
+        };
+        let bytes = hex_bytes(&line[2..], i)?;
+        if bytes.is_empty() || bytes.len() != bytes[0] as usize + 1 {
+            return Err(format!("Line {}: wrong record length", i));
+        }
+        if bytes.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)) != 0xff {
+            return Err(format!("Line {}: checksum error", i));
+        }
+        let address_length = match record_type {
+            0 | 1 | 5 | 9 => 2,
+            2 | 6 | 8 => 3,
+            3 | 7 => 4,
+            _ => return Err(format!("Line {}: unsupported record type S{}", i, record_type)),
+        };
+        if bytes.len() < address_length + 2 {
+            return Err(format!("Line {}: wrong record length", i));
+        }
+        let address = bytes[1..=address_length].iter().fold(0u32, |a, b| (a << 8) | *b as u32);
+        let data = &bytes[address_length + 1..bytes.len() - 1];
+        match record_type {
+            1..=3 => program.add(address, data)?,
+            7..=9 => program.run_address = Some(address_16(address, i)?),
+            // Header and record counts
+            _ => {}
+        }
+    }
+    Ok(program)
+}
+
+// BASIC token for SYS
+const SYS_TOKEN: u8 = 0x9e;
+
+fn parse_prg(bytes: &[u8]) -> Result<Program, String> {
+    if bytes.len() < 2 {
+        return Err("File is too short for a load address".to_string());
+    }
+    let load_address = u16::from_le_bytes([bytes[0], bytes[1]]);
+    let mut program = Program { segments: Vec::new(), run_address: None };
+    program.add(load_address as u32, &bytes[2..])?;
+    // Programs starting with a BASIC line like 10 SYS 2064 run from that address
+    program.run_address = Some(sys_address(&bytes[2..]).unwrap_or(load_address));
+    Ok(program)
+}
+
+// The address in the SYS statement of the first BASIC line, if there is one
+fn sys_address(basic: &[u8]) -> Option<u16> {
+    // Skip the link to the next line and the line number
+    let mut statement = basic.get(4..)?.iter()
+        .take_while(|b| **b != 0)
+        .skip_while(|b| **b == b' ');
+    if *statement.next()? != SYS_TOKEN {
+        return None;
+    }
+    let digits: String = statement
+        .skip_while(|b| **b == b' ' || **b == b'(')
+        .take_while(|b| b.is_ascii_digit())
+        .map(|b| *b as char)
+        .collect();
+    digits.parse().ok()
+}
+
+// Atari OS run and init vectors
+const RUNAD: u16 = 0x02e0;
+const INITAD: u16 = 0x02e2;
+
+fn parse_xex(bytes: &[u8]) -> Result<Program, String> {
+    if !bytes.starts_with(&[0xff, 0xff]) {
+        return Err("Missing $FFFF header".to_string());
+    }
+    let mut program = Program { segments: Vec::new(), run_address: None };
+    let mut position = 2;
+    while position < bytes.len() {
+        let word = |at: usize| bytes.get(at..at + 2)
+            .map(|w| u16::from_le_bytes([w[0], w[1]]))
+            .ok_or_else(|| format!("Segment header at offset {:x} is cut short", at));
+        let mut start = word(position)?;
+        // Any segment can repeat the header
+        if start == 0xffff {
+            position += 2;
+            start = word(position)?;
+        }
+        let end = word(position + 2)?;
+        if end < start {
+            return Err(format!("Segment ends at 0x{:04x} before it starts at 0x{:04x}", end, start));
+        }
+        position += 4;
+        let length = (end - start) as usize + 1;
+        let data = bytes.get(position..position + length)
+            .ok_or_else(|| format!("Segment at 0x{:04x} is cut short", start))?;
+        // The OS calls INITAD during loading. There's no OS here, so those
+        // routines are loaded but not run.
+        if start <= INITAD + 1 && end >= INITAD {
+            log::warn!("Ignoring init address in segment at 0x{:04x}", start);
+        }
+        if start <= RUNAD && end > RUNAD {
+            let offset = (RUNAD - start) as usize;
+            program.run_address = Some(u16::from_le_bytes([data[offset], data[offset + 1]]));
+        }
+        program.add(start as u32, data)?;
+        position += length;
+    }
+    Ok(program)
+}
+
+const O65_MAGIC: [u8; 5] = [0x01, 0x00, b'o', b'6', b'5'];
+const O65_MODE_65816: u16 = 0x8000;
+const O65_MODE_32_BIT: u16 = 0x2000;
+const O65_MODE_OBJECT: u16 = 0x1000;
+
+fn parse_o65(bytes: &[u8]) -> Result<Program, String> {
+    if !bytes.starts_with(&O65_MAGIC) {
+        return Err("Missing o65 header".to_string());
+    }
+    let cut_short = || "File is cut short".to_string();
+    let word = |at: usize| bytes.get(at..at + 2)
+        .map(|w| u16::from_le_bytes([w[0], w[1]]))
+        .ok_or_else(cut_short);
+
+    let mode = word(6)?;
+    if mode & O65_MODE_65816 != 0 {
+        return Err("65816 code is not supported".to_string());
+    }
+    if mode & O65_MODE_32_BIT != 0 {
+        return Err("32 bit o65 files are not supported".to_string());
+    }
+    if mode & O65_MODE_OBJECT != 0 {
+        return Err("Object files need linking first".to_string());
+    }
+    // tbase, tlen, dbase, dlen, bbase, blen, zbase, zlen, stack
+    let header: Vec<u16> = (0..9).map(|i| word(8 + 2 * i)).collect::<Result<_, _>>()?;
+    let (text_base, text_length) = (header[0], header[1] as usize);
+    let (data_base, data_length) = (header[2], header[3] as usize);
+    let (bss_base, bss_length) = (header[4], header[5] as usize);
+
+    // Skip the header options
+    let mut position = 26;
+    loop {
+        let length = *bytes.get(position).ok_or_else(cut_short)? as usize;
+        if length == 0 {
+            position += 1;
+            break;
+        }
+        position += length;
+    }
+
+    let text = bytes.get(position..position + text_length).ok_or_else(cut_short)?;
+    position += text_length;
+    let data = bytes.get(position..position + data_length).ok_or_else(cut_short)?;
+    position += data_length;
+    if word(position)? != 0 {
+        return Err("File has undefined references".to_string());
+    }
+
+    let mut program = Program { segments: Vec::new(), run_address: Some(text_base) };
+    program.add(text_base as u32, text)?;
+    program.add(data_base as u32, data)?;
+    // BSS is expected to be cleared by the loader
+    program.add(bss_base as u32, &vec![0; bss_length])?;
+    Ok(program)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn segment(address: u16, data: &[u8]) -> Segment {
+        Segment { address, data: data.to_vec() }
+    }
+
+    #[test]
+    fn detect_format() {
+        let detect = |name: &str, bytes: &[u8]| Format::detect(&PathBuf::from(name), bytes);
+        assert_eq!(detect("a.HEX", b""), Format::Ihex);
+        assert_eq!(detect("a.s19", b""), Format::Srec);
+        assert_eq!(detect("a.prg", b"\x01\x08"), Format::Prg);
+        assert_eq!(detect("a.xex", b""), Format::Xex);
+        assert_eq!(detect("a.o65", b""), Format::O65);
+        assert_eq!(detect("a", b":00000001FF\n"), Format::Ihex);
+        assert_eq!(detect("a", b"S9030000FC\n"), Format::Srec);
+        assert_eq!(detect("a", &[0xff, 0xff, 0x00, 0x20]), Format::Xex);
+        assert_eq!(detect("a.rom", &[0xff, 0xff, 0x00, 0x20]), Format::Raw);
+        assert_eq!(detect("a", &O65_MAGIC), Format::O65);
+        assert_eq!(detect("a.bin", &[0xa9, 0x01]), Format::Raw);
+        assert_eq!(detect("a", b":binary\x00"), Format::Raw);
+
+        // An image starting with erased bytes, without a name to go by, isn't an Atari file after all
+        let file_name = std::env::temp_dir().join(format!("m6502-test-{}-erased", std::process::id()));
+        let image = [0xff, 0xff, 0x01, 0x60, 0x02, 0x60, 0xea];
+        std::fs::write(&file_name, image).unwrap();
+        let program = load_file(&file_name, None, 0xfff9);
+        std::fs::remove_file(&file_name).unwrap();
+        assert_eq!(program.unwrap().segments, vec![segment(0xfff9, &image)]);
+    }
+
+    #[test]
+    fn raw() {
+        let program = parse(Format::Raw, &[0xa9, 0x01], 0x1000).unwrap();
+        assert_eq!(program.segments, vec![segment(0x1000, &[0xa9, 0x01])]);
+        assert_eq!(program.run_address, None);
+        assert!(parse(Format::Raw, &[0; 0x101], 0xff00).is_err());
+    }
+
+    #[test]
+    fn intel_hex() {
+        let text = ":0300300002337A1E\n\
+                    :02003300FFEEDE\n\
+                    :02010000AABB98\n\
+                    :0400000500000030C7\n\
+                    :00000001FF\n";
+        let program = parse(Format::Ihex, text.as_bytes(), 0).unwrap();
+        assert_eq!(program.segments, vec![
+            segment(0x0030, &[0x02, 0x33, 0x7a, 0xff, 0xee]),
+            segment(0x0100, &[0xaa, 0xbb]),
+        ]);
+        assert_eq!(program.run_address, Some(0x0030));
+
+        assert!(parse(Format::Ihex, b":0300300002337A1F\n", 0).is_err());
+        assert!(parse(Format::Ihex, b":020000040001F9\n:01000000AA55\n", 0).is_err());
+    }
+
+    #[test]
+    fn invalid_hex() {
+        assert_eq!(hex_bytes("A9+1", 3), Err("Line 3: invalid hex '+1'".to_string()));
+        // Characters that aren't ASCII don't split into hex digits
+        assert!(hex_bytes("0é0", 1).unwrap_err().starts_with("Line 1: invalid hex"));
+        assert!(parse(Format::Ihex, ":0é00300002337A1E\n".as_bytes(), 0).is_err());
+        assert!(parse(Format::Srec, "S1é61000A9018DB2\n".as_bytes(), 0).is_err());
+    }
+
+    #[test]
+    fn s_records() {
+        let text = "S00600004844521B\n\
+                    S1061000A9018DB2\n\
+                    S104100300E8\n\
+                    S5030002FA\n\
+                    S9031000EC\n";
+        let program = parse(Format::Srec, text.as_bytes(), 0).unwrap();
+        assert_eq!(program.segments, vec![segment(0x1000, &[0xa9, 0x01, 0x8d, 0x00])]);
+        assert_eq!(program.run_address, Some(0x1000));
+
+        assert!(parse(Format::Srec, b"S1061000A9018DB3\n", 0).is_err());
+        assert!(parse(Format::Srec, b"S2060100000000F8\n", 0).is_err());
+    }
+
+    #[test]
+    fn commodore_prg() {
+        // 10 SYS 2062, followed by the machine code
+        let bytes = [
+            0x01, 0x08,
+            0x0c, 0x08, 0x0a, 0x00, 0x9e, b' ', b'2', b'0', b'6', b'2', 0x00,
+            0x00, 0x00,
+            0xa9, 0x01, 0x60,
+        ];
+        let program = parse(Format::Prg, &bytes, 0).unwrap();
+        assert_eq!(program.segments, vec![segment(0x0801, &bytes[2..])]);
+        assert_eq!(program.run_address, Some(2062));
+
+        // Without a BASIC stub, run from the load address
+        let program = parse(Format::Prg, &[0x00, 0xc0, 0xa9, 0x01], 0).unwrap();
+        assert_eq!(program.run_address, Some(0xc000));
+    }
+
+    #[test]
+    fn atari_xex() {
+        let bytes = [
+            0xff, 0xff, 0x00, 0x20, 0x01, 0x20, 0xa9, 0x01,
+            0xff, 0xff, 0x02, 0x20, 0x02, 0x20, 0x60,
+            0xe0, 0x02, 0xe1, 0x02, 0x00, 0x20,
+        ];
+        let program = parse(Format::Xex, &bytes, 0).unwrap();
+        assert_eq!(program.segments, vec![
+            segment(0x2000, &[0xa9, 0x01, 0x60]),
+            segment(0x02e0, &[0x00, 0x20]),
+        ]);
+        assert_eq!(program.run_address, Some(0x2000));
+
+        assert!(parse(Format::Xex, &bytes[..bytes.len() - 1], 0).is_err());
+    }
+
+    fn o65(mode: u16, undefined: u16) -> Vec<u8> {
+        let mut bytes = O65_MAGIC.to_vec();
+        bytes.push(0); // version
+        bytes.extend(mode.to_le_bytes());
+        for value in [0x1000u16, 3, 0x2000, 1, 0x2100, 2, 0x0002, 0, 0] {
+            bytes.extend(value.to_le_bytes());
+        }
+        // An option, then the end of the options
+        bytes.extend([4, 0, b'x', 0, 0]);
+        bytes.extend([0xa9, 0x01, 0x60]); // text
+        bytes.push(0x42); // data
+        bytes.extend(undefined.to_le_bytes());
+        bytes
+    }
+
+    #[test]
+    fn o65_executable() {
+        let program = parse(Format::O65, &o65(0, 0), 0).unwrap();
+        assert_eq!(program.segments, vec![
+            segment(0x1000, &[0xa9, 0x01, 0x60]),
+            segment(0x2000, &[0x42]),
+            segment(0x2100, &[0x00, 0x00]),
+        ]);
+        assert_eq!(program.run_address, Some(0x1000));
+
+        assert!(parse(Format::O65, &o65(0, 1), 0).is_err());
+        assert!(parse(Format::O65, &o65(O65_MODE_OBJECT, 0), 0).is_err());
+        assert!(parse(Format::O65, &o65(O65_MODE_32_BIT, 0), 0).is_err());
+    }
+}
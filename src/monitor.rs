@@ -0,0 +1,224 @@
+// Monitor commands, as typed in the command palette of the TUI
+
+use std::path::PathBuf;
+
+use crate::computer::symbols::parse_address;
+use crate::tui::config::Panel;
+
+/*
+ * Locations are symbols or hexadecimal addresses, and values are hexadecimal,
+ * like in most monitors. Commands can be shortened to the letters in brackets.
+ */
+pub const HELP: &[(&str, &str)] = &[
+    ("(r)un", "run until something stops it"),
+    ("pause", "stop running"),
+    ("(s)tep [count]", "execute instructions"),
+    ("(o)ver", "step over a subroutine call"),
+    ("o(u)t", "run until the subroutine returns"),
+    ("(n)ext", "run to the next source line"),
+    ("until LOCATION", "run until the location"),
+    ("reset", "start again from the reset vector"),
+    ("(b)reak LOCATION", "add a breakpoint"),
+    ("(d)elete LOCATION", "remove a breakpoint"),
+    ("set REGISTER VALUE", "change a, x, y, sp, pc or flags"),
+    ("(m)em [LOCATION]", "edit memory, at the location"),
+    ("(dis)assemble LOCATION", "show the code at the location"),
+    ("poke LOCATION BYTES..", "write bytes to memory"),
+    ("(a)ssemble LOCATION LINE", "assemble a line into memory"),
+    ("(w)atch [EXPRESSION]", "add a watch, or edit the watches"),
+    ("load rom|program|symbols|state [FILE [ADDRESS]]", "load a file, or pick one"),
+    ("save state FILE", "save the registers and memory"),
+    ("console", "type into the console"),
+    ("hide|show PANEL", "hide or show cpu, stack, disassembly, lcd, console, source, memory, watch or history"),
+    ("log", "show the log"),
+    ("help", "show the keys and commands"),
+    ("(q)uit", "leave the emulator"),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Register {
+    A,
+    X,
+    Y,
+    Sp,
+    Pc,
+    Flags,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileKind {
+    Rom,
+    Program,
+    Symbols,
+    State,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    Run,
+    Pause,
+    Step(u32),
+    Over,
+    Out,
+    Next,
+    Until(String),
+    Reset,
+    Break(String),
+    Delete(String),
+    Set(Register, String),
+    Memory(Option<String>),
+    Disassemble(String),
+    Poke(String, Vec<u8>),
+    Assemble(String, String),
+    Watch(Option<String>),
+    // Without a file, one is picked
+    Load(FileKind, Option<PathBuf>, Option<u16>),
+    SaveState(PathBuf),
+    Console,
+    Hide(Panel),
+    Show(Panel),
+    Log,
+    Help,
+    Quit,
+}
+
+impl Command {
+    pub fn parse(line: &str) -> Result<Self, String> {
+        let line = line.trim();
+        let (name, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let rest = rest.trim();
+        let args: Vec<&str> = rest.split_whitespace().collect();
+
+        // The one argument a command needs, or none at all
+        let one = |what: &str| match args.as_slice() {
+            [arg] => Ok(arg.to_string()),
+            _ => Err(format!("'{}' needs {}", name, what)),
+        };
+        let none = |command: Command| match args.is_empty() {
+            true => Ok(command),
+            false => Err(format!("'{}' has no arguments", name)),
+        };
+        let optional = || match args.as_slice() {
+            [] => Ok(None),
+            [arg] => Ok(Some(arg.to_string())),
+            _ => Err(format!("'{}' has at most one argument", name)),
+        };
+
+        match name.to_lowercase().as_str() {
+            "r" | "run" => none(Command::Run),
+            "pause" => none(Command::Pause),
+            "s" | "step" => match optional()? {
+                Some(count) => count.parse()
+                    .map(Command::Step)
+                    .map_err(|_| format!("Invalid count '{}'", count)),
+                None => Ok(Command::Step(1)),
+            },
+            "o" | "over" => none(Command::Over),
+            "u" | "out" => none(Command::Out),
+            "n" | "next" => none(Command::Next),
+            "until" => Ok(Command::Until(one("a location")?)),
+            "reset" => none(Command::Reset),
+            "b" | "break" => Ok(Command::Break(one("a location")?)),
+            "d" | "delete" => Ok(Command::Delete(one("a location")?)),
+            "set" => match args.as_slice() {
+                [register, value] => {
+                    let register = match register.to_lowercase().as_str() {
+                        "a" => Register::A,
+                        "x" => Register::X,
+                        "y" => Register::Y,
+                        "sp" => Register::Sp,
+                        "pc" => Register::Pc,
+                        "flags" | "p" => Register::Flags,
+                        _ => return Err(format!("Unknown register '{}'", register)),
+                    };
+                    Ok(Command::Set(register, value.to_string()))
+                }
+                _ => Err("'set' needs a register and a value".to_string()),
+            },
+            "m" | "mem" => Ok(Command::Memory(optional()?)),
+            "dis" | "disassemble" => Ok(Command::Disassemble(one("a location")?)),
+            "poke" => match args.split_first() {
+                Some((location, bytes)) if !bytes.is_empty() => {
+                    let bytes = bytes.iter().map(|byte| parse_byte(byte)).collect::<Result<_, _>>()?;
+                    Ok(Command::Poke(location.to_string(), bytes))
+                }
+                _ => Err("'poke' needs a location and bytes".to_string()),
+            },
+            "a" | "assemble" => match rest.split_once(char::is_whitespace) {
+                Some((location, line)) => Ok(Command::Assemble(location.to_string(), line.trim().to_string())),
+                None => Err("'assemble' needs a location and a line".to_string()),
+            },
+            "w" | "watch" => Ok(Command::Watch((!rest.is_empty()).then(|| rest.to_string()))),
+            "load" => {
+                let (kind, file, address) = match args.as_slice() {
+                    [kind] => (kind, None, None),
+                    [kind, file] => (kind, Some(PathBuf::from(file)), None),
+                    [kind, file, address] => (kind, Some(PathBuf::from(file)), Some(parse_address(address)?)),
+                    _ => return Err("'load' needs what to load".to_string()),
+                };
+                let kind = match kind.to_lowercase().as_str() {
+                    "rom" => FileKind::Rom,
+                    "program" => FileKind::Program,
+                    "symbols" => FileKind::Symbols,
+                    "state" => FileKind::State,
+                    _ => return Err(format!("Can't load '{}', only rom, program, symbols or state", kind)),
+                };
+                Ok(Command::Load(kind, file, address))
+            }
+            "save" => match args.as_slice() {
+                [kind, file] if kind.eq_ignore_ascii_case("state") => Ok(Command::SaveState(PathBuf::from(file))),
+                _ => Err("'save' needs 'state' and a file".to_string()),
+            },
+            "console" => none(Command::Console),
+            "hide" => Ok(Command::Hide(one("a panel")?.parse()?)),
+            "show" => Ok(Command::Show(one("a panel")?.parse()?)),
+            "log" => none(Command::Log),
+            "help" | "?" => none(Command::Help),
+            "q" | "quit" => none(Command::Quit),
+            "" => Err("No command".to_string()),
+            _ => Err(format!("Unknown command '{}', try 'help'", name)),
+        }
+    }
+}
+
+fn parse_byte(text: &str) -> Result<u8, String> {
+    let digits = text.strip_prefix('$').unwrap_or(text);
+    u8::from_str_radix(digits, 16).map_err(|_| format!("Invalid byte '{}'", text))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn commands() {
+        assert_eq!(Command::parse("step"), Ok(Command::Step(1)));
+        assert_eq!(Command::parse(" s 10 "), Ok(Command::Step(10)));
+        assert_eq!(Command::parse("B loop"), Ok(Command::Break("loop".to_string())));
+        assert_eq!(Command::parse("set sp $f0"), Ok(Command::Set(Register::Sp, "$f0".to_string())));
+        assert_eq!(Command::parse("poke $0200 41 $42"), Ok(Command::Poke("$0200".to_string(), vec![0x41, 0x42])));
+        assert_eq!(Command::parse("a $1000 LDA #$01"),
+            Ok(Command::Assemble("$1000".to_string(), "LDA #$01".to_string())));
+        assert_eq!(Command::parse("watch word pointer"), Ok(Command::Watch(Some("word pointer".to_string()))));
+        assert_eq!(Command::parse("m"), Ok(Command::Memory(None)));
+        assert_eq!(Command::parse("load program demo.bin 0800"),
+            Ok(Command::Load(FileKind::Program, Some(PathBuf::from("demo.bin")), Some(0x0800))));
+        assert_eq!(Command::parse("load rom"), Ok(Command::Load(FileKind::Rom, None, None)));
+        assert_eq!(Command::parse("save state game.state"), Ok(Command::SaveState(PathBuf::from("game.state"))));
+        assert_eq!(Command::parse("hide Stack"), Ok(Command::Hide(Panel::Stack)));
+    }
+
+    #[test]
+    fn errors() {
+        assert!(Command::parse("").is_err());
+        assert!(Command::parse("jump").is_err());
+        assert!(Command::parse("run now").is_err());
+        assert!(Command::parse("break").is_err());
+        assert!(Command::parse("set q 1").is_err());
+        assert!(Command::parse("poke $0200 100").is_err());
+        assert!(Command::parse("load disk demo.d64").is_err());
+        assert!(Command::parse("save state").is_err());
+        assert!(Command::parse("save rom demo.rom").is_err());
+        assert!(Command::parse("show registers").is_err());
+    }
+}
@@ -1,21 +1,28 @@
-use crate::computer::{cpu::inspect::CpuState, Computer};
+use std::path::Path;
+
+use crate::computer::config::DEFAULT_LOAD_ADDRESS;
+use crate::computer::debug_info::SourceLocation;
+use crate::computer::cpu::status::Status;
+use crate::computer::{cpu::inspect::CpuState, devices::Lcd, Computer, StackEntry, Stop};
+use crate::loader;
+use crate::monitor::Register;
+use crate::scripting::Script;
+use crate::watch::Watch;
 
 // App contains the model functionality for any UI to display
 // the state of a computer
 pub struct ComputerProxy<'a> {
     // A private reference to the computer we're shadowing
-    computer: &'a Computer,
+    computer: &'a mut Computer,
 
     // The current state of the CPU. Refresh with self.update()
     pub cpu_state: CpuState,
 }
 
 impl<'a> ComputerProxy<'a> {
-    pub fn new(computer: &'a Computer) -> Self {
-        Self {
-            computer,
-            cpu_state: computer.get_cpu_state(),
-        }
+    pub fn new(computer: &'a mut Computer) -> Self {
+        let cpu_state = computer.get_cpu_state();
+        Self { computer, cpu_state }
     }
 
     // Update the state from the computer
@@ -23,12 +30,166 @@ impl<'a> ComputerProxy<'a> {
         self.cpu_state = self.computer.get_cpu_state();
     }
 
-    // Get memory contents from the computer's bus.
-    pub fn get_memory_lines(&self, address: u16, line_count: u16, line_length: u16) -> Vec<(u16, Vec<u8>)> {
-        // start needs to be aligned with line_length, and address should be on the second line
-        // TODO guard against dropping below 0?
-        let start = address - address % line_length - line_length;
-        self.computer.get_memory_lines(start, line_count, line_length)
+    // Run until something stops the computer, at the speed of its clock
+    pub fn run(&mut self) -> Stop {
+        self.computer.run()
+    }
+
+    // Let the computer run for a number of clock cycles. Returns why it stopped early, if it did.
+    pub fn run_cycles(&mut self, cycles: u32) -> Option<Stop> {
+        self.computer.run_cycles(cycles).err()
+    }
+
+    // Run until the next source line. Returns why it stopped early, if it did.
+    pub fn step_line(&mut self) -> Option<Stop> {
+        self.computer.step_line().err()
+    }
+
+    // Execute a single instruction
+    pub fn step_instruction(&mut self) -> Stop {
+        match self.computer.step() {
+            Some(_) => Stop::Step,
+            None => Stop::Halted,
+        }
+    }
+
+    // Execute an instruction, or a whole subroutine. Returns why it stopped early, if it did.
+    pub fn step_over(&mut self) -> Option<Stop> {
+        self.computer.step_over().err()
+    }
+
+    // Run until the current subroutine returns. Returns why it stopped early, if it did.
+    pub fn step_out(&mut self) -> Option<Stop> {
+        self.computer.step_out().err()
+    }
+
+    // Have the computer stop when it gets to the address
+    pub fn run_to(&mut self, address: u16) {
+        self.computer.set_temporary_breakpoint(address);
+    }
+
+    pub fn reset(&mut self) {
+        self.computer.reset();
+    }
+
+    pub fn is_breakpoint(&self, address: u16) -> bool {
+        self.computer.breakpoints().any(|breakpoint| breakpoint == address)
+    }
+
+    // Add a breakpoint, or remove it if it's already there
+    pub fn toggle_breakpoint(&mut self, address: u16) {
+        if !self.computer.remove_breakpoint(address) {
+            self.computer.add_breakpoint(address);
+        }
+    }
+
+    // Let a script handle a stop. Returns whether the computer should keep running.
+    pub fn handle_stop(&mut self, script: &mut Script, stop: Stop) -> Result<bool, String> {
+        script.handle_stop(self.computer, stop)
+    }
+
+    // Assemble a line and write it to memory. Returns the number of bytes written.
+    pub fn assemble_at(&mut self, address: u16, line: &str) -> Result<usize, String> {
+        self.computer.assemble_at(address, line)
+    }
+
+    // Replace the ROM with the one in a file, and start it
+    pub fn load_rom(&mut self, file_name: &Path) -> Result<(), String> {
+        let rom = std::fs::read(file_name)
+            .map_err(|e| format!("Was not able to load {}: {}", file_name.display(), e))?;
+        self.computer.load_rom(&rom)
+    }
+
+    // Load a program into memory and continue there. Returns where it runs from.
+    pub fn load_program(&mut self, file_name: &Path, address: Option<u16>) -> Result<u16, String> {
+        let address = address.unwrap_or(DEFAULT_LOAD_ADDRESS);
+        let program = loader::load_file(file_name, None, address)?;
+        for segment in &program.segments {
+            self.computer.write_memory(segment.address, &segment.data);
+        }
+        let run_address = program.run_address.unwrap_or(address);
+        self.computer.set_program_counter(run_address);
+        Ok(run_address)
+    }
+
+    pub fn load_state(&mut self, file_name: &Path) -> Result<(), String> {
+        self.computer.load_state(file_name)
+    }
+
+    pub fn save_state(&self, file_name: &Path) -> Result<(), String> {
+        self.computer.save_state(file_name)
+    }
+
+    // Returns the number of symbols loaded
+    pub fn load_symbols(&mut self, file_name: &Path) -> Result<usize, String> {
+        self.computer.load_symbols(file_name)
+    }
+
+    // Change a register to a symbol or hexadecimal value
+    pub fn set_register(&mut self, register: Register, value: &str) -> Result<(), String> {
+        let value = self.resolve_address(value)?;
+        let byte = || u8::try_from(value).map_err(|_| format!("${:x} doesn't fit in a byte", value));
+        let mut state = self.computer.get_cpu_state();
+        match register {
+            Register::A => state.accumulator = byte()?,
+            Register::X => state.x_index = byte()?,
+            Register::Y => state.y_index = byte()?,
+            Register::Sp => state.stack_pointer = byte()?,
+            Register::Pc => state.program_counter = value,
+            Register::Flags => state.status = Status::from_byte(byte()?),
+        }
+        self.computer.set_cpu_state(&state);
+        Ok(())
+    }
+
+    // Flip bits of the status register
+    pub fn toggle_flags(&mut self, bits: u8) {
+        let mut state = self.computer.get_cpu_state();
+        state.status = Status::from_byte(state.status.as_byte() ^ bits);
+        self.computer.set_cpu_state(&state);
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.computer.is_halted()
+    }
+
+    pub fn clock_speed(&self) -> u32 {
+        self.computer.clock_speed()
+    }
+
+    pub fn lcd(&self) -> Option<Lcd> {
+        self.computer.lcd()
+    }
+
+    pub fn has_console(&self) -> bool {
+        self.computer.console().is_some()
+    }
+
+    // What the program wrote to the console since the last time
+    pub fn console_output(&self) -> Vec<u8> {
+        self.computer.console().map(|console| console.receiver.try_iter().collect()).unwrap_or_default()
+    }
+
+    // Type a byte into the console, for the program to read
+    pub fn console_input(&self, byte: u8) {
+        if let Some(console) = self.computer.console() {
+            let _ = console.sender.send(byte);
+        }
+    }
+
+    // Memory contents, without reading them: None where nothing is mapped
+    pub fn peek_memory(&self, address: u16, length: u16) -> Vec<Option<u8>> {
+        self.computer.peek_memory(address, length)
+    }
+
+    // Write to memory through the computer's bus
+    pub fn write_memory(&mut self, address: u16, data: &[u8]) {
+        self.computer.write_memory(address, data);
+    }
+
+    // The address for a symbol or a hexadecimal address
+    pub fn resolve_address(&self, text: &str) -> Result<u16, String> {
+        self.computer.symbols().resolve(text)
     }
 
     pub fn current_opcode_to_string(&self) -> String {
@@ -39,11 +200,52 @@ impl<'a> ComputerProxy<'a> {
         self.computer.disassemble(start_address, length)
     }
 
-    pub fn get_execution_history(&self) -> Vec<(u16, String)> {
-        self.computer.get_execution_history()
+    pub fn disassemble_lines(&self, start_address: u16, count: usize) -> Vec<(u16, String)> {
+        self.computer.disassemble_lines(start_address, count)
     }
 
-    pub fn get_execution_future(&self) -> Vec<(u16, String)> {
-        self.computer.disassemble(self.cpu_state.program_counter, 16)
+    pub fn next_instruction(&self, address: u16) -> u16 {
+        self.computer.next_instruction(address)
     }
-}
\ No newline at end of file
+
+    pub fn previous_instruction(&self, address: u16) -> u16 {
+        self.computer.previous_instruction(address)
+    }
+
+    pub fn symbol_at(&self, address: u16) -> Option<&str> {
+        self.computer.symbols().name_for(address)
+    }
+
+    pub fn address_to_string(&self, address: u16) -> String {
+        self.computer.address_to_string(address)
+    }
+
+    pub fn current_source_location(&self) -> Option<SourceLocation> {
+        self.computer.source_location(self.cpu_state.program_counter)
+    }
+
+    pub fn current_source_lines(&self, before: usize, after: usize) -> Vec<(usize, String)> {
+        self.computer.source_lines(self.cpu_state.program_counter, before, after)
+    }
+
+    // Segment and scope of the current program counter, like "CODE, print"
+    pub fn current_segment_and_scope(&self) -> Option<String> {
+        let address = self.cpu_state.program_counter;
+        let segment = self.computer.segment_for(address).map(|segment| segment.name.clone());
+        let parts: Vec<String> = segment.into_iter().chain(self.computer.scope_for(address)).collect();
+        (!parts.is_empty()).then(|| parts.join(", "))
+    }
+
+    // What's on the stack, from the top down
+    pub fn stack_entries(&self) -> Vec<StackEntry> {
+        self.computer.stack_entries()
+    }
+
+    pub fn evaluate_watch(&self, watch: &Watch) -> Result<String, String> {
+        watch.evaluate(self.computer)
+    }
+
+    pub fn get_execution_history(&self) -> Vec<(u16, String)> {
+        self.computer.get_execution_history()
+    }
+}
@@ -0,0 +1,316 @@
+// Scripting emulator sessions with Rhai
+
+use std::cell::{RefCell, RefMut};
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::rc::Rc;
+
+use rhai::{Dynamic, Engine, EvalAltResult, FnPtr, NativeCallContext, Scope, AST};
+
+use crate::computer::bus::{Access, AccessKind};
+use crate::computer::Computer;
+use crate::computer::Stop;
+
+/*
+ * Scripts see the computer through these functions. Addresses can be
+ * numbers, or strings with a symbol or hexadecimal address.
+ *
+ *   a() x() y() sp() pc() flags()     registers, and set_a(v) etc. to change them
+ *   peek(address) peek_word(address)  read memory
+ *   poke(address, value)              write memory
+ *   address(name)                     the address of a symbol
+ *   break_at(address)                 add a breakpoint, clear_break(address) to remove it
+ *   step()                            execute an instruction, returns the cycles used
+ *   run(cycles)                       run for at most about this many cycles. Returns why
+ *                                     it stopped: "cycles", "halted", "breakpoint",
+ *                                     "exit", "watchpoint" or "step".
+ *   on_read(address, callback)        call callback(address, value) after an instruction
+ *   on_write(address, callback)       read or wrote the address. Returning true from
+ *                                     the callback stops the computer.
+ *
+ * The script is run once, when it's loaded. Callbacks are also called when
+ * the computer runs outside the script, like in the TUI.
+ */
+
+type Callbacks = Rc<RefCell<BTreeMap<(u16, AccessKind), FnPtr>>>;
+type SharedComputer = Rc<RefCell<Option<Computer>>>;
+type RhaiResult<T> = Result<T, Box<EvalAltResult>>;
+
+pub struct Script {
+    engine: Engine,
+    ast: AST,
+    scope: Scope<'static>,
+    // The computer the script works on. Functions registered with Rhai can't
+    // borrow it, so while the script runs, the caller's computer is moved in
+    // here. Otherwise there is none.
+    computer: SharedComputer,
+    callbacks: Callbacks,
+}
+
+impl Script {
+    pub fn from_file(file_name: &Path) -> Result<Self, String> {
+        let source = std::fs::read_to_string(file_name)
+            .map_err(|e| format!("Was not able to read {}: {}", file_name.display(), e))?;
+        Self::new(&source).map_err(|e| format!("{}: {}", file_name.display(), e))
+    }
+
+    pub fn new(source: &str) -> Result<Self, String> {
+        let computer = SharedComputer::default();
+        let callbacks = Callbacks::default();
+
+        let mut engine = Engine::new();
+        engine.on_print(|text| log::info!("{}", text));
+        engine.on_debug(|text, _, position| log::debug!("{}: {}", position, text));
+        register_functions(&mut engine, &computer, &callbacks);
+        let ast = engine.compile(source).map_err(|e| e.to_string())?;
+
+        Ok(Self {
+            engine,
+            ast,
+            scope: Scope::new(),
+            computer,
+            callbacks,
+        })
+    }
+
+    fn with_computer<T>(&mut self, computer: &mut Computer, f: impl FnOnce(&mut Self) -> T) -> T {
+        *self.computer.borrow_mut() = Some(std::mem::take(computer));
+        let result = f(self);
+        if let Some(taken) = self.computer.borrow_mut().take() {
+            *computer = taken;
+        }
+        result
+    }
+
+    // Run the script, for the computer
+    pub fn run(&mut self, computer: &mut Computer) -> Result<(), String> {
+        self.with_computer(computer, |script| {
+            script.engine.run_ast_with_scope(&mut script.scope, &script.ast)
+                .map_err(|e| e.to_string())
+        })
+    }
+
+    // Call the callbacks for a watchpoint the computer stopped at. Returns
+    // whether it should continue running.
+    pub fn handle_stop(&mut self, computer: &mut Computer, stop: Stop) -> Result<bool, String> {
+        let Stop::Watchpoint(access) = stop else {
+            return Ok(false);
+        };
+        let Some(callback) = self.callbacks.borrow().get(&(access.address, access.kind)).cloned() else {
+            return Ok(false);
+        };
+        self.with_computer(computer, |script| {
+            let result: Dynamic = callback
+                .call(&script.engine, &script.ast, (access.address as i64, access.value as i64))
+                .map_err(|e| e.to_string())?;
+            Ok(!result.as_bool().unwrap_or(false))
+        })
+    }
+}
+
+fn stop_name(stop: Stop) -> &'static str {
+    match stop {
+        Stop::Halted => "halted",
+        Stop::Breakpoint(_) => "breakpoint",
+        Stop::Exit(_) => "exit",
+        Stop::Watchpoint(_) => "watchpoint",
+        Stop::Step => "step",
+        Stop::Paused => "paused",
+    }
+}
+
+// Call the callback for an access from within the script. Returns whether
+// to continue running.
+fn call_back(context: &NativeCallContext, callbacks: &Callbacks, access: Access) -> RhaiResult<bool> {
+    let Some(callback) = callbacks.borrow().get(&(access.address, access.kind)).cloned() else {
+        return Ok(false);
+    };
+    let result: Dynamic = callback.call_within_context(context, (access.address as i64, access.value as i64))?;
+    Ok(!result.as_bool().unwrap_or(false))
+}
+
+// The computer the script is running for
+fn running(computer: &SharedComputer) -> RhaiResult<RefMut<'_, Computer>> {
+    RefMut::filter_map(computer.borrow_mut(), Option::as_mut)
+        .map_err(|_| "There is no computer when the script isn't running".into())
+}
+
+fn register_functions(engine: &mut Engine, computer: &SharedComputer, callbacks: &Callbacks) {
+    // Registers
+    macro_rules! register {
+        ($get:literal, $set:literal, $field:ident, $type:ty) => {
+            let c = computer.clone();
+            engine.register_fn($get, move || -> RhaiResult<i64> {
+                Ok(running(&c)?.get_cpu_state().$field as i64)
+            });
+            let c = computer.clone();
+            engine.register_fn($set, move |value: i64| -> RhaiResult<()> {
+                let mut computer = running(&c)?;
+                let mut state = computer.get_cpu_state();
+                state.$field = value as $type;
+                computer.set_cpu_state(&state);
+                Ok(())
+            });
+        };
+    }
+    register!("a", "set_a", accumulator, u8);
+    register!("x", "set_x", x_index, u8);
+    register!("y", "set_y", y_index, u8);
+    register!("sp", "set_sp", stack_pointer, u8);
+    register!("pc", "set_pc", program_counter, u16);
+    let c = computer.clone();
+    engine.register_fn("flags", move || -> RhaiResult<i64> {
+        Ok(running(&c)?.get_cpu_state().status.as_byte() as i64)
+    });
+    let c = computer.clone();
+    engine.register_fn("set_flags", move |value: i64| -> RhaiResult<()> {
+        let mut computer = running(&c)?;
+        let mut state = computer.get_cpu_state();
+        state.status = crate::computer::cpu::status::Status::from_byte(value as u8);
+        computer.set_cpu_state(&state);
+        Ok(())
+    });
+
+    // Memory and symbols
+    let c = computer.clone();
+    engine.register_fn("peek", move |address: i64| -> RhaiResult<i64> {
+        Ok(running(&c)?.read_memory(address as u16, 1)[0] as i64)
+    });
+    let c = computer.clone();
+    engine.register_fn("peek_word", move |address: i64| -> RhaiResult<i64> {
+        let bytes = running(&c)?.read_memory(address as u16, 2);
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]) as i64)
+    });
+    let c = computer.clone();
+    engine.register_fn("poke", move |address: i64, value: i64| -> RhaiResult<()> {
+        running(&c)?.write_memory(address as u16, &[value as u8]);
+        Ok(())
+    });
+    let c = computer.clone();
+    engine.register_fn("address", move |name: &str| -> RhaiResult<i64> {
+        Ok(running(&c)?.symbols().resolve(name)? as i64)
+    });
+
+    // Breakpoints
+    let c = computer.clone();
+    engine.register_fn("break_at", move |address: i64| -> RhaiResult<()> {
+        running(&c)?.add_breakpoint(address as u16);
+        Ok(())
+    });
+    let c = computer.clone();
+    engine.register_fn("break_at", move |location: &str| -> RhaiResult<()> {
+        running(&c)?.add_breakpoint_at(location)?;
+        Ok(())
+    });
+    let c = computer.clone();
+    engine.register_fn("clear_break", move |address: i64| -> RhaiResult<bool> {
+        Ok(running(&c)?.remove_breakpoint(address as u16))
+    });
+
+    // Running
+    let c = computer.clone();
+    engine.register_fn("step", move || -> RhaiResult<i64> {
+        Ok(running(&c)?.step().map_or(-1, |cycles| cycles as i64))
+    });
+    let (c, cb) = (computer.clone(), callbacks.clone());
+    engine.register_fn("run", move |context: NativeCallContext, cycles: i64| -> RhaiResult<String> {
+        // Cycles are counted again after each callback
+        loop {
+            let result = running(&c)?.run_cycles(cycles.max(0) as u32);
+            match result {
+                Ok(_) => return Ok("cycles".to_string()),
+                Err(Stop::Watchpoint(access)) if call_back(&context, &cb, access)? => {}
+                Err(stop) => return Ok(stop_name(stop).to_string()),
+            }
+        }
+    });
+
+    // Callbacks on bus access
+    for (name, kind) in [("on_read", AccessKind::Read), ("on_write", AccessKind::Write)] {
+        let (c, cb) = (computer.clone(), callbacks.clone());
+        engine.register_fn(name, move |address: i64, callback: FnPtr| -> RhaiResult<()> {
+            running(&c)?.add_watchpoint(address as u16, kind);
+            cb.borrow_mut().insert((address as u16, kind), callback);
+            Ok(())
+        });
+        let (c, cb) = (computer.clone(), callbacks.clone());
+        engine.register_fn(name, move |location: &str, callback: FnPtr| -> RhaiResult<()> {
+            let mut computer = running(&c)?;
+            let address = computer.symbols().resolve(location)?;
+            computer.add_watchpoint(address, kind);
+            cb.borrow_mut().insert((address, kind), callback);
+            Ok(())
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::computer::clock::{Clock, ClockMode};
+
+    fn create_computer() -> Computer {
+        let mut rom = vec![0xea; 0x100];
+        // reset vector at $ff00, which does BRK
+        rom[0x00] = 0x00;
+        rom[0xfc] = 0x00;
+        rom[0xfd] = 0xff;
+        let mut computer = Computer::new()
+            .with_rom(rom)
+            .with_clock(Clock::new(ClockMode::Speedy))
+            .build()
+            .unwrap();
+        // LDX #$00, loop: INX, STX $10, JMP loop
+        computer.load_program(0x1000, &[0xa2, 0x00, 0xe8, 0x86, 0x10, 0x4c, 0x02, 0x10]);
+        computer.symbols_mut().add("counter", 0x0010);
+        computer
+    }
+
+    #[test]
+    fn registers_and_memory() {
+        let mut computer = create_computer();
+        let mut script = Script::new("
+            set_a(0x42);
+            set_x(peek(0x1000) + 1);
+            poke(address(\"counter\"), 7);
+            if peek_word(0x1006) != 0x1002 { throw \"wrong word\"; }
+            set_pc(0x1002);
+        ").unwrap();
+        script.run(&mut computer).unwrap();
+        let state = computer.get_cpu_state();
+        assert_eq!((state.accumulator, state.x_index, state.program_counter), (0x42, 0xa3, 0x1002));
+        assert_eq!(computer.read_memory(0x0010, 1), vec![7]);
+    }
+
+    #[test]
+    fn running_with_callbacks() {
+        let mut computer = create_computer();
+        let mut script = Script::new("
+            on_write(\"counter\", |address, value| value == 5);
+            break_at(0x1005);
+            if run(1000) != \"breakpoint\" { throw \"no breakpoint\"; }
+            clear_break(0x1005);
+            if run(1000) != \"watchpoint\" { throw \"no watchpoint\"; }
+            if x() != 5 { throw \"stopped at \" + x(); }
+            step();
+        ").unwrap();
+        script.run(&mut computer).unwrap();
+        assert_eq!(computer.get_cpu_state().program_counter, 0x1002);
+
+        // Callbacks also work when the computer runs outside of the script
+        let stop = computer.run_cycles(1000).unwrap_err();
+        assert_eq!(stop, Stop::Watchpoint(Access { kind: AccessKind::Write, address: 0x0010, value: 6 }));
+        assert_eq!(script.handle_stop(&mut computer, stop), Ok(true));
+        assert_eq!(script.handle_stop(&mut computer, Stop::Halted), Ok(false));
+    }
+
+    #[test]
+    fn errors() {
+        assert!(Script::new("let = 1;").is_err());
+        let mut computer = create_computer();
+        let mut script = Script::new("break_at(\"nowhere\");").unwrap();
+        assert!(script.run(&mut computer).unwrap_err().contains("nowhere"));
+        // The computer is given back after an error too
+        assert_eq!(computer.symbols().resolve("counter"), Ok(0x0010));
+    }
+}
@@ -1,36 +1,119 @@
+pub mod config;
 mod widgets;
 
-use crossterm::event::KeyEvent;
+use crossterm::event::{KeyEvent, MouseButton, MouseEvent, MouseEventKind};
 use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
 use ratatui::layout::Flex;
 use ratatui::prelude::*;
 use ratatui::widgets::*;
 use tui_logger::{TuiWidgetEvent, TuiWidgetState};
+use tui_menu::{Menu, MenuEvent, MenuItem, MenuState};
 
 use std::cell::RefCell;
 use std::ops::Deref;
 use std::ops::DerefMut;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
 
-use crate::computer::Computer;
+use crate::computer::devices::Lcd;
+use crate::computer::symbols::parse_address;
+use crate::computer::{Computer, Stop};
+use crate::monitor::{self, Command, FileKind};
 use crate::proxy::ComputerProxy;
+use crate::scripting::Script;
+use crate::watch::Watch;
 
+use config::{LayoutConfig, Panel, Theme, TuiConfig};
 use widgets::*;
 
-// const NORMAL_STYLE: Style = Style::new().fg(Color::White).bg(Color::Black);
-const BLOCK_TITLE_STYLE: Style = Style::new().fg(Color::Yellow).add_modifier(Modifier::BOLD);
-const APP_TITLE_STYLE: Style = Style::new()
-    .fg(Color::Blue)
-    .bg(Color::Yellow)
-    .add_modifier(Modifier::BOLD.union(Modifier::ITALIC));
-const STATUS_BAR_STYLE: Style = Style::new().fg(Color::Black).bg(Color::White);
-const SELECTED_STYLE: Style = Style::new().fg(Color::Black).bg(Color::Yellow);
 const BLOCK_PADDING: Padding = Padding::horizontal(1);
 const PAD_SPACE_V: u16 = BLOCK_PADDING.top + BLOCK_PADDING.bottom;
 
+// The side columns don't get narrower than this
+const MIN_COLUMN_WIDTH: u16 = 12;
+// Lines moved for every step of the mouse wheel
+const SCROLL_LINES: i32 = 3;
+
+// How often the screen is redrawn while the computer runs
+const FRAME_TIME: Duration = Duration::from_millis(20);
+// How long to wait for input when there is nothing running
+const IDLE_TIME: Duration = Duration::from_millis(250);
+
 #[derive(Clone, Copy, Debug)]
 enum AppDisplayState {
     MainWindow,
     LogPopup, // true = display timestamp
+    LineAssembler,
+    MemoryEditor,
+    WatchEditor,
+    Palette,
+    Menu,
+    HelpPopup,
+    FilePicker,
+    // Keys go to the program, through the console
+    Console,
+}
+
+// Where the line assembler puts the next line, what has been typed, and
+// the outcome of the last line
+#[derive(Default)]
+struct LineAssembler {
+    address: u16,
+    input: String,
+    result: Option<Result<String, String>>,
+}
+
+// What has been typed in the command palette, the commands before it, and
+// the outcome of the last one
+#[derive(Default)]
+struct Palette {
+    input: String,
+    history: Vec<String>,
+    // Where in the history Up and Down are
+    recalled: Option<usize>,
+    result: Option<Result<String, String>>,
+}
+
+// The menu bar. Items are palette commands; the ones that need more, like a
+// file name, end in a space and open the palette to finish them.
+fn create_menu() -> MenuState<String> {
+    let item = |name: &'static str, command: &str| MenuItem::item(name, command.to_string());
+    MenuState::new(vec![
+        MenuItem::group("File", vec![
+            item("Load ROM…", "load rom"),
+            item("Load program…", "load program"),
+            item("Load symbols…", "load symbols"),
+            item("Load state…", "load state"),
+            item("Save state…", "save state "),
+            item("Quit", "quit"),
+        ]),
+        MenuItem::group("Run", vec![
+            item("Run", "run"),
+            item("Pause", "pause"),
+            item("Run until…", "until "),
+            item("Reset", "reset"),
+        ]),
+        MenuItem::group("Debug", vec![
+            item("Step", "step"),
+            item("Step over", "over"),
+            item("Step out", "out"),
+            item("Next line", "next"),
+            item("Breakpoint…", "break "),
+            item("Command…", ""),
+        ]),
+        MenuItem::group("View", vec![
+            item("Memory", "mem"),
+            item("Watches", "watch"),
+            item("History", "show history"),
+            item("Console", "console"),
+            item("Log", "log"),
+            item("Hide panel…", "hide "),
+            item("Show panel…", "show "),
+        ]),
+        MenuItem::group("Help", vec![
+            item("Keys and commands", "help"),
+        ]),
+    ])
 }
 
 pub struct App<'a> {
@@ -38,62 +121,154 @@ pub struct App<'a> {
     version: String,
 
     proxy: ComputerProxy<'a>,
+    // Why the computer is no longer running, if it isn't
+    stop: Option<Stop>,
+    // Gets to handle stops, for its callbacks
+    script: Option<Script>,
 
     should_quit: bool,
 
     display_state: AppDisplayState,
     display_log_timestamp: bool,
+    theme: Theme,
+    layout: LayoutConfig,
+    line_assembler: LineAssembler,
+    palette: Palette,
+    file_picker: Option<FilePicker>,
+    // Where the last file was picked
+    file_directory: Option<PathBuf>,
 
     // State for widgets
-    assembly_list_state: RefCell<ListState>,
+    console_view: ConsoleView,
+    disassembly_view: RefCell<DisassemblyView>,
+    memory_view: RefCell<MemoryView>,
+    watch_view: RefCell<WatchView>,
     log_widget_state: RefCell<TuiWidgetState>,
+    menu_state: RefCell<MenuState<String>>,
+    // Where the panels and the status flags were drawn, for the mouse
+    panel_areas: RefCell<Vec<(Panel, Rect)>>,
+    flags_area: RefCell<Rect>,
 }
 
 impl<'a> App<'a> {
-    pub fn new(computer: &'a Computer) -> Self {
+    pub fn new(computer: &'a mut Computer) -> Self {
         Self {
             title: "CMOS 6502 emulator".to_string(),
             version: "0.0.1".to_string(),
 
             proxy: ComputerProxy::new(computer),
+            stop: None,
+            script: None,
 
             should_quit: false,
 
             display_state: AppDisplayState::MainWindow,
             display_log_timestamp: true,
+            theme: Theme::default(),
+            layout: LayoutConfig::default(),
+            line_assembler: LineAssembler::default(),
+            palette: Palette::default(),
+            file_picker: None,
+            file_directory: None,
 
-            assembly_list_state: ListState::default().into(),
+            console_view: ConsoleView::default(),
+            disassembly_view: DisassemblyView::default().into(),
+            memory_view: MemoryView::default().into(),
+            watch_view: WatchView::default().into(),
             log_widget_state: TuiWidgetState::new()
                 .set_default_display_level(log::LevelFilter::Debug)
                 .into(),
+            menu_state: create_menu().into(),
+            panel_areas: RefCell::new(Vec::new()),
+            flags_area: RefCell::new(Rect::default()),
+        }
+    }
+
+    pub fn with_script(mut self, script: Option<Script>) -> Self {
+        self.script = script;
+        self
+    }
+
+    pub fn with_config(mut self, config: TuiConfig) -> Self {
+        self.theme = config.theme;
+        self.layout = config.layout;
+        self
+    }
+
+    // Keep the watches in this file, if there is one
+    pub fn with_watch_file(mut self, file: Option<PathBuf>) -> Self {
+        if let Some(file) = file {
+            match WatchView::load(&file) {
+                Ok(view) => self.watch_view = view.into(),
+                Err(e) => log::error!("{}", e),
+            }
         }
+        self
     }
 
     pub fn run(mut self, mut terminal: ratatui::DefaultTerminal) -> color_eyre::Result<()> {
         while !self.should_quit {
+            let frame_start = Instant::now();
+
+            // Run the computer for as long as a frame lasts
+            if self.is_running() {
+                let cycles = (self.proxy.clock_speed() as u128 * FRAME_TIME.as_micros() / 1_000_000) as u32;
+                self.stop = self.proxy.run_cycles(cycles);
+                if let (Some(stop), Some(script)) = (self.stop, self.script.as_mut()) {
+                    match self.proxy.handle_stop(script, stop) {
+                        Ok(true) => self.stop = None,
+                        Ok(false) => {}
+                        Err(e) => log::error!("Script: {}", e),
+                    }
+                }
+            }
+
             // Update the internal state of the App
             self.proxy.update();
+            let output = self.proxy.console_output();
+            self.console_view.receive(output);
 
             // Draw the terminal, based on that state
             terminal.draw(|f| self.draw_tui(f))?;
 
-            // Process any interesting events
-            self.process_events()?;
+            // Process any interesting events, until it's time for the next frame
+            let timeout = if self.is_running() {
+                FRAME_TIME.saturating_sub(frame_start.elapsed())
+            } else {
+                IDLE_TIME
+            };
+            self.process_events(timeout)?;
         }
         // If we're here, we're quitting
         Ok(())
     }
 
-    fn process_events(&mut self) -> std::io::Result<()> {
-        if let Event::Key(key) = event::read()? {
-            // Common/global keys
-            if key.kind != KeyEventKind::Release {
-                match key.code {
-                    KeyCode::Char('q') => {
-                        self.should_quit = true;
-                    }
-                    _ => {}
-                }
+    fn is_running(&self) -> bool {
+        self.stop.is_none() && !self.proxy.is_halted()
+    }
+
+    fn process_events(&mut self, timeout: Duration) -> std::io::Result<()> {
+        if !event::poll(timeout)? {
+            return Ok(());
+        }
+        let event = event::read()?;
+        if let Event::Mouse(mouse) = event {
+            self.process_mouse_event(mouse);
+        }
+        if let Event::Key(key) = event {
+            // Common/global keys, except when typing
+            let typing = match self.display_state {
+                AppDisplayState::LineAssembler
+                | AppDisplayState::WatchEditor
+                | AppDisplayState::Palette
+                | AppDisplayState::FilePicker
+                | AppDisplayState::Console => true,
+                AppDisplayState::MemoryEditor => self.memory_view.borrow().goto_input.is_some(),
+                AppDisplayState::MainWindow => self.disassembly_view.borrow().goto_input.is_some(),
+                _ => false,
+            };
+            if key.kind != KeyEventKind::Release && key.code == KeyCode::Char('q') && !typing {
+                self.should_quit = true;
             }
 
             match self.display_state {
@@ -103,6 +278,32 @@ impl<'a> App<'a> {
                 AppDisplayState::LogPopup => {
                     self.process_log_popup_event(key);
                 }
+                AppDisplayState::LineAssembler => {
+                    self.process_line_assembler_event(key);
+                }
+                AppDisplayState::MemoryEditor => {
+                    self.process_memory_editor_event(key);
+                }
+                AppDisplayState::WatchEditor => {
+                    self.process_watch_editor_event(key);
+                }
+                AppDisplayState::Palette => {
+                    self.process_palette_event(key);
+                }
+                AppDisplayState::Menu => {
+                    self.process_menu_event(key);
+                }
+                AppDisplayState::FilePicker => {
+                    self.process_file_picker_event(key);
+                }
+                AppDisplayState::Console => {
+                    self.process_console_event(key);
+                }
+                AppDisplayState::HelpPopup => {
+                    if key.kind != KeyEventKind::Release {
+                        self.display_state = AppDisplayState::MainWindow;
+                    }
+                }
             }
         }
         // Keys depending on application state
@@ -110,13 +311,632 @@ impl<'a> App<'a> {
     }
 
     fn process_main_window_event(&mut self, key: KeyEvent) {
-        if key.kind != KeyEventKind::Release {
+        if key.kind == KeyEventKind::Release {
+            return;
+        }
+        let running = self.is_running();
+        let stopped = !running && !self.proxy.is_halted();
+
+        // Keys that do what a command does
+        let command = match key.code {
+            KeyCode::Char('r') => Some(Command::Run),
+            KeyCode::Char('p') | KeyCode::Char(' ') if running => Some(Command::Pause),
+            KeyCode::Char(' ') => Some(Command::Run),
+            KeyCode::Char('R') => Some(Command::Reset),
+            KeyCode::Char('s') if stopped => Some(Command::Step(1)),
+            KeyCode::Char('o') if stopped => Some(Command::Over),
+            KeyCode::Char('u') if stopped => Some(Command::Out),
+            KeyCode::Char('n') if stopped => Some(Command::Next),
+            KeyCode::Char('l') => Some(Command::Log),
+            KeyCode::Char('m') => Some(Command::Memory(None)),
+            KeyCode::Char('w') => Some(Command::Watch(None)),
+            KeyCode::Char('?') => Some(Command::Help),
+            KeyCode::F(2) => Some(Command::Console),
+            _ => None,
+        };
+        if self.disassembly_view.get_mut().goto_input.is_none() {
+            if let Some(command) = command {
+                if let Err(e) = self.execute_command(command) {
+                    log::warn!("{}", e);
+                }
+                return;
+            }
+        }
+        let view = self.disassembly_view.get_mut();
+        let resize = |width: u16, change: i16| width.saturating_add_signed(change).max(MIN_COLUMN_WIDTH);
+
+        // Typing an address to go to in the disassembly
+        if let Some(input) = view.goto_input.as_mut() {
             match key.code {
-                KeyCode::Char('l') => {
-                    self.display_state = AppDisplayState::LogPopup;
+                KeyCode::Esc => view.goto_input = None,
+                KeyCode::Backspace => {
+                    input.pop();
+                }
+                KeyCode::Char(c) => input.push(c),
+                KeyCode::Enter => match self.proxy.resolve_address(input.trim()) {
+                    Ok(address) => {
+                        view.goto(address);
+                        view.goto_input = None;
+                    }
+                    Err(e) => log::warn!("{}", e),
+                },
+                _ => {}
+            }
+            return;
+        }
+
+        match key.code {
+            // The command palette, and the menu
+            KeyCode::Char(':') => {
+                self.palette.input.clear();
+                self.palette.result = None;
+                self.display_state = AppDisplayState::Palette;
+            }
+            KeyCode::F(10) => {
+                self.menu_state.get_mut().activate();
+                self.display_state = AppDisplayState::Menu;
+            }
+            // Assemble lines into memory, starting at the program counter
+            KeyCode::Char('a') => {
+                self.line_assembler = LineAssembler {
+                    address: self.proxy.cpu_state.program_counter,
+                    ..Default::default()
+                };
+                self.display_state = AppDisplayState::LineAssembler;
+            }
+            // Move the cursor through the disassembly
+            KeyCode::Up => view.goto(self.proxy.previous_instruction(view.cursor)),
+            KeyCode::Down => view.goto(self.proxy.next_instruction(view.cursor)),
+            KeyCode::PageUp => {
+                let address = (0..view.page_size()).fold(view.cursor, |a, _| self.proxy.previous_instruction(a));
+                view.goto(address);
+            }
+            KeyCode::PageDown => {
+                let address = (0..view.page_size()).fold(view.cursor, |a, _| self.proxy.next_instruction(a));
+                view.goto(address);
+            }
+            KeyCode::Char('g') => view.goto_input = Some(String::new()),
+            KeyCode::Char('f') => view.follow(),
+            // Breakpoints, and running to the cursor
+            KeyCode::Char('b') => self.proxy.toggle_breakpoint(view.cursor),
+            KeyCode::Char('c') if stopped => {
+                self.proxy.run_to(view.cursor);
+                self.stop = None;
+                view.follow();
+            }
+            // Move the inner edge of the left and right columns
+            KeyCode::Char('<') => self.layout.left_width = resize(self.layout.left_width, -2),
+            KeyCode::Char('>') => self.layout.left_width = resize(self.layout.left_width, 2),
+            KeyCode::Char('{') => self.layout.right_width = resize(self.layout.right_width, 2),
+            KeyCode::Char('}') => self.layout.right_width = resize(self.layout.right_width, -2),
+            _ => {}
+        }
+    }
+
+    // Clicks focus the panel under the mouse, and the wheel scrolls it
+    fn process_mouse_event(&mut self, mouse: MouseEvent) {
+        // Not while a popup or a prompt is open
+        if !matches!(
+            self.display_state,
+            AppDisplayState::MainWindow
+                | AppDisplayState::MemoryEditor
+                | AppDisplayState::WatchEditor
+                | AppDisplayState::Console
+        ) {
+            return;
+        }
+        let (column, row) = (mouse.column, mouse.row);
+        let Some(panel) = self.panel_areas.get_mut().iter()
+            .find(|(_, area)| area.contains(Position::new(column, row)))
+            .map(|(panel, _)| *panel)
+        else {
+            return;
+        };
+
+        match mouse.kind {
+            MouseEventKind::Down(MouseButton::Left) => self.click_panel(panel, column, row),
+            MouseEventKind::ScrollUp => self.scroll_panel(panel, -SCROLL_LINES),
+            MouseEventKind::ScrollDown => self.scroll_panel(panel, SCROLL_LINES),
+            _ => {}
+        }
+    }
+
+    fn click_panel(&mut self, panel: Panel, column: u16, row: u16) {
+        let command = match panel {
+            Panel::Memory => Some(Command::Memory(None)),
+            Panel::Watch => Some(Command::Watch(None)),
+            Panel::Console => Some(Command::Console),
+            _ => None,
+        };
+        match command {
+            Some(command) => {
+                if let Err(e) = self.execute_command(command) {
+                    log::warn!("{}", e);
+                }
+            }
+            None => self.display_state = AppDisplayState::MainWindow,
+        }
+
+        match panel {
+            // Select an instruction, or toggle the breakpoint on it in the gutter
+            Panel::Disassembly => {
+                let view = self.disassembly_view.get_mut();
+                match view.address_at(column, row) {
+                    Some((address, true)) => self.proxy.toggle_breakpoint(address),
+                    Some((address, false)) => view.goto(address),
+                    None => {}
                 }
+            }
+            Panel::Memory => {
+                let view = self.memory_view.get_mut();
+                if let Some(address) = view.address_at(column, row) {
+                    view.goto(address);
+                }
+            }
+            // The flags come after "SR: ", from N down to C
+            Panel::Cpu => {
+                let area = *self.flags_area.get_mut();
+                if row == area.y && (area.x + 4..area.x + 12).contains(&column) {
+                    // Changing the registers wakes a halted computer, but it shouldn't run off
+                    if !self.is_running() {
+                        self.stop = Some(Stop::Paused);
+                    }
+                    self.proxy.toggle_flags(0x80 >> (column - area.x - 4));
+                    self.proxy.update();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn scroll_panel(&mut self, panel: Panel, lines: i32) {
+        match panel {
+            Panel::Memory => self.memory_view.get_mut().scroll(lines),
+            Panel::Disassembly => {
+                let view = self.disassembly_view.get_mut();
+                let address = (0..lines.abs()).fold(view.cursor, |address, _| match lines < 0 {
+                    true => self.proxy.previous_instruction(address),
+                    false => self.proxy.next_instruction(address),
+                });
+                view.goto(address);
+            }
+            _ => {}
+        }
+    }
+
+    fn process_console_event(&mut self, key: KeyEvent) {
+        if key.kind == KeyEventKind::Release {
+            return;
+        }
+        match key.code {
+            KeyCode::F(2) => self.display_state = AppDisplayState::MainWindow,
+            code => {
+                if let Some(byte) = ConsoleView::key_bytes(code) {
+                    self.proxy.console_input(byte);
+                }
+            }
+        }
+    }
+
+    fn process_palette_event(&mut self, key: KeyEvent) {
+        if key.kind == KeyEventKind::Release {
+            return;
+        }
+        let palette = &mut self.palette;
+        match key.code {
+            KeyCode::Esc => self.display_state = AppDisplayState::MainWindow,
+            KeyCode::Backspace => {
+                palette.input.pop();
+            }
+            KeyCode::Char(c) => palette.input.push(c),
+            // Go through the commands that were typed before
+            KeyCode::Up if !palette.history.is_empty() => {
+                let index = palette.recalled.map_or(palette.history.len(), |i| i).saturating_sub(1);
+                palette.input = palette.history[index].clone();
+                palette.recalled = Some(index);
+            }
+            KeyCode::Down => match palette.recalled {
+                Some(index) if index + 1 < palette.history.len() => {
+                    palette.input = palette.history[index + 1].clone();
+                    palette.recalled = Some(index + 1);
+                }
+                _ => {
+                    palette.input.clear();
+                    palette.recalled = None;
+                }
+            },
+            KeyCode::Enter => {
+                let line = palette.input.trim().to_string();
+                if !line.is_empty() && palette.history.last() != Some(&line) {
+                    palette.history.push(line.clone());
+                }
+                palette.recalled = None;
+                self.run_palette_command(&line);
+            }
+            _ => {}
+        }
+    }
+
+    // Run a command, and show how it went in the palette. The palette closes
+    // when there is nothing to show.
+    fn run_palette_command(&mut self, line: &str) {
+        self.display_state = AppDisplayState::Palette;
+        let result = Command::parse(line).and_then(|command| self.execute_command(command));
+        self.show_command_result(line, result);
+    }
+
+    fn show_command_result(&mut self, line: &str, result: Result<Option<String>, String>) {
+        match result {
+            Ok(None) if matches!(self.display_state, AppDisplayState::Palette) => {
+                self.display_state = AppDisplayState::MainWindow;
+            }
+            Ok(None) => {}
+            Ok(Some(message)) => {
+                self.palette.input.clear();
+                self.palette.result = Some(Ok(message));
+                self.display_state = AppDisplayState::Palette;
+            }
+            Err(e) => {
+                self.palette.input = line.to_string();
+                self.palette.result = Some(Err(e));
+                self.display_state = AppDisplayState::Palette;
+            }
+        }
+    }
+
+    fn process_menu_event(&mut self, key: KeyEvent) {
+        if key.kind == KeyEventKind::Release {
+            return;
+        }
+        let menu = self.menu_state.get_mut();
+        match key.code {
+            KeyCode::Esc | KeyCode::F(10) => {
+                menu.reset();
+                self.display_state = AppDisplayState::MainWindow;
+            }
+            KeyCode::Left => menu.left(),
+            KeyCode::Right => menu.right(),
+            KeyCode::Up => menu.up(),
+            KeyCode::Down => menu.down(),
+            KeyCode::Enter => menu.select(),
+            _ => {}
+        }
+
+        let selected: Vec<String> = menu.drain_events()
+            .map(|MenuEvent::Selected(command)| command)
+            .collect();
+        for command in selected {
+            self.menu_state.get_mut().reset();
+            self.display_state = AppDisplayState::MainWindow;
+            // Commands that need more are finished in the palette
+            if command.is_empty() || command.ends_with(' ') {
+                self.palette.input = command;
+                self.palette.result = None;
+                self.display_state = AppDisplayState::Palette;
+            } else {
+                self.run_palette_command(&command);
+            }
+        }
+    }
+
+    fn process_file_picker_event(&mut self, key: KeyEvent) {
+        if key.kind == KeyEventKind::Release {
+            return;
+        }
+        let Some(picker) = self.file_picker.as_mut() else {
+            self.display_state = AppDisplayState::MainWindow;
+            return;
+        };
+
+        // Typing the address to load a program at
+        if let Some(file) = picker.chosen.clone() {
+            match key.code {
+                KeyCode::Esc => picker.chosen = None,
+                KeyCode::Backspace => {
+                    picker.address_input.pop();
+                }
+                KeyCode::Char(c) => picker.address_input.push(c),
+                KeyCode::Enter => match picker.load_address() {
+                    Ok(address) => self.load_picked_file(file, address),
+                    Err(e) => picker.error = Some(e),
+                },
                 _ => {}
             }
+            return;
+        }
+
+        match key.code {
+            KeyCode::Esc | KeyCode::Char('q') => {
+                self.file_picker = None;
+                self.display_state = AppDisplayState::MainWindow;
+            }
+            KeyCode::Up | KeyCode::Char('k') => picker.move_selection(true),
+            KeyCode::Down | KeyCode::Char('j') => picker.move_selection(false),
+            KeyCode::Left | KeyCode::Backspace | KeyCode::Char('h') => picker.open_parent(),
+            KeyCode::Right | KeyCode::Enter | KeyCode::Char('l') => {
+                if let Some(file) = picker.choose() {
+                    self.load_picked_file(file, None);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // Load the file from the picker, which stays open when that goes wrong
+    fn load_picked_file(&mut self, file: PathBuf, address: Option<u16>) {
+        let Some(picker) = self.file_picker.as_mut() else {
+            return;
+        };
+        let kind = picker.kind;
+        let directory = picker.directory().to_path_buf();
+        match self.execute_command(Command::Load(kind, Some(file), address)) {
+            Err(e) => {
+                if let Some(picker) = self.file_picker.as_mut() {
+                    picker.error = Some(e);
+                }
+            }
+            result => {
+                self.file_picker = None;
+                self.file_directory = Some(directory);
+                self.display_state = AppDisplayState::MainWindow;
+                self.show_command_result("", result);
+            }
+        }
+    }
+
+    // Do what a monitor command says. Returns a message to show, if there is one.
+    fn execute_command(&mut self, command: Command) -> Result<Option<String>, String> {
+        let running = self.is_running();
+        let stopped = !running && !self.proxy.is_halted();
+        // Stepping only makes sense when the computer isn't running
+        let check_stopped = || match stopped {
+            true => Ok(()),
+            false if running => Err("The computer is running, pause it first".to_string()),
+            false => Err("The computer has halted".to_string()),
+        };
+        let view = self.disassembly_view.get_mut();
+
+        match command {
+            Command::Run => {
+                self.stop = None;
+                view.follow();
+            }
+            Command::Pause if running => self.stop = Some(Stop::Paused),
+            Command::Pause => {}
+            Command::Step(count) => {
+                check_stopped()?;
+                for _ in 0..count {
+                    let stop = self.proxy.step_instruction();
+                    self.stop = Some(stop);
+                    if stop != Stop::Step {
+                        break;
+                    }
+                }
+                view.follow();
+            }
+            Command::Over => {
+                check_stopped()?;
+                self.stop = Some(self.proxy.step_over().unwrap_or(Stop::Step));
+                view.follow();
+            }
+            Command::Out => {
+                check_stopped()?;
+                self.stop = Some(self.proxy.step_out().unwrap_or(Stop::Step));
+                view.follow();
+            }
+            Command::Next => {
+                check_stopped()?;
+                self.stop = Some(self.proxy.step_line().unwrap_or(Stop::Step));
+                view.follow();
+            }
+            Command::Until(location) => {
+                let address = self.proxy.resolve_address(&location)?;
+                self.proxy.run_to(address);
+                self.stop = None;
+                view.follow();
+            }
+            Command::Reset => {
+                self.proxy.reset();
+                self.stop = Some(Stop::Paused);
+                view.follow();
+            }
+            Command::Break(location) => {
+                let address = self.proxy.resolve_address(&location)?;
+                if !self.proxy.is_breakpoint(address) {
+                    self.proxy.toggle_breakpoint(address);
+                }
+            }
+            Command::Delete(location) => {
+                let address = self.proxy.resolve_address(&location)?;
+                if !self.proxy.is_breakpoint(address) {
+                    return Err(format!("There is no breakpoint at {}", location));
+                }
+                self.proxy.toggle_breakpoint(address);
+            }
+            Command::Set(register, value) => {
+                self.proxy.set_register(register, &value)?;
+                self.proxy.update();
+            }
+            Command::Memory(location) => {
+                if let Some(location) = location {
+                    let address = self.proxy.resolve_address(&location)?;
+                    self.memory_view.get_mut().goto(address);
+                }
+                self.layout.show(Panel::Memory);
+                self.display_state = AppDisplayState::MemoryEditor;
+            }
+            Command::Disassemble(location) => view.goto(self.proxy.resolve_address(&location)?),
+            Command::Poke(location, bytes) => {
+                let address = self.proxy.resolve_address(&location)?;
+                self.proxy.write_memory(address, &bytes);
+            }
+            Command::Assemble(location, line) => {
+                let address = self.proxy.resolve_address(&location)?;
+                let size = self.proxy.assemble_at(address, &line)?;
+                return Ok(Some(format!("{} bytes written at ${:04x}", size, address)));
+            }
+            Command::Watch(Some(text)) => {
+                let watch_view = self.watch_view.get_mut();
+                watch_view.list.add(Watch::parse(&text)?)?;
+                watch_view.selected = watch_view.list.watches.len() - 1;
+            }
+            Command::Watch(None) => {
+                self.layout.show(Panel::Watch);
+                self.display_state = AppDisplayState::WatchEditor;
+            }
+            Command::Load(kind, None, _) => {
+                self.file_picker = Some(FilePicker::new(kind, self.file_directory.as_deref(), &self.theme)?);
+                self.display_state = AppDisplayState::FilePicker;
+            }
+            Command::Load(FileKind::Rom, Some(file), _) => {
+                self.proxy.load_rom(&file)?;
+                self.stop = Some(Stop::Paused);
+                view.follow();
+                return Ok(Some(format!("Loaded {}", file.display())));
+            }
+            Command::Load(FileKind::Program, Some(file), address) => {
+                let run_address = self.proxy.load_program(&file, address)?;
+                self.stop = Some(Stop::Paused);
+                view.follow();
+                return Ok(Some(format!("Loaded {}, to run from ${:04x}", file.display(), run_address)));
+            }
+            Command::Load(FileKind::Symbols, Some(file), _) => {
+                let count = self.proxy.load_symbols(&file)?;
+                return Ok(Some(format!("Loaded {} symbols from {}", count, file.display())));
+            }
+            Command::Load(FileKind::State, Some(file), _) => {
+                self.proxy.load_state(&file)?;
+                self.stop = Some(Stop::Paused);
+                view.follow();
+                return Ok(Some(format!("Loaded {}", file.display())));
+            }
+            Command::SaveState(file) => {
+                self.proxy.save_state(&file)?;
+                return Ok(Some(format!("Saved {}", file.display())));
+            }
+            Command::Console if !self.proxy.has_console() => {
+                return Err("There is no console, connect a serial port to it with --serial console".to_string());
+            }
+            Command::Console => {
+                self.layout.show(Panel::Console);
+                self.display_state = AppDisplayState::Console;
+            }
+            Command::Hide(panel) => self.layout.hide(panel),
+            Command::Show(panel) => self.layout.show(panel),
+            Command::Log => self.display_state = AppDisplayState::LogPopup,
+            Command::Help => self.display_state = AppDisplayState::HelpPopup,
+            Command::Quit => self.should_quit = true,
+        }
+        Ok(None)
+    }
+
+    fn process_line_assembler_event(&mut self, key: KeyEvent) {
+        if key.kind == KeyEventKind::Release {
+            return;
+        }
+        let assembler = &mut self.line_assembler;
+        match key.code {
+            KeyCode::Esc => self.display_state = AppDisplayState::MainWindow,
+            KeyCode::Backspace => {
+                assembler.input.pop();
+            }
+            KeyCode::Char(c) => assembler.input.push(c),
+            KeyCode::Enter => {
+                // A line can start with "$address:" to assemble somewhere else
+                let input = assembler.input.trim();
+                let (address, line) = match input.split_once(':') {
+                    Some((address, line)) if address.starts_with('$') => match parse_address(address) {
+                        Ok(address) => (address, line.trim()),
+                        Err(e) => {
+                            assembler.result = Some(Err(e));
+                            return;
+                        }
+                    },
+                    _ => (assembler.address, input),
+                };
+                let result = self.proxy.assemble_at(address, line);
+                let assembler = &mut self.line_assembler;
+                assembler.result = Some(match result {
+                    Ok(size) => {
+                        assembler.address = address.wrapping_add(size as u16);
+                        assembler.input.clear();
+                        Ok(format!("{} bytes written at ${:04x}", size, address))
+                    }
+                    Err(e) => Err(e),
+                });
+            }
+            _ => {}
+        }
+    }
+
+    fn process_memory_editor_event(&mut self, key: KeyEvent) {
+        if key.kind == KeyEventKind::Release {
+            return;
+        }
+        let mut view = self.memory_view.borrow_mut();
+
+        // Typing an address to go to
+        if let Some(input) = view.goto_input.as_mut() {
+            match key.code {
+                KeyCode::Esc => view.goto_input = None,
+                KeyCode::Backspace => {
+                    input.pop();
+                }
+                KeyCode::Char(c) => input.push(c),
+                KeyCode::Enter => match self.proxy.resolve_address(input.trim()) {
+                    Ok(address) => {
+                        view.goto(address);
+                        view.goto_input = None;
+                    }
+                    Err(e) => log::warn!("{}", e),
+                },
+                _ => {}
+            }
+            return;
+        }
+
+        match key.code {
+            KeyCode::Esc if !view.cancel_edit() => self.display_state = AppDisplayState::MainWindow,
+            KeyCode::Left => view.move_cursor(-1),
+            KeyCode::Right => view.move_cursor(1),
+            KeyCode::Up => view.move_cursor(-(BYTES_PER_LINE as i32)),
+            KeyCode::Down => view.move_cursor(BYTES_PER_LINE as i32),
+            KeyCode::PageUp => view.move_pages(-1),
+            KeyCode::PageDown => view.move_pages(1),
+            KeyCode::Home => view.goto(0x0000),
+            KeyCode::End => view.goto(0xffff),
+            KeyCode::Tab => view.next_bookmark(true, &self.proxy.cpu_state),
+            KeyCode::BackTab => view.next_bookmark(false, &self.proxy.cpu_state),
+            KeyCode::Char('m') => view.toggle_bookmark(),
+            KeyCode::Char('t') => view.toggle_charset(),
+            KeyCode::Char('g') => view.goto_input = Some(String::new()),
+            // Hexadecimal digits change the byte at the cursor
+            KeyCode::Char(c) if c.is_ascii_hexdigit() => {
+                let digit = c.to_digit(16).unwrap() as u8;
+                if let Some((address, value)) = view.type_digit(digit) {
+                    self.proxy.write_memory(address, &[value]);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn process_watch_editor_event(&mut self, key: KeyEvent) {
+        if key.kind == KeyEventKind::Release {
+            return;
+        }
+        let view = self.watch_view.get_mut();
+        match key.code {
+            KeyCode::Esc => self.display_state = AppDisplayState::MainWindow,
+            KeyCode::Up => view.move_selection(-1),
+            KeyCode::Down => view.move_selection(1),
+            KeyCode::Delete => view.remove_selected(),
+            KeyCode::Backspace => {
+                view.input.pop();
+            }
+            KeyCode::Char(c) => view.input.push(c),
+            KeyCode::Enter => view.add_input(),
+            _ => {}
         }
     }
 
@@ -160,40 +980,225 @@ impl<'a> App<'a> {
         .areas(frame.area());
 
         self.draw_top_area(top, frame);
-
-        let [left_middle, right_middle] = Layout::horizontal([
-            Constraint::Length(28), // left bar
-            Constraint::Min(1),     // central area
-        ])
-        .areas(middle);
-
         self.draw_status_bar(bottom, frame);
 
         match self.display_state {
-            AppDisplayState::MainWindow => {
-                self.draw_left_bar(left_middle, frame);
-                self.draw_memory_area(right_middle, frame);
+            AppDisplayState::MainWindow
+            | AppDisplayState::MemoryEditor
+            | AppDisplayState::WatchEditor
+            | AppDisplayState::Menu
+            | AppDisplayState::Console => {
+                self.draw_columns(middle, frame);
             }
             AppDisplayState::LogPopup => {
                 self.draw_log_popup(frame);
             }
+            AppDisplayState::LineAssembler => {
+                let area = self.draw_columns(middle, frame);
+                self.draw_line_assembler(area, frame);
+            }
+            AppDisplayState::Palette => {
+                let area = self.draw_columns(middle, frame);
+                self.draw_palette(area, frame);
+            }
+            AppDisplayState::HelpPopup => {
+                self.draw_help_popup(frame);
+            }
+            AppDisplayState::FilePicker => {
+                self.draw_columns(middle, frame);
+                if let Some(picker) = &self.file_picker {
+                    let area = self.global_popup_area(frame, 60, 70);
+                    frame.render_widget(FilePickerWidget::new(picker), area);
+                }
+            }
         }
+
+        // Last, so the menus drop down over everything else
+        self.draw_menu(top, frame);
     }
 
-    fn draw_left_bar(&self, area: Rect, frame: &mut Frame) {
-        let [cpu_area, execution_area] =
-            Layout::vertical([Constraint::Length(5 + PAD_SPACE_V), Constraint::Fill(1)])
-                .areas(area);
+    // The panels, in their columns. Returns the area right of the left
+    // column, where prompts go.
+    fn draw_columns(&self, area: Rect, frame: &mut Frame) -> Rect {
+        let layout = &self.layout;
+        let width = |panels: &Vec<Panel>, width: u16| if panels.is_empty() { 0 } else { width };
+        let [left, middle, right] = Layout::horizontal([
+            Constraint::Length(width(&layout.left, layout.left_width)),
+            Constraint::Min(1),
+            Constraint::Length(width(&layout.right, layout.right_width)),
+        ])
+        .areas(area);
 
-        self.draw_cpu_monitor(cpu_area, frame);
-        self.draw_execution(execution_area, frame);
+        self.panel_areas.borrow_mut().clear();
+        for (panels, column_area) in layout.columns().into_iter().zip([left, middle, right]) {
+            self.draw_column(panels, column_area, frame);
+        }
+        middle.union(right)
+    }
+
+    fn draw_column(&self, panels: &[Panel], area: Rect, frame: &mut Frame) {
+        // Panels with nothing to show, like an LCD on a computer without one, are left out
+        let panels: Vec<(Panel, Constraint)> = panels.iter()
+            .filter_map(|&panel| self.panel_height(panel).map(|height| (panel, height)))
+            .collect();
+        let areas = Layout::vertical(panels.iter().map(|(_, height)| *height)).split(area);
+        for ((panel, _), &panel_area) in panels.into_iter().zip(areas.iter()) {
+            self.panel_areas.borrow_mut().push((panel, panel_area));
+            self.draw_panel(panel, panel_area, frame);
+        }
+    }
+
+    fn panel_height(&self, panel: Panel) -> Option<Constraint> {
+        let layout = &self.layout;
+        match panel {
+            Panel::Cpu => Some(Constraint::Length(5 + PAD_SPACE_V)),
+            Panel::Stack => Some(Constraint::Length(layout.stack_lines + 2 + PAD_SPACE_V)),
+            Panel::Lcd => self.proxy.lcd()
+                .map(|lcd| Constraint::Length(lcd.controller().rows() as u16 + 2 + PAD_SPACE_V)),
+            Panel::Console => self.proxy.has_console().then_some(Constraint::Length(layout.console_lines + 2)),
+            Panel::Source => self.proxy.current_source_location()
+                .map(|_| Constraint::Length(layout.source_lines + 2)),
+            Panel::History => Some(Constraint::Length(layout.history_lines + 2 + PAD_SPACE_V)),
+            Panel::Disassembly | Panel::Memory | Panel::Watch => Some(Constraint::Fill(1)),
+        }
+    }
+
+    fn draw_panel(&self, panel: Panel, area: Rect, frame: &mut Frame) {
+        match panel {
+            Panel::Cpu => self.draw_cpu_monitor(area, frame),
+            Panel::Stack => self.draw_stack(area, frame),
+            Panel::Disassembly => self.draw_execution(area, frame),
+            Panel::Lcd => {
+                if let Some(lcd) = self.proxy.lcd() {
+                    self.draw_lcd(&lcd, area, frame);
+                }
+            }
+            Panel::Console => self.draw_console(area, frame),
+            Panel::Source => self.draw_source(area, frame),
+            Panel::Memory => self.draw_memory(area, frame),
+            Panel::Watch => self.draw_watches(area, frame),
+            Panel::History => self.draw_history(area, frame),
+        }
+    }
+
+    fn draw_palette(&self, area: Rect, frame: &mut Frame) {
+        let [_, area] = Layout::vertical([Constraint::Fill(1), Constraint::Length(2 + 2 + PAD_SPACE_V)])
+            .areas(area);
+        frame.render_widget(Clear, area);
+
+        let palette = &self.palette;
+        let block = Block::bordered()
+            .padding(BLOCK_PADDING)
+            .title(" Command ")
+            .title_style(self.theme.title);
+        let input_area = block.inner(area);
+        frame.render_widget(block, area);
+
+        let mut lines = vec![Line::from(vec![
+            Span::raw(":"),
+            Span::raw(palette.input.as_str()),
+            Span::raw(" ").style(self.theme.selected),
+        ])];
+        match &palette.result {
+            Some(Ok(message)) => lines.push(Line::raw(message.as_str())),
+            Some(Err(message)) => lines.push(Line::raw(message.as_str()).style(self.theme.error)),
+            None => {}
+        }
+        frame.render_widget(Paragraph::new(lines), input_area);
+    }
+
+    fn draw_help_popup(&self, frame: &mut Frame) {
+        let area = self.global_popup_area(frame, 80, 90);
+        frame.render_widget(Clear, area);
+
+        let block = Block::bordered()
+            .padding(BLOCK_PADDING)
+            .title(" Help ")
+            .title_style(self.theme.title)
+            .title_bottom(" press any key to return ");
+        let help_area = block.inner(area);
+        frame.render_widget(block, area);
+
+        let keys = [
+            ("space", "run or pause"),
+            ("s o u n", "step, step over, step out, next line"),
+            ("R", "reset"),
+            ("↑ ↓ PgUp PgDn", "move through the disassembly"),
+            ("g f", "go to an address, follow the program counter"),
+            ("b c", "toggle a breakpoint, run to the cursor"),
+            ("a", "assemble lines into memory"),
+            ("m w l", "memory, watches, log"),
+            ("F2", "switch between the console and the debugger"),
+            ("< > { }", "move the edge of the left or right column"),
+            ("mouse", "click to select or focus, in the gutter for breakpoints, on flags to flip them"),
+            ("mouse wheel", "scroll the memory or the disassembly"),
+            (":", "type a command"),
+            ("F10", "menu"),
+            ("q", "quit"),
+        ];
+        let row = |(name, text): &(&str, &str)| Line::from(vec![
+            Span::raw(format!("{:<42}", name)).style(self.theme.title),
+            Span::raw(text.to_string()),
+        ]);
+        let mut lines = vec![Line::raw("Keys")];
+        lines.extend(keys.iter().map(row));
+        lines.push(Line::raw(""));
+        lines.push(Line::raw("Commands, after ':'"));
+        lines.extend(monitor::HELP.iter().map(row));
+        frame.render_widget(Paragraph::new(lines), help_area);
+    }
+
+    fn draw_menu(&self, area: Rect, frame: &mut Frame) {
+        let area = Rect::new(area.x + 2, area.y + 1, area.width.saturating_sub(4), 1);
+        let menu = Menu::new()
+            .default_style(self.theme.menu)
+            .highlight(self.theme.selected)
+            .dropdown_width(22)
+            .dropdown_style(self.theme.menu_dropdown);
+        frame.render_stateful_widget(menu, area, self.menu_state.borrow_mut().deref_mut());
+    }
+
+    fn draw_line_assembler(&self, area: Rect, frame: &mut Frame) {
+        let [_, area] = Layout::vertical([Constraint::Fill(1), Constraint::Length(2 + 2 + PAD_SPACE_V)])
+            .areas(area);
+        frame.render_widget(Clear, area);
+
+        let assembler = &self.line_assembler;
+        let block = Block::bordered()
+            .padding(BLOCK_PADDING)
+            .title(format!(" Assemble at ${:04x} ", assembler.address))
+            .title_style(self.theme.title);
+        let input_area = block.inner(area);
+        frame.render_widget(block, area);
+
+        let mut lines = vec![Line::from(vec![
+            Span::raw("> "),
+            Span::raw(assembler.input.as_str()),
+            Span::raw(" ").style(self.theme.selected),
+        ])];
+        match &assembler.result {
+            Some(Ok(message)) => lines.push(Line::raw(message.as_str())),
+            Some(Err(message)) => lines.push(Line::raw(message.as_str()).style(self.theme.error)),
+            None => {}
+        }
+        frame.render_widget(Paragraph::new(lines), input_area);
+    }
+
+    fn draw_stack(&self, area: Rect, frame: &mut Frame) {
+        let block = Block::bordered()
+            .padding(BLOCK_PADDING)
+            .title(" Stack ")
+            .title_style(self.theme.title);
+        let stack_area = block.inner(area);
+        frame.render_widget(block, area);
+        frame.render_widget(StackWidget::new(self), stack_area);
     }
 
     fn draw_cpu_monitor(&self, area: Rect, frame: &mut Frame) {
         let left = Block::bordered()
             .padding(BLOCK_PADDING)
             .title(" Cpu ")
-            .title_style(BLOCK_TITLE_STYLE);
+            .title_style(self.theme.title);
         let left_area = left.inner(area);
 
         frame.render_widget(left, area);
@@ -219,8 +1224,9 @@ impl<'a> App<'a> {
 
         self.draw_cpu_registers(register_area, frame);
 
-        let status = StatusRegisterWidget::new(self.proxy.cpu_state.status);
+        let status = StatusRegisterWidget::new(self.proxy.cpu_state.status, &self.theme);
         frame.render_widget(status, status_register_area);
+        *self.flags_area.borrow_mut() = status_register_area;
     }
 
     fn draw_cpu_registers(&self, area: Rect, frame: &mut Frame) {
@@ -251,51 +1257,154 @@ impl<'a> App<'a> {
     }
 
     fn draw_execution(&self, area: Rect, frame: &mut Frame) {
-        let right = Block::bordered()
+        let mut view = self.disassembly_view.borrow_mut();
+        let mut right = Block::bordered()
             .padding(BLOCK_PADDING)
             .title(" Program assembly ")
-            .title_style(BLOCK_TITLE_STYLE);
+            .title_style(self.theme.title);
+        if let Some(input) = &view.goto_input {
+            right = right.title_bottom(format!(" go to: {}_ ", input));
+        } else if !view.is_following() {
+            right = right.title_bottom(format!(" ${:04x} ", view.cursor));
+        }
         let right_area = right.inner(area);
         frame.render_widget(right, area);
 
-        let history = self.proxy.get_execution_history();
-
-        let all_items: Vec<String> = history
-            .iter()
-            .chain(self.proxy.get_execution_future().iter())
-            .map(|x| format!("{:04x}: {}", x.0, x.1))
-            .collect();
-
-        //let mut state = ListState::default();
-        //state.select(Some(history.len()));
-
-        let list = List::new(all_items)
-            .highlight_style(SELECTED_STYLE)
-            .highlight_symbol(">");
-
-        let mut state = self.assembly_list_state.borrow_mut();
-        frame.render_stateful_widget(list, right_area, state.deref_mut());
+        frame.render_stateful_widget(DisassemblyWidget::new(self), right_area, view.deref_mut());
     }
 
-    fn draw_memory_area(&self, area: Rect, frame: &mut Frame) {
-        let right = Block::bordered()
+    fn draw_memory(&self, area: Rect, frame: &mut Frame) {
+        let mut view = self.memory_view.borrow_mut();
+        let editing = matches!(self.display_state, AppDisplayState::MemoryEditor);
+        let mut right = Block::bordered()
             .title(" Memory ")
             .padding(Padding::uniform(1))
-            .title_style(BLOCK_TITLE_STYLE);
+            .title_style(self.theme.title);
+        // Show the bookmarks, and what's being typed, when editing
+        if editing {
+            let bookmarks: Vec<Span> = view.bookmarks.iter().enumerate()
+                .map(|(i, bookmark)| match i == view.bookmark {
+                    true => Span::raw(format!(" {} ", bookmark.name())).style(self.theme.selected),
+                    false => Span::raw(format!(" {} ", bookmark.name())),
+                })
+                .collect();
+            right = right
+                .border_style(self.theme.focused_border)
+                .title(Line::from(bookmarks).right_aligned())
+                .title_bottom(format!(
+                    " ${:04x}{} {} ",
+                    view.cursor,
+                    if view.is_following() { " (pc)" } else { "" },
+                    view.charset.name()
+                ));
+            if let Some(input) = &view.goto_input {
+                right = right.title_bottom(Line::from(format!(" go to: {}_ ", input)).right_aligned());
+            }
+        }
         let memory_area = right.inner(area);
         frame.render_widget(right, area);
 
         let program_counter = self.proxy.cpu_state.program_counter;
-        let memory_widget =
-            MemoryWidget::new(self, program_counter - 16).set_focus(program_counter);
-        frame.render_widget(memory_widget, memory_area);
+        let memory_widget = MemoryWidget::new(self).set_focus(program_counter);
+        frame.render_stateful_widget(memory_widget, memory_area, view.deref_mut());
+    }
+
+    fn draw_console(&self, area: Rect, frame: &mut Frame) {
+        let focused = matches!(self.display_state, AppDisplayState::Console);
+        let mut block = Block::bordered()
+            .padding(BLOCK_PADDING)
+            .title(" Console ")
+            .title_style(self.theme.title);
+        if focused {
+            block = block.border_style(self.theme.focused_border);
+        }
+        let console_area = block.inner(area);
+        frame.render_widget(block, area);
+        frame.render_widget(ConsoleWidget::new(&self.console_view, focused, &self.theme), console_area);
+    }
+
+    fn draw_watches(&self, area: Rect, frame: &mut Frame) {
+        let editing = matches!(self.display_state, AppDisplayState::WatchEditor);
+        let mut block = Block::bordered()
+            .padding(BLOCK_PADDING)
+            .title(" Watch ")
+            .title_style(self.theme.title);
+        if editing {
+            block = block.border_style(self.theme.focused_border);
+        }
+        let watch_area = block.inner(area);
+        frame.render_widget(block, area);
+
+        let mut view = self.watch_view.borrow_mut();
+        frame.render_stateful_widget(WatchWidget::new(self, editing), watch_area, view.deref_mut());
+    }
+
+    // The source of the program, if there is debug info for it
+    // The instructions executed last, the latest at the bottom
+    fn draw_history(&self, area: Rect, frame: &mut Frame) {
+        let block = Block::bordered()
+            .padding(BLOCK_PADDING)
+            .title(" History ")
+            .title_style(self.theme.title);
+        let history_area = block.inner(area);
+        frame.render_widget(block, area);
+
+        // Labels go on a line of their own, like in an assembly listing
+        let mut lines = Vec::new();
+        for (address, text) in self.proxy.get_execution_history() {
+            if let Some(name) = self.proxy.symbol_at(address) {
+                lines.push(Line::raw(format!("{}:", name)).style(self.theme.note));
+            }
+            lines.push(Line::raw(format!("{:04x}: {}", address, text)));
+        }
+        let skip = lines.len().saturating_sub(history_area.height as usize);
+        frame.render_widget(Paragraph::new(lines.split_off(skip)), history_area);
+    }
+
+    fn draw_source(&self, area: Rect, frame: &mut Frame) {
+        let Some(location) = self.proxy.current_source_location() else {
+            return;
+        };
+        let mut title = format!(" {}:{} ", location.file, location.line);
+        if let Some(segment_and_scope) = self.proxy.current_segment_and_scope() {
+            title.push_str(&format!("({}) ", segment_and_scope));
+        }
+        let block = Block::bordered()
+            .padding(BLOCK_PADDING)
+            .title(title)
+            .title_style(self.theme.title);
+        let source_area = block.inner(area);
+        frame.render_widget(block, area);
+
+        let context = self.layout.source_lines as usize / 2;
+        let lines: Vec<Line> = self.proxy.current_source_lines(context, context)
+            .into_iter()
+            .map(|(n, text)| {
+                let line = Line::raw(format!("{:5} {}", n, text.replace('\t', "        ")));
+                if n == location.line { line.style(self.theme.selected) } else { line }
+            })
+            .collect();
+        frame.render_widget(Paragraph::new(lines), source_area);
+    }
+
+    fn draw_lcd(&self, lcd: &Lcd, area: Rect, frame: &mut Frame) {
+        let block = Block::bordered()
+            .padding(BLOCK_PADDING)
+            .title(" LCD ")
+            .title_style(self.theme.title);
+        let lcd_area = block.inner(area);
+        frame.render_widget(block, area);
+
+        let columns = lcd.controller().columns() as u16;
+        let [lcd_area] = Layout::horizontal([Constraint::Length(columns)]).areas(lcd_area);
+        frame.render_widget(LcdWidget::new(lcd, &self.theme), lcd_area);
     }
 
     fn draw_top_area(&self, area: Rect, frame: &mut Frame) {
         // Top: Menu area
         let top = Block::bordered()
             .title(format!(" {} - {} ", self.title, self.version))
-            .title_style(APP_TITLE_STYLE)
+            .title_style(self.theme.app_title)
             .title_alignment(Alignment::Center);
 
         frame.render_widget(top, area);
@@ -305,16 +1414,47 @@ impl<'a> App<'a> {
         let message = format!(
             " {} ",
             match self.display_state {
-                AppDisplayState::MainWindow => "press l to display log",
+                AppDisplayState::MainWindow => {
+                    "space run/pause, s o u n step, ↑↓ g f move, b break, c run to, a assemble, m w l view, F2 console, : command, F10 menu, ? help"
+                }
+                AppDisplayState::Palette => "Enter to run, ↑↓ for earlier commands, 'help' for the list, Esc to return",
+                AppDisplayState::Menu => "arrows to choose, Enter to select, Esc to return",
+                AppDisplayState::HelpPopup => "press any key to return",
+                AppDisplayState::Console => "typing goes to the program, F2 to return to the debugger",
+                AppDisplayState::FilePicker if self.file_picker.as_ref().is_some_and(|p| p.chosen.is_some()) => {
+                    "type a hexadecimal address, or nothing, and Enter to load, Esc to pick another file"
+                }
+                AppDisplayState::FilePicker => "↑↓ choose, → Enter open, ← Backspace up a directory, Esc to return",
+                AppDisplayState::WatchEditor => {
+                    "type [word|signed|bcd|string] location and Enter to add, ↑↓ Del to remove, Esc to return"
+                }
+                AppDisplayState::MemoryEditor => {
+                    "arrows PgUp PgDn move, 0-f edit, g go to, Tab bookmarks, m mark, t charset, Esc return"
+                }
                 AppDisplayState::LogPopup => "press 'l' to return",
+                AppDisplayState::LineAssembler => "Enter to assemble, start with $address: to move, Esc to return",
             }
         );
+        let status = match self.stop {
+            Some(Stop::Breakpoint(address)) => {
+                format!(" breakpoint at {} ", self.proxy.address_to_string(address))
+            }
+            Some(Stop::Exit(value)) => format!(" exited with ${:02x} ", value),
+            Some(Stop::Watchpoint(access)) => format!(
+                " {:?} of ${:02x} at {} ",
+                access.kind, access.value, self.proxy.address_to_string(access.address)
+            ),
+            Some(Stop::Step) => " stopped ".to_string(),
+            Some(Stop::Paused) => " paused ".to_string(),
+            _ if self.proxy.is_halted() => " halted ".to_string(),
+            _ => " running ".to_string(),
+        };
         // Bottom: status and hint area
         let bottom = Block::new()
-            .title(Line::from(" status ").right_aligned())
+            .title(Line::from(status).right_aligned())
             .title(Line::from(" hint ").left_aligned())
             .title(Line::from(message).centered())
-            .style(STATUS_BAR_STYLE);
+            .style(self.theme.status_bar);
 
         frame.render_widget(bottom, area);
     }
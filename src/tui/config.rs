@@ -0,0 +1,368 @@
+// Configuration of the TUI: which panels go where, and what they look like
+
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::str::FromStr;
+
+use ratatui::style::{Color, Modifier, Style};
+use serde::Deserialize;
+
+/*
+ * A TOML file, in which everything can be left out:
+ *
+ *   theme = "monochrome"
+ *
+ *   [layout]
+ *   left = ["cpu", "stack", "disassembly"]
+ *   middle = ["lcd", "console", "source", "memory"]
+ *   right = ["watch", "history"]
+ *   left_width = 28
+ *   right_width = 34
+ *
+ *   [styles]
+ *   selected = "black on yellow"
+ *   title = "light-cyan bold"
+ *
+ * Panels are stacked in three columns, and the ones left out are hidden. A
+ * style is a colour, "on" and a background colour, and modifiers, in any
+ * order and all optional.
+ */
+#[derive(Debug, Clone, Default)]
+pub struct TuiConfig {
+    pub layout: LayoutConfig,
+    pub theme: Theme,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConfigFile {
+    theme: Option<String>,
+    #[serde(default)]
+    layout: LayoutConfig,
+    #[serde(default)]
+    styles: BTreeMap<String, String>,
+}
+
+impl TuiConfig {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let file: ConfigFile = toml::from_str(text).map_err(|e| e.to_string())?;
+        let mut theme = match &file.theme {
+            Some(name) => Theme::named(name)?,
+            None => Theme::default(),
+        };
+        for (name, style) in &file.styles {
+            *theme.style_mut(name).ok_or_else(|| format!("Unknown style '{}'", name))? = parse_style(style)?;
+        }
+        Ok(Self { layout: file.layout, theme })
+    }
+
+    pub fn from_file(file_name: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(file_name)
+            .map_err(|e| format!("Was not able to read {}: {}", file_name.display(), e))?;
+        Self::parse(&text).map_err(|e| format!("Invalid configuration in {}: {}", file_name.display(), e))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(try_from = "String")]
+pub enum Panel {
+    Cpu,
+    Stack,
+    Disassembly,
+    Lcd,
+    Console,
+    Source,
+    Memory,
+    Watch,
+    History,
+}
+
+const PANELS: [(Panel, &str); 9] = [
+    (Panel::Cpu, "cpu"),
+    (Panel::Stack, "stack"),
+    (Panel::Disassembly, "disassembly"),
+    (Panel::Lcd, "lcd"),
+    (Panel::Console, "console"),
+    (Panel::Source, "source"),
+    (Panel::Memory, "memory"),
+    (Panel::Watch, "watch"),
+    (Panel::History, "history"),
+];
+
+impl FromStr for Panel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        PANELS.iter()
+            .find(|(_, name)| name.eq_ignore_ascii_case(s))
+            .map(|(panel, _)| *panel)
+            .ok_or_else(|| {
+                let names: Vec<&str> = PANELS.iter().map(|(_, name)| *name).collect();
+                format!("Unknown panel '{}', expected one of {}", s, names.join(", "))
+            })
+    }
+}
+
+impl TryFrom<String> for Panel {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LayoutConfig {
+    // Panels from the top down, in each column
+    pub left: Vec<Panel>,
+    pub middle: Vec<Panel>,
+    pub right: Vec<Panel>,
+    pub left_width: u16,
+    pub right_width: u16,
+    pub stack_lines: u16,
+    // Lines around the current source line
+    pub source_lines: u16,
+    pub console_lines: u16,
+    // Instructions executed last
+    pub history_lines: u16,
+}
+
+impl Default for LayoutConfig {
+    fn default() -> Self {
+        Self {
+            left: vec![Panel::Cpu, Panel::Stack, Panel::Disassembly],
+            middle: vec![Panel::Lcd, Panel::Console, Panel::Source, Panel::Memory],
+            right: vec![Panel::Watch, Panel::History],
+            left_width: 28,
+            right_width: 34,
+            stack_lines: 8,
+            source_lines: 9,
+            console_lines: 12,
+            history_lines: 8,
+        }
+    }
+}
+
+impl LayoutConfig {
+    pub fn is_shown(&self, panel: Panel) -> bool {
+        self.columns().iter().any(|column| column.contains(&panel))
+    }
+
+    pub fn columns(&self) -> [&Vec<Panel>; 3] {
+        [&self.left, &self.middle, &self.right]
+    }
+
+    pub fn hide(&mut self, panel: Panel) {
+        for column in [&mut self.left, &mut self.middle, &mut self.right] {
+            column.retain(|&shown| shown != panel);
+        }
+    }
+
+    // Show a panel again, at the end of the column it's in by default
+    pub fn show(&mut self, panel: Panel) {
+        if self.is_shown(panel) {
+            return;
+        }
+        let default = Self::default();
+        let column = match panel {
+            _ if default.left.contains(&panel) => &mut self.left,
+            _ if default.right.contains(&panel) => &mut self.right,
+            _ => &mut self.middle,
+        };
+        column.push(panel);
+    }
+}
+
+/*
+ * Theme
+ *
+ * The styles used throughout the TUI. The default one is colourful, the
+ * monochrome one only uses bold, reversed and the like, for high contrast.
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Theme {
+    pub title: Style,
+    pub app_title: Style,
+    pub status_bar: Style,
+    pub selected: Style,
+    pub cursor: Style,
+    pub focused_border: Style,
+    pub error: Style,
+    pub changed: Style,
+    pub breakpoint: Style,
+    pub note: Style,
+    pub directory: Style,
+    pub program_counter: Style,
+    pub lcd: Style,
+    pub flag_set: Style,
+    pub flag_clear: Style,
+    pub menu: Style,
+    pub menu_dropdown: Style,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        let title = Style::new().fg(Color::Yellow).add_modifier(Modifier::BOLD);
+        Self {
+            title,
+            app_title: Style::new()
+                .fg(Color::Blue)
+                .bg(Color::Yellow)
+                .add_modifier(Modifier::BOLD.union(Modifier::ITALIC)),
+            status_bar: Style::new().fg(Color::Black).bg(Color::White),
+            selected: Style::new().fg(Color::Black).bg(Color::Yellow),
+            cursor: Style::new().fg(Color::Black).bg(Color::White),
+            focused_border: title,
+            error: Style::new().fg(Color::Red),
+            changed: Style::new().fg(Color::LightGreen).add_modifier(Modifier::BOLD),
+            breakpoint: Style::new().fg(Color::Red),
+            note: Style::new().fg(Color::Cyan),
+            directory: Style::new().fg(Color::LightBlue),
+            program_counter: Style::new().bg(Color::Red),
+            lcd: Style::new().fg(Color::Black).bg(Color::LightGreen),
+            flag_set: Style::new().fg(Color::LightYellow),
+            flag_clear: Style::new().fg(Color::DarkGray),
+            menu: Style::new().fg(Color::White),
+            menu_dropdown: Style::new().bg(Color::DarkGray),
+        }
+    }
+}
+
+impl Theme {
+    pub fn monochrome() -> Self {
+        let plain = Style::new();
+        let bold = plain.add_modifier(Modifier::BOLD);
+        let reversed = plain.add_modifier(Modifier::REVERSED);
+        let underlined = bold.add_modifier(Modifier::UNDERLINED);
+        Self {
+            title: bold,
+            app_title: reversed.add_modifier(Modifier::BOLD),
+            status_bar: reversed,
+            selected: reversed,
+            cursor: reversed,
+            focused_border: bold,
+            error: underlined,
+            changed: underlined,
+            breakpoint: bold,
+            note: plain.add_modifier(Modifier::ITALIC),
+            directory: bold,
+            program_counter: underlined,
+            lcd: reversed,
+            flag_set: bold,
+            flag_clear: plain.add_modifier(Modifier::DIM),
+            menu: plain,
+            menu_dropdown: plain,
+        }
+    }
+
+    pub fn named(name: &str) -> Result<Self, String> {
+        match name {
+            "default" => Ok(Self::default()),
+            "monochrome" => Ok(Self::monochrome()),
+            _ => Err(format!("Unknown theme '{}', expected default or monochrome", name)),
+        }
+    }
+
+    fn style_mut(&mut self, name: &str) -> Option<&mut Style> {
+        Some(match name {
+            "title" => &mut self.title,
+            "app_title" => &mut self.app_title,
+            "status_bar" => &mut self.status_bar,
+            "selected" => &mut self.selected,
+            "cursor" => &mut self.cursor,
+            "focused_border" => &mut self.focused_border,
+            "error" => &mut self.error,
+            "changed" => &mut self.changed,
+            "breakpoint" => &mut self.breakpoint,
+            "note" => &mut self.note,
+            "directory" => &mut self.directory,
+            "program_counter" => &mut self.program_counter,
+            "lcd" => &mut self.lcd,
+            "flag_set" => &mut self.flag_set,
+            "flag_clear" => &mut self.flag_clear,
+            "menu" => &mut self.menu,
+            "menu_dropdown" => &mut self.menu_dropdown,
+            _ => return None,
+        })
+    }
+}
+
+// A style like "black on yellow bold"
+fn parse_style(text: &str) -> Result<Style, String> {
+    let mut style = Style::new();
+    let mut words = text.split_whitespace();
+    while let Some(word) = words.next() {
+        let modifier = match word.to_lowercase().as_str() {
+            "bold" => Modifier::BOLD,
+            "dim" => Modifier::DIM,
+            "italic" => Modifier::ITALIC,
+            "underlined" => Modifier::UNDERLINED,
+            "reversed" => Modifier::REVERSED,
+            "blink" => Modifier::SLOW_BLINK,
+            "on" => {
+                let color = words.next().ok_or_else(|| format!("No background colour in '{}'", text))?;
+                style = style.bg(parse_color(color)?);
+                continue;
+            }
+            _ => {
+                style = style.fg(parse_color(word)?);
+                continue;
+            }
+        };
+        style = style.add_modifier(modifier);
+    }
+    Ok(style)
+}
+
+fn parse_color(text: &str) -> Result<Color, String> {
+    Color::from_str(text).map_err(|_| format!("Unknown colour '{}'", text))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parsing() {
+        let config = TuiConfig::parse(r##"
+            theme = "monochrome"
+
+            [layout]
+            left = ["cpu", "disassembly"]
+            right = []
+            left_width = 40
+
+            [styles]
+            selected = "black on #ffcc00 bold"
+        "##).unwrap();
+        assert_eq!(config.layout.left, vec![Panel::Cpu, Panel::Disassembly]);
+        assert_eq!(config.layout.middle, LayoutConfig::default().middle);
+        assert_eq!(config.layout.left_width, 40);
+        assert!(!config.layout.is_shown(Panel::Watch));
+        assert_eq!(config.theme.selected,
+            Style::new().fg(Color::Black).bg(Color::Rgb(0xff, 0xcc, 0x00)).add_modifier(Modifier::BOLD));
+        assert_eq!(config.theme.title, Theme::monochrome().title);
+
+        assert!(TuiConfig::parse("").is_ok());
+        assert!(TuiConfig::parse("theme = \"neon\"").is_err());
+        assert!(TuiConfig::parse("[layout]\nleft = [\"registers\"]").is_err());
+        assert!(TuiConfig::parse("[styles]\nselected = \"black on\"").is_err());
+        assert!(TuiConfig::parse("[styles]\nborder = \"red\"").is_err());
+    }
+
+    #[test]
+    fn hiding_and_showing() {
+        let mut layout = LayoutConfig::default();
+        layout.hide(Panel::Stack);
+        layout.hide(Panel::Watch);
+        assert_eq!(layout.left, vec![Panel::Cpu, Panel::Disassembly]);
+        assert_eq!(layout.right, vec![Panel::History]);
+
+        layout.show(Panel::Stack);
+        layout.show(Panel::Stack);
+        layout.show(Panel::Watch);
+        assert_eq!(layout.left, vec![Panel::Cpu, Panel::Disassembly, Panel::Stack]);
+        assert_eq!(layout.right, vec![Panel::History, Panel::Watch]);
+    }
+}
@@ -1,10 +1,22 @@
 pub mod address;
+pub mod console;
+pub mod disassembly;
+pub mod file_picker;
+pub mod lcd;
 pub mod memory;
 pub mod register;
+pub mod stack;
 pub mod status_register;
+pub mod watch;
 
 
 pub use address::AddressWidget;
-pub use memory::MemoryWidget;
+pub use console::{ConsoleView, ConsoleWidget};
+pub use disassembly::{DisassemblyView, DisassemblyWidget};
+pub use file_picker::{FilePicker, FilePickerWidget};
+pub use lcd::LcdWidget;
+pub use memory::{MemoryView, MemoryWidget, BYTES_PER_LINE};
 pub use register::RegisterWidget;
-pub use status_register::StatusRegisterWidget;
\ No newline at end of file
+pub use stack::StackWidget;
+pub use status_register::StatusRegisterWidget;
+pub use watch::{WatchView, WatchWidget};
\ No newline at end of file
@@ -0,0 +1,124 @@
+use std::collections::VecDeque;
+
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Paragraph, Widget};
+use ratatui::style::Style;
+use ratatui::layout::Rect;
+use ratatui::buffer::Buffer;
+
+use crate::tui::config::Theme;
+
+// Lines kept once they scrolled out of view
+const MAX_LINES: usize = 1000;
+
+const BACKSPACE: u8 = 0x08;
+const DELETE: u8 = 0x7f;
+
+/*
+ * Console view
+ *
+ * A simple terminal for a serial device: what the program wrote to it, line
+ * by line. Most 6502 software ends lines with a carriage return, some with a
+ * line feed too, or only a line feed, and all of those start a new line.
+ */
+pub struct ConsoleView {
+    lines: VecDeque<String>,
+    // Whether the last byte was a carriage return, to skip the line feed after it
+    carriage_return: bool,
+}
+
+impl Default for ConsoleView {
+    fn default() -> Self {
+        Self {
+            lines: VecDeque::from([String::new()]),
+            carriage_return: false,
+        }
+    }
+}
+
+impl ConsoleView {
+    // Show the bytes written by the program
+    pub fn receive(&mut self, bytes: impl IntoIterator<Item = u8>) {
+        for byte in bytes {
+            let line_feed_after_return = byte == b'\n' && self.carriage_return;
+            self.carriage_return = byte == b'\r';
+            match byte {
+                b'\n' if line_feed_after_return => {}
+                b'\r' | b'\n' => {
+                    self.lines.push_back(String::new());
+                    if self.lines.len() > MAX_LINES {
+                        self.lines.pop_front();
+                    }
+                }
+                BACKSPACE | DELETE => {
+                    self.current_line().pop();
+                }
+                byte if byte.is_ascii_graphic() || byte == b' ' => self.current_line().push(byte as char),
+                _ => {}
+            }
+        }
+    }
+
+    // The bytes to send for a key, typed into the console
+    pub fn key_bytes(code: crossterm::event::KeyCode) -> Option<u8> {
+        use crossterm::event::KeyCode;
+        match code {
+            KeyCode::Char(c) if c.is_ascii() => Some(c as u8),
+            KeyCode::Enter => Some(b'\r'),
+            KeyCode::Backspace => Some(BACKSPACE),
+            KeyCode::Esc => Some(0x1b),
+            KeyCode::Tab => Some(b'\t'),
+            _ => None,
+        }
+    }
+
+    fn current_line(&mut self) -> &mut String {
+        self.lines.back_mut().unwrap()
+    }
+}
+
+pub struct ConsoleWidget<'a> {
+    view: &'a ConsoleView,
+    // Where the cursor is drawn, when the console has the keyboard
+    cursor: Option<Style>,
+}
+
+impl<'a> ConsoleWidget<'a> {
+    pub fn new(view: &'a ConsoleView, focused: bool, theme: &Theme) -> Self {
+        Self { view, cursor: focused.then_some(theme.cursor) }
+    }
+}
+
+impl Widget for ConsoleWidget<'_> {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        // The last lines, that fit
+        let skip = self.view.lines.len().saturating_sub(area.height as usize);
+        let count = self.view.lines.len() - skip;
+        let lines: Vec<Line> = self.view.lines.iter().skip(skip).enumerate()
+            .map(|(i, text)| match self.cursor {
+                Some(cursor) if i == count - 1 => Line::from(vec![Span::raw(text.as_str()), Span::raw(" ").style(cursor)]),
+                _ => Line::raw(text.as_str()),
+            })
+            .collect();
+        Paragraph::new(lines).render(area, buf);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(view: &ConsoleView) -> Vec<&str> {
+        view.lines.iter().map(String::as_str).collect()
+    }
+
+    #[test]
+    fn line_endings() {
+        let mut view = ConsoleView::default();
+        view.receive(*b"READY.\r\nLIST\rRUN\nAB\x08C\x07");
+        assert_eq!(lines(&view), vec!["READY.", "LIST", "RUN", "AC"]);
+
+        view.receive((0..MAX_LINES).map(|_| b'\r'));
+        assert_eq!(view.lines.len(), MAX_LINES);
+    }
+}
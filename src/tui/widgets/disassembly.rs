@@ -0,0 +1,182 @@
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{StatefulWidget, Widget};
+use ratatui::layout::{Position, Rect};
+use ratatui::buffer::Buffer;
+
+use crate::tui::App;
+
+// Number of instructions shown before the cursor, when the view jumps to it
+const CONTEXT_LINES: usize = 3;
+
+
+/*
+ * Disassembly view
+ *
+ * The state of the disassembly pane: the instruction the cursor is on, and
+ * the first one shown. The cursor follows the program counter, until it's
+ * moved somewhere else.
+ */
+pub struct DisassemblyView {
+    pub cursor: u16,
+    // First instruction shown
+    top: u16,
+    // Number of rows shown the last time the view was drawn, where, and the
+    // instructions on them
+    rows: u16,
+    area: Rect,
+    addresses: Vec<Option<u16>>,
+    following: bool,
+    // Address being typed, to go to
+    pub goto_input: Option<String>,
+}
+
+impl Default for DisassemblyView {
+    fn default() -> Self {
+        Self {
+            cursor: 0x0000,
+            top: 0x0000,
+            rows: 16,
+            area: Rect::default(),
+            addresses: Vec::new(),
+            following: true,
+            goto_input: None,
+        }
+    }
+}
+
+impl DisassemblyView {
+    pub fn is_following(&self) -> bool {
+        self.following
+    }
+
+    // Go back to following the program counter
+    pub fn follow(&mut self) {
+        self.following = true;
+    }
+
+    pub fn goto(&mut self, address: u16) {
+        self.cursor = address;
+        self.following = false;
+    }
+
+    // The instruction shown at a position on the screen, and whether the
+    // position is in the gutter before it, where breakpoints are
+    pub fn address_at(&self, column: u16, row: u16) -> Option<(u16, bool)> {
+        if !self.area.contains(Position::new(column, row)) {
+            return None;
+        }
+        let address = (*self.addresses.get((row - self.area.y) as usize)?)?;
+        Some((address, column < self.area.x + 2))
+    }
+
+    // Number of rows in a page, to move the cursor by
+    pub fn page_size(&self) -> u16 {
+        self.rows.saturating_sub(1).max(1)
+    }
+}
+
+pub struct DisassemblyWidget<'a> {
+    app: &'a App<'a>,
+}
+
+impl<'a> DisassemblyWidget<'a> {
+    pub fn new(app: &'a App) -> Self {
+        Self { app }
+    }
+
+    // The rows for instructions from the top, with labels on a row of their own,
+    // like in an assembly listing. Rows with an instruction have its address.
+    fn rows(&self, top: u16, count: usize) -> Vec<(Option<u16>, String)> {
+        let proxy = &self.app.proxy;
+        let mut rows = Vec::with_capacity(count + 1);
+        for (address, text) in proxy.disassemble_lines(top, count) {
+            if let Some(name) = proxy.symbol_at(address) {
+                rows.push((None, format!("{}:", name)));
+            }
+            rows.push((Some(address), format!("{:04x}: {}", address, text)));
+            if rows.len() >= count {
+                break;
+            }
+        }
+        rows.truncate(count);
+        rows
+    }
+}
+
+impl StatefulWidget for DisassemblyWidget<'_> {
+    type State = DisassemblyView;
+
+    fn render(self, area: Rect, buf: &mut Buffer, view: &mut DisassemblyView) {
+        let proxy = &self.app.proxy;
+        let theme = &self.app.theme;
+        let program_counter = proxy.cpu_state.program_counter;
+        if view.following {
+            view.cursor = program_counter;
+        }
+        view.rows = area.height;
+
+        // Jump to the cursor when it's not in view, with some context before it
+        let count = area.height as usize;
+        let mut rows = self.rows(view.top, count);
+        if !rows.iter().any(|(address, _)| *address == Some(view.cursor)) {
+            view.top = (0..CONTEXT_LINES.min(count.saturating_sub(1)))
+                .fold(view.cursor, |address, _| proxy.previous_instruction(address));
+            rows = self.rows(view.top, count);
+        }
+        view.area = area;
+        view.addresses = rows.iter().map(|(address, _)| *address).collect();
+
+        for (i, (address, text)) in rows.into_iter().enumerate() {
+            let row_area = Rect::new(area.x, area.y + i as u16, area.width, 1);
+            let line = match address {
+                Some(address) => {
+                    let breakpoint = match proxy.is_breakpoint(address) {
+                        true => Span::from("●").style(theme.breakpoint),
+                        false => Span::from(" "),
+                    };
+                    let current = if address == program_counter { ">" } else { " " };
+                    let text = Span::from(text);
+                    let text = if address == view.cursor { text.style(theme.selected) } else { text };
+                    Line::from(vec![breakpoint, Span::from(current), text])
+                }
+                None => Line::from(format!("  {}", text)),
+            };
+            line.render(row_area, buf);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn following() {
+        let mut view = DisassemblyView::default();
+        assert!(view.is_following());
+        view.goto(0x1000);
+        assert!(!view.is_following());
+        assert_eq!(view.cursor, 0x1000);
+        view.follow();
+        assert!(view.is_following());
+
+        view.rows = 1;
+        assert_eq!(view.page_size(), 1);
+        view.rows = 20;
+        assert_eq!(view.page_size(), 19);
+    }
+
+    #[test]
+    fn mouse() {
+        let view = DisassemblyView {
+            area: Rect::new(2, 10, 20, 3),
+            addresses: vec![None, Some(0x1000), Some(0x1002)],
+            ..Default::default()
+        };
+        assert_eq!(view.address_at(10, 10), None);
+        assert_eq!(view.address_at(10, 11), Some((0x1000, false)));
+        assert_eq!(view.address_at(2, 12), Some((0x1002, true)));
+        assert_eq!(view.address_at(10, 13), None);
+        assert_eq!(view.address_at(1, 11), None);
+    }
+}
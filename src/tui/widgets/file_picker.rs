@@ -0,0 +1,171 @@
+use std::path::{Path, PathBuf};
+
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Clear, Padding, Paragraph, Widget, WidgetRef};
+use ratatui::style::Style;
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::buffer::Buffer;
+use ratatui_explorer::{FileExplorer, Input};
+
+use crate::computer::config::DEFAULT_LOAD_ADDRESS;
+use crate::computer::symbols::parse_address;
+use crate::monitor::FileKind;
+use crate::tui::config::Theme;
+
+/*
+ * File picker
+ *
+ * Browses the file system for a file to load. Programs also need an address
+ * to load them at, which is asked for once the file is chosen.
+ */
+pub struct FilePicker {
+    pub kind: FileKind,
+    explorer: FileExplorer,
+    // The program chosen, while its load address is typed
+    pub chosen: Option<PathBuf>,
+    pub address_input: String,
+    pub error: Option<String>,
+    theme: Theme,
+}
+
+impl FilePicker {
+    // Start in the directory, or in the current one
+    pub fn new(kind: FileKind, directory: Option<&Path>, theme: &Theme) -> Result<Self, String> {
+        // The picker draws its own border
+        let explorer_theme = ratatui_explorer::Theme::default()
+            .with_block(Block::new())
+            .with_item_style(Style::new())
+            .with_dir_style(theme.directory)
+            .with_highlight_item_style(theme.selected)
+            .with_highlight_dir_style(theme.selected);
+        let mut explorer = FileExplorer::with_theme(explorer_theme).map_err(|e| e.to_string())?;
+        if let Some(directory) = directory {
+            explorer.set_cwd(directory).map_err(|e| format!("Can't open {}: {}", directory.display(), e))?;
+        }
+        Ok(Self { kind, explorer, chosen: None, address_input: String::new(), error: None, theme: *theme })
+    }
+
+    pub fn directory(&self) -> &Path {
+        self.explorer.cwd()
+    }
+
+    pub fn move_selection(&mut self, up: bool) {
+        self.handle(if up { Input::Up } else { Input::Down });
+    }
+
+    pub fn open_parent(&mut self) {
+        self.handle(Input::Left);
+    }
+
+    // Open the directory under the cursor, or choose the file. Returns the
+    // file, unless there is an address to ask for first.
+    pub fn choose(&mut self) -> Option<PathBuf> {
+        let current = self.explorer.current();
+        if current.is_dir() {
+            self.handle(Input::Right);
+            return None;
+        }
+        let file = current.path().clone();
+        match self.kind {
+            FileKind::Program => {
+                self.chosen = Some(file);
+                self.address_input.clear();
+                None
+            }
+            _ => Some(file),
+        }
+    }
+
+    // The address typed for the program, if one was
+    pub fn load_address(&self) -> Result<Option<u16>, String> {
+        match self.address_input.trim() {
+            "" => Ok(None),
+            address => parse_address(address).map(Some),
+        }
+    }
+
+    fn handle(&mut self, input: Input) {
+        self.error = self.explorer.handle(input).err().map(|e| e.to_string());
+    }
+}
+
+pub struct FilePickerWidget<'a> {
+    picker: &'a FilePicker,
+}
+
+impl<'a> FilePickerWidget<'a> {
+    pub fn new(picker: &'a FilePicker) -> Self {
+        Self { picker }
+    }
+}
+
+impl Widget for FilePickerWidget<'_> {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        let picker = self.picker;
+        let title = match picker.kind {
+            FileKind::Rom => " Load ROM ",
+            FileKind::Program => " Load program ",
+            FileKind::Symbols => " Load symbols ",
+            FileKind::State => " Load state ",
+        };
+        let block = Block::bordered()
+            .padding(Padding::horizontal(1))
+            .title(title)
+            .title_style(picker.theme.title)
+            .title_bottom(format!(" {} ", picker.directory().display()));
+        let inner = block.inner(area);
+        Clear.render(area, buf);
+        block.render(area, buf);
+
+        let [list_area, prompt_area] = Layout::vertical([Constraint::Fill(1), Constraint::Length(2)])
+            .areas(inner);
+        picker.explorer.widget().render_ref(list_area, buf);
+
+        let mut lines = Vec::new();
+        if let Some(file) = &picker.chosen {
+            let name = file.file_name().unwrap_or_default().to_string_lossy();
+            lines.push(Line::from(vec![
+                Span::raw(format!("Load {} at (${:04x}, or where it says): ", name, DEFAULT_LOAD_ADDRESS)),
+                Span::raw(picker.address_input.as_str()),
+                Span::raw(" ").style(picker.theme.selected),
+            ]));
+        }
+        if let Some(error) = &picker.error {
+            lines.push(Line::raw(error.as_str()).style(picker.theme.error));
+        }
+        Paragraph::new(lines).render(prompt_area, buf);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn choosing() {
+        let directory = std::env::temp_dir().join(format!("m6502-picker-{}", std::process::id()));
+        std::fs::create_dir_all(directory.join("sub")).unwrap();
+        std::fs::write(directory.join("demo.bin"), [0xea]).unwrap();
+
+        // The parent comes first, then directories, then files
+        let mut picker = FilePicker::new(FileKind::Program, Some(&directory), &Theme::default()).unwrap();
+        picker.move_selection(false);
+        assert_eq!(picker.choose(), None);
+        assert_eq!(picker.directory(), directory.join("sub"));
+        picker.open_parent();
+        picker.move_selection(true);
+        assert_eq!(picker.choose(), None);
+        assert_eq!(picker.chosen, Some(directory.join("demo.bin")));
+
+        assert_eq!(picker.load_address(), Ok(None));
+        picker.address_input = "$0800".to_string();
+        assert_eq!(picker.load_address(), Ok(Some(0x0800)));
+        picker.address_input = "nowhere".to_string();
+        assert!(picker.load_address().is_err());
+
+        let mut picker = FilePicker::new(FileKind::Rom, Some(&directory), &Theme::default()).unwrap();
+        picker.move_selection(true);
+        assert_eq!(picker.choose(), Some(directory.join("demo.bin")));
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,51 @@
+use crate::computer::devices::hd44780::to_char;
+use crate::computer::devices::Lcd;
+use crate::tui::config::Theme;
+
+use ratatui::buffer::Buffer;
+use ratatui::layout::Rect;
+use ratatui::style::{Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::Widget;
+
+// This widget shows the text on a character LCD
+pub struct LcdWidget<'a> {
+    lcd: &'a Lcd,
+    style: Style,
+}
+
+impl<'a> LcdWidget<'a> {
+    pub fn new(lcd: &'a Lcd, theme: &Theme) -> Self {
+        Self { lcd, style: theme.lcd }
+    }
+}
+
+impl Widget for LcdWidget<'_> {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        let controller = self.lcd.controller();
+        let cursor = controller.cursor();
+        let cursor_style = if controller.blink_on() {
+            self.style.add_modifier(Modifier::REVERSED | Modifier::SLOW_BLINK)
+        } else {
+            self.style.add_modifier(Modifier::UNDERLINED)
+        };
+
+        for (row, line) in controller.lines().iter().enumerate() {
+            if row as u16 >= area.height {
+                break;
+            }
+            let spans: Vec<Span> = line.iter().enumerate()
+                .map(|(column, &code)| {
+                    let style = if cursor == Some((row as u8, column as u8)) {
+                        cursor_style
+                    } else {
+                        self.style
+                    };
+                    Span::styled(to_char(code).to_string(), style)
+                })
+                .collect();
+            let line_area = Rect::new(area.x, area.y + row as u16, area.width, 1);
+            Line::from(spans).render(line_area, buf);
+        }
+    }
+}
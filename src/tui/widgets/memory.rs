@@ -1,23 +1,276 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
 use ratatui::text::{Line, Span};
-use ratatui::widgets::Widget;
-use ratatui::style::{Color, Style};
-use ratatui::layout::Rect;
+use ratatui::widgets::{StatefulWidget, Widget};
+use ratatui::style::Style;
+use ratatui::layout::{Position, Rect};
 use ratatui::buffer::Buffer;
 
+use crate::computer::cpu::inspect::CpuState;
 use crate::tui::App;
 
+pub const BYTES_PER_LINE: u16 = 16;
+
+// How long bytes stay highlighted after they changed
+const CHANGE_HIGHLIGHT: Duration = Duration::from_secs(1);
+
+
+// How bytes are shown as characters, next to the hexadecimal values
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Charset {
+    Ascii,
+    // The Commodore character set, in its upper and lower case mode
+    Petscii,
+}
+
+impl Charset {
+    pub fn to_char(self, byte: u8) -> char {
+        match self {
+            Charset::Ascii => match byte {
+                0x20..=0x7e => byte as char,
+                _ => '.',
+            },
+            Charset::Petscii => match byte {
+                0x41..=0x5a => byte.to_ascii_lowercase() as char,
+                0x61..=0x7a | 0xc1..=0xda => (byte & 0x1f | 0x40) as char,
+                0x5c => '£',
+                0x5e => '↑',
+                0x5f => '←',
+                0x20..=0x5d => byte as char,
+                _ => '.',
+            },
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Charset::Ascii => "ASCII",
+            Charset::Petscii => "PETSCII",
+        }
+    }
+}
+
+// Places in memory to jump to. Some of them move with the CPU.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bookmark {
+    ZeroPage,
+    Stack,
+    ProgramCounter,
+    Custom(u16),
+}
+
+impl Bookmark {
+    pub fn address(self, cpu_state: &CpuState) -> u16 {
+        match self {
+            Bookmark::ZeroPage => 0x0000,
+            Bookmark::Stack => 0x0100 | cpu_state.stack_pointer as u16,
+            Bookmark::ProgramCounter => cpu_state.program_counter,
+            Bookmark::Custom(address) => address,
+        }
+    }
+
+    pub fn name(self) -> String {
+        match self {
+            Bookmark::ZeroPage => "zp".to_string(),
+            Bookmark::Stack => "stack".to_string(),
+            Bookmark::ProgramCounter => "pc".to_string(),
+            Bookmark::Custom(address) => format!("${:04x}", address),
+        }
+    }
+}
+
+/*
+ * Memory view
+ *
+ * The state of the hex view: where the cursor is, which part of memory is
+ * shown, the bookmarks, and what changed recently. The view follows the
+ * program counter until the cursor is moved somewhere else.
+ */
+pub struct MemoryView {
+    pub cursor: u16,
+    // First address shown, always at the start of a line
+    top: u16,
+    // Number of lines shown the last time the view was drawn, and where
+    lines: u16,
+    area: Rect,
+    following: bool,
+    // The high nibble of a byte being typed
+    pending: Option<u8>,
+    pub charset: Charset,
+    pub bookmarks: Vec<Bookmark>,
+    pub bookmark: usize,
+    // Address being typed, to go to
+    pub goto_input: Option<String>,
+    // The bytes shown last time, to see what changed
+    previous: HashMap<u16, u8>,
+    changed: HashMap<u16, Instant>,
+}
+
+impl Default for MemoryView {
+    fn default() -> Self {
+        Self {
+            cursor: 0x0000,
+            top: 0x0000,
+            lines: 12,
+            area: Rect::default(),
+            following: true,
+            pending: None,
+            charset: Charset::Ascii,
+            bookmarks: vec![Bookmark::ProgramCounter, Bookmark::ZeroPage, Bookmark::Stack],
+            bookmark: 0,
+            goto_input: None,
+            previous: HashMap::new(),
+            changed: HashMap::new(),
+        }
+    }
+}
+
+impl MemoryView {
+    pub fn is_following(&self) -> bool {
+        self.following
+    }
+
+    pub fn goto(&mut self, address: u16) {
+        self.cursor = address;
+        self.pending = None;
+        self.following = false;
+    }
+
+    // Move the cursor by a number of bytes, stopping at the ends of memory
+    pub fn move_cursor(&mut self, delta: i32) {
+        self.goto((self.cursor as i32 + delta).clamp(0x0000, 0xffff) as u16);
+    }
+
+    pub fn move_pages(&mut self, pages: i32) {
+        self.move_cursor(pages * (self.lines * BYTES_PER_LINE) as i32);
+    }
+
+    // Scroll by a number of lines, taking the cursor along
+    pub fn scroll(&mut self, lines: i32) {
+        let delta = lines * BYTES_PER_LINE as i32;
+        let last_top = 0x10000 - (self.lines * BYTES_PER_LINE) as i32;
+        self.top = (self.top as i32 + delta).clamp(0, last_top) as u16;
+        self.move_cursor(delta);
+    }
+
+    // The byte shown at a position on the screen, in hexadecimal or as text
+    pub fn address_at(&self, column: u16, row: u16) -> Option<u16> {
+        if !self.area.contains(Position::new(column, row)) {
+            return None;
+        }
+        // Lines are "0200: " followed by "xx " for every byte, a space and the text
+        let offset = match column - self.area.x {
+            x @ 6..=53 => (x - 6) / 3,
+            x @ 55..=70 => x - 55,
+            _ => return None,
+        };
+        let address = self.top as u32 + (row - self.area.y) as u32 * BYTES_PER_LINE as u32 + offset as u32;
+        u16::try_from(address).ok()
+    }
+
+    // Type a hexadecimal digit at the cursor. When it completes a byte,
+    // returns where to write what, and moves on to the next byte.
+    pub fn type_digit(&mut self, digit: u8) -> Option<(u16, u8)> {
+        match self.pending.take() {
+            None => {
+                self.pending = Some(digit);
+                self.following = false;
+                None
+            }
+            Some(high) => {
+                let address = self.cursor;
+                self.cursor = self.cursor.saturating_add(1);
+                Some((address, high << 4 | digit))
+            }
+        }
+    }
+
+    pub fn cancel_edit(&mut self) -> bool {
+        self.pending.take().is_some()
+    }
+
+    // Jump to the next (or previous) bookmark
+    pub fn next_bookmark(&mut self, forward: bool, cpu_state: &CpuState) {
+        let count = self.bookmarks.len();
+        self.bookmark = match forward {
+            true => (self.bookmark + 1) % count,
+            false => (self.bookmark + count - 1) % count,
+        };
+        let bookmark = self.bookmarks[self.bookmark];
+        self.goto(bookmark.address(cpu_state));
+        self.following = bookmark == Bookmark::ProgramCounter;
+    }
+
+    // Add a bookmark at the cursor, or remove the one that's there
+    pub fn toggle_bookmark(&mut self) {
+        let bookmark = Bookmark::Custom(self.cursor);
+        match self.bookmarks.iter().position(|b| *b == bookmark) {
+            Some(i) => {
+                self.bookmarks.remove(i);
+                self.bookmark = self.bookmark.min(self.bookmarks.len() - 1);
+            }
+            None => {
+                self.bookmarks.push(bookmark);
+                self.bookmark = self.bookmarks.len() - 1;
+            }
+        }
+    }
+
+    pub fn toggle_charset(&mut self) {
+        self.charset = match self.charset {
+            Charset::Ascii => Charset::Petscii,
+            Charset::Petscii => Charset::Ascii,
+        };
+    }
+
+    // Scroll as little as possible to show the cursor, in a view of this many lines
+    fn scroll_to_cursor(&mut self, lines: u16) {
+        self.lines = lines.max(1);
+        let line = self.cursor / BYTES_PER_LINE;
+        let top_line = self.top / BYTES_PER_LINE;
+        let last_top_line = (0x10000 / BYTES_PER_LINE as u32 - self.lines as u32) as u16;
+        let top_line = if line < top_line {
+            line
+        } else if line >= top_line + self.lines {
+            line + 1 - self.lines
+        } else {
+            top_line
+        };
+        self.top = top_line.min(last_top_line) * BYTES_PER_LINE;
+    }
+
+    // Remember when shown bytes changed since the last time
+    fn track_changes(&mut self, start: u16, bytes: &[Option<u8>], now: Instant) {
+        self.changed.retain(|_, time| now.duration_since(*time) < CHANGE_HIGHLIGHT);
+        let mut previous = HashMap::with_capacity(bytes.len());
+        for (i, &byte) in bytes.iter().enumerate() {
+            let Some(byte) = byte else {
+                continue;
+            };
+            let address = start.wrapping_add(i as u16);
+            if self.previous.get(&address).is_some_and(|old| *old != byte) {
+                self.changed.insert(address, now);
+            }
+            previous.insert(address, byte);
+        }
+        self.previous = previous;
+    }
+
+    fn is_changed(&self, address: u16) -> bool {
+        self.changed.contains_key(&address)
+    }
+}
+
 pub struct MemoryWidget<'a> {
     app: &'a App<'a>,
-    start: u16,
     focus: u16,
 }
 
 impl<'a> MemoryWidget<'a> {
-    pub fn new(app: &'a App, start: u16) -> Self {
-        // Adjust start to nearest lower boundary
+    pub fn new(app: &'a App) -> Self {
         Self {
             app,
-            start,
             focus: 0x0000,
         }
     }
@@ -28,34 +281,170 @@ impl<'a> MemoryWidget<'a> {
     }
 }
 
-impl Widget for MemoryWidget<'_> {
-    // TODO Check area boundaries
-    fn render(self, area: Rect, buf: &mut Buffer) {
-        let lines = self.app.proxy.get_memory_lines(self.start, 12, 16);
+impl StatefulWidget for MemoryWidget<'_> {
+    type State = MemoryView;
 
-        for (i, (address, line)) in lines.iter().enumerate() {
+    fn render(self, area: Rect, buf: &mut Buffer, view: &mut MemoryView) {
+        if view.following {
+            view.cursor = self.focus;
+        }
+        view.scroll_to_cursor(area.height);
+        view.area = area;
+        let length = (view.lines as u32 * BYTES_PER_LINE as u32).min(0x10000 - view.top as u32) as u16;
+        let bytes = self.app.proxy.peek_memory(view.top, length);
+        view.track_changes(view.top, &bytes, Instant::now());
+        let theme = &self.app.theme;
+
+        for (i, line) in bytes.chunks(BYTES_PER_LINE as usize).enumerate() {
             let line_area = Rect::new(area.x, area.y + i as u16, area.width, 1);
-            let style = Style::default();
+            let start = view.top + i as u16 * BYTES_PER_LINE;
 
             let mut spans = vec![];
-            spans.push(Span::from(format!("{address:04x}")));
-            spans.push(Span::from(": " ));
+            spans.push(Span::from(format!("{start:04x}")));
+            spans.push(Span::from(": "));
 
-            for (j, value) in line.iter().enumerate() {
+            let mut text = String::new();
+            for (j, &value) in line.iter().enumerate() {
+                // Nothing answers at unmapped addresses
+                let hex = match value {
+                    Some(value) => format!("{value:02x}"),
+                    None => "--".to_string(),
+                };
+                let address = start + j as u16;
                 // TODO We probably should use cpu::instruction::decode_instruction()
                 // and cpu::instruction::AddressMode::get_operand_size()
                 // to determine how many bytes to colour, assuming this is an instruction
-                let style = if address + j as u16 == self.focus {
-                    style.bg(Color::Red)
+                let (hex, style) = if address == view.cursor {
+                    match view.pending {
+                        Some(high) => (format!("{high:x}_"), theme.selected),
+                        None => (hex, theme.selected),
+                    }
+                } else if address == self.focus {
+                    (hex, theme.program_counter)
+                } else if view.is_changed(address) {
+                    (hex, theme.changed)
                 } else {
-                    style
+                    (hex, Style::default())
                 };
-                spans.push(Span::from(format!("{value:02x}")).style(style));
-                spans.push(Span::from(" ".to_string()));
+                spans.push(Span::from(hex).style(style));
+                spans.push(Span::from(" "));
+                text.push(value.map_or(' ', |value| view.charset.to_char(value)));
             }
+            spans.push(Span::from(format!(" {text}")));
 
-            let line = Line::from(spans);
-            line.render(line_area, buf);
+            Line::from(spans).render(line_area, buf);
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scrolling() {
+        let mut view = MemoryView::default();
+        view.goto(0x0005);
+        view.scroll_to_cursor(4);
+        assert_eq!(view.top, 0x0000);
+
+        // Moving down past the last line scrolls by a line
+        view.move_cursor(0x40);
+        view.scroll_to_cursor(4);
+        assert_eq!(view.top, 0x0010);
+
+        // Moving up above the first line scrolls to it
+        view.goto(0x0008);
+        view.scroll_to_cursor(4);
+        assert_eq!(view.top, 0x0000);
+
+        // The cursor and the view stop at the end of memory
+        view.move_pages(0x1000);
+        view.scroll_to_cursor(4);
+        assert_eq!((view.cursor, view.top), (0xffff, 0xffc0));
+        view.move_pages(-0x2000);
+        assert_eq!(view.cursor, 0x0000);
+    }
+
+    #[test]
+    fn mouse() {
+        let mut view = MemoryView {
+            top: 0x0200,
+            area: Rect::new(10, 5, 80, 4),
+            ..Default::default()
+        };
+        view.goto(0x0205);
+        assert_eq!(view.address_at(16, 5), Some(0x0200));
+        assert_eq!(view.address_at(23, 6), Some(0x0212));
+        assert_eq!(view.address_at(67, 8), Some(0x0232));
+        assert_eq!(view.address_at(12, 5), None);
+        assert_eq!(view.address_at(16, 9), None);
+
+        // Scrolling takes the cursor along, and stops at the ends of memory
+        view.scroll(3);
+        assert_eq!((view.cursor, view.top), (0x0235, 0x0230));
+        view.scroll(-0x100);
+        assert_eq!((view.cursor, view.top), (0x0000, 0x0000));
+    }
+
+    #[test]
+    fn editing() {
+        let mut view = MemoryView::default();
+        view.goto(0x1000);
+        assert_eq!(view.type_digit(0xa), None);
+        assert_eq!(view.type_digit(0x9), Some((0x1000, 0xa9)));
+        assert_eq!(view.cursor, 0x1001);
+        assert_eq!(view.type_digit(0x1), None);
+        assert!(view.cancel_edit());
+        assert!(!view.cancel_edit());
+    }
+
+    #[test]
+    fn bookmarks() {
+        let cpu_state = CpuState {
+            program_counter: 0x1234,
+            stack_pointer: 0xf0,
+            ..CpuState::default()
+        };
+        let mut view = MemoryView::default();
+        assert!(view.is_following());
+        view.next_bookmark(true, &cpu_state);
+        assert_eq!(view.cursor, 0x0000);
+        view.next_bookmark(true, &cpu_state);
+        assert_eq!(view.cursor, 0x01f0);
+        assert!(!view.is_following());
+
+        view.goto(0x2000);
+        view.toggle_bookmark();
+        view.next_bookmark(true, &cpu_state);
+        view.next_bookmark(false, &cpu_state);
+        assert_eq!(view.cursor, 0x2000);
+        view.toggle_bookmark();
+        assert_eq!(view.bookmarks.len(), 3);
+
+        view.next_bookmark(true, &cpu_state);
+        assert_eq!(view.cursor, 0x1234);
+        assert!(view.is_following());
+    }
+
+    #[test]
+    fn changes() {
+        let mut view = MemoryView::default();
+        let now = Instant::now();
+        view.track_changes(0x0200, &[Some(1), Some(2), None], now);
+        assert!(!view.is_changed(0x0201));
+        view.track_changes(0x0200, &[Some(1), Some(5), Some(3)], now);
+        assert!(view.is_changed(0x0201));
+        assert!(!view.is_changed(0x0200));
+        assert!(!view.is_changed(0x0202));
+        view.track_changes(0x0200, &[Some(1), Some(5), Some(3)], now + CHANGE_HIGHLIGHT);
+        assert!(!view.is_changed(0x0201));
+    }
+
+    #[test]
+    fn charsets() {
+        let text = |charset: Charset| -> String { b"Hi\x01\xc1".iter().map(|&b| charset.to_char(b)).collect() };
+        assert_eq!(text(Charset::Ascii), "Hi..");
+        assert_eq!(text(Charset::Petscii), "hI.A");
+    }
+}
@@ -0,0 +1,83 @@
+use ratatui::text::{Line, Span};
+use ratatui::widgets::Widget;
+use ratatui::layout::Rect;
+use ratatui::buffer::Buffer;
+
+use crate::computer::StackItem;
+use crate::tui::App;
+
+// The stack, from the top down. Return addresses show the JSR they return
+// from, and interrupts where they return to, on the line after them.
+pub struct StackWidget<'a> {
+    app: &'a App<'a>,
+}
+
+impl<'a> StackWidget<'a> {
+    pub fn new(app: &'a App) -> Self {
+        Self { app }
+    }
+
+    fn lines(&self) -> Vec<Line<'static>> {
+        let proxy = &self.app.proxy;
+        let note = self.app.theme.note;
+        let entries = proxy.stack_entries();
+        if entries.is_empty() {
+            return vec![Line::raw("empty")];
+        }
+
+        let mut lines = Vec::new();
+        for entry in entries {
+            let bytes = proxy.peek_memory(entry.address, entry.item.size());
+            let hex: Vec<String> = bytes.iter()
+                .map(|byte| byte.map_or("--".to_string(), |byte| format!("{:02x}", byte)))
+                .collect();
+            let hex = format!("{:04x}: {:<9}", entry.address, hex.join(" "));
+            match entry.item {
+                StackItem::ReturnAddress { call } => {
+                    let text = proxy.disassemble_lines(call, 1).pop().map(|(_, text)| text).unwrap_or_default();
+                    lines.push(Line::from(vec![Span::raw(hex), Span::raw("return").style(note)]));
+                    lines.push(Line::raw(format!("  {:04x}: {}", call, text)).style(note));
+                }
+                StackItem::Interrupt { status, return_address } => {
+                    lines.push(Line::from(vec![Span::raw(hex), Span::raw("interrupt").style(note)]));
+                    lines.push(Line::raw(format!("  to {} {}", proxy.address_to_string(return_address), flags(status)))
+                        .style(note));
+                }
+                StackItem::Status(status) => {
+                    lines.push(Line::from(vec![Span::raw(hex), Span::raw(flags(status)).style(note)]));
+                }
+                StackItem::Byte(_) => lines.push(Line::raw(hex)),
+            }
+        }
+        lines
+    }
+}
+
+// Status flags as letters, in upper case when set
+fn flags(status: u8) -> String {
+    "NV-BDIZC".chars().enumerate()
+        .map(|(i, c)| match status & (0x80 >> i) != 0 {
+            true => c,
+            false => c.to_ascii_lowercase(),
+        })
+        .collect()
+}
+
+impl Widget for StackWidget<'_> {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        for (i, line) in self.lines().into_iter().take(area.height as usize).enumerate() {
+            let line_area = Rect::new(area.x, area.y + i as u16, area.width, 1);
+            line.render(line_area, buf);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn status_flags() {
+        assert_eq!(flags(0b1010_0101), "Nv-bdIzC");
+    }
+}
@@ -1,19 +1,22 @@
 use crate::computer::cpu;
+use crate::tui::config::Theme;
 
 use ratatui::buffer::Buffer;
 use ratatui::layout::Rect;
-use ratatui::style::{Color, Style};
+use ratatui::style::Style;
 use ratatui::text::{Line, Span};
 use ratatui::widgets::Widget;
 
 #[derive(Clone, Copy)]
 pub struct StatusRegisterWidget {
     status: cpu::status::Status,
+    set: Style,
+    clear: Style,
 }
 
 impl StatusRegisterWidget {
-    pub fn new(status: cpu::status::Status) -> Self {
-        Self { status }
+    pub fn new(status: cpu::status::Status, theme: &Theme) -> Self {
+        Self { status, set: theme.flag_set, clear: theme.flag_clear }
     }
 }
 
@@ -37,12 +40,8 @@ impl Widget for StatusRegisterWidget {
 }
 
 impl StatusRegisterWidget {
-    fn bit_span(&self, name: char, status: bool) -> Span {
-        let style = Style::default().fg(if status {
-            Color::LightYellow
-        } else {
-            Color::DarkGray
-        });
+    fn bit_span(&self, name: char, status: bool) -> Span<'_> {
+        let style = if status { self.set } else { self.clear };
         let name = if status {
             name.to_string()
         } else {
@@ -0,0 +1,153 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{StatefulWidget, Widget};
+use ratatui::style::Style;
+use ratatui::layout::Rect;
+use ratatui::buffer::Buffer;
+
+use crate::tui::App;
+use crate::watch::{Watch, WatchList};
+
+// How long values stay highlighted after they changed
+const CHANGE_HIGHLIGHT: Duration = Duration::from_secs(1);
+
+
+/*
+ * Watch view
+ *
+ * The watches, with the values they had the last time they were shown, to
+ * see which ones changed. A new watch is typed on the line below them.
+ */
+#[derive(Default)]
+pub struct WatchView {
+    pub list: WatchList,
+    pub selected: usize,
+    pub input: String,
+    // The outcome of the last change to the watches, if it went wrong
+    pub error: Option<String>,
+    // By watch text: the last value, and when it changed
+    values: HashMap<String, (String, Option<Instant>)>,
+}
+
+impl WatchView {
+    pub fn load(file: &Path) -> Result<Self, String> {
+        Ok(Self { list: WatchList::load(file)?, ..Default::default() })
+    }
+
+    // Add the watch that has been typed
+    pub fn add_input(&mut self) {
+        let result = Watch::parse(&self.input).and_then(|watch| self.list.add(watch));
+        match result {
+            Ok(()) => {
+                self.input.clear();
+                self.selected = self.list.watches.len() - 1;
+                self.error = None;
+            }
+            Err(e) => self.error = Some(e),
+        }
+    }
+
+    pub fn remove_selected(&mut self) {
+        self.error = self.list.remove(self.selected).err();
+        self.selected = self.selected.min(self.list.watches.len().saturating_sub(1));
+    }
+
+    pub fn move_selection(&mut self, delta: isize) {
+        let last = self.list.watches.len().saturating_sub(1);
+        self.selected = self.selected.saturating_add_signed(delta).min(last);
+    }
+
+    // Remember the value of a watch. Returns whether it changed recently.
+    fn track_change(&mut self, text: String, value: &str, now: Instant) -> bool {
+        let changed = match self.values.get(&text) {
+            Some((old, _)) if old != value => Some(now),
+            Some((_, changed)) => *changed,
+            None => None,
+        };
+        self.values.insert(text, (value.to_string(), changed));
+        changed.is_some_and(|time| now.duration_since(time) < CHANGE_HIGHLIGHT)
+    }
+}
+
+pub struct WatchWidget<'a> {
+    app: &'a App<'a>,
+    editing: bool,
+}
+
+impl<'a> WatchWidget<'a> {
+    pub fn new(app: &'a App, editing: bool) -> Self {
+        Self { app, editing }
+    }
+}
+
+impl StatefulWidget for WatchWidget<'_> {
+    type State = WatchView;
+
+    fn render(self, area: Rect, buf: &mut Buffer, view: &mut WatchView) {
+        let now = Instant::now();
+        let theme = &self.app.theme;
+        let mut lines = Vec::new();
+        for (i, watch) in view.list.watches.clone().iter().enumerate() {
+            let name = Span::raw(format!("{}: ", watch));
+            let name = if self.editing && i == view.selected { name.style(theme.selected) } else { name };
+            let value = match self.app.proxy.evaluate_watch(watch) {
+                Ok(value) => {
+                    let changed = view.track_change(watch.to_string(), &value, now);
+                    Span::raw(value).style(if changed { theme.changed } else { Style::default() })
+                }
+                Err(e) => Span::raw(e).style(theme.error),
+            };
+            lines.push(Line::from(vec![name, value]));
+        }
+        if self.editing {
+            lines.push(Line::from(vec![Span::raw("> "), Span::raw(view.input.clone()), Span::raw(" ").style(theme.selected)]));
+            if let Some(error) = &view.error {
+                lines.push(Line::raw(error.clone()).style(theme.error));
+            }
+        } else if lines.is_empty() {
+            lines.push(Line::raw("press 'w' to add"));
+        }
+
+        // Keep the line being typed in view
+        let skip = lines.len().saturating_sub(area.height as usize);
+        for (i, line) in lines.into_iter().skip(skip).enumerate() {
+            let line_area = Rect::new(area.x, area.y + i as u16, area.width, 1);
+            line.render(line_area, buf);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn changes() {
+        let mut view = WatchView::default();
+        let now = Instant::now();
+        assert!(!view.track_change("a".to_string(), "1", now));
+        assert!(view.track_change("a".to_string(), "2", now));
+        assert!(view.track_change("a".to_string(), "2", now));
+        assert!(!view.track_change("a".to_string(), "2", now + CHANGE_HIGHLIGHT));
+    }
+
+    #[test]
+    fn editing() {
+        let mut view = WatchView { input: "word $10".to_string(), ..Default::default() };
+        view.add_input();
+        view.input = "float $10".to_string();
+        view.add_input();
+        assert!(view.error.is_some());
+        assert_eq!(view.input, "float $10");
+        view.input = "$20".to_string();
+        view.add_input();
+        assert_eq!(view.selected, 1);
+        view.move_selection(-5);
+        assert_eq!(view.selected, 0);
+        view.remove_selected();
+        assert_eq!(view.list.watches, vec![Watch::parse("$20").unwrap()]);
+    }
+}
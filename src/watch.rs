@@ -0,0 +1,232 @@
+// Watch expressions: values in memory to keep an eye on while a program runs
+
+use std::path::{Path, PathBuf};
+
+use crate::computer::Computer;
+
+/*
+ * A watch is a location, optionally preceded by how to show what's there:
+ *
+ *   counter            the byte at a symbol, also as $10 or counter+1
+ *   word pointer       a little endian word
+ *   signed delta       a byte, as a signed number
+ *   bcd score          a byte with two decimal digits
+ *   string message     text up to a zero byte
+ */
+
+// How many characters of a string are shown, at most
+const MAX_STRING_LENGTH: u16 = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchFormat {
+    Byte,
+    Word,
+    Signed,
+    Bcd,
+    String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Watch {
+    format: WatchFormat,
+    // A symbol or an address, with an optional offset
+    location: String,
+    offset: u16,
+}
+
+impl Watch {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let text = text.trim();
+        let format = |name: &str| match name.to_lowercase().as_str() {
+            "byte" => Some(WatchFormat::Byte),
+            "word" => Some(WatchFormat::Word),
+            "signed" => Some(WatchFormat::Signed),
+            "bcd" => Some(WatchFormat::Bcd),
+            "string" => Some(WatchFormat::String),
+            _ => None,
+        };
+        let (format, location) = text.split_once(char::is_whitespace)
+            .and_then(|(name, location)| Some((format(name)?, location.trim())))
+            .unwrap_or((WatchFormat::Byte, text));
+        if location.is_empty() {
+            return Err("Watch without a location".to_string());
+        }
+        let (location, offset) = match location.split_once('+') {
+            Some((location, offset)) => {
+                let offset = offset.trim();
+                let offset = match offset.strip_prefix('$') {
+                    Some(hex) => u16::from_str_radix(hex, 16),
+                    None => offset.parse(),
+                }
+                .map_err(|_| format!("Invalid offset '{}'", offset))?;
+                (location.trim(), offset)
+            }
+            None => (location, 0),
+        };
+        if location.contains(char::is_whitespace) {
+            return Err(format!("Unknown watch format or location '{}'", location));
+        }
+        Ok(Self { format, location: location.to_string(), offset })
+    }
+
+    // The address of the watched value, which can change when symbols are loaded
+    pub fn address(&self, computer: &Computer) -> Result<u16, String> {
+        Ok(computer.symbols().resolve(&self.location)?.wrapping_add(self.offset))
+    }
+
+    // The value as it is now. Memory is only peeked at, so watching an I/O
+    // register doesn't change what the program reads from it.
+    pub fn evaluate(&self, computer: &Computer) -> Result<String, String> {
+        let address = self.address(computer)?;
+        let peek = |address: u16| computer.peek_memory(address, 1)[0]
+            .ok_or_else(|| format!("Nothing at ${:04x}", address));
+        let byte = peek(address)?;
+        Ok(match self.format {
+            WatchFormat::Byte => format!("${:02x} {}", byte, byte),
+            WatchFormat::Word => {
+                let word = u16::from_le_bytes([byte, peek(address.wrapping_add(1))?]);
+                format!("${:04x} {}", word, word)
+            }
+            WatchFormat::Signed => format!("{}", byte as i8),
+            WatchFormat::Bcd if byte >> 4 > 9 || byte & 0x0f > 9 => format!("${:02x} (not BCD)", byte),
+            WatchFormat::Bcd => format!("{:02x}", byte),
+            WatchFormat::String => {
+                let text: String = computer.peek_memory(address, MAX_STRING_LENGTH).into_iter()
+                    .map_while(|byte| byte.filter(|&byte| byte != 0))
+                    .map(|byte| if byte.is_ascii_graphic() || byte == b' ' { byte as char } else { '.' })
+                    .collect();
+                format!("\"{}\"", text)
+            }
+        })
+    }
+}
+
+impl std::fmt::Display for Watch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.format {
+            WatchFormat::Byte => {}
+            WatchFormat::Word => write!(f, "word ")?,
+            WatchFormat::Signed => write!(f, "signed ")?,
+            WatchFormat::Bcd => write!(f, "bcd ")?,
+            WatchFormat::String => write!(f, "string ")?,
+        }
+        write!(f, "{}", self.location)?;
+        if self.offset != 0 {
+            write!(f, "+{}", self.offset)?;
+        }
+        Ok(())
+    }
+}
+
+// Watches, kept in a file with one per line, so they're still there next time
+#[derive(Debug, Default)]
+pub struct WatchList {
+    pub watches: Vec<Watch>,
+    file: Option<PathBuf>,
+}
+
+impl WatchList {
+    // Read the watches from a file, if there is one yet
+    pub fn load(file: &Path) -> Result<Self, String> {
+        let mut list = Self { watches: Vec::new(), file: Some(file.to_path_buf()) };
+        if !file.exists() {
+            return Ok(list);
+        }
+        let text = std::fs::read_to_string(file)
+            .map_err(|e| format!("Was not able to read {}: {}", file.display(), e))?;
+        for (n, line) in text.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let watch = Watch::parse(line).map_err(|e| format!("{}:{}: {}", file.display(), n + 1, e))?;
+            list.watches.push(watch);
+        }
+        Ok(list)
+    }
+
+    pub fn add(&mut self, watch: Watch) -> Result<(), String> {
+        self.watches.push(watch);
+        self.save()
+    }
+
+    pub fn remove(&mut self, index: usize) -> Result<(), String> {
+        if index < self.watches.len() {
+            self.watches.remove(index);
+        }
+        self.save()
+    }
+
+    fn save(&self) -> Result<(), String> {
+        let Some(file) = &self.file else {
+            return Ok(());
+        };
+        let text: String = self.watches.iter().map(|watch| format!("{}\n", watch)).collect();
+        // The configuration directory may not be there yet
+        if let Some(directory) = file.parent().filter(|directory| !directory.as_os_str().is_empty()) {
+            std::fs::create_dir_all(directory)
+                .map_err(|e| format!("Was not able to create {}: {}", directory.display(), e))?;
+        }
+        std::fs::write(file, text).map_err(|e| format!("Was not able to write {}: {}", file.display(), e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_computer() -> Computer {
+        let mut computer = Computer::new()
+            .with_rom(vec![0; 0x100])
+            .with_memory_size(0x1000)
+            .without_rom_initialisation()
+            .build()
+            .unwrap();
+        computer.write_memory(0x0010, &[0x34, 0x12, 0xfe, 0x99, 0x9a]);
+        computer.write_memory(0x0200, b"Hi!\0");
+        computer.write_memory(0x0ffe, b"Hi");
+        computer.symbols_mut().add("pointer", 0x0010);
+        computer.symbols_mut().add("message", 0x0200);
+        computer
+    }
+
+    #[test]
+    fn formats() {
+        let computer = create_computer();
+        let value = |text: &str| Watch::parse(text).unwrap().evaluate(&computer);
+        assert_eq!(value("pointer"), Ok("$34 52".to_string()));
+        assert_eq!(value("word pointer"), Ok("$1234 4660".to_string()));
+        assert_eq!(value("signed pointer+2"), Ok("-2".to_string()));
+        assert_eq!(value("bcd $13"), Ok("99".to_string()));
+        assert_eq!(value("BCD pointer+$4"), Ok("$9a (not BCD)".to_string()));
+        assert_eq!(value("string message"), Ok("\"Hi!\"".to_string()));
+        assert!(value("nowhere").is_err());
+
+        // Up to where the memory ends
+        assert_eq!(value("string $ffe"), Ok("\"Hi\"".to_string()));
+        assert_eq!(value("word $fff"), Err("Nothing at $1000".to_string()));
+        assert!(value("$8000").is_err());
+    }
+
+    #[test]
+    fn parsing() {
+        assert_eq!(Watch::parse(" word  pointer + 2 ").unwrap().to_string(), "word pointer+2");
+        assert_eq!(Watch::parse("$10").unwrap().to_string(), "$10");
+        assert!(Watch::parse("float pointer").is_err());
+        assert!(Watch::parse("pointer+x").is_err());
+        assert!(Watch::parse("").is_err());
+    }
+
+    #[test]
+    fn saving() {
+        let file = std::env::temp_dir().join(format!("m6502-watch-{}.watch", std::process::id()));
+        let mut list = WatchList::load(&file).unwrap();
+        assert!(list.watches.is_empty());
+        list.add(Watch::parse("word pointer").unwrap()).unwrap();
+        list.add(Watch::parse("message").unwrap()).unwrap();
+        list.remove(1).unwrap();
+
+        let list = WatchList::load(&file).unwrap();
+        std::fs::remove_file(&file).unwrap();
+        assert_eq!(list.watches, vec![Watch::parse("word pointer").unwrap()]);
+    }
+}